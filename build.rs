@@ -0,0 +1,5 @@
+fn main() {
+    // Generating the gRPC service needs protoc, so only builds with the grpc feature do it
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/diffgraph.proto").expect("Unable to compile proto/diffgraph.proto");
+}
@@ -0,0 +1,48 @@
+#ifndef DIFFGRAPH_H
+#define DIFFGRAPH_H
+
+#include <stdbool.h>
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct DiffGraphHandle DiffGraphHandle;
+
+typedef struct DiffGraphNode {
+    size_t id;
+    /* Valid until the graph is freed */
+    const char *kind;
+    /* Index of the file the node is from */
+    size_t file;
+    size_t start_byte;
+    size_t end_byte;
+} DiffGraphNode;
+
+typedef struct DiffGraphEdge {
+    size_t from;
+    size_t to;
+} DiffGraphEdge;
+
+/* The message of the last failure on this thread, or NULL */
+const char *diffgraph_last_error(void);
+
+/* Graphs diff against the git repository at repo, NULL on failure */
+DiffGraphHandle *diffgraph_build(const char *repo, const char *diff);
+void diffgraph_free(DiffGraphHandle *graph);
+
+/* The graph in the JSON export schema, released with diffgraph_string_free */
+char *diffgraph_to_json(const DiffGraphHandle *graph);
+void diffgraph_string_free(char *value);
+
+size_t diffgraph_node_count(const DiffGraphHandle *graph);
+bool diffgraph_node_at(const DiffGraphHandle *graph, size_t index, DiffGraphNode *node);
+size_t diffgraph_edge_count(const DiffGraphHandle *graph);
+bool diffgraph_edge_at(const DiffGraphHandle *graph, size_t index, DiffGraphEdge *edge);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,195 @@
+//! C interface to diffgraph, see `include/diffgraph.h`.
+//!
+//! Graphs are built into an opaque handle, which is either serialized to JSON or walked node
+//! by node and edge by edge. Functions that fail return null or false and keep a message for
+//! `diffgraph_last_error`.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+use diffdiagram::error::DiffGraphError;
+use diffdiagram::graph::{DiffGraph, DiffGraphParams};
+use diffdiagram::progress;
+use diffdiagram::snapshot::GraphSnapshot;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, recording its error or panic instead of letting either cross the C boundary.
+fn guard<T>(f: impl FnOnce() -> Result<T, DiffGraphError>) -> Option<T> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            None
+        },
+        Err(_) => {
+            set_last_error("diffgraph panicked".into());
+            None
+        },
+    }
+}
+
+unsafe fn try_get_str<'a>(value: *const c_char, name: &str) -> Result<&'a str, DiffGraphError> {
+    if value.is_null() {
+        return Err(DiffGraphError::Usage(format!("{} must not be null", name)));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| DiffGraphError::Usage(format!("{} is not valid UTF-8", name)))
+}
+
+/// A built graph, owned by the caller until passed to `diffgraph_free`.
+pub struct DiffGraphHandle {
+    snapshot: GraphSnapshot,
+    /// Node kinds as C strings, parallel to `snapshot.nodes`
+    kinds: Vec<CString>,
+}
+
+#[repr(C)]
+pub struct DiffGraphNode {
+    pub id: usize,
+    /// Valid until the graph is freed
+    pub kind: *const c_char,
+    /// Index of the file the node is from
+    pub file: usize,
+    pub start_byte: usize,
+    pub end_byte: usize,
+}
+
+#[repr(C)]
+pub struct DiffGraphEdge {
+    pub from: usize,
+    pub to: usize,
+}
+
+/// The message of the last failure on this thread, or null. Valid until the next call that fails.
+#[no_mangle]
+pub extern "C" fn diffgraph_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| last_error.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+/// Graphs `diff` against the git repository at `repo`, taking the same diffs as `--diff`
+/// besides GitHub and Gerrit URLs.
+/// Returns null on failure.
+///
+/// # Safety
+/// `repo` and `diff` must be null or valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn diffgraph_build(repo: *const c_char, diff: *const c_char) -> *mut DiffGraphHandle {
+    let handle = guard(|| {
+        let repo = try_get_str(repo, "repo")?;
+        let diff = try_get_str(diff, "diff")?;
+        progress::set_enabled(false);
+        let params = DiffGraphParams::try_from_repository(Path::new(repo), diff)?;
+        let snapshot = GraphSnapshot::from_graph(&DiffGraph::create(params)?);
+        let kinds = snapshot.nodes
+            .iter()
+            .map(|node| CString::new(node.kind.as_ref()).unwrap_or_default())
+            .collect();
+        Ok(DiffGraphHandle { snapshot, kinds })
+    });
+
+    handle.map_or(ptr::null_mut(), |handle| Box::into_raw(Box::new(handle)))
+}
+
+/// # Safety
+/// `graph` must be null or returned by `diffgraph_build` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn diffgraph_free(graph: *mut DiffGraphHandle) {
+    if !graph.is_null() {
+        drop(Box::from_raw(graph));
+    }
+}
+
+/// The graph in the JSON export schema, to be released with `diffgraph_string_free`. Null on failure.
+///
+/// # Safety
+/// `graph` must be returned by `diffgraph_build` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn diffgraph_to_json(graph: *const DiffGraphHandle) -> *mut c_char {
+    let json = guard(|| {
+        let graph = graph.as_ref().ok_or_else(|| DiffGraphError::Usage("graph must not be null".into()))?;
+        let json = serde_json::to_string(&graph.snapshot)
+            .map_err(|e| DiffGraphError::Usage(e.to_string()))?;
+        CString::new(json).map_err(|e| DiffGraphError::Usage(e.to_string()))
+    });
+
+    json.map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// # Safety
+/// `value` must be null or returned by `diffgraph_to_json` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn diffgraph_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+/// # Safety
+/// `graph` must be returned by `diffgraph_build` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn diffgraph_node_count(graph: *const DiffGraphHandle) -> usize {
+    graph.as_ref().map_or(0, |graph| graph.snapshot.nodes.len())
+}
+
+/// Fills `node` with the node at `index`, returning false when the index is out of range.
+///
+/// # Safety
+/// `graph` must be returned by `diffgraph_build` and not freed yet, `node` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn diffgraph_node_at(graph: *const DiffGraphHandle, index: usize, node: *mut DiffGraphNode) -> bool {
+    let (graph, node) = match (graph.as_ref(), node.as_mut()) {
+        (Some(graph), Some(node)) => (graph, node),
+        _ => return false,
+    };
+    match (graph.snapshot.nodes.get(index), graph.kinds.get(index)) {
+        (Some(info), Some(kind)) => {
+            *node = DiffGraphNode {
+                id: info.id,
+                kind: kind.as_ptr(),
+                file: info.file,
+                start_byte: info.byte_range.start,
+                end_byte: info.byte_range.end,
+            };
+            true
+        },
+        _ => false,
+    }
+}
+
+/// # Safety
+/// `graph` must be returned by `diffgraph_build` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn diffgraph_edge_count(graph: *const DiffGraphHandle) -> usize {
+    graph.as_ref().map_or(0, |graph| graph.snapshot.edges.len())
+}
+
+/// Fills `edge` with the edge at `index`, returning false when the index is out of range.
+///
+/// # Safety
+/// `graph` must be returned by `diffgraph_build` and not freed yet, `edge` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn diffgraph_edge_at(graph: *const DiffGraphHandle, index: usize, edge: *mut DiffGraphEdge) -> bool {
+    let (graph, edge) = match (graph.as_ref(), edge.as_mut()) {
+        (Some(graph), Some(edge)) => (graph, edge),
+        _ => return false,
+    };
+    match graph.snapshot.edges.get(index) {
+        Some(snapshot_edge) => {
+            *edge = DiffGraphEdge { from: snapshot_edge.from, to: snapshot_edge.to };
+            true
+        },
+        None => false,
+    }
+}
@@ -0,0 +1,11 @@
+/** Graphs `diffSpec` against the git repository at `repoPath`, taking the same diffs as `--diff` besides GitHub and Gerrit URLs. */
+export function createDiffGraph(repoPath: string, diffSpec: string): Promise<DiffGraphExport>
+
+/** The graph in the JSON export schema */
+export interface DiffGraphExport {
+  files: Array<{ path: string; language: string }>
+  nodes: Array<{ id: number; kind_id: number; kind: string; file: number; byte_range: { start: number; end: number } }>
+  edges: Array<{ from: number; to: number }>
+  changes: Array<object>
+  truncations: Array<string>
+}
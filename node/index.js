@@ -0,0 +1,16 @@
+const { existsSync } = require('fs')
+const { join } = require('path')
+
+// `napi build --platform` names the addon after the platform it was built for
+const candidates = [
+  `diffgraph.${process.platform}-${process.arch}.node`,
+  `diffgraph.${process.platform}-${process.arch}-gnu.node`,
+  `diffgraph.${process.platform}-${process.arch}-msvc.node`,
+  'diffgraph.node',
+]
+const addon = candidates.map((name) => join(__dirname, name)).find(existsSync)
+if (!addon) {
+  throw new Error(`diffgraph has not been built for ${process.platform}-${process.arch}, run npm run build`)
+}
+
+module.exports = require(addon)
@@ -0,0 +1,40 @@
+//! The `diffgraph` npm package, graphs are built off the JavaScript thread and resolved as the JSON export.
+
+use std::path::Path;
+use napi::bindgen_prelude::*;
+use napi::JsUnknown;
+use napi_derive::napi;
+
+use diffdiagram::graph::{DiffGraph, DiffGraphParams};
+use diffdiagram::progress;
+use diffdiagram::snapshot::GraphSnapshot;
+
+pub struct CreateDiffGraph {
+    repo_path: String,
+    diff_spec: String,
+}
+
+impl Task for CreateDiffGraph {
+    type Output = GraphSnapshot;
+    type JsValue = JsUnknown;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        progress::set_enabled(false);
+        let params = DiffGraphParams::try_from_repository(Path::new(&self.repo_path), &self.diff_spec)
+            .map_err(|e| Error::from_reason(e.to_string()))?;
+        DiffGraph::create(params)
+            .map(|graph| GraphSnapshot::from_graph(&graph))
+            .map_err(|e| Error::from_reason(e.to_string()))
+    }
+
+    fn resolve(&mut self, env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        env.to_js_value(&output)
+    }
+}
+
+/// Graphs `diff_spec` against the git repository at `repo_path`, taking the same diffs as `--diff`
+/// besides GitHub and Gerrit URLs.
+#[napi(ts_return_type = "Promise<DiffGraphExport>")]
+pub fn create_diff_graph(repo_path: String, diff_spec: String) -> AsyncTask<CreateDiffGraph> {
+    AsyncTask::new(CreateDiffGraph { repo_path, diff_spec })
+}
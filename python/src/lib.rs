@@ -0,0 +1,59 @@
+//! The `diffgraph` Python module. Nodes and edges come back in the shapes networkx takes:
+//!
+//! ```python
+//! import diffgraph, networkx
+//! nodes, edges = diffgraph.build_graph(".", "main..feature")
+//! graph = networkx.DiGraph()
+//! graph.add_nodes_from(nodes)
+//! graph.add_edges_from(edges)
+//! ```
+
+use std::path::Path;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use diffdiagram::graph::{DiffGraph, DiffGraphParams};
+use diffdiagram::progress;
+use diffdiagram::snapshot::GraphSnapshot;
+
+type NodeList = Vec<(usize, PyObject)>;
+type EdgeList = Vec<(usize, usize)>;
+
+/// Graphs `diff` against the git repository at `repo`, taking the same diffs as `--diff`
+/// besides GitHub and Gerrit URLs.
+/// Returns `(nodes, edges)`: nodes as `(id, attributes)` pairs and edges as `(from, to)` pairs.
+#[pyfunction]
+fn build_graph(py: Python<'_>, repo: &str, diff: &str) -> PyResult<(NodeList, EdgeList)> {
+    let snapshot = py
+        .allow_threads(|| {
+            progress::set_enabled(false);
+            let params = DiffGraphParams::try_from_repository(Path::new(repo), diff)?;
+            DiffGraph::create(params).map(|graph| GraphSnapshot::from_graph(&graph))
+        })
+        .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+
+    let nodes = snapshot.nodes
+        .iter()
+        .map(|node| {
+            let attributes = PyDict::new(py);
+            attributes.set_item("kind", node.kind.as_ref())?;
+            attributes.set_item("file", snapshot.files.get(node.file).map(|file| file.path.as_str()))?;
+            attributes.set_item("start_byte", node.byte_range.start)?;
+            attributes.set_item("end_byte", node.byte_range.end)?;
+            Ok((node.id, attributes.to_object(py)))
+        })
+        .collect::<PyResult<NodeList>>()?;
+    let edges = snapshot.edges
+        .iter()
+        .map(|edge| (edge.from, edge.to))
+        .collect();
+
+    Ok((nodes, edges))
+}
+
+#[pymodule]
+fn diffgraph(_py: Python<'_>, module: &PyModule) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(build_graph, module)?)?;
+    Ok(())
+}
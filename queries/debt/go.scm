@@ -0,0 +1,14 @@
+; Debt and panics introduced in Go. Captures name the tag of the node they capture, captures
+; starting with an underscore only serve the predicates.
+
+; Comments left for later
+((comment) @todo
+ (#match? @todo "\\b(TODO|XXX)\\b"))
+
+((comment) @fixme
+ (#match? @fixme "\\b(FIXME|HACK)\\b"))
+
+; Panicking outright
+((call_expression
+  function: (identifier) @_function) @panic
+ (#eq? @_function "panic"))
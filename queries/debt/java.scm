@@ -0,0 +1,15 @@
+; Debt introduced in Java. Captures name the tag of the node they capture, captures starting
+; with an underscore only serve the predicates.
+
+; Comments left for later
+(([(line_comment) (block_comment)]) @todo
+ (#match? @todo "\\b(TODO|XXX)\\b"))
+
+(([(line_comment) (block_comment)]) @fixme
+ (#match? @fixme "\\b(FIXME|HACK)\\b"))
+
+; Failing at runtime on purpose
+((throw_statement
+  (object_creation_expression
+    type: (type_identifier) @_exception)) @panic
+ (#eq? @_exception "UnsupportedOperationException"))
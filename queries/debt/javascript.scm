@@ -0,0 +1,12 @@
+; Debt introduced in JavaScript and TypeScript. Captures name the tag of the node they
+; capture, captures starting with an underscore only serve the predicates.
+
+; Comments left for later
+((comment) @todo
+ (#match? @todo "\\b(TODO|XXX)\\b"))
+
+((comment) @fixme
+ (#match? @fixme "\\b(FIXME|HACK)\\b"))
+
+; Breakpoints left in
+(debugger_statement) @debugger
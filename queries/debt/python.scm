@@ -0,0 +1,24 @@
+; Debt and swallowed errors introduced in Python. Captures name the tag of the node they
+; capture, captures starting with an underscore only serve the predicates.
+
+; Comments left for later
+((comment) @todo
+ (#match? @todo "\\b(TODO|XXX)\\b"))
+
+((comment) @fixme
+ (#match? @fixme "\\b(FIXME|HACK)\\b"))
+
+; Catching everything, including KeyboardInterrupt and SystemExit
+((except_clause
+  .
+  (block)) @bare-except)
+
+; Failing at runtime on purpose
+((raise_statement
+  (call
+    function: (identifier) @_exception)) @panic
+ (#eq? @_exception "NotImplementedError"))
+
+((raise_statement
+  (identifier) @_exception) @panic
+ (#eq? @_exception "NotImplementedError"))
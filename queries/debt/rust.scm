@@ -0,0 +1,20 @@
+; Debt and panics introduced in Rust. Captures name the tag of the node they capture,
+; captures starting with an underscore only serve the predicates.
+
+; Comments left for later
+(([(line_comment) (block_comment)]) @todo
+ (#match? @todo "\\b(TODO|XXX)\\b"))
+
+(([(line_comment) (block_comment)]) @fixme
+ (#match? @fixme "\\b(FIXME|HACK)\\b"))
+
+; Panicking on None and Err
+((call_expression
+  function: (field_expression
+    field: (field_identifier) @_method)) @unwrap
+ (#match? @_method "^(unwrap|expect|unwrap_err|expect_err)$"))
+
+; Panicking outright
+((macro_invocation
+  macro: (identifier) @_macro) @panic
+ (#match? @_macro "^(panic|todo|unimplemented|unreachable)$"))
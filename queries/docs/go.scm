@@ -0,0 +1,7 @@
+; Doc comments of Go declarations. @doc captures the last comment before the declaration
+; captured as @declaration, the adjacent comments above it are part of the doc too.
+
+((comment) @doc
+  .
+  [(function_declaration) (method_declaration) (type_declaration) (const_declaration)
+   (var_declaration)] @declaration)
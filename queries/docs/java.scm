@@ -0,0 +1,8 @@
+; Javadoc comments of Java declarations. @doc captures the comment right before the
+; declaration captured as @declaration, whichever kind of comment node the grammar has.
+
+(((_) @doc
+  .
+  [(class_declaration) (interface_declaration) (enum_declaration) (method_declaration)
+   (constructor_declaration) (field_declaration)] @declaration)
+ (#match? @doc "^/\\*\\*"))
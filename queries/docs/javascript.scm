@@ -0,0 +1,13 @@
+; JSDoc comments of JavaScript and TypeScript declarations. @doc captures the comment right
+; before the declaration captured as @declaration.
+
+(((comment) @doc
+  .
+  [(function_declaration) (generator_function_declaration) (class_declaration) (method_definition)
+   (lexical_declaration) (variable_declaration)] @declaration)
+ (#match? @doc "^/\\*\\*"))
+
+(((comment) @doc
+  .
+  (export_statement declaration: (_) @declaration))
+ (#match? @doc "^/\\*\\*"))
@@ -0,0 +1,8 @@
+; Docstrings of Python declarations: @doc captures the string opening the body of the
+; declaration captured as @declaration.
+
+(function_definition
+  body: (block . (expression_statement (string) @doc))) @declaration
+
+(class_definition
+  body: (block . (expression_statement (string) @doc))) @declaration
@@ -0,0 +1,20 @@
+; Doc comments of Rust declarations. @doc captures the last comment before the declaration
+; captured as @declaration, the adjacent comments above it are part of the doc too.
+
+(((line_comment) @doc
+  .
+  (attribute_item)*
+  .
+  [(function_item) (function_signature_item) (struct_item) (enum_item) (union_item) (trait_item)
+   (impl_item) (type_item) (mod_item) (const_item) (static_item) (macro_definition)
+   (enum_variant) (field_declaration)] @declaration)
+ (#match? @doc "^///"))
+
+(((block_comment) @doc
+  .
+  (attribute_item)*
+  .
+  [(function_item) (function_signature_item) (struct_item) (enum_item) (union_item) (trait_item)
+   (impl_item) (type_item) (mod_item) (const_item) (static_item) (macro_definition)
+   (enum_variant) (field_declaration)] @declaration)
+ (#match? @doc "^/\\*\\*"))
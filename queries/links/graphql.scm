@@ -0,0 +1,8 @@
+; Definitions of GraphQL schemas that resolvers and clients in other languages refer to by
+; name. Each pattern captures the definition, named after its kind, and its @name.
+
+(object_type_definition (name) @name) @type
+(input_object_type_definition (name) @name) @input
+(interface_type_definition (name) @name) @interface
+(enum_type_definition (name) @name) @enum
+(union_type_definition (name) @name) @union
@@ -0,0 +1,7 @@
+; Definitions of Protocol Buffers files that generated code in other languages refers to by
+; name. Each pattern captures the definition, named after its kind, and its @name.
+
+(message (message_name) @name) @message
+(enum (enum_name) @name) @enum
+(service (service_name) @name) @service
+(rpc (rpc_name) @name) @rpc
@@ -0,0 +1,8 @@
+; Definitions of Thrift files that generated code in other languages refers to by name. Each
+; pattern captures the definition, named after its kind, and its @name.
+
+(struct_definition (identifier) @name) @struct
+(exception_definition (identifier) @name) @exception
+(enum_definition (identifier) @name) @enum
+(service_definition (identifier) @name) @service
+(function_definition (identifier) @name) @function
@@ -0,0 +1,31 @@
+; Security-sensitive patterns in Go. Captures name the tag of the node they capture,
+; captures starting with an underscore only serve the predicates.
+
+; Running commands
+((call_expression
+  function: (selector_expression
+    operand: (identifier) @_package
+    field: (field_identifier) @_function)) @exec
+ (#eq? @_package "exec")
+ (#match? @_function "^(Command|CommandContext)$"))
+
+; SQL built by concatenating or formatting strings
+((call_expression
+  function: (selector_expression
+    field: (field_identifier) @_function)
+  arguments: (argument_list . (binary_expression))) @sql
+ (#match? @_function "^(Query|QueryRow|QueryContext|QueryRowContext|Exec|ExecContext|Prepare)$"))
+
+((call_expression
+  function: (selector_expression
+    field: (field_identifier) @_function)
+  arguments: (argument_list . (call_expression
+    function: (selector_expression
+      operand: (identifier) @_fmt)))) @sql
+ (#match? @_function "^(Query|QueryRow|QueryContext|QueryRowContext|Exec|ExecContext|Prepare)$")
+ (#eq? @_fmt "fmt"))
+
+; Cryptographic primitives
+((selector_expression
+  operand: (identifier) @_package) @crypto
+ (#match? @_package "^(md5|sha1|sha256|sha512|aes|des|cipher|rsa|ecdsa|ed25519|hmac|tls|x509)$"))
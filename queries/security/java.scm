@@ -0,0 +1,32 @@
+; Security-sensitive patterns in Java. Captures name the tag of the node they capture,
+; captures starting with an underscore only serve the predicates.
+
+; Running commands
+((method_invocation
+  object: (method_invocation
+    name: (identifier) @_runtime)
+  name: (identifier) @_function) @exec
+ (#eq? @_runtime "getRuntime")
+ (#eq? @_function "exec"))
+
+((object_creation_expression
+  type: (type_identifier) @_type) @exec
+ (#eq? @_type "ProcessBuilder"))
+
+; Evaluating code, such as with a ScriptEngine
+((method_invocation
+  name: (identifier) @_function) @eval
+ (#eq? @_function "eval"))
+
+; SQL built by concatenating strings
+((method_invocation
+  name: (identifier) @_function
+  arguments: (argument_list . (binary_expression))) @sql
+ (#match? @_function "^(executeQuery|executeUpdate|execute|prepareStatement|addBatch|createQuery|createNativeQuery)$"))
+
+; Cryptographic primitives
+((method_invocation
+  object: (identifier) @_class
+  name: (identifier) @_function) @crypto
+ (#match? @_class "^(MessageDigest|Cipher|KeyGenerator|KeyPairGenerator|Mac|SecretKeyFactory|Signature|SecureRandom)$")
+ (#eq? @_function "getInstance"))
@@ -0,0 +1,35 @@
+; Security-sensitive patterns in JavaScript and TypeScript. Captures name the tag of the node
+; they capture, captures starting with an underscore only serve the predicates.
+
+; Running commands
+((call_expression
+  function: (member_expression
+    object: (identifier) @_module)) @exec
+ (#match? @_module "^(child_process|childProcess|cp)$"))
+
+((call_expression
+  function: [(identifier) @_function
+             (member_expression property: (property_identifier) @_function)]) @exec
+ (#match? @_function "^(execSync|execFile|execFileSync|spawnSync)$"))
+
+; Evaluating code
+((call_expression
+  function: (identifier) @_function) @eval
+ (#match? @_function "^(eval|Function)$"))
+
+((new_expression
+  constructor: (identifier) @_constructor) @eval
+ (#eq? @_constructor "Function"))
+
+; SQL built by concatenating or interpolating strings
+((call_expression
+  function: (member_expression
+    property: (property_identifier) @_function)
+  arguments: (arguments . [(binary_expression) (template_string (template_substitution))])) @sql
+ (#match? @_function "^(query|execute|raw|unsafe)$"))
+
+; Cryptographic primitives
+((call_expression
+  function: (member_expression
+    object: (identifier) @_module)) @crypto
+ (#match? @_module "^(crypto|subtle|CryptoJS|forge|bcrypt)$"))
@@ -0,0 +1,42 @@
+; Security-sensitive patterns in Python. Captures name the tag of the node they capture,
+; captures starting with an underscore only serve the predicates.
+
+; Running commands
+((call
+  function: (attribute
+    object: (identifier) @_module
+    attribute: (identifier) @_function)) @exec
+ (#eq? @_module "os")
+ (#match? @_function "^(system|popen|exec[lv]p?e?|spawn[lv]p?e?)$"))
+
+((call
+  function: (attribute
+    object: (identifier) @_module)) @exec
+ (#eq? @_module "subprocess"))
+
+; Evaluating code
+((call
+  function: (identifier) @_function) @eval
+ (#match? @_function "^(eval|exec|compile|__import__)$"))
+
+; SQL built by concatenating or formatting strings
+((call
+  function: (attribute
+    attribute: (identifier) @_function)
+  arguments: (argument_list . [(binary_operator) (string (interpolation))])) @sql
+ (#match? @_function "^(execute|executemany|executescript|raw)$"))
+
+((call
+  function: (attribute
+    attribute: (identifier) @_function)
+  arguments: (argument_list . (call
+    function: (attribute
+      object: (string)
+      attribute: (identifier) @_format)))) @sql
+ (#match? @_function "^(execute|executemany|executescript|raw)$")
+ (#eq? @_format "format"))
+
+; Cryptographic primitives
+((attribute
+  object: (identifier) @_module) @crypto
+ (#match? @_module "^(hashlib|hmac|ssl|secrets|Crypto|cryptography)$"))
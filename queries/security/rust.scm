@@ -0,0 +1,21 @@
+; Security-sensitive patterns in Rust. Captures name the tag of the node they capture,
+; captures starting with an underscore only serve the predicates.
+
+; Running commands
+((scoped_identifier
+  path: (identifier) @_type
+  name: (identifier) @_function) @exec
+ (#eq? @_type "Command")
+ (#eq? @_function "new"))
+
+; SQL built with format!
+((macro_invocation
+  macro: (identifier) @_macro
+  (token_tree . (string_literal) @_query)) @sql
+ (#eq? @_macro "format")
+ (#match? @_query "(?i)\\b(select|insert|update|delete)\\b"))
+
+; Cryptographic primitives
+((scoped_identifier
+  path: (identifier) @_crate) @crypto
+ (#match? @_crate "^(ring|openssl|rustls|aes|aes_gcm|chacha20poly1305|rsa|ed25519_dalek|hmac|md5|sha1|sha2|sha3)$"))
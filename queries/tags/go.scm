@@ -0,0 +1,9 @@
+; Definitions and references in Go, following tree-sitter's tags conventions: @name captures
+; the symbol's name, @definition.<kind> or @reference.<kind> the node it is the name of.
+
+(function_declaration name: (identifier) @name) @definition.function
+(method_declaration name: (field_identifier) @name) @definition.method
+(type_spec name: (type_identifier) @name) @definition.type
+
+(call_expression function: (identifier) @name) @reference.call
+(call_expression function: (selector_expression field: (field_identifier) @name)) @reference.call
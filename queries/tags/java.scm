@@ -0,0 +1,11 @@
+; Definitions and references in Java, following tree-sitter's tags conventions: @name captures
+; the symbol's name, @definition.<kind> or @reference.<kind> the node it is the name of.
+
+(class_declaration name: (identifier) @name) @definition.class
+(interface_declaration name: (identifier) @name) @definition.interface
+(enum_declaration name: (identifier) @name) @definition.class
+(method_declaration name: (identifier) @name) @definition.method
+(constructor_declaration name: (identifier) @name) @definition.method
+
+(method_invocation name: (identifier) @name) @reference.call
+(object_creation_expression type: (type_identifier) @name) @reference.class
@@ -0,0 +1,13 @@
+; Definitions and references in JavaScript and TypeScript, following tree-sitter's tags
+; conventions: @name captures the symbol's name, @definition.<kind> or @reference.<kind> the
+; node it is the name of.
+
+(function_declaration name: (identifier) @name) @definition.function
+(generator_function_declaration name: (identifier) @name) @definition.function
+(class_declaration name: (_) @name) @definition.class
+(method_definition name: (property_identifier) @name) @definition.method
+(variable_declarator name: (identifier) @name value: (arrow_function)) @definition.function
+
+(call_expression function: (identifier) @name) @reference.call
+(call_expression function: (member_expression property: (property_identifier) @name)) @reference.call
+(new_expression constructor: (identifier) @name) @reference.class
@@ -0,0 +1,8 @@
+; Definitions and references in Python, following tree-sitter's tags conventions: @name captures
+; the symbol's name, @definition.<kind> or @reference.<kind> the node it is the name of.
+
+(function_definition name: (identifier) @name) @definition.function
+(class_definition name: (identifier) @name) @definition.class
+
+(call function: (identifier) @name) @reference.call
+(call function: (attribute attribute: (identifier) @name)) @reference.call
@@ -0,0 +1,246 @@
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::checklist::Checklist;
+use crate::churn::ChurnReport;
+use crate::color;
+use crate::complexity::ComplexityReport;
+use crate::conflicts::ConflictReport;
+use crate::coupling::CouplingReport;
+use crate::dead_code::DeadCodeReport;
+use crate::duplicates::DuplicatesReport;
+use crate::error::DiffGraphError;
+use crate::export::OutputTarget;
+use crate::git;
+use crate::graph::{DiffGraph, DiffGraphParams};
+use crate::hotspots::HotspotReport;
+use crate::index::SymbolIndex;
+use crate::languages::LanguageReport;
+use crate::ownership::{Authors, OwnershipReport};
+use crate::parser;
+use crate::patch::ParsedDiff;
+use crate::risk::RiskReport;
+use crate::splits::SplitReport;
+use crate::test_impact::TestImpactReport;
+
+/// Reports drawn from a diff's graph, run with `analyze <ANALYSIS>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Analysis {
+    Risk,
+    Complexity,
+    Tests,
+    Coupling,
+    Hotspots,
+    Checklist,
+    DeadCode,
+    Duplicates,
+    Languages,
+    Churn,
+    Split,
+    Conflicts,
+    Ownership,
+}
+
+impl Analysis {
+    pub const ALL: [Analysis; 13] = [
+        Analysis::Risk,
+        Analysis::Complexity,
+        Analysis::Tests,
+        Analysis::Coupling,
+        Analysis::Hotspots,
+        Analysis::Checklist,
+        Analysis::DeadCode,
+        Analysis::Duplicates,
+        Analysis::Languages,
+        Analysis::Churn,
+        Analysis::Split,
+        Analysis::Conflicts,
+        Analysis::Ownership,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Analysis::Risk => "risk",
+            Analysis::Complexity => "complexity",
+            Analysis::Tests => "tests",
+            Analysis::Coupling => "coupling",
+            Analysis::Hotspots => "hotspots",
+            Analysis::Checklist => "checklist",
+            Analysis::DeadCode => "dead-code",
+            Analysis::Duplicates => "duplicates",
+            Analysis::Languages => "languages",
+            Analysis::Churn => "churn",
+            Analysis::Split => "split",
+            Analysis::Conflicts => "conflicts",
+            Analysis::Ownership => "ownership",
+        }
+    }
+
+    pub fn about(&self) -> &'static str {
+        match self {
+            Analysis::Risk => "Rank the changed declarations by risk, from their fan-in, nesting depth, size of change and parse errors",
+            Analysis::Complexity => "Compare the complexity of each changed function before and after the patch, flagging those it takes past --threshold",
+            Analysis::Tests => "List the tests of the repository that reference the changed declarations, for running only those",
+            Analysis::Coupling => "Pair up changed files that import each other or use what the other changes, flagging pairs across modules",
+            Analysis::Hotspots => "Graph each commit of a range of revisions and rank the declarations by how often they changed, then by how many nodes",
+            Analysis::Checklist => "Write a markdown review checklist per cluster of coupled changes, with the files in review order and what to look out for",
+            Analysis::DeadCode => "List the declarations the patch keeps but removes the last references to, within the patched files",
+            Analysis::Duplicates => "Find code the patch adds more than once, up to names and literals, with how similar the copies are",
+            Analysis::Languages => "Break the patch down by language: files, hunks, changed lines and declarations, graph nodes and owners of each",
+            Analysis::Churn => "Write a CSV row per changed declaration: its size, the lines the patch adds and removes in it and, with --history, how often it changed before",
+            Analysis::Split => "Suggest how to split the patch into commits reviewable one at a time, keeping files that depend on each other together",
+            Analysis::Conflicts => "Compare the graph of the diff with that of --other-diff, predicting where the patches conflict, including where a line-level merge would not notice",
+            Analysis::Ownership => "Blame the lines the patch changes and flag the declarations last written by authors who left or by another team, going by --authors",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Analysis::ALL.into_iter().find(|analysis| analysis.as_str() == name)
+    }
+}
+
+#[derive(Debug)]
+pub struct AnalyzeParams {
+    pub graph_params: DiffGraphParams,
+    pub analysis: Analysis,
+    /// Write the report as JSON instead of text
+    pub json: bool,
+    pub target: OutputTarget,
+    /// Complexity past which changed functions are flagged, for `complexity`
+    pub complexity_threshold: usize,
+    /// Similarity from which added subtrees are reported, for `duplicates`
+    pub min_similarity: f64,
+    /// Range of revisions graphed one commit at a time, for `hotspots` and the `--history` of `churn`
+    pub revisions: Option<String>,
+    /// Patch compared with the diff, for `conflicts`
+    pub other_diff: Option<ParsedDiff>,
+    /// File mapping authors to teams, for `ownership`
+    pub authors: Option<PathBuf>,
+    /// Email of the patch's author, for `ownership`
+    pub author: Option<String>,
+}
+
+pub fn write_report<T: Serialize + fmt::Display>(report: &T, json: bool, target: &OutputTarget) -> Result<(), DiffGraphError> {
+    let mut writer = target.try_open(false)?;
+    let written = match json {
+        true => serde_json::to_writer_pretty(&mut writer, report)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(writer)),
+        // Colors are for the terminal only
+        false if *target == OutputTarget::Stdout => write!(writer, "{}", report),
+        false => write!(writer, "{}", color::strip(&report.to_string())),
+    };
+    written.map_err(|e| DiffGraphError::io("Unable to write the report", e))?;
+    writer.try_finish()
+}
+
+/// Graphs every commit of the range on its own, with the options of `graph_params`. Commits
+/// whose graph fails are left out with a warning, rather than losing the rest of the range.
+fn try_get_hotspots(graph_params: DiffGraphParams, range: &str) -> Result<HotspotReport, DiffGraphError> {
+    let repo_path = Path::new(&graph_params.diff_repository_dir);
+    let commits = git::try_list_commits(repo_path, range)?;
+    let mut report = HotspotReport::new(range);
+    for (i, commit) in commits.iter().enumerate() {
+        // Each graph shows its own progress, so commits are counted in the log
+        info!("Graphing commit {} of {}: {}", i + 1, commits.len(), commit);
+        let graph = git::try_get_commit_diff(repo_path, commit).and_then(|diff| DiffGraph::create(DiffGraphParams {
+            diff: diff.patch,
+            source_revision: diff.source_revision,
+            git_dir: diff.git_dir,
+            binary_files: diff.binary_files,
+            ..graph_params.clone()
+        }));
+        match graph {
+            Ok(graph) => report.add_graph(&graph),
+            Err(e @ DiffGraphError::Cancelled(_)) => return Err(e),
+            Err(e) => warn!("Leaving out commit {}: {}", commit, e),
+        }
+    }
+    Ok(report.finish())
+}
+
+/// Builds the graph and writes the report the analysis asks for.
+pub fn run(params: AnalyzeParams) -> Result<(), DiffGraphError> {
+    let AnalyzeParams { graph_params, analysis, json, target, complexity_threshold, min_similarity, revisions, other_diff, authors, author } = params;
+    match analysis {
+        Analysis::Risk => {
+            let graph = DiffGraph::create(graph_params)?;
+            write_report(&RiskReport::from_graph(&graph), json, &target)
+        },
+        Analysis::Complexity => {
+            let graph = DiffGraph::create(graph_params)?;
+            write_report(&ComplexityReport::from_graph(&graph, complexity_threshold), json, &target)
+        },
+        Analysis::Tests => {
+            // Tests are searched for in the rest of the repository, which the graph does not cover
+            let grammars = parser::try_load_grammars(&graph_params)?;
+            let source_location = graph_params.get_source_location();
+            let index = graph_params.symbol_index.as_deref().map(SymbolIndex::try_load).transpose()?;
+            let graph = DiffGraph::create(graph_params)?;
+            write_report(&TestImpactReport::try_from_graph(&graph, &grammars, &source_location, index.as_ref())?, json, &target)
+        },
+        Analysis::Coupling => {
+            let graph = DiffGraph::create(graph_params)?;
+            write_report(&CouplingReport::from_graph(&graph), json, &target)
+        },
+        Analysis::Hotspots => {
+            let range = revisions.unwrap_or_default();
+            write_report(&try_get_hotspots(graph_params, &range)?, json, &target)
+        },
+        Analysis::Checklist => {
+            let graph = DiffGraph::create(graph_params)?;
+            write_report(&Checklist::from_graph(&graph), json, &target)
+        },
+        Analysis::DeadCode => {
+            let graph = DiffGraph::create(graph_params)?;
+            write_report(&DeadCodeReport::from_graph(&graph), json, &target)
+        },
+        Analysis::Duplicates => {
+            let graph = DiffGraph::create(graph_params)?;
+            write_report(&DuplicatesReport::from_graph(&graph, min_similarity), json, &target)
+        },
+        Analysis::Languages => {
+            let graph = DiffGraph::create(graph_params)?;
+            write_report(&LanguageReport::from_graph(&graph), json, &target)
+        },
+        Analysis::Churn => {
+            let history = match revisions {
+                Some(range) => Some(try_get_hotspots(graph_params.clone(), &range)?),
+                None => None,
+            };
+            let graph = DiffGraph::create(graph_params)?;
+            write_report(&ChurnReport::from_graph(&graph, history.as_ref()), json, &target)
+        },
+        Analysis::Split => {
+            let graph = DiffGraph::create(graph_params)?;
+            write_report(&SplitReport::from_graph(&graph), json, &target)
+        },
+        Analysis::Conflicts => {
+            let Some(other_diff) = other_diff else {
+                return Err(DiffGraphError::Usage("conflicts needs --other-diff".into()));
+            };
+            let other = DiffGraph::create(DiffGraphParams {
+                diff: other_diff.patch,
+                source_revision: other_diff.source_revision,
+                git_dir: other_diff.git_dir,
+                binary_files: other_diff.binary_files,
+                ..graph_params.clone()
+            })?;
+            let graph = DiffGraph::create(graph_params)?;
+            write_report(&ConflictReport::from_graphs(&graph, &other), json, &target)
+        },
+        Analysis::Ownership => {
+            let Some(authors) = authors else {
+                return Err(DiffGraphError::Usage("ownership needs --authors".into()));
+            };
+            let authors = Authors::try_load(&authors)?;
+            let source_location = graph_params.get_source_location();
+            let graph = DiffGraph::create(graph_params)?;
+            write_report(&OwnershipReport::try_from_graph(&graph, &source_location, &authors, author.as_deref())?, json, &target)
+        },
+    }
+}
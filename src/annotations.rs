@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+
+use serde::Serialize;
+use tree_sitter::Node as TSNode;
+
+use crate::api::{self, BreakingChangeKind};
+use crate::graph::DiffGraph;
+use crate::parser::Diff;
+use crate::syntax;
+
+/// Lines beyond which a changed function is flagged as too large to review comfortably
+const LARGE_FUNCTION_LINES: usize = 80;
+
+/// Annotation levels of the GitHub Checks API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationLevel {
+    Notice,
+    Warning,
+    Failure,
+}
+
+/// A check run annotation, pointing at lines of the patched file.
+#[derive(Debug, Clone, Serialize)]
+pub struct Annotation {
+    pub path: String,
+    /// 1-based, inclusive
+    pub start_line: usize,
+    pub end_line: usize,
+    pub annotation_level: AnnotationLevel,
+    pub title: String,
+    pub message: String,
+}
+
+/// The `output` object of a check run, which GitHub accepts at most 50 annotations at a time for.
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckOutput {
+    pub title: String,
+    pub summary: String,
+    pub annotations: Vec<Annotation>,
+}
+
+impl CheckOutput {
+    pub fn from_graph(graph: &DiffGraph) -> Self {
+        let mut annotations: Vec<Annotation> = graph.diffs().iter().flat_map(annotate_diff).collect();
+        annotations.extend(graph.security_tags().iter().map(|tag| Annotation {
+            path: tag.file.clone(),
+            start_line: tag.start_line,
+            end_line: tag.end_line,
+            annotation_level: AnnotationLevel::Warning,
+            title: format!("Security-sensitive change: {}", tag.tag),
+            message: format!("{}, review it with care", tag),
+        }));
+        annotations.extend(graph.untested().iter().map(|change| Annotation {
+            path: change.file.clone(),
+            start_line: change.start_line,
+            end_line: change.end_line,
+            annotation_level: AnnotationLevel::Warning,
+            title: "Untested change".to_string(),
+            message: format!("{}, lines {} have no coverage", change, join_lines(&change.uncovered_lines)),
+        }));
+        let count = |level: AnnotationLevel| annotations.iter().filter(|a| a.annotation_level == level).count();
+        Self {
+            title: format!("{} annotations", annotations.len()),
+            summary: format!("{} files, {} warnings, {} notices", graph.diffs().len(),
+                count(AnnotationLevel::Warning), count(AnnotationLevel::Notice)),
+            annotations,
+        }
+    }
+}
+
+fn join_lines(lines: &[usize]) -> String {
+    lines.iter().map(|line| line.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn annotation(diff: &Diff, rows: (usize, usize), level: AnnotationLevel, title: &str, message: String) -> Annotation {
+    Annotation {
+        path: diff.source_file_path.clone(),
+        start_line: rows.0 + 1,
+        end_line: rows.1 + 1,
+        annotation_level: level,
+        title: title.to_string(),
+        message,
+    }
+}
+
+fn describe(node: &TSNode, source: &str) -> String {
+    match syntax::get_declaration_name(node, source) {
+        Some(name) => format!("{} `{}`", node.kind(), name),
+        None => node.kind().to_string(),
+    }
+}
+
+/// Row of the patched file a removed row ends up at, shifted past the rows added before it.
+fn map_removed_row(diff: &Diff, row: usize) -> usize {
+    let removed_before = diff.removed_rows.iter().filter(|removed| **removed < row).count();
+    let mut target_row = row.saturating_sub(removed_before);
+    let mut added_rows = diff.added_rows.clone();
+    added_rows.sort_unstable();
+    for added in added_rows {
+        if added <= target_row {
+            target_row += 1;
+        } else {
+            break;
+        }
+    }
+    target_row.min(diff.target_source.lines().count().saturating_sub(1))
+}
+
+/// The innermost declaration enclosing each row, each listed once.
+fn changed_declarations<'t>(tree: &'t tree_sitter::Tree, source: &str, rows: &[usize]) -> Vec<TSNode<'t>> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut seen = HashSet::new();
+    let mut declarations = Vec::new();
+    for row in rows.iter() {
+        let point = syntax::get_line_start(*row, lines.get(*row).unwrap_or(&""));
+        if let Some(declaration) = syntax::find_enclosing_declaration(tree, point) {
+            if seen.insert(declaration.id()) {
+                declarations.push(declaration);
+            }
+        }
+    }
+    declarations
+}
+
+/// Parse errors, public API changes, breaking changes and large changed functions in a patched file.
+fn annotate_diff(diff: &Diff) -> Vec<Annotation> {
+    let mut annotations = Vec::new();
+
+    let added_rows: HashSet<usize> = diff.added_rows.iter().copied().collect();
+    for node in syntax::find_error_nodes(&diff.target_tree) {
+        let rows = (node.start_position().row, node.end_position().row);
+        // Errors outside the patch were there before it and are not the author's to fix
+        if !(rows.0..=rows.1).any(|row| added_rows.contains(&row)) {
+            continue;
+        }
+        let message = if node.is_missing() {
+            format!("Syntax error: missing {}", node.kind())
+        } else {
+            "Syntax error: the parser could not make sense of this code".to_string()
+        };
+        annotations.push(annotation(diff, rows, AnnotationLevel::Warning, "Parse error", message));
+    }
+
+    let language = diff.language_name.as_str();
+    let target_declarations = changed_declarations(&diff.target_tree, &diff.target_source, &diff.added_rows);
+    for declaration in target_declarations.iter() {
+        let rows = (declaration.start_position().row, declaration.end_position().row);
+        if syntax::is_public_declaration(declaration, &diff.target_source, language) {
+            annotations.push(annotation(diff, rows, AnnotationLevel::Notice, "Public API change",
+                format!("Public {} changed, check its callers and downstream users", describe(declaration, &diff.target_source))));
+        }
+        let kind = declaration.kind();
+        let lines = rows.1 - rows.0 + 1;
+        if (kind.contains("function") || kind.contains("method")) && lines > LARGE_FUNCTION_LINES {
+            annotations.push(annotation(diff, rows, AnnotationLevel::Notice, "Large function",
+                format!("{} is {} lines long, consider splitting it up", describe(declaration, &diff.target_source), lines)));
+        }
+    }
+
+    // Removed declarations are pointed at where they used to be in the patched file
+    for change in api::find_breaking_changes(diff) {
+        let row = change.target_row.unwrap_or_else(|| map_removed_row(diff, change.row));
+        let title = match change.change {
+            BreakingChangeKind::Removed => "Public API removal",
+            BreakingChangeKind::MadePrivate | BreakingChangeKind::SignatureChanged => "Breaking change",
+        };
+        annotations.push(annotation(diff, (row, row), AnnotationLevel::Warning, title,
+            format!("{}, which may break its users", change)));
+    }
+
+    annotations
+}
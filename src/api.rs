@@ -0,0 +1,255 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
+use tree_sitter::{Node as TSNode, Tree};
+
+use crate::graph::DiffGraph;
+use crate::parser::Diff;
+use crate::structural::{self, DeclarationChange};
+use crate::syntax;
+
+/// How a public declaration changed in a way that may break its users.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BreakingChangeKind {
+    Removed,
+    /// Still declared, but no longer public
+    MadePrivate,
+    SignatureChanged,
+}
+
+/// A public declaration of the file before the patch that its users may no longer compile or
+/// behave the same against.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BreakingChange {
+    pub file: String,
+    pub kind: String,
+    /// Name qualified by the declarations enclosing it, such as `Parser::parse`
+    pub name: String,
+    pub change: BreakingChangeKind,
+    pub old_signature: String,
+    pub new_signature: Option<String>,
+    /// Row of the declaration in the file before the patch
+    pub row: usize,
+    /// Row of the declaration in the patched file, when it is still there
+    pub target_row: Option<usize>,
+}
+
+impl fmt::Display for BreakingChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.change, &self.new_signature) {
+            (BreakingChangeKind::Removed, _) => write!(f, "public {} `{}` in {} was removed", self.kind, self.name, self.file),
+            (BreakingChangeKind::MadePrivate, _) => write!(f, "{} `{}` in {} is no longer public", self.kind, self.name, self.file),
+            (BreakingChangeKind::SignatureChanged, Some(new_signature)) => write!(f, "signature of public {} `{}` in {} changed from `{}` to `{}`",
+                self.kind, self.name, self.file, self.old_signature, new_signature),
+            (BreakingChangeKind::SignatureChanged, None) => write!(f, "signature of public {} `{}` in {} changed", self.kind, self.name, self.file),
+        }
+    }
+}
+
+/// How the patch changes a public declaration, or a declaration into or out of being public.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiChangeKind {
+    Added,
+    Removed,
+    MadePublic,
+    MadePrivate,
+    SignatureChanged,
+    /// Same signature, different implementation
+    BodyChanged,
+}
+
+/// A change to the public API of a file, for release notes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiChange {
+    pub file: String,
+    pub language: String,
+    pub kind: String,
+    /// Name qualified by the declarations enclosing it, such as `Parser::parse`
+    pub name: String,
+    pub change: ApiChangeKind,
+    /// Signature before the patch, None for added declarations
+    pub old_signature: Option<String>,
+    /// Signature after the patch, None for removed declarations
+    pub new_signature: Option<String>,
+    /// First line of the declaration, counted from 1, in the patched file unless it was removed
+    pub line: usize,
+}
+
+/// The public API the patch changes across its files, written as `api-changes.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiChanges {
+    pub changes: Vec<ApiChange>,
+}
+
+/// A declaration of one side of the patch, keyed by its kind and qualified name.
+struct ApiDeclaration<'t> {
+    node: TSNode<'t>,
+    public: bool,
+    signature: String,
+}
+
+/// The name a declaration is known by to its users. Declarations without a name, such as Rust
+/// `impl` blocks, go by the type they are for.
+fn get_qualifier(node: &TSNode, source: &str) -> Option<String> {
+    syntax::get_declaration_name(node, source).or_else(|| node
+        .child_by_field_name("type")
+        .and_then(|name| name.utf8_text(source.as_bytes()).ok())
+        .map(|name| name.to_string()))
+}
+
+/// The declaration's text up to its body, with whitespace collapsed so reformatting it is not
+/// a change.
+pub fn get_signature(node: &TSNode, source: &str) -> String {
+    let end = node.child_by_field_name("body").map_or(node.end_byte(), |body| body.start_byte());
+    let text = source.get(node.start_byte()..end).unwrap_or_default();
+    text.split_whitespace().collect::<Vec<_>>().join(" ").trim_end_matches([';', '{', ' ']).to_string()
+}
+
+/// Every named declaration of the tree by kind and qualified name, with the declarations of the
+/// same name, such as Java overloads, in order.
+fn index_declarations<'t>(tree: &'t Tree, source: &str, language: &str) -> HashMap<(String, String), Vec<ApiDeclaration<'t>>> {
+    let mut declarations: HashMap<(String, String), Vec<ApiDeclaration>> = HashMap::new();
+    let mut scopes: Vec<(usize, String)> = Vec::new();
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        // Scopes the cursor has left end where their node does
+        while scopes.last().is_some_and(|(end, _)| *end <= node.start_byte()) {
+            scopes.pop();
+        }
+        if node.is_named() && syntax::is_declaration_kind(node.kind()) {
+            if let Some(qualifier) = get_qualifier(&node, source) {
+                let name = scopes.iter().map(|(_, scope)| scope.as_str()).chain([qualifier.as_str()]).collect::<Vec<_>>().join("::");
+                declarations.entry((node.kind().to_string(), name)).or_default().push(ApiDeclaration {
+                    node,
+                    public: syntax::is_public_declaration(&node, source, language),
+                    signature: get_signature(&node, source),
+                });
+                scopes.push((node.end_byte(), qualifier));
+            }
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return declarations;
+            }
+        }
+    }
+}
+
+/// Public declarations of the file before the patch that it removes, makes private or changes
+/// the signature of. Declarations are matched by kind and qualified name, so a renamed
+/// declaration is a removal.
+pub fn find_breaking_changes(diff: &Diff) -> Vec<BreakingChange> {
+    let language = diff.language_name.as_str();
+    let old = index_declarations(&diff.tree, &diff.source, language);
+    let new = index_declarations(&diff.target_tree, &diff.target_source, language);
+
+    let mut changes = Vec::new();
+    for ((kind, name), old_declarations) in old.iter() {
+        let new_declarations = new.get(&(kind.clone(), name.clone()));
+        for old_declaration in old_declarations.iter().filter(|declaration| declaration.public) {
+            let same = new_declarations.and_then(|declarations| declarations
+                .iter()
+                .find(|declaration| declaration.signature == old_declaration.signature));
+            let (change, new_declaration) = match (same, new_declarations) {
+                (Some(same), _) if same.public => continue,
+                (Some(same), _) => (BreakingChangeKind::MadePrivate, Some(same)),
+                (None, Some(declarations)) => {
+                    // Overloads are matched up in order
+                    let index = old_declarations.iter().position(|d| d.node.id() == old_declaration.node.id()).unwrap_or(0);
+                    let Some(declaration) = declarations.get(index).or(declarations.last()) else {
+                        continue;
+                    };
+                    match declaration.public {
+                        true => (BreakingChangeKind::SignatureChanged, Some(declaration)),
+                        false => (BreakingChangeKind::MadePrivate, Some(declaration)),
+                    }
+                },
+                (None, None) => (BreakingChangeKind::Removed, None),
+            };
+            changes.push(BreakingChange {
+                file: diff.source_file_path.clone(),
+                kind: kind.clone(),
+                name: name.clone(),
+                change,
+                old_signature: old_declaration.signature.clone(),
+                new_signature: match change {
+                    BreakingChangeKind::SignatureChanged => new_declaration.map(|declaration| declaration.signature.clone()),
+                    _ => None,
+                },
+                row: old_declaration.node.start_position().row,
+                target_row: new_declaration.map(|declaration| declaration.node.start_position().row),
+            });
+        }
+    }
+    changes.sort_by_key(|change| change.row);
+    changes
+}
+
+/// Declarations of the file that are public on either side of the patch and that it adds,
+/// removes, makes public or private, or changes the signature or implementation of. Overloads
+/// are matched by signature, then in order.
+pub fn find_api_changes(diff: &Diff) -> Vec<ApiChange> {
+    let language = diff.language_name.as_str();
+    let old = index_declarations(&diff.tree, &diff.source, language);
+    let new = index_declarations(&diff.target_tree, &diff.target_source, language);
+
+    let mut changes = Vec::new();
+    let keys: BTreeSet<&(String, String)> = old.keys().chain(new.keys()).collect();
+    for key in keys {
+        let old_declarations = old.get(key).map_or(&[][..], |declarations| declarations.as_slice());
+        let new_declarations = new.get(key).map_or(&[][..], |declarations| declarations.as_slice());
+        let mut push = |change: ApiChangeKind, old: Option<&ApiDeclaration>, new: Option<&ApiDeclaration>| changes.push(ApiChange {
+            file: diff.source_file_path.clone(),
+            language: diff.language_name.clone(),
+            kind: key.0.clone(),
+            name: key.1.clone(),
+            change,
+            old_signature: old.map(|declaration| declaration.signature.clone()),
+            new_signature: new.map(|declaration| declaration.signature.clone()),
+            line: new.or(old).map_or(0, |declaration| declaration.node.start_position().row + 1),
+        });
+
+        for (i, new_declaration) in new_declarations.iter().enumerate() {
+            let old_declaration = old_declarations
+                .iter()
+                .find(|declaration| declaration.signature == new_declaration.signature)
+                .or(old_declarations.get(i));
+            let change = match old_declaration {
+                None if new_declaration.public => ApiChangeKind::Added,
+                None => continue,
+                Some(old_declaration) => match (old_declaration.public, new_declaration.public) {
+                    (false, false) => continue,
+                    (false, true) => ApiChangeKind::MadePublic,
+                    (true, false) => ApiChangeKind::MadePrivate,
+                    (true, true) if old_declaration.signature != new_declaration.signature => ApiChangeKind::SignatureChanged,
+                    (true, true) => match structural::compare(&old_declaration.node, &diff.source, &new_declaration.node, &diff.target_source) {
+                        DeclarationChange::Modified { .. } => ApiChangeKind::BodyChanged,
+                        _ => continue,
+                    },
+                },
+            };
+            push(change, old_declaration, Some(new_declaration));
+        }
+        for (i, old_declaration) in old_declarations.iter().enumerate().filter(|(_, declaration)| declaration.public) {
+            let matched = new_declarations.iter().any(|declaration| declaration.signature == old_declaration.signature) || i < new_declarations.len();
+            if !matched {
+                push(ApiChangeKind::Removed, Some(old_declaration), None);
+            }
+        }
+    }
+    changes.sort_by_key(|change| change.line);
+    changes
+}
+
+impl ApiChanges {
+    pub fn from_graph(graph: &DiffGraph) -> Self {
+        Self { changes: graph.diffs().iter().flat_map(find_api_changes).collect() }
+    }
+}
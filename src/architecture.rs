@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+use regex::Regex;
+use serde::Serialize;
+use tracing::warn;
+use tree_sitter::{Node as TSNode, Tree};
+
+use crate::coupling;
+use crate::error::DiffGraphError;
+use crate::index::SymbolIndex;
+use crate::owners;
+use crate::parser::Diff;
+
+/// Separates the pattern of the files a rule is about from the pattern of those they may not depend on
+const FORBIDS: &str = "!->";
+
+/// Words of import statements that name no module
+const IMPORT_KEYWORDS: [&str; 12] = ["use", "import", "from", "as", "include", "using", "pub", "static", "namespace", "crate", "self", "super"];
+
+/// A dependency the architecture forbids, such as `ui/ !-> db/`.
+#[derive(Debug, Clone)]
+pub struct ArchitectureRule {
+    /// Pattern of the files that may not depend on the others
+    pub from: String,
+    /// Pattern of the files, or of the modules imports name, they may not depend on
+    pub to: String,
+    from_regex: Regex,
+    to_regex: Regex,
+}
+
+impl fmt::Display for ArchitectureRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.from, FORBIDS, self.to)
+    }
+}
+
+/// Dependencies new code may not add, read from a file with a rule per line: the pattern of
+/// the files depending, `!->`, and the pattern of what they may not depend on, such as
+/// `ui/ !-> db/`. Patterns are CODEOWNERS patterns, and `#` starts a comment.
+#[derive(Debug, Clone, Default)]
+pub struct ArchitectureRules {
+    rules: Vec<ArchitectureRule>,
+}
+
+/// How a changed file came to depend on what a rule forbids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyKind {
+    /// The patch adds an import of the module
+    Import,
+    /// The changed lines call or reference declarations of the other file
+    Use,
+}
+
+/// A dependency the patch adds that a rule forbids.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchitectureViolation {
+    pub rule: String,
+    pub file: String,
+    pub dependency: DependencyKind,
+    /// The module the import names, or the file whose declarations are used
+    pub target: String,
+    /// Line of the import in the patched file, counted from 1, None for uses
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for ArchitectureViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.dependency, self.line) {
+            (DependencyKind::Import, Some(line)) => write!(f, "{}:{} imports {}, breaking {}", self.file, line, self.target, self.rule),
+            (DependencyKind::Import, None) => write!(f, "{} imports {}, breaking {}", self.file, self.target, self.rule),
+            (DependencyKind::Use, _) => write!(f, "{} uses declarations changed in {}, breaking {}", self.file, self.target, self.rule),
+        }
+    }
+}
+
+/// The module an import names, as a path: its quoted path for languages that import files,
+/// such as `../db/conn` for `import conn from "../db/conn"`, otherwise its words, such as
+/// `app/db/conn` for `from app.db import conn`.
+fn get_import_path(text: &str) -> String {
+    if let Some(quoted) = text.split(['"', '\'', '<', '>']).nth(1).filter(|quoted| !quoted.trim().is_empty()) {
+        return quoted
+            .split('/')
+            .filter(|component| !component.is_empty() && *component != "." && *component != "..")
+            .collect::<Vec<_>>()
+            .join("/");
+    }
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty() && !IMPORT_KEYWORDS.contains(word))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Import statements of the tree, with their text collapsed so reformatting them is not a change.
+fn collect_imports<'t>(tree: &'t Tree, source: &str) -> Vec<(TSNode<'t>, String)> {
+    let mut imports = Vec::new();
+    let mut cursor = tree.walk();
+    'walk: loop {
+        let node = cursor.node();
+        if node.is_named() && coupling::IMPORT_KINDS.contains(&node.kind()) {
+            let text = source.get(node.byte_range()).unwrap_or_default();
+            imports.push((node, text.split_whitespace().collect::<Vec<_>>().join(" ")));
+        } else if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+    imports
+}
+
+impl ArchitectureRules {
+    /// Reads the lines of a rules file. Lines that are not rules and patterns that do not
+    /// compile are left out.
+    pub fn parse(text: &str) -> Self {
+        let mut rules = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((from, to)) = line.split_once(FORBIDS).map(|(from, to)| (from.trim(), to.trim())) else {
+                warn!("Ignoring architecture rules line {}, '{}' has no {}", number + 1, line, FORBIDS);
+                continue;
+            };
+            if from.is_empty() || to.is_empty() {
+                warn!("Ignoring architecture rules line {}, '{}' is missing a pattern", number + 1, line);
+                continue;
+            }
+            match (Regex::new(&owners::pattern_to_regex(from)), Regex::new(&owners::pattern_to_regex(to))) {
+                (Ok(from_regex), Ok(to_regex)) => rules.push(ArchitectureRule {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                    from_regex,
+                    to_regex,
+                }),
+                (Err(e), _) | (_, Err(e)) => warn!("Ignoring architecture rules line {}, '{}' is not a valid rule: {}", number + 1, line, e),
+            }
+        }
+        Self { rules }
+    }
+
+    pub fn try_load(path: &Path) -> Result<Self, DiffGraphError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| DiffGraphError::io(format!("Unable to read architecture rules '{}'", path.display()), e))?;
+        Ok(Self::parse(&text))
+    }
+
+    /// Dependencies the patch adds that the rules forbid: imports on added lines that the file
+    /// did not have before the patch, matched by the module they name, and uses of declarations
+    /// the patch changes in other files, matched by the path of those files. With an index, uses
+    /// of declarations outside of the diff are checked too. Files are in patch order, imports first.
+    pub fn check(&self, diffs: &[Diff], index: Option<&SymbolIndex>) -> Vec<ArchitectureViolation> {
+        let mut violations = Vec::new();
+        if self.rules.is_empty() {
+            return violations;
+        }
+
+        for diff in diffs.iter() {
+            let rules: Vec<&ArchitectureRule> = self.rules.iter().filter(|rule| rule.from_regex.is_match(&diff.source_file_path)).collect();
+            if rules.is_empty() {
+                continue;
+            }
+            let added_rows: HashSet<usize> = diff.added_rows.iter().copied().collect();
+            let old_imports: HashSet<String> = collect_imports(&diff.tree, &diff.source).into_iter().map(|(_, text)| text).collect();
+            for (node, text) in collect_imports(&diff.target_tree, &diff.target_source) {
+                let rows = node.start_position().row..=node.end_position().row;
+                if old_imports.contains(&text) || !rows.into_iter().any(|row| added_rows.contains(&row)) {
+                    continue;
+                }
+                let target = get_import_path(&text);
+                for rule in rules.iter().filter(|rule| rule.to_regex.is_match(&target)) {
+                    violations.push(ArchitectureViolation {
+                        rule: rule.to_string(),
+                        file: diff.source_file_path.clone(),
+                        dependency: DependencyKind::Import,
+                        target: target.clone(),
+                        line: Some(node.start_position().row + 1),
+                    });
+                }
+            }
+        }
+
+        for edge in coupling::find_uses(diffs, index) {
+            for rule in self.rules.iter().filter(|rule| rule.from_regex.is_match(&edge.user) && rule.to_regex.is_match(&edge.declarer)) {
+                violations.push(ArchitectureViolation {
+                    rule: rule.to_string(),
+                    file: edge.user.clone(),
+                    dependency: DependencyKind::Use,
+                    target: edge.declarer.clone(),
+                    line: None,
+                });
+            }
+        }
+        violations
+    }
+}
@@ -0,0 +1,130 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+use crate::error::DiffGraphError;
+use crate::graph::{DiffGraph, DiffGraphParams};
+use crate::snapshot::GraphSnapshot;
+use crate::timings;
+
+#[derive(Debug)]
+pub struct BenchParams {
+    pub graph_params: DiffGraphParams,
+    pub iterations: usize,
+}
+
+/// Timings of one run of the pipeline.
+struct Iteration {
+    total: Duration,
+    phases: Vec<(&'static str, Duration)>,
+    files: usize,
+    nodes: usize,
+    /// How much the peak grew by making edited copies of every file's tree, which parsing the
+    /// patch did before `Diff::edited_tree`, where the platform tells the peak
+    edited_tree_copies: Option<u64>,
+}
+
+/// Peak memory added by holding an edited copy of every file's tree alongside the graph.
+fn measure_edited_tree_copies(graph: &DiffGraph) -> Option<u64> {
+    let before = timings::get_peak_rss()?;
+    let copies: Vec<_> = graph.diffs().iter().map(|diff| diff.edited_tree()).collect();
+    let after = timings::get_peak_rss()?;
+    drop(copies);
+    Some(after.saturating_sub(before))
+}
+
+fn try_run_once(params: &DiffGraphParams) -> Result<Iteration, DiffGraphError> {
+    timings::take_phases();
+    timings::take_files();
+
+    let start = Instant::now();
+    let graph = DiffGraph::create(params.clone())?;
+    // The JSON export stands in for every format, written nowhere so disk speed does not count
+    timings::phase("export", || serde_json::to_writer(io::sink(), &GraphSnapshot::from_graph(&graph)))
+        .map_err(|e| DiffGraphError::io("Unable to export the graph", e.into()))?;
+    let total = start.elapsed();
+
+    Ok(Iteration {
+        total,
+        phases: timings::take_phases(),
+        files: graph.diffs().len(),
+        nodes: graph.node_count(),
+        edited_tree_copies: measure_edited_tree_copies(&graph),
+    })
+}
+
+/// Minimum, mean and maximum, in seconds.
+fn spread(durations: &[Duration]) -> (f64, f64, f64) {
+    let seconds: Vec<f64> = durations.iter().map(|d| d.as_secs_f64()).collect();
+    let min = seconds.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = seconds.iter().copied().fold(0.0, f64::max);
+    let mean = seconds.iter().sum::<f64>() / seconds.len().max(1) as f64;
+    (min, mean, max)
+}
+
+fn print_report(iterations: &[Iteration]) {
+    let mut phase_names: Vec<&'static str> = Vec::new();
+    for iteration in iterations.iter() {
+        for (name, _) in iteration.phases.iter() {
+            if !phase_names.contains(name) {
+                phase_names.push(name);
+            }
+        }
+    }
+
+    let width = phase_names.iter().map(|name| name.len()).max().unwrap_or(0).max("total".len());
+    println!("{:<width$}  {:>9}  {:>9}  {:>9}", "phase", "min", "mean", "max", width = width);
+    let print_row = |name: &str, durations: &[Duration]| {
+        let (min, mean, max) = spread(durations);
+        println!("{:<width$}  {:>8.3}s  {:>8.3}s  {:>8.3}s", name, min, mean, max, width = width);
+    };
+    for name in phase_names {
+        // Phases such as installing grammars only run on some iterations, and count as 0 on the others
+        let durations: Vec<Duration> = iterations
+            .iter()
+            .map(|iteration| iteration.phases.iter().find(|(phase, _)| *phase == name).map_or(Duration::ZERO, |(_, d)| *d))
+            .collect();
+        print_row(name, &durations);
+    }
+    let totals: Vec<Duration> = iterations.iter().map(|iteration| iteration.total).collect();
+    print_row("total", &totals);
+
+    let (_, mean, _) = spread(&totals);
+    let last = &iterations[iterations.len() - 1];
+    println!();
+    println!("{} iterations of {} files and {} nodes", iterations.len(), last.files, last.nodes);
+    if mean > 0.0 {
+        println!("{:.1} files/s, {:.0} nodes/s", last.files as f64 / mean, last.nodes as f64 / mean);
+    }
+    if let Some(peak) = timings::get_peak_rss() {
+        println!("{} peak memory", timings::format_bytes(peak));
+    }
+    if let Some(copies) = iterations.iter().filter_map(|iteration| iteration.edited_tree_copies).max() {
+        println!("{} more with an edited copy of every tree", timings::format_bytes(copies));
+    }
+}
+
+/// Runs the pipeline over the diff the given number of times and prints the time each phase
+/// took, the throughput and the peak memory, so releases can be compared on the same repository
+/// and diff.
+pub fn run(params: BenchParams) -> Result<(), DiffGraphError> {
+    if params.iterations == 0 {
+        return Err(DiffGraphError::Usage("--iterations must be at least 1".into()));
+    }
+
+    let was_enabled = timings::is_enabled();
+    timings::set_enabled(true);
+    let iterations: Result<Vec<Iteration>, DiffGraphError> = (0..params.iterations)
+        .map(|i| {
+            let iteration = try_run_once(&params.graph_params)?;
+            info!("Iteration {} of {} took {:.3}s", i + 1, params.iterations, iteration.total.as_secs_f64());
+            Ok(iteration)
+        })
+        .collect();
+    timings::set_enabled(was_enabled);
+
+    let iterations = iterations?;
+    print_report(&iterations);
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::api::ApiChanges;
+use crate::comment;
+use crate::error::DiffGraphError;
+use crate::export;
+use crate::graph::DiffGraph;
+use crate::hints::ScopeHint;
+use crate::sarif::SarifLog;
+use crate::snapshot::GraphSnapshot;
+use crate::summary::{FileSummary, Summary};
+
+/// Totals of a build, for CI to chart across builds.
+#[derive(Debug, Serialize)]
+pub struct Metrics {
+    pub files: usize,
+    pub nodes: usize,
+    pub edges: usize,
+    pub changed_declarations: usize,
+    pub added_lines: usize,
+    pub removed_lines: usize,
+    pub parse_errors: usize,
+    pub skipped_files: usize,
+    pub truncated: bool,
+    pub per_file: Vec<FileSummary>,
+    /// Conventional-commit scopes of the changed files, the likeliest first
+    pub scopes: Vec<ScopeHint>,
+}
+
+impl Metrics {
+    pub fn from_graph(graph: &DiffGraph) -> Self {
+        let summary = Summary::from_graph(graph);
+        Self {
+            files: summary.files.len(),
+            nodes: summary.node_count,
+            edges: summary.edge_count,
+            changed_declarations: summary.files.iter().map(|file| file.changed_declarations).sum(),
+            added_lines: summary.files.iter().map(|file| file.added_lines).sum(),
+            removed_lines: summary.files.iter().map(|file| file.removed_lines).sum(),
+            parse_errors: summary.files.iter().map(|file| file.parse_errors).sum(),
+            skipped_files: summary.skipped.len(),
+            truncated: !graph.truncations().is_empty(),
+            per_file: summary.files,
+            scopes: summary.scopes,
+        }
+    }
+}
+
+fn try_create(path: &Path) -> Result<BufWriter<File>, DiffGraphError> {
+    File::create(path)
+        .map(BufWriter::new)
+        .map_err(|e| DiffGraphError::io(format!("Unable to create '{}'", path.display()), e))
+}
+
+fn try_write_file(path: &Path, write: impl FnOnce(&mut dyn Write) -> std::io::Result<()>) -> Result<(), DiffGraphError> {
+    let mut writer = try_create(path)?;
+    write(&mut writer)
+        .and_then(|_| writer.flush())
+        .map_err(|e| DiffGraphError::io(format!("Unable to write '{}'", path.display()), e))
+}
+
+/// Renders DOT to SVG with Graphviz, false when `dot` is not installed.
+pub fn try_render_svg(dot: &[u8], path: &Path) -> Result<bool, DiffGraphError> {
+    let child = Command::new("dot")
+        .arg("-Tsvg")
+        .arg("-o")
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(DiffGraphError::io("Unable to run dot", e)),
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(dot).map_err(|e| DiffGraphError::io("Unable to write to dot", e))?;
+    }
+    let output = child.wait_with_output().map_err(|e| DiffGraphError::io("Unable to run dot", e))?;
+    if !output.status.success() {
+        return Err(DiffGraphError::io(format!("Unable to render '{}'", path.display()),
+            std::io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string())));
+    }
+    Ok(true)
+}
+
+/// Name of a file's SVG, its path flattened so the bundle stays one level deep.
+fn svg_name(path: &str) -> String {
+    let name: String = path.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect();
+    format!("{}.svg", name)
+}
+
+fn write_index(graph: &DiffGraph, svgs: &[(String, String)], writer: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html><head><meta charset=\"utf-8\"><title>diffgraph</title></head><body>")?;
+    writeln!(writer, "<h1>{}</h1>", html_escape(&graph.to_string()))?;
+    writeln!(writer, "<p><a href=\"summary.md\">summary.md</a> &middot; <a href=\"graph.json\">graph.json</a> &middot; <a href=\"metrics.json\">metrics.json</a></p>")?;
+    for (title, name) in svgs.iter() {
+        writeln!(writer, "<h2>{}</h2>", html_escape(title))?;
+        writeln!(writer, "<img src=\"svg/{}\" alt=\"{}\">", name, html_escape(title))?;
+    }
+    writeln!(writer, "</body></html>")
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Writes the bundle into the directory: graph.json, summary.md, metrics.json, security.sarif,
+/// api-changes.json, an index.html for CI to publish, and under svg/ the whole graph and each
+/// file rendered with Graphviz. Without Graphviz the DOT sources are written in place of the SVGs.
+pub fn try_write(graph: &DiffGraph, dir: &Path) -> Result<(), DiffGraphError> {
+    let svg_dir = dir.join("svg");
+    fs::create_dir_all(&svg_dir).map_err(|e| DiffGraphError::io(format!("Unable to create '{}'", svg_dir.display()), e))?;
+
+    try_write_file(&dir.join("graph.json"), |writer| {
+        serde_json::to_writer_pretty(&mut *writer, &GraphSnapshot::from_graph(graph))?;
+        writeln!(writer)
+    })?;
+    try_write_file(&dir.join("summary.md"), |writer| writer.write_all(comment::to_markdown(graph).as_bytes()))?;
+    try_write_file(&dir.join("metrics.json"), |writer| {
+        serde_json::to_writer_pretty(&mut *writer, &Metrics::from_graph(graph))?;
+        writeln!(writer)
+    })?;
+    try_write_file(&dir.join("security.sarif"), |writer| {
+        serde_json::to_writer_pretty(&mut *writer, &SarifLog::from_graph(graph))?;
+        writeln!(writer)
+    })?;
+    try_write_file(&dir.join("api-changes.json"), |writer| {
+        serde_json::to_writer_pretty(&mut *writer, &ApiChanges::from_graph(graph))?;
+        writeln!(writer)
+    })?;
+
+    let mut renders = vec![("All files".to_string(), None, "graph.svg".to_string())];
+    for (file, diff) in graph.diffs().iter().enumerate() {
+        renders.push((diff.source_file_path.clone(), Some(file), svg_name(&diff.source_file_path)));
+    }
+    let mut svgs = Vec::new();
+    for (title, file, name) in renders {
+        let mut dot = Vec::new();
+        export::write_file_dot(graph, file, &mut dot).map_err(|e| DiffGraphError::io("Unable to write DOT", e))?;
+        if try_render_svg(&dot, &svg_dir.join(&name))? {
+            svgs.push((title, name));
+        } else {
+            let dot_path = svg_dir.join(name.trim_end_matches(".svg").to_string() + ".dot");
+            if file.is_none() {
+                warn!("Graphviz dot was not found, writing DOT files to {} instead of SVGs", svg_dir.display());
+            }
+            try_write_file(&dot_path, |writer| writer.write_all(&dot))?;
+        }
+    }
+
+    try_write_file(&dir.join("index.html"), |writer| write_index(graph, &svgs, writer))
+}
@@ -0,0 +1,102 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, warn};
+
+use crate::error::DiffGraphError;
+use crate::export::ExportFormat;
+use crate::graph::DiffGraphParams;
+use crate::parser;
+use crate::stats;
+
+const CACHE_DIR: &str = "diffgraph";
+const RESULTS_DIR: &str = "results";
+
+/// A graph export stored on disk, keyed by the base commit, the patch and every option that
+/// changes the result, so analyzing the same change again skips parsing and graphing.
+#[derive(Debug, Clone)]
+pub struct ResultCache {
+    path: PathBuf,
+}
+
+pub fn get_default_cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|path| path.join(CACHE_DIR).join(RESULTS_DIR))
+}
+
+fn try_resolve_commit(repo_dir: &Path, git_dir: Option<&Path>, revision: &str) -> Result<String, DiffGraphError> {
+    let output = parser::git_command(repo_dir, git_dir)
+        .args(["rev-parse", "--verify", "--quiet"])
+        .arg(format!("{}^{{commit}}", revision))
+        .output()
+        .map_err(|e| DiffGraphError::io("Unable to run git rev-parse", e))?;
+    if !output.status.success() {
+        return Err(DiffGraphError::Git(format!("Unable to resolve {} to a commit", revision)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl ResultCache {
+    /// The cache entry for the export of this diff, or None when the diff applies to the working
+    /// tree or in-memory sources, whose contents no commit pins down.
+    pub fn try_new(cache_dir: &Path, params: &DiffGraphParams, format: ExportFormat) -> Result<Option<Self>, DiffGraphError> {
+        let revision = match (&params.sources, &params.source_revision) {
+            (None, Some(revision)) => revision,
+            _ => return Ok(None),
+        };
+        let base = try_resolve_commit(Path::new(&params.diff_repository_dir), params.git_dir.as_deref(), revision)?;
+
+        // The patch stands in for the head commit, which it is the difference to from the base
+        let mut hasher = DefaultHasher::new();
+        params.diff.to_string().hash(&mut hasher);
+        let patch_hash = hasher.finish();
+
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        format.as_str().hash(&mut hasher);
+        format!("{:?}", params.parser_config_path).hash(&mut hasher);
+        params.exclude_kinds.hash(&mut hasher);
+        format!("{:?}", params.limits).hash(&mut hasher);
+        format!("{:?}", params.policy).hash(&mut hasher);
+        format!("{:?}", params.path_filter).hash(&mut hasher);
+        params.languages.hash(&mut hasher);
+        params.binary_files.hash(&mut hasher);
+        params.share_subtrees.hash(&mut hasher);
+        params.full_tree.hash(&mut hasher);
+        params.ignore_formatting.hash(&mut hasher);
+        format!("{:?}", params.security_queries).hash(&mut hasher);
+        format!("{:?}", params.debt_queries).hash(&mut hasher);
+        format!("{:?}", params.architecture_rules).hash(&mut hasher);
+        format!("{:?}", params.symbol_index).hash(&mut hasher);
+        format!("{:?}", params.coverage).hash(&mut hasher);
+        params.best_effort.hash(&mut hasher);
+        params.strict.hash(&mut hasher);
+        params.shard.hash(&mut hasher);
+        let config_hash = hasher.finish();
+
+        Ok(Some(Self {
+            path: cache_dir.join(format!("{}-{:016x}-{:016x}.{}", base, patch_hash, config_hash, format.as_str())),
+        }))
+    }
+
+    pub fn load(&self) -> Option<Vec<u8>> {
+        let Ok(cached) = std::fs::read(&self.path) else {
+            stats::miss(stats::Cache::Results);
+            return None;
+        };
+        stats::hit(stats::Cache::Results);
+        debug!("Using cached result {}", self.path.display());
+        Some(cached)
+    }
+
+    /// Stores the export, only warning on failure since the result was produced regardless.
+    pub fn store(&self, export: &[u8]) {
+        let stored = self.path.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&self.path, export));
+        match stored {
+            Ok(_) => debug!("Cached result at {}", self.path.display()),
+            Err(e) => warn!("Unable to cache the result at {}: {}", self.path.display(), e),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::error::DiffGraphError;
+
+const RUNNING: usize = 0;
+const INTERRUPTED: usize = 1;
+const TIMED_OUT: usize = 2;
+
+/// Non-zero once the run is cancelled. tree-sitter checks it while parsing, so in-flight parses
+/// stop too, see `Parser::set_cancellation_flag`.
+static FLAG: AtomicUsize = AtomicUsize::new(RUNNING);
+static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+/// The flag handed to tree-sitter parsers.
+pub fn flag() -> &'static AtomicUsize {
+    &FLAG
+}
+
+/// Cancels the run because the user asked to, such as with Ctrl-C.
+pub fn interrupt() {
+    let _ = FLAG.compare_exchange(RUNNING, INTERRUPTED, Ordering::SeqCst, Ordering::SeqCst);
+}
+
+/// Cancels the run once the timeout has elapsed, unless it finished or was cancelled before.
+pub fn set_timeout(timeout: Duration) {
+    if TIMEOUT.set(timeout).is_err() {
+        warn!("A timeout was already set, ignoring {:?}", timeout);
+        return;
+    }
+    thread::spawn(move || {
+        thread::sleep(timeout);
+        let _ = FLAG.compare_exchange(RUNNING, TIMED_OUT, Ordering::SeqCst, Ordering::SeqCst);
+    });
+}
+
+pub fn is_cancelled() -> bool {
+    FLAG.load(Ordering::SeqCst) != RUNNING
+}
+
+/// Fails with why the run was cancelled, for long running steps to call between units of work.
+pub fn try_check() -> Result<(), DiffGraphError> {
+    match FLAG.load(Ordering::SeqCst) {
+        RUNNING => Ok(()),
+        INTERRUPTED => Err(DiffGraphError::Cancelled("Interrupted".into())),
+        _ => Err(DiffGraphError::Cancelled(format!("Timed out after {}s (--timeout)",
+            TIMEOUT.get().map_or(0, |timeout| timeout.as_secs())))),
+    }
+}
@@ -0,0 +1,127 @@
+use std::io;
+use std::path::Path;
+
+use crate::error::DiffGraphError;
+use crate::grammars::{self, Grammars};
+use crate::graph::DiffGraphParams;
+use crate::limits::{LimitAction, Truncation};
+use crate::parser::{self, get_patch_file_path, SourceLocation};
+use crate::policy::{Condition, Finding};
+
+fn try_resolve_revision(repo_dir: &Path, git_dir: Option<&Path>, revision: &str) -> Result<String, DiffGraphError> {
+    let output = parser::git_command(repo_dir, git_dir)
+        .arg("rev-parse")
+        .arg("--verify")
+        .arg("--quiet")
+        .arg(format!("{}^{{commit}}", revision))
+        .output()
+        .map_err(|e| DiffGraphError::io("Unable to run git rev-parse", e))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(DiffGraphError::Git(format!("Revision '{}' does not exist in {}", revision, repo_dir.display())))
+    }
+}
+
+/// What a run would do with a patched file.
+enum FileCheck {
+    /// The file would be parsed with this language
+    Ready(String),
+    /// The file would be skipped or fail the run, depending on the policy
+    Finding(Finding),
+    OverLimit(Truncation),
+    /// The file would be left out by the options, for this reason
+    Skipped(String),
+    SourceMissing,
+}
+
+fn check_file(path: &str, is_added_file: bool, params: &DiffGraphParams, source_location: &SourceLocation, grammars: &Grammars) -> FileCheck {
+    if !is_added_file {
+        match source_location.try_get_size(path) {
+            None => return FileCheck::SourceMissing,
+            Some(size) => if let Some(limit) = params.limits.max_bytes.filter(|limit| size > *limit) {
+                return FileCheck::OverLimit(Truncation::Bytes { file: path.to_string(), size, limit });
+            },
+        }
+    }
+
+    let file_path = source_location.get_repo_dir().join(path);
+    match grammars.find_candidates(&file_path).first() {
+        Some(candidate) => {
+            let language = grammars::get_language_name(candidate.scope.as_deref(), &candidate.root_path);
+            if !params.languages.is_empty() && !params.languages.iter().any(|l| l.eq_ignore_ascii_case(&language)) {
+                return FileCheck::Skipped(format!("language {} not selected", language));
+            }
+            FileCheck::Ready(format!("{} ({})", language, candidate.root_path.display()))
+        },
+        None if !params.languages.is_empty() => FileCheck::Skipped("language unknown not selected".into()),
+        None => FileCheck::Finding(Finding::new(Condition::UnknownLanguage, format!("No grammar is configured for {}", path))),
+    }
+}
+
+/// Reports what a graph run would do with these parameters, without parsing files or building
+/// the graph. Fails the same way the run would, after every file has been reported.
+pub fn run(params: &DiffGraphParams) -> Result<(), DiffGraphError> {
+    let source_location = params.get_source_location();
+    let repo_dir = source_location.get_repo_dir();
+    println!("repository: {}", repo_dir.display());
+    match &params.source_revision {
+        Some(revision) => {
+            let commit = try_resolve_revision(repo_dir, params.git_dir.as_deref(), revision)?;
+            println!("source: revision {} ({})", revision, commit);
+        },
+        None => println!("source: working tree"),
+    }
+
+    let grammars = Grammars::load(params.parser_config_path.clone(), params.save_default_if_missing)?;
+
+    let patch_files = params.get_patch_files();
+    let analyzed_files = params.limits.max_files.map_or(patch_files.len(), |max| max.min(patch_files.len()));
+    let mut findings = params.get_binary_file_findings();
+    println!("files: {} patched, {} binary", patch_files.len(), findings.len());
+
+    let mut missing_sources = Vec::new();
+    let mut truncations = Vec::new();
+    if analyzed_files < patch_files.len() {
+        truncations.push(Truncation::Files { kept: analyzed_files, total: patch_files.len() });
+    }
+    for (i, patch_file) in patch_files.iter().enumerate() {
+        let is_added_file = patch_file.source_file == "/dev/null";
+        let path = get_patch_file_path(patch_file);
+        if i >= analyzed_files {
+            println!("  {}: skipped (--max-files)", path);
+            continue;
+        }
+        match check_file(path, is_added_file, params, &source_location, &grammars) {
+            FileCheck::Ready(language) => println!("  {}: {}", path, language),
+            FileCheck::OverLimit(truncation) => {
+                println!("  {}: skipped (--max-bytes)", path);
+                truncations.push(truncation);
+            },
+            FileCheck::Skipped(reason) => println!("  {}: skipped ({})", path, reason),
+            FileCheck::Finding(finding) => {
+                println!("  {}: {}", path, finding.condition);
+                findings.push(finding);
+            },
+            FileCheck::SourceMissing => {
+                println!("  {}: source file not found", path);
+                missing_sources.push(path.to_string());
+            },
+        }
+    }
+    for file in params.binary_files.iter().filter(|file| params.path_filter.matches(file)) {
+        println!("  {}: binary, skipped", file);
+    }
+
+    if let Some(path) = missing_sources.first() {
+        return Err(DiffGraphError::io(format!("Unable to read '{}' in {}", path, repo_dir.display()), io::ErrorKind::NotFound.into()));
+    }
+    if let (LimitAction::Fail, Some(truncation)) = (params.limits.action, truncations.first()) {
+        return Err(DiffGraphError::LimitExceeded(truncation.clone()));
+    }
+    findings.extend(truncations
+        .iter()
+        .map(|truncation| Finding::new(Condition::Truncated, truncation.to_string())));
+    params.policy.evaluate(&findings)
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::api;
+use crate::coupling::CouplingReport;
+use crate::graph::DiffGraph;
+use crate::structural::ChangeClass;
+use crate::syntax;
+use crate::test_impact;
+
+/// What to look at closely in one cluster of coupled changes, see `CouplingReport::clusters`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReviewCluster {
+    /// After the declaration the cluster changes the most, such as `function_item parse`
+    pub name: String,
+    /// Files of the cluster in the order to review them: files others import first, tests last
+    pub files: Vec<String>,
+    /// Parse errors, API changes and other changes that need a careful look
+    pub flags: Vec<String>,
+}
+
+/// A review checklist with an entry per cluster of coupled changes, in patch order.
+#[derive(Debug, Clone, Serialize)]
+pub struct Checklist {
+    pub clusters: Vec<ReviewCluster>,
+}
+
+/// The declaration with the most changed nodes in the files, the file's path when none changed.
+fn get_dominant_declaration(graph: &DiffGraph, files: &[usize]) -> String {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for file in files.iter() {
+        for declaration in graph.diffs()[*file].changed_nodes(*file).into_iter().filter_map(|changed| changed.declaration) {
+            let label = match &declaration.name {
+                Some(name) => format!("{} {}", declaration.kind, name),
+                None => declaration.kind.clone(),
+            };
+            match counts.iter_mut().find(|(known, _)| *known == label) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((label, 1)),
+            }
+        }
+    }
+    // The first of the most changed, in patch order
+    let most = counts.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    match counts.into_iter().find(|(_, count)| *count == most) {
+        Some((label, _)) => label,
+        None => graph.diffs()[files[0]].source_file_path.clone(),
+    }
+}
+
+/// Files in the order to review them: what other files of the cluster import comes before
+/// them, and tests come after what they test. Ties keep patch order.
+fn get_review_order(graph: &DiffGraph, coupling: &CouplingReport, files: &[usize]) -> Vec<String> {
+    let mut imported: HashMap<&str, usize> = HashMap::new();
+    for edge in coupling.pairs.iter().flat_map(|pair| pair.imports.iter()) {
+        *imported.entry(edge.imported.as_str()).or_default() += 1;
+    }
+    let mut paths: Vec<&str> = files.iter().map(|file| graph.diffs()[*file].source_file_path.as_str()).collect();
+    paths.sort_by_key(|path| (test_impact::is_test_path(path), std::cmp::Reverse(imported.get(path).copied().unwrap_or(0))));
+    paths.into_iter().map(|path| path.to_string()).collect()
+}
+
+fn get_flags(graph: &DiffGraph, coupling: &CouplingReport, files: &[usize]) -> Vec<String> {
+    let mut flags = Vec::new();
+    for file in files.iter() {
+        let diff = &graph.diffs()[*file];
+        let path = &diff.source_file_path;
+        let errors = syntax::count_error_nodes(&diff.target_tree);
+        if errors > 0 {
+            flags.push(format!("`{}` has {} parse errors after the change", path, errors));
+        }
+        for change in api::find_breaking_changes(diff) {
+            flags.push(change.to_string());
+        }
+        let declarations = diff.changed_declarations();
+        let signatures = declarations.iter().filter(|declaration| declaration.change == Some(ChangeClass::SignatureChange)).count();
+        if signatures > 0 {
+            flags.push(format!("`{}` changes the signature of {} declarations, check their callers", path, signatures));
+        }
+        let removed = declarations.iter().filter(|declaration| declaration.change == Some(ChangeClass::Removed)).count();
+        if removed > 0 {
+            flags.push(format!("`{}` removes {} declarations, check that nothing still uses them", path, removed));
+        }
+    }
+    for tag in graph.security_tags().iter().filter(|tag| files.iter().any(|file| graph.diffs()[*file].source_file_path == tag.file)) {
+        flags.push(tag.to_string());
+    }
+
+    let paths: Vec<&str> = files.iter().map(|file| graph.diffs()[*file].source_file_path.as_str()).collect();
+    let across = coupling.cross_module().filter(|pair| paths.contains(&pair.files[0].as_str())).count();
+    if across > 0 {
+        flags.push(format!("{} coupled pairs of files are in different modules, check the change belongs in both", across));
+    }
+    if !paths.iter().any(|path| test_impact::is_test_path(path)) {
+        flags.push("no tests change with these files".to_string());
+    }
+    flags
+}
+
+impl Checklist {
+    pub fn from_graph(graph: &DiffGraph) -> Self {
+        let coupling = CouplingReport::from_graph(graph);
+        let clusters = coupling.clusters(graph)
+            .iter()
+            .map(|files| ReviewCluster {
+                name: get_dominant_declaration(graph, files),
+                files: get_review_order(graph, &coupling, files),
+                flags: get_flags(graph, &coupling, files),
+            })
+            .collect();
+        Self { clusters }
+    }
+}
+
+/// The checklist as markdown, with a box to tick per file.
+impl fmt::Display for Checklist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "## Review checklist")?;
+        if self.clusters.is_empty() {
+            writeln!(f)?;
+            return writeln!(f, "No source files changed.");
+        }
+
+        for (i, cluster) in self.clusters.iter().enumerate() {
+            writeln!(f)?;
+            writeln!(f, "### {}. `{}`", i + 1, cluster.name)?;
+            writeln!(f)?;
+            for path in cluster.files.iter() {
+                writeln!(f, "- [ ] `{}`", path)?;
+            }
+            if !cluster.flags.is_empty() {
+                writeln!(f)?;
+                writeln!(f, "Look out for:")?;
+                writeln!(f)?;
+                for flag in cluster.flags.iter() {
+                    writeln!(f, "- {}", flag)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt;
+
+use serde::Serialize;
+use tree_sitter::{Node as TSNode, Tree};
+
+use crate::graph::DiffGraph;
+use crate::hotspots::HotspotReport;
+use crate::parser::Diff;
+use crate::structural::{self, ChangeClass, DeclarationKey};
+use crate::syntax;
+
+const HEADERS: [&str; 9] = ["file", "kind", "name", "line", "lines", "added", "removed", "change", "prior_changes"];
+
+/// Metrics of one changed declaration.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeclarationChurn {
+    pub file: String,
+    pub kind: String,
+    pub name: Option<String>,
+    /// First line of the declaration, counted from 1, in the patched file unless it was removed
+    pub line: usize,
+    /// Lines the declaration spans, in the patched file unless it was removed
+    pub lines: usize,
+    /// Lines the patch adds within the declaration
+    pub added_lines: usize,
+    /// Lines the patch removes within the declaration
+    pub removed_lines: usize,
+    pub change: Option<ChangeClass>,
+    /// Commits of the `--history` range that changed the declaration, without `--history` none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prior_changes: Option<usize>,
+}
+
+/// Per-declaration metrics of the patch as a flat table, for aggregating in dashboards. Written
+/// as CSV, or as JSON with `--format json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChurnReport {
+    /// Range of revisions the prior changes are counted over
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history: Option<String>,
+    pub declarations: Vec<DeclarationChurn>,
+}
+
+/// The innermost declaration starting each row of one side of the patch, with how many of the
+/// rows it encloses.
+fn count_rows<'t>(tree: &'t Tree, source: &str, rows: &[usize]) -> Vec<(DeclarationKey, TSNode<'t>, usize)> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut counts: Vec<(DeclarationKey, TSNode, usize)> = Vec::new();
+    for row in rows.iter() {
+        let point = syntax::get_line_start(*row, lines.get(*row).unwrap_or(&""));
+        let Some(node) = syntax::find_enclosing_declaration(tree, point) else {
+            continue;
+        };
+        match counts.iter_mut().find(|(_, known, _)| known.id() == node.id()) {
+            Some((_, _, count)) => *count += 1,
+            None => counts.push((structural::declaration_key(&node, source), node, 1)),
+        }
+    }
+    counts
+}
+
+/// Declarations enclosing changed lines of the file, matched between both sides of the patch by
+/// kind and the names of them and the declarations enclosing them.
+fn measure_file(diff: &Diff) -> Vec<DeclarationChurn> {
+    let changes = structural::diff_file(diff);
+    let new_row = |node: &TSNode| DeclarationChurn {
+        file: diff.source_file_path.clone(),
+        kind: node.kind().to_string(),
+        name: syntax::get_declaration_name(node, &diff.target_source),
+        line: node.start_position().row + 1,
+        lines: node.end_position().row - node.start_position().row + 1,
+        added_lines: 0,
+        removed_lines: 0,
+        change: None,
+        prior_changes: None,
+    };
+
+    let added = count_rows(&diff.target_tree, &diff.target_source, &diff.added_rows);
+    let mut declarations: Vec<(Option<DeclarationKey>, DeclarationChurn)> = added
+        .into_iter()
+        .map(|(key, node, count)| {
+            // Unnamed declarations, such as Rust `impl` blocks, are only matched by position
+            let key = syntax::get_declaration_name(&node, &diff.target_source).map(|_| key);
+            (key, DeclarationChurn { added_lines: count, ..new_row(&node) })
+        })
+        .collect();
+    for (key, node, count) in count_rows(&diff.tree, &diff.source, &diff.removed_rows) {
+        let named = syntax::get_declaration_name(&node, &diff.source).is_some();
+        match declarations.iter_mut().find(|(known, _)| named && known.as_ref() == Some(&key)) {
+            Some((_, declaration)) => declaration.removed_lines += count,
+            None => declarations.push((None, DeclarationChurn {
+                name: syntax::get_declaration_name(&node, &diff.source),
+                removed_lines: count,
+                ..new_row(&node)
+            })),
+        }
+    }
+
+    declarations
+        .into_iter()
+        .map(|(_, mut declaration)| {
+            // Only declarations of the patched file have added lines, the others were removed
+            let patched = declaration.added_lines > 0;
+            declaration.change = structural::find_class(&changes, patched, &declaration.kind, declaration.line - 1);
+            declaration
+        })
+        .collect()
+}
+
+impl ChurnReport {
+    /// Measures the changed declarations of the graph, and with the hotspots of a range of
+    /// commits before it, how often each of them changed in that range.
+    pub fn from_graph(graph: &DiffGraph, history: Option<&HotspotReport>) -> Self {
+        let mut declarations: Vec<DeclarationChurn> = graph.diffs().iter().flat_map(measure_file).collect();
+        if let Some(history) = history {
+            for declaration in declarations.iter_mut() {
+                let prior = history.hotspots
+                    .iter()
+                    .find(|hotspot| hotspot.file == declaration.file
+                        && hotspot.kind.as_ref() == Some(&declaration.kind)
+                        && hotspot.name == declaration.name)
+                    .map_or(0, |hotspot| hotspot.changes);
+                declaration.prior_changes = Some(prior);
+            }
+        }
+        Self { history: history.map(|history| history.range.clone()), declarations }
+    }
+}
+
+/// Quotes a CSV field when it holds a separator, quote or line break.
+fn escape_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// The report as CSV with a header row, empty fields standing for unnamed declarations, unknown
+/// changes and prior changes without `--history`.
+impl fmt::Display for ChurnReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADERS.join(","))?;
+        for declaration in self.declarations.iter() {
+            let fields = [
+                declaration.file.clone(),
+                declaration.kind.clone(),
+                declaration.name.clone().unwrap_or_default(),
+                declaration.line.to_string(),
+                declaration.lines.to_string(),
+                declaration.added_lines.to_string(),
+                declaration.removed_lines.to_string(),
+                declaration.change.map(|change| change.to_string()).unwrap_or_default(),
+                declaration.prior_changes.map(|prior| prior.to_string()).unwrap_or_default(),
+            ];
+            writeln!(f, "{}", fields.map(|field| escape_field(&field)).join(","))?;
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,86 @@
-use std::process::Command;
-use clap::{Arg, ArgAction};
+use std::process::{Child, Command, Output, Stdio};
+use clap::{Arg, ArgAction, ArgMatches};
 use url::Url;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
-use regex::Regex;
-use unidiff::PatchSet;
+use std::time::Duration;
+use tracing::{debug, info, Level};
 
 use crate::graph::DiffGraphParams;
+use crate::analyze::{Analysis, AnalyzeParams};
+use crate::bench::BenchParams;
+use crate::cache::{self, ResultCache};
+use crate::comment::{CommentTarget, PullRequest};
+use crate::gerrit::GerritReview;
+use crate::export::{ExportFormat, OutputOptions, OutputTarget};
+use crate::progress;
+use crate::color::{self, ColorChoice, Stream};
+use crate::complexity;
+use crate::duplicates;
+use crate::git::{dir_is_git_repository, dir_is_working_copy, try_get_diff_patch, try_parse_diff, try_resolve_revision_range};
+use crate::index;
+use crate::jj;
+use crate::patch::{try_create_parsed_diff, ParsedDiff};
+use crate::timings;
+use crate::stats;
+use crate::cancel;
+use crate::error::{self, DiffGraphError, ErrorCode, ErrorFormat, EXIT_CODES_HELP};
+use crate::config::Config;
+use crate::watch::WatchParams;
+use crate::server::{ServerParams, Transport};
+use crate::mcp::McpParams;
+use crate::limits::{Limits, LimitAction};
+use crate::policy::{Condition, Policy};
+use crate::pathspec::PathFilter;
+
+#[derive(Debug)]
+pub enum CliCommand {
+    Graph {
+        params: DiffGraphParams,
+        output: OutputOptions,
+    },
+    /// Report what a graph run would do without parsing or building the graph
+    Check(DiffGraphParams),
+    /// Report on the graph, such as ranking the changed declarations by risk
+    Analyze(AnalyzeParams),
+    /// Write each file's pruned graph as it is parsed instead of building the whole graph
+    Stream {
+        params: DiffGraphParams,
+        target: OutputTarget,
+        compress: bool,
+    },
+    GrammarsWhich {
+        file: PathBuf,
+        parser_config_path: Option<PathBuf>,
+    },
+    GrammarsSyncRegistry {
+        parser_config_path: Option<PathBuf>,
+    },
+    /// Combine the graphs of the shards of a patch
+    Merge {
+        inputs: Vec<PathBuf>,
+        target: OutputTarget,
+        compress: bool,
+    },
+    Watch(WatchParams),
+    Serve(ServerParams),
+    Mcp(McpParams),
+    Bench(BenchParams),
+    /// Check that the patch changes nothing but whitespace, layout and comments
+    Equivalent(DiffGraphParams),
+    /// Index the definitions of the repository's files into the target
+    Index {
+        params: DiffGraphParams,
+        target: PathBuf,
+    },
+    /// Compare the changed declarations of the diff with those of another
+    CompareRevs {
+        params: DiffGraphParams,
+        other: ParsedDiff,
+        json: bool,
+        target: OutputTarget,
+    },
+}
 
 #[derive(Debug)]
 enum ArgValue {
@@ -15,10 +90,6 @@ enum ArgValue {
         exists: bool,
     },
     Url(Url),
-    Commit {
-        from: String,
-        to: String,
-    },
 }
 
 impl ArgValue {
@@ -38,141 +109,325 @@ impl ArgValue {
             exists: path.exists(),
         })
     }
-    pub fn try_parse_commit(arg: &str) -> Option<ArgValue> {
-        let re = Regex::new(r"^([0-9a-fA-F]+)\.\.([0-9a-fA-F]+)$").unwrap();
-        if let Some(captures) = re.captures(arg) {
-            Some(ArgValue::Commit { 
-                from: captures.get(1).unwrap().as_str().into(),
-                to: captures.get(2).unwrap().as_str().into(),
-            })
-        } else {
-            let re = Regex::new(r"^[0-9a-fA-F]{6,64}$").unwrap();
-            if re.is_match(arg) {
-                Some(ArgValue::Commit { from: "HEAD".into(), to: arg.into() })
-            } else {
-                None
-            }
-        }
-    }
 }
 
-fn try_get_diff_patch(rev_from: &str, rev_to: &str) -> Result<String, String> {
-    let cmd_gitdiff = Command::new("git")
-        .arg("diff")
-        .arg(format!("{}..{}", rev_from, rev_to))
-        .output();
+/// The email git commits as in the repository, if one is configured.
+fn get_git_user_email(repo_path: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("config")
+        .arg("user.email")
+        .current_dir(repo_path)
+        .output()
+        .ok()?;
+    let email = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !email.is_empty()).then_some(email)
+}
 
-    match cmd_gitdiff {
-        Ok(output) => Ok(String::from_utf8_lossy(&output.stdout).to_string()),
-        Err(e) => Err(e.to_string())
-    }
+/// A diff named by a GitHub `/compare/<from>...<to>` or `/commit/<sha>` URL.
+#[derive(Debug)]
+pub struct GitHubDiff {
+    repository_url: String,
+    /// None for a single commit, which is diffed against its parent
+    from: Option<String>,
+    to: String,
+    /// `...` diffs from the merge base of both ends, as GitHub compares do
+    range: &'static str,
 }
 
-fn try_check_apply_patch(file_path: &PathBuf, repo_path: &PathBuf) -> Result<bool, String> {
-    let cmd_gitapply = Command::new("git")
-        .arg("apply")
-        .arg("--check")
-        .arg(file_path)
-        .current_dir(repo_path)
-        .status()
-        .map_err(|e| e.to_string())?;
+impl GitHubDiff {
+    pub fn try_parse(arg: &str) -> Option<Self> {
+        let url = Url::parse(arg).ok()?;
+        if url.host_str() != Some("github.com") {
+            return None;
+        }
+        let segments: Vec<&str> = url.path_segments()?.collect();
+        if segments.len() < 4 {
+            return None;
+        }
+        let repository_url = format!("https://github.com/{}/{}.git", segments[0], segments[1].trim_end_matches(".git"));
+        // Branch names may contain slashes, and GitHub serves raw diffs with these suffixes
+        let rest = segments[3..].join("/");
+        let rest = rest.trim_end_matches(".diff").trim_end_matches(".patch");
 
-    Ok(cmd_gitapply.success())
-}
+        match segments[2] {
+            "commit" => Some(Self {
+                repository_url,
+                from: None,
+                to: rest.to_string(),
+                range: "..",
+            }),
+            "compare" => {
+                let (from, to, range) = match rest.split_once("...") {
+                    Some((from, to)) => (from, to, "..."),
+                    None => {
+                        let (from, to) = rest.split_once("..")?;
+                        (from, to, "..")
+                    },
+                };
+                Some(Self {
+                    repository_url,
+                    from: Some(from.to_string()),
+                    to: to.to_string(),
+                    range,
+                })
+            },
+            _ => None,
+        }
+    }
+
+    /// Fetches the revisions from GitHub into `refs/diffgraph/`, whatever the repository's
+    /// remotes are, then diffs them.
+    pub fn try_fetch(&self, repo_path: &Path) -> Result<ParsedDiff, DiffGraphError> {
+        let mut refspecs = vec![format!("+{}:refs/diffgraph/to", self.to)];
+        let range = match &self.from {
+            Some(from) => {
+                refspecs.push(format!("+{}:refs/diffgraph/from", from));
+                format!("refs/diffgraph/from{}refs/diffgraph/to", self.range)
+            },
+            None => "refs/diffgraph/to^..refs/diffgraph/to".to_string(),
+        };
 
-fn try_create_patch_set(diff: &str) -> Result<PatchSet, String> {
-    let mut patch = PatchSet::new();
-    match patch.parse(diff) {
-        Ok(_) => Ok(patch),
-        Err(e) => Err(e.to_string()),
+        let spinner = progress::spinner(&format!("Fetching {} from {}", self.to, self.repository_url));
+        let output = Command::new("git")
+            .arg("fetch")
+            .arg("--no-tags")
+            .arg(&self.repository_url)
+            .args(&refspecs)
+            .current_dir(repo_path)
+            .output()
+            .map_err(|e| DiffGraphError::io("Unable to run git fetch", e))?;
+        spinner.finish_and_clear();
+        if !output.status.success() {
+            return Err(DiffGraphError::Network(format!("Unable to fetch {} from {}: {}", refspecs.join(" "), self.repository_url,
+                String::from_utf8_lossy(&output.stderr).trim())));
+        }
+
+        match try_resolve_revision_range(repo_path, &range)? {
+            Some((from, to)) => try_create_parsed_diff(&try_get_diff_patch(repo_path, &from, &to)?, Some(from)),
+            None => Err(DiffGraphError::Git(format!("Unable to resolve {} after fetching it", range))),
+        }
     }
 }
 
-fn try_load_diff_file(file_path: &PathBuf) -> Result<String, String> {
-    let path = Path::new(file_path);
-    match std::fs::read_to_string(path) {
-        Ok(file_contents) => Ok(file_contents),
-        Err(err) => Err(err.to_string()),
-    }
+/// A Gerrit change, from its web URL `https://HOST/c/PROJECT/+/NUMBER[/PATCHSET]` or as
+/// `gerrit:NUMBER[/PATCHSET]` to fetch it from the repository's origin.
+#[derive(Debug, Clone)]
+pub struct GerritChange {
+    /// Repository URL to fetch from, or None for the origin remote
+    repository_url: Option<String>,
+    number: u64,
+    /// None for the latest patchset
+    patchset: Option<u32>,
 }
 
-fn try_parse_diff(diff_arg: &str, repo_path: &PathBuf) -> Result<PatchSet, String> {
-    let diff_from_commit;
-    match ArgValue::try_parse_commit(&diff_arg) {
-        Some(ArgValue::Commit { from, to }) => match try_get_diff_patch(&from, &to) {
-            Ok(patch) => diff_from_commit = Some(patch),
-            Err(err) => return Err(err.to_string()),
-        },
-        None => diff_from_commit = None,
-        Some(unsupported_arg) => return Err(format!("Unsupported type [{:?}] from argument {}", unsupported_arg, diff_arg)),
-    };
-    let diff;
-    if let Some(diff_from_commit) = diff_from_commit {
-        diff = diff_from_commit;
-    } else {
-        diff = match ArgValue::try_parse_file(&diff_arg) {
-            Some(ArgValue::Path { path, is_dir, exists }) => {
-                if exists {
-                    if is_dir {
-                        return Err(format!("diff path must be a file, directory is not supported at the moment..."))
-                    } else {
-                        // Check that the file can apply to our repository
-                        if !try_check_apply_patch(&path, repo_path)? {
-                            return Err(format!("diff '{:?}' could not be applied to repository at {:?}", path, repo_path.display()));
-                        }
-                        // Load it
-                        match try_load_diff_file(&path) {
-                            Ok(diff) => diff,
-                            Err(err) => return Err(err.to_string()) 
-                        }
-                    }
-                } else {
-                    return Err(format!("diff path '{:?}' does not exist.", path))
-                }
-            },
-            _ => return Err(format!("Unable to parse diff from argument '{}'", diff_arg)),
+impl GerritChange {
+    pub fn try_parse(arg: &str) -> Option<Self> {
+        let parse_change = |number: &str, patchset: Option<&str>| -> Option<(u64, Option<u32>)> {
+            let number = number.parse().ok()?;
+            match patchset {
+                Some(patchset) if !patchset.is_empty() => Some((number, Some(patchset.parse().ok()?))),
+                _ => Some((number, None)),
+            }
         };
+
+        if let Some(change) = arg.strip_prefix("gerrit:") {
+            let mut parts = change.splitn(2, '/');
+            let (number, patchset) = parse_change(parts.next()?, parts.next())?;
+            return Some(Self { repository_url: None, number, patchset });
+        }
+
+        let url = Url::parse(arg).ok()?;
+        let path = url.path();
+        // Gerrit may be served under a path prefix, which the repository URL keeps
+        let (prefix, change) = path.split_once("/c/")?;
+        let (project, change) = change.split_once("/+/")?;
+        let mut parts = change.trim_end_matches('/').split('/');
+        let (number, patchset) = parse_change(parts.next()?, parts.next())?;
+        let mut repository_url = url.clone();
+        repository_url.set_path(&format!("{}/{}", prefix, project));
+        repository_url.set_query(None);
+        repository_url.set_fragment(None);
+
+        Some(Self {
+            repository_url: Some(repository_url.to_string()),
+            number,
+            patchset,
+        })
     }
 
-    match try_create_patch_set(&diff) {
-        Ok(patch) => Ok(patch),
-        Err(e) => Err(e),
+    fn remote(&self) -> &str {
+        self.repository_url.as_deref().unwrap_or("origin")
     }
-}
 
-fn dir_is_git_repository(dir: &PathBuf) -> bool {
-    let output = Command::new("git")
-        .arg("rev-parse")
-        .arg("--is-inside-work-tree")
-        .current_dir(dir)
-        .output()
-        .expect("Failed to execute git command");
+    /// Changes are stored under `refs/changes/`, sharded by the last two digits of their number.
+    fn change_ref(&self, patchset: u32) -> String {
+        format!("refs/changes/{:02}/{}/{}", self.number % 100, self.number, patchset)
+    }
+
+    fn try_get_latest_patchset(&self, repo_path: &Path) -> Result<u32, DiffGraphError> {
+        let refs = format!("refs/changes/{:02}/{}/*", self.number % 100, self.number);
+        let output = Command::new("git")
+            .arg("ls-remote")
+            .arg(self.remote())
+            .arg(&refs)
+            .current_dir(repo_path)
+            .output()
+            .map_err(|e| DiffGraphError::io("Unable to run git ls-remote", e))?;
+        if !output.status.success() {
+            return Err(DiffGraphError::Network(format!("Unable to list the patchsets of change {} on {}: {}", self.number, self.remote(),
+                String::from_utf8_lossy(&output.stderr).trim())));
+        }
+
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.rsplit('/').next()?.parse::<u32>().ok())
+            .max()
+            .ok_or_else(|| DiffGraphError::Git(format!("Change {} has no patchsets on {}", self.number, self.remote())))
+    }
+
+    /// Fetches the patchset into `refs/diffgraph/` and diffs it against its parent, returning the
+    /// patchset number along with the diff.
+    pub fn try_fetch(&self, repo_path: &Path) -> Result<(ParsedDiff, u32), DiffGraphError> {
+        let patchset = match self.patchset {
+            Some(patchset) => patchset,
+            None => self.try_get_latest_patchset(repo_path)?,
+        };
+        let refspec = format!("+{}:refs/diffgraph/to", self.change_ref(patchset));
 
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        let spinner = progress::spinner(&format!("Fetching change {},{} from {}", self.number, patchset, self.remote()));
+        let output = Command::new("git")
+            .arg("fetch")
+            .arg("--no-tags")
+            .arg(self.remote())
+            .arg(&refspec)
+            .current_dir(repo_path)
+            .output()
+            .map_err(|e| DiffGraphError::io("Unable to run git fetch", e))?;
+        spinner.finish_and_clear();
+        if !output.status.success() {
+            return Err(DiffGraphError::Network(format!("Unable to fetch {} from {}: {}", refspec, self.remote(),
+                String::from_utf8_lossy(&output.stderr).trim())));
+        }
+
+        let range = "refs/diffgraph/to^..refs/diffgraph/to";
+        match try_resolve_revision_range(repo_path, range)? {
+            Some((from, to)) => Ok((try_create_parsed_diff(&try_get_diff_patch(repo_path, &from, &to)?, Some(from))?, patchset)),
+            None => Err(DiffGraphError::Git(format!("Unable to resolve {} after fetching it", range))),
+        }
+    }
 
-    stdout.trim() == "true" && stderr.is_empty()
+    /// Where to post a review of the patchset, from the repository URL or the origin's.
+    fn try_review_target(&self, repo_path: &Path, patchset: u32, credentials: (String, String)) -> Result<GerritReview, DiffGraphError> {
+        let remote_url = match &self.repository_url {
+            Some(url) => url.clone(),
+            None => {
+                let output = Command::new("git")
+                    .args(["remote", "get-url", "origin"])
+                    .current_dir(repo_path)
+                    .output()
+                    .map_err(|e| DiffGraphError::io("Unable to run git remote", e))?;
+                String::from_utf8_lossy(&output.stdout).trim().to_string()
+            },
+        };
+        let url = Url::parse(&remote_url)
+            .map_err(|_| DiffGraphError::Usage(format!("Unable to find the Gerrit server of '{}', use the change URL instead", remote_url)))?;
+        let host = url.host_str()
+            .ok_or_else(|| DiffGraphError::Usage(format!("Remote '{}' has no host", remote_url)))?;
+        // Authenticated git access goes through /a/, and SSH remotes use Gerrit's own port
+        let path = url.path().trim_start_matches('/');
+        let project = path.strip_prefix("a/").unwrap_or(path).trim_end_matches(".git").trim_end_matches('/');
+        let server_url = match (url.scheme(), url.port()) {
+            ("http" | "https", Some(port)) => format!("{}://{}:{}", url.scheme(), host, port),
+            ("http" | "https", None) => format!("{}://{}", url.scheme(), host),
+            _ => format!("https://{}", host),
+        };
+
+        Ok(GerritReview {
+            server_url,
+            project: project.to_string(),
+            change: self.number,
+            patchset,
+            username: credentials.0,
+            password: credentials.1,
+        })
+    }
 }
 
-fn try_clone_repo(url: &str, clone_path: &str) -> Result<PathBuf, String> {
-    dbg!(url, clone_path);
+/// Waits for the git command to finish, killing it if the run is cancelled first.
+fn try_wait_for_git(mut child: Child) -> Result<Output, DiffGraphError> {
+    loop {
+        if cancel::is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            cancel::try_check()?;
+        }
+        match child.try_wait() {
+            Ok(Some(_)) => return child.wait_with_output().map_err(|e| DiffGraphError::io("Unable to wait for git", e)),
+            Ok(None) => std::thread::sleep(Duration::from_millis(50)),
+            Err(e) => return Err(DiffGraphError::io("Unable to wait for git", e)),
+        }
+    }
+}
 
-    let output = Command::new("git")
+pub fn try_clone_repo(url: &str, clone_path: &str) -> Result<PathBuf, DiffGraphError> {
+    debug!("Cloning {} into {}", url, clone_path);
+
+    let existed = Path::new(clone_path).exists();
+    let child = Command::new("git")
         .arg("clone")
         .arg(url)
         .arg(clone_path)
-        .output()
-        .expect("Failed to execute git clone command");
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| DiffGraphError::io("Unable to run git clone", e))?;
+    let spinner = progress::spinner(&format!("Cloning {}", url));
+    let output = try_wait_for_git(child);
+    spinner.finish_and_clear();
+
+    // A clone cut short is of no use to later runs, so only a clone path that was there before is kept
+    let output = match output {
+        Err(e @ DiffGraphError::Cancelled(_)) if !existed => {
+            if let Err(remove_error) = std::fs::remove_dir_all(clone_path) {
+                debug!("Unable to remove the partial clone at {}: {}", clone_path, remove_error);
+            }
+            return Err(e);
+        },
+        output => output?,
+    };
 
     if output.status.success() {
         Ok(Path::new(clone_path).to_path_buf())
     } else {
         let error_message = String::from_utf8_lossy(&output.stderr).to_string();
-        Err(error_message)
+        Err(DiffGraphError::Git(error_message))
     }
 }
 
-fn try_parse_repo(repo_arg: &str, clone_path: Option<String>) -> Result<Option<PathBuf>, String> {
+fn try_fetch_repo(repo_path: &Path) -> Result<PathBuf, DiffGraphError> {
+    debug!("Fetching cached clone at {}", repo_path.display());
+
+    let spinner = progress::spinner(&format!("Fetching {}", repo_path.display()));
+    let child = Command::new("git")
+        .arg("fetch")
+        .arg("--all")
+        .current_dir(repo_path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| DiffGraphError::io("Unable to run git fetch", e))?;
+    let output = try_wait_for_git(child);
+    spinner.finish_and_clear();
+    let output = output?;
+
+    if output.status.success() {
+        Ok(repo_path.to_path_buf())
+    } else {
+        Err(DiffGraphError::Git(String::from_utf8_lossy(&output.stderr).to_string()))
+    }
+}
+
+fn try_parse_repo(repo_arg: &str, clone_path: Option<String>, clone_cache_dir: Option<&Path>) -> Result<Option<PathBuf>, DiffGraphError> {
     fn fallback_value(url: &Url) -> String {
         if url.path().len() > 0 {
             url.path().to_string()
@@ -203,7 +458,19 @@ fn try_parse_repo(repo_arg: &str, clone_path: Option<String>) -> Result<Option<P
                     None => fallback_value(&url)
                 }
             };
-            return match try_clone_repo(&url.as_str(), &clone_path) {
+            let clone_path = match clone_cache_dir {
+                Some(cache_dir) => {
+                    let cached_path = cache_dir.join(clone_path.trim_start_matches('/'));
+                    if cached_path.is_dir() && dir_is_git_repository(&cached_path) {
+                        stats::hit(stats::Cache::Clones);
+                        return timings::phase("clone", || try_fetch_repo(&cached_path)).map(Some);
+                    }
+                    stats::miss(stats::Cache::Clones);
+                    cached_path.to_string_lossy().to_string()
+                },
+                None => clone_path,
+            };
+            return match timings::phase("clone", || try_clone_repo(url.as_str(), &clone_path)) {
                 Ok(repo_path) => Ok(Some(repo_path)),
                 Err(e) => Err(e),
             }
@@ -215,79 +482,915 @@ fn try_parse_repo(repo_arg: &str, clone_path: Option<String>) -> Result<Option<P
         Some(ArgValue::Path { path, is_dir, exists }) => {
             if exists {
                 if is_dir {
-                    if dir_is_git_repository(&path) {
+                    if dir_is_git_repository(&path) || jj::is_repository(&path) || dir_is_working_copy(&path) {
                         Ok(Some(path))
                     } else {
-                        Err(format!("Repository path '{:?}' is not a git repository", path))
+                        Err(DiffGraphError::Git(format!("Repository path '{:?}' is not a git or jj repository, or a Mercurial or Subversion working copy", path)))
                     }
                 } else {
-                    Err(format!("Repository path '{:?}' must be a directory", path))
+                    Err(DiffGraphError::Git(format!("Repository path '{:?}' must be a directory", path)))
                 }
             } else {
-                Err(format!("Repository path '{:?}' does not exist", path))
+                Err(DiffGraphError::Git(format!("Repository path '{:?}' does not exist", path)))
             }
         },
         _ => Ok(None),
     }
 }
 
-pub fn get_params() -> Result<DiffGraphParams, String> {
-    let matches = clap::Command::new("diffdiagram")
-        .arg(Arg::new("repo")
-            .short('r')
-            .long("repository")
-            .value_name("URL or PATH")
-            .default_value(".")
+fn try_get_repository_path(repo_arg: Option<&String>, clone_path: Option<&String>, config: &Config) -> Result<PathBuf, DiffGraphError> {
+    let repo_arg = repo_arg
+        .cloned()
+        .or(config.repository.clone())
+        .unwrap_or(".".into());
+
+    match try_parse_repo(&repo_arg, clone_path.cloned(), config.clone_cache_dir.as_deref()) {
+        Ok(Some(repo)) => {
+            info!("Repository path: {:?}", repo);
+            Ok(repo)
+        },
+        Ok(None) => Err(DiffGraphError::Git(format!("No repository found at {}", repo_arg))),
+        Err(e) => Err(e),
+    }
+}
+
+fn repository_arg() -> Arg {
+    Arg::new("repo")
+        .short('r')
+        .long("repository")
+        .env("DIFFGRAPH_REPO")
+        .value_name("URL or PATH")
+        .help("Specify a URL or path to repository to diff against [default: .]")
+}
+
+fn diff_arg() -> Arg {
+    Arg::new("diff")
+        .short('d')
+        .long("diff")
+        .env("DIFFGRAPH_DIFF")
+        .value_name("PATCH FILE or GIT REVISIONS")
+        .required(true)
+        .help("Specify diff patch file, git revisions (a..b, a...b), a branch to diff from its merge base with the default branch, jj change ids or revsets (a..b, or one change against its parent) in Jujutsu repositories, a GitHub compare or commit URL, a Gerrit change URL or gerrit:NUMBER[/PATCHSET], or - to read a unified diff from stdin")
+}
+
+/// The `analyze` subcommand of the analysis, with the options it takes.
+fn analysis_command(analysis: Analysis) -> clap::Command {
+    let command = clap::Command::new(analysis.as_str())
+        .about(analysis.about())
+        .arg(repository_arg())
+        .arg(diff_arg());
+    match analysis {
+        Analysis::Risk | Analysis::Tests | Analysis::Coupling | Analysis::Hotspots | Analysis::Checklist | Analysis::DeadCode | Analysis::Languages | Analysis::Split => command,
+        Analysis::Conflicts => command.arg(Arg::new("other-diff")
+            .long("other-diff")
+            .value_name("PATCH FILE or GIT REVISIONS")
             .required(true)
-            .help("Specify a URL or path to repository to diff against"))
+            .help("Patch to compare the diff with, a patch file or git revisions of the same repository")),
+        Analysis::Ownership => command
+            .arg(Arg::new("authors")
+                .long("authors")
+                .env("DIFFGRAPH_AUTHORS")
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("File with a line per author: their email and team, or - for authors who left"))
+            .arg(Arg::new("author")
+                .long("author")
+                .env("DIFFGRAPH_AUTHOR")
+                .value_name("EMAIL")
+                .help("Author of the patch, whose team the blamed authors are compared with [default: git config user.email]")),
+        Analysis::Churn => command.arg(Arg::new("history")
+            .long("history")
+            .env("DIFFGRAPH_CHURN_HISTORY")
+            .value_name("GIT REVISIONS")
+            .help("Count how often each declaration changed in this range of commits, such as main~100..main")),
+        Analysis::Duplicates => command.arg(Arg::new("min-similarity")
+            .long("min-similarity")
+            .env("DIFFGRAPH_MIN_SIMILARITY")
+            .value_name("RATIO")
+            .value_parser(clap::value_parser!(f64))
+            .help(format!("Report added subtrees at least this similar, from 0 to 1 [default: {}]", duplicates::DEFAULT_SIMILARITY))),
+        Analysis::Complexity => command.arg(Arg::new("threshold")
+            .long("threshold")
+            .env("DIFFGRAPH_COMPLEXITY_THRESHOLD")
+            .value_name("COMPLEXITY")
+            .value_parser(clap::value_parser!(usize))
+            .help(format!("Flag changed functions whose branch count goes past this [default: {}]", complexity::DEFAULT_THRESHOLD))),
+    }
+}
+
+fn init_logging(verbosity: u8, quiet: bool, ansi: bool) {
+    let level = if quiet {
+        Level::ERROR
+    } else {
+        match verbosity {
+            0 => Level::WARN,
+            1 => Level::INFO,
+            2 => Level::DEBUG,
+            _ => Level::TRACE,
+        }
+    };
+
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_ansi(ansi)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+/// Resolves a diff argument the way `--diff` does: a GitHub URL, a Gerrit change, a commit, a branch or range
+/// of revisions, a patch file, or `-` for stdin.
+pub fn try_resolve_diff(diff_arg: &str, repo_path: &Path) -> Result<ParsedDiff, DiffGraphError> {
+    if let Some(github_diff) = GitHubDiff::try_parse(diff_arg) {
+        return github_diff.try_fetch(repo_path);
+    }
+    match GerritChange::try_parse(diff_arg) {
+        Some(gerrit_change) => gerrit_change.try_fetch(repo_path).map(|(diff, _)| diff),
+        None => try_parse_diff(diff_arg, repo_path),
+    }
+}
+
+/// The cache entry for the export, when the export is all that is asked for and --no-cache is not given.
+fn try_result_cache(matches: &ArgMatches, config: &Config, params: &DiffGraphParams, output: &OutputOptions) -> Result<Option<ResultCache>, DiffGraphError> {
+    // Side effects such as posting comments happen after the export, so a cached export would skip them
+    let side_effects = output.comment.is_some() || output.review.is_some() || output.open || output.notify_url.is_some() || params.dump_trees.is_some();
+    if matches.get_flag("no-cache") || output.summary || side_effects {
+        return Ok(None);
+    }
+    let format = match (output.format, &output.target) {
+        (Some(ExportFormat::CiBundle | ExportFormat::MatrixMarket) | None, _) => return Ok(None),
+        // Colored for the terminal, which a cached copy may not be written to again
+        (Some(ExportFormat::Structural), OutputTarget::Stdout) => return Ok(None),
+        (Some(format), _) => format,
+    };
+    let cache_dir = match config.result_cache_dir.clone().or_else(cache::get_default_cache_dir) {
+        Some(cache_dir) => cache_dir,
+        None => return Ok(None),
+    };
+    ResultCache::try_new(&cache_dir, params, format)
+}
+
+/// Subcommands that keep running, and building graphs, until they are stopped.
+const LONG_RUNNING_SUBCOMMANDS: [&str; 3] = ["watch", "serve", "mcp"];
+
+/// Whether the parsed subcommand keeps running until it is stopped.
+pub fn runs_until_stopped(matches: &ArgMatches) -> bool {
+    matches.subcommand_name().is_some_and(|name| LONG_RUNNING_SUBCOMMANDS.contains(&name))
+}
+
+/// Parses the arguments, exiting on usage errors and `--help`. Nothing is cloned or fetched yet,
+/// `get_params` does that.
+pub fn parse_args() -> ArgMatches {
+    let matches = clap::Command::new("diffdiagram")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .after_help(EXIT_CODES_HELP)
+        .arg(Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .global(true)
+            .action(ArgAction::Count)
+            .help("Increase logging verbosity (-v info, -vv debug, -vvv trace)"))
+        .arg(Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .env("DIFFGRAPH_QUIET")
+            .global(true)
+            .conflicts_with("verbose")
+            .action(ArgAction::SetTrue)
+            .help("Only log errors"))
+        .arg(Arg::new("color")
+            .long("color")
+            .env("DIFFGRAPH_COLOR")
+            .global(true)
+            .value_name("WHEN")
+            .value_parser(["auto", "always", "never"])
+            .help("Color output, auto colors terminals unless NO_COLOR is set [default: auto]"))
+        .arg(Arg::new("error-format")
+            .long("error-format")
+            .env("DIFFGRAPH_ERROR_FORMAT")
+            .global(true)
+            .value_name("FORMAT")
+            .value_parser(["text", "json"])
+            .help("Emit errors on stderr as plain text or structured JSON [default: text]"))
+        .arg(Arg::new("config")
+            .long("config")
+            .env("DIFFGRAPH_CONFIG")
+            .global(true)
+            .value_name("PATH")
+            .help("Read default options from this config file instead of ~/.config/diffgraph/config.toml"))
+        .arg(Arg::new("threads")
+            .short('j')
+            .long("threads")
+            .env("DIFFGRAPH_THREADS")
+            .global(true)
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .help("Number of worker threads for parsing and grammar installation [default: one per CPU]"))
+        .arg(Arg::new("parser-config")
+            .long("parser-config")
+            .env("DIFFGRAPH_PARSER_DIR")
+            .global(true)
+            .value_name("PATH")
+            .help("Directory or parsers.json file with the tree-sitter parser configuration"))
+        .arg(repository_arg())
         .arg(Arg::new("clone")
-            .requires("repo")
             .short('c')
             .long("clone-path")
+            .env("DIFFGRAPH_CLONE_PATH")
             .value_name("PATH")
             .help("Specify a clone path for the diff repository to clone to"))
-        .arg(Arg::new("diff")
-            .short('d')
-            .long("diff")
-            .value_name("PATCH FILE or GIT REVISIONS")
-            .required(true)
-            .help("Specify diff patch file or git revision to create a diff"))
+        .arg(diff_arg())
         .arg(Arg::new("install-missing")
             .short('i')
             .long("install-missing")
+            .env("DIFFGRAPH_INSTALL_MISSING")
+            .global(true)
             .action(ArgAction::SetTrue)
             .help("Install missing tree-sitter parsers automatically"))
-        .get_matches();
+        .arg(Arg::new("grammar-cache-dir")
+            .long("grammar-cache-dir")
+            .env("DIFFGRAPH_GRAMMAR_CACHE_DIR")
+            .value_name("DIR")
+            .global(true)
+            .help("Directory to cache compiled grammars in, which can be shared between machines"))
+        .arg(Arg::new("exclude-kind")
+            .long("exclude-kind")
+            .env("DIFFGRAPH_EXCLUDE_KIND")
+            .global(true)
+            .value_name("KIND")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .help("Leave tree-sitter nodes of these kinds out of the graph, may be repeated or comma separated"))
+        .arg(Arg::new("share-subtrees")
+            .long("share-subtrees")
+            .env("DIFFGRAPH_SHARE_SUBTREES")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Merge structurally identical unchanged subtrees, such as repeated imports, into the nodes of the first one"))
+        .arg(Arg::new("full-tree")
+            .long("full-tree")
+            .env("DIFFGRAPH_FULL_TREE")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Graph every node of the patched files, not only the changed subtrees and the declarations enclosing them"))
+        .arg(Arg::new("ignore-formatting")
+            .long("ignore-formatting")
+            .env("DIFFGRAPH_IGNORE_FORMATTING")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Leave out hunks that only change whitespace, layout or comments, so reformatting does not drown out real changes"))
+        .arg(Arg::new("security-queries")
+            .long("security-queries")
+            .env("DIFFGRAPH_SECURITY_QUERIES")
+            .value_name("DIR")
+            .global(true)
+            .help("Directory of tree-sitter queries named after their language, such as python.scm, tagging security-sensitive patterns along with the shipped ones"))
+        .arg(Arg::new("debt-queries")
+            .long("debt-queries")
+            .env("DIFFGRAPH_DEBT_QUERIES")
+            .value_name("DIR")
+            .global(true)
+            .help("Directory of tree-sitter queries named after their language, such as python.scm, tagging TODOs, panics and similar debt along with the shipped ones"))
+        .arg(Arg::new("architecture-rules")
+            .long("architecture-rules")
+            .env("DIFFGRAPH_ARCHITECTURE_RULES")
+            .value_name("FILE")
+            .global(true)
+            .help("File of dependencies the patch may not add, a rule per line such as 'ui/ !-> db/', reported as architecture-violations"))
+        .arg(Arg::new("symbol-index")
+            .long("symbol-index")
+            .env("DIFFGRAPH_SYMBOL_INDEX")
+            .value_name("FILE")
+            .global(true)
+            .help("Symbol index built by the index command, resolving names the patch uses to definitions outside of it [default: the repository's, if built]"))
+        .arg(Arg::new("coverage")
+            .long("coverage")
+            .env("DIFFGRAPH_COVERAGE")
+            .value_name("FILE")
+            .global(true)
+            .help("lcov tracefile or Cobertura XML report of the patched code, listing the changed lines no test runs in the summary and annotations"))
+        .arg(Arg::new("dump-trees")
+            .long("dump-trees")
+            .env("DIFFGRAPH_DUMP_TREES")
+            .value_name("DIR")
+            .global(true)
+            .help("Write the trees of each patched file before and after the patch to DIR/<path>.dot, with the changed nodes highlighted, for debugging grammars and queries"))
+        .arg(Arg::new("path")
+            .long("path")
+            .env("DIFFGRAPH_PATH")
+            .global(true)
+            .value_name("PATTERN")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .help("Only analyze patched files matching this git pathspec style glob, or not matching it when prefixed with ! or :!, may be repeated or comma separated"))
+        .arg(Arg::new("language")
+            .long("language")
+            .env("DIFFGRAPH_LANGUAGE")
+            .global(true)
+            .value_name("LANGUAGE")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .help("Only analyze files in these languages, such as rust,python, skipping everything else"))
+        .arg(Arg::new("max-files")
+            .long("max-files")
+            .env("DIFFGRAPH_MAX_FILES")
+            .global(true)
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .help("Analyze at most N patched files"))
+        .arg(Arg::new("max-nodes")
+            .long("max-nodes")
+            .env("DIFFGRAPH_MAX_NODES")
+            .global(true)
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .help("Stop building the graph once it holds N nodes"))
+        .arg(Arg::new("max-bytes")
+            .long("max-bytes")
+            .env("DIFFGRAPH_MAX_BYTES")
+            .global(true)
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .help("Skip source files larger than N bytes"))
+        .arg(Arg::new("max-depth")
+            .long("max-depth")
+            .env("DIFFGRAPH_MAX_DEPTH")
+            .global(true)
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .help("Leave out nodes more than N levels deep in a file's tree"))
+        .arg(Arg::new("max-children")
+            .long("max-children")
+            .env("DIFFGRAPH_MAX_CHILDREN")
+            .global(true)
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .help("Only walk the first N children of each node, for minified or generated files"))
+        .arg(Arg::new("best-effort")
+            .long("best-effort")
+            .env("DIFFGRAPH_BEST_EFFORT")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Leave out hunks and files the patch does not apply to, reporting them as degraded, instead of failing"))
+        .arg(Arg::new("strict")
+            .long("strict")
+            .env("DIFFGRAPH_STRICT")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Fail on the first file that cannot be read, applied or parsed, instead of leaving it out and reporting it"))
+        .arg(Arg::new("shard")
+            .long("shard")
+            .env("DIFFGRAPH_SHARD")
+            .global(true)
+            .value_name("INDEX/COUNT")
+            .help("Only graph this share of the patch's files, such as 2/4, for the graphs of every shard to be combined with merge"))
+        .arg(Arg::new("on-limit")
+            .long("on-limit")
+            .env("DIFFGRAPH_ON_LIMIT")
+            .global(true)
+            .value_name("ACTION")
+            .value_parser(["truncate", "fail"])
+            .help("Truncate the graph or fail when a --max-* limit is exceeded [default: truncate]"))
+        .arg(Arg::new("fail-on")
+            .long("fail-on")
+            .env("DIFFGRAPH_FAIL_ON")
+            .global(true)
+            .value_name("CONDITION")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .value_parser(Condition::ALL.map(|c| c.as_str()))
+            .help("Fail the run on these conditions [default: unknown-language]"))
+        .arg(Arg::new("warn-on")
+            .long("warn-on")
+            .env("DIFFGRAPH_WARN_ON")
+            .global(true)
+            .value_name("CONDITION")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .value_parser(Condition::ALL.map(|c| c.as_str()))
+            .help("Only warn on these conditions, others not failing the run are logged at debug level [default: all]"))
+        .arg(Arg::new("check")
+            .long("check")
+            .env("DIFFGRAPH_CHECK")
+            .action(ArgAction::SetTrue)
+            .help("Verify the repository, revisions, patch and grammars for every file without building the graph"))
+        .arg(Arg::new("stream")
+            .long("stream")
+            .env("DIFFGRAPH_STREAM")
+            .conflicts_with_all(["check", "summary", "format", "open", "post-comment", "post-review"])
+            .action(ArgAction::SetTrue)
+            .help("Parse one file at a time and write its graph, pruned to the changed subtrees, as a JSON line before the next, for patches too large to hold in memory"))
+        .arg(Arg::new("timeout")
+            .long("timeout")
+            .env("DIFFGRAPH_TIMEOUT")
+            .global(true)
+            .value_name("SECONDS")
+            .value_parser(clap::value_parser!(u64))
+            .help("Cancel the run after this many seconds, failing with exit code 10. Nothing is written then, except by --stream which has written the files parsed before. Not taken by watch, serve and mcp"))
+        .arg(Arg::new("timings")
+            .long("timings")
+            .env("DIFFGRAPH_TIMINGS")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Report wall time per phase and the slowest files on stderr"))
+        .arg(Arg::new("stats")
+            .long("stats")
+            .env("DIFFGRAPH_STATS")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Report peak memory, graph size, interned strings and cache hit rates on stderr"))
+        .arg(Arg::new("output")
+            .short('o')
+            .long("output")
+            .env("DIFFGRAPH_OUTPUT")
+            .global(true)
+            .value_name("PATH")
+            .help("Write the graph to this file, or directory for ci-bundle, or - for stdout [default: - when --format is given]"))
+        .arg(Arg::new("format")
+            .long("format")
+            .env("DIFFGRAPH_FORMAT")
+            .global(true)
+            .value_name("FORMAT")
+            .value_parser(ExportFormat::ALL.map(|f| f.as_str()))
+            .help("Format to export the graph in [default: dot for .dot outputs, matrix-market for .mtx outputs, sarif for .sarif outputs, json otherwise]"))
+        .arg(Arg::new("compress")
+            .long("compress")
+            .env("DIFFGRAPH_COMPRESS")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Gzip the json, annotations, matrix-market, sarif, api-changes or --stream output [default: on for .gz outputs]"))
+        .arg(Arg::new("summary")
+            .long("summary")
+            .env("DIFFGRAPH_SUMMARY")
+            .global(true)
+            .conflicts_with_all(["output", "format"])
+            .action(ArgAction::SetTrue)
+            .help("Print a table of files, languages, changed declarations, graph size and parse errors instead of the graph"))
+        .arg(Arg::new("open")
+            .long("open")
+            .env("DIFFGRAPH_OPEN")
+            .conflicts_with("summary")
+            .action(ArgAction::SetTrue)
+            .help("Open the graph in xdot or the browser once it is exported"))
+        .arg(Arg::new("no-cache")
+            .long("no-cache")
+            .env("DIFFGRAPH_NO_CACHE")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Analyze the diff even when the same export of it is already cached"))
+        .arg(Arg::new("notify-url")
+            .long("notify-url")
+            .env("DIFFGRAPH_NOTIFY_URL")
+            .global(true)
+            .value_name("URL")
+            .help("POST the JSON export, or the summary with --summary, to this webhook when the analysis finishes"))
+        .arg(Arg::new("post-comment")
+            .long("post-comment")
+            .env("DIFFGRAPH_POST_COMMENT")
+            .value_name("PULL_REQUEST")
+            .conflicts_with("summary")
+            .help("Post the pr-comment markdown on this GitHub pull request URL"))
+        .arg(Arg::new("github-token")
+            .long("github-token")
+            .env("GITHUB_TOKEN")
+            .hide_env_values(true)
+            .value_name("TOKEN")
+            .help("Token to post the pull request comment with"))
+        .arg(Arg::new("post-review")
+            .long("post-review")
+            .env("DIFFGRAPH_POST_REVIEW")
+            .conflicts_with("summary")
+            .action(ArgAction::SetTrue)
+            .help("Post the summary as a review comment on the Gerrit change given as --diff"))
+        .arg(Arg::new("gerrit-user")
+            .long("gerrit-user")
+            .env("GERRIT_USER")
+            .value_name("USER")
+            .help("User to post the Gerrit review as"))
+        .arg(Arg::new("gerrit-password")
+            .long("gerrit-password")
+            .env("GERRIT_HTTP_PASSWORD")
+            .hide_env_values(true)
+            .value_name("PASSWORD")
+            .help("HTTP password of the Gerrit user"))
+        .subcommand(clap::Command::new("grammars")
+            .about("Inspect the configured tree-sitter grammars")
+            .subcommand_required(true)
+            .subcommand(clap::Command::new("which")
+                .about("Show which language configuration and parser directory would be used for a file")
+                .arg(Arg::new("file")
+                    .value_name("FILE")
+                    .required(true)
+                    .help("File name or path to look up")))
+            .subcommand(clap::Command::new("sync-registry")
+                .about("Merge parsers from the upstream tree-sitter parser list into parsers.json")))
+        .subcommand(clap::Command::new("analyze")
+            .about("Build the graph of a diff and report on it")
+            .subcommand_required(true)
+            .subcommands(Analysis::ALL.map(analysis_command)))
+        .subcommand(clap::Command::new("merge")
+            .about("Combine the JSON graphs of every --shard of a patch into the graph of the whole patch")
+            .arg(Arg::new("graphs")
+                .value_name("GRAPH")
+                .required(true)
+                .num_args(1..)
+                .help("JSON graphs of the shards, gzip compressed or not")))
+        .subcommand(clap::Command::new("watch")
+            .about("Rebuild the graph of uncommitted changes whenever a file in the repository changes")
+            .arg(repository_arg()))
+        .subcommand(clap::Command::new("serve")
+            .about("Answer editor queries about uncommitted changes with JSON-RPC over stdio")
+            .arg(repository_arg())
+            .arg(Arg::new("grpc")
+                .long("grpc")
+                .value_name("ADDRESS")
+                .value_parser(clap::value_parser!(SocketAddr))
+                .help("Serve the gRPC API in proto/diffgraph.proto on this address instead, such as 127.0.0.1:50051"))
+            .arg(Arg::new("http")
+                .long("http")
+                .value_name("ADDRESS")
+                .value_parser(clap::value_parser!(SocketAddr))
+                .conflicts_with("grpc")
+                .help("Serve the REST API on this address instead, such as 127.0.0.1:8080")))
+        .subcommand(clap::Command::new("mcp")
+            .about("Serve the graph as Model Context Protocol tools over stdio, for LLM agents")
+            .arg(repository_arg()))
+        .subcommand(clap::Command::new("bench")
+            .about("Build the graph of a diff several times and report the time per phase, the throughput and the peak memory")
+            .arg(repository_arg())
+            .arg(diff_arg())
+            .arg(Arg::new("iterations")
+                .short('n')
+                .long("iterations")
+                .value_name("N")
+                .default_value("5")
+                .value_parser(clap::value_parser!(usize))
+                .help("Number of times to run the pipeline")))
+        .subcommand(clap::Command::new("index")
+            .about("Index the definitions of every file of the repository, which analyses consult to resolve names defined outside of the diff. Written to the repository's git directory unless --output is given")
+            .arg(repository_arg()))
+        .subcommand(clap::Command::new("compare-revs")
+            .about("Graph two diffs and report the changed declarations they share and those only one of them changes, such as to check that a backport or cherry-pick touches the same code as the original")
+            .arg(repository_arg())
+            .arg(Arg::new("diff")
+                .value_name("FIRST")
+                .required(true)
+                .help("The first diff, as git revisions (a..b) or anything else --diff takes"))
+            .arg(Arg::new("other")
+                .value_name("SECOND")
+                .required(true)
+                .help("The diff to compare with the first, such as the backport of it, as anything --diff takes including GitHub and Gerrit URLs")))
+        .subcommand(clap::Command::new("equivalent")
+            .about("Exit with 0 only if every changed file's tree is the same before and after the patch, up to whitespace, layout and comments, such as after running a formatter")
+            .arg(repository_arg())
+            .arg(diff_arg()))
+        .try_get_matches();
 
-    let clone_path = matches.get_one::<String>("clone");
-    let repo_arg = matches.get_one::<String>("repo").unwrap();
-    let repository_path;
-    match try_parse_repo(repo_arg, clone_path.cloned()) {
-        Ok(Some(repo)) => {
-            println!("Repository path: {:?}", repo);
-            repository_path = repo;
+    // clap exits with 2 on usage errors, which we reserve for parse failures
+    match matches {
+        Ok(matches) => matches,
+        Err(e) if e.use_stderr() => {
+            let _ = e.print();
+            std::process::exit(ErrorCode::Usage.exit_code() as i32);
+        },
+        Err(e) => e.exit(),
+    }
+}
+
+pub fn get_params(matches: &ArgMatches) -> Result<CliCommand, DiffGraphError> {
+
+    color::set_choice(match matches.get_one::<String>("color").map(|c| c.as_str()) {
+        Some("always") => ColorChoice::Always,
+        Some("never") => ColorChoice::Never,
+        _ => ColorChoice::Auto,
+    });
+    init_logging(matches.get_count("verbose"), matches.get_flag("quiet"), color::is_enabled(Stream::Stderr));
+    progress::set_enabled(!matches.get_flag("quiet"));
+    timings::set_enabled(matches.get_flag("timings"));
+    stats::set_enabled(matches.get_flag("stats"));
+    if let Some(timeout) = matches.get_one::<u64>("timeout") {
+        // The deadline is one for the whole process, which would cancel every job after it
+        if runs_until_stopped(matches) {
+            return Err(DiffGraphError::Usage(format!("--timeout cannot be used with {}, which runs until stopped",
+                matches.subcommand_name().unwrap_or_default())));
+        }
+        cancel::set_timeout(Duration::from_secs(*timeout));
+    }
+    let set_error_format = |format: Option<&str>| match format {
+        Some("json") => error::set_format(ErrorFormat::Json),
+        _ => error::set_format(ErrorFormat::Text),
+    };
+    set_error_format(matches.get_one::<String>("error-format").map(|f| f.as_str()));
+
+    let config_path = matches.get_one::<String>("config").map(PathBuf::from);
+    let config = Config::try_load(config_path.as_deref())?;
+    if !matches.contains_id("error-format") {
+        set_error_format(config.error_format.as_deref());
+    }
+
+    let threads = matches.get_one::<usize>("threads").copied().or(config.threads).unwrap_or(0);
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+        .map_err(|e| DiffGraphError::Usage(e.to_string()))?;
+
+    let parser_config_path = matches.get_one::<String>("parser-config")
+        .map(PathBuf::from)
+        .or(config.parser_config.clone());
+
+    if let Some(("grammars", grammars_matches)) = matches.subcommand() {
+        return match grammars_matches.subcommand() {
+            Some(("which", which_matches)) => Ok(CliCommand::GrammarsWhich {
+                file: which_matches.get_one::<String>("file").unwrap().into(),
+                parser_config_path,
+            }),
+            Some(("sync-registry", _)) => Ok(CliCommand::GrammarsSyncRegistry { parser_config_path }),
+            _ => Err(DiffGraphError::Usage("Unknown grammars subcommand".into())),
+        };
+    }
+
+    let install_lang_if_missing = matches.get_flag("install-missing") || config.install_missing.unwrap_or(false);
+    let grammar_cache_dir = matches.get_one::<String>("grammar-cache-dir").map(PathBuf::from).or(config.grammar_cache_dir.clone());
+    let exclude_kinds = match matches.get_many::<String>("exclude-kind") {
+        Some(kinds) => kinds.cloned().collect(),
+        None => config.exclude_kinds.clone(),
+    };
+
+    let path_patterns: Vec<String> = match matches.get_many::<String>("path") {
+        Some(patterns) => patterns.cloned().collect(),
+        None => config.paths.clone(),
+    };
+    let languages = match matches.get_many::<String>("language") {
+        Some(languages) => languages.cloned().collect(),
+        None => config.languages.clone(),
+    };
+    let path_filter = PathFilter::try_new(&path_patterns)?;
+
+    let on_limit = matches.get_one::<String>("on-limit").cloned().or(config.on_limit.clone());
+    let limits = Limits {
+        max_files: matches.get_one::<usize>("max-files").copied().or(config.max_files),
+        max_nodes: matches.get_one::<usize>("max-nodes").copied().or(config.max_nodes),
+        max_bytes: matches.get_one::<usize>("max-bytes").copied().or(config.max_bytes),
+        max_depth: matches.get_one::<usize>("max-depth").copied().or(config.max_depth),
+        max_children: matches.get_one::<usize>("max-children").copied().or(config.max_children),
+        action: match on_limit.as_deref() {
+            Some("fail") => LimitAction::Fail,
+            _ => LimitAction::Truncate,
         },
-        Ok(None) => return Err(format!("No repository found at {}", repo_arg)),
-        Err(e) => return Err(e.to_string()),
     };
-    
-    let diff_arg = matches.get_one::<String>("diff").unwrap();
-    let diff;
-    match try_parse_diff(diff_arg, &repository_path) {
-        Ok(parsed_diff) => diff = parsed_diff,
-        Err(err) => return Err(err.to_string()),
+
+    let parse_conditions = |id: &str, configured: &Option<Vec<String>>| -> Result<Option<Vec<Condition>>, DiffGraphError> {
+        let conditions: Option<Vec<String>> = matches.get_many::<String>(id)
+            .map(|values| values.cloned().collect())
+            .or(configured.clone());
+        conditions
+            .map(|conditions| conditions
+                .iter()
+                .map(|c| c.parse())
+                .collect::<Result<Vec<Condition>, String>>())
+            .transpose()
+            .map_err(DiffGraphError::Usage)
+    };
+    let default_policy = Policy::default();
+    let policy = Policy {
+        fail_on: parse_conditions("fail-on", &config.fail_on)?.unwrap_or(default_policy.fail_on),
+        warn_on: parse_conditions("warn-on", &config.warn_on)?.unwrap_or(default_policy.warn_on),
     };
 
-    let install_lang_if_missing = matches.get_flag("install-missing");
+    let target = matches.get_one::<String>("output").map(|o| OutputTarget::from_arg(o));
+    let is_gzip = |path: &Path| path.extension().is_some_and(|e| e == "gz");
+    let compress = matches.get_flag("compress") || matches!(&target, Some(OutputTarget::File(path)) if is_gzip(path));
+    // The format of `graph.mtx.gz` is that of `graph.mtx`
+    let format_path = match &target {
+        Some(OutputTarget::File(path)) if is_gzip(path) => Some(path.with_extension("")),
+        Some(OutputTarget::File(path)) => Some(path.clone()),
+        _ => None,
+    };
+    let format = match (matches.get_one::<String>("format"), &target, &format_path) {
+        (Some(format), _, _) => Some(format.parse().map_err(DiffGraphError::Usage)?),
+        (None, _, Some(path)) if path.extension().is_some_and(|e| e == "dot") => Some(ExportFormat::Dot),
+        (None, _, Some(path)) if path.extension().is_some_and(|e| e == "mtx") => Some(ExportFormat::MatrixMarket),
+        (None, _, Some(path)) if path.extension().is_some_and(|e| e == "sarif") => Some(ExportFormat::Sarif),
+        (None, Some(_), _) => Some(ExportFormat::Json),
+        (None, None, _) => None,
+    };
+    if let (true, Some(format)) = (compress, format) {
+        if !format.is_compressible() && !matches.get_flag("stream") {
+            return Err(DiffGraphError::Usage(format!("--compress is for the json, annotations, matrix-market, sarif and api-changes formats and --stream, not {}", format)));
+        }
+    }
+    let mut output = OutputOptions {
+        summary: matches.get_flag("summary"),
+        format,
+        target: target.unwrap_or(OutputTarget::Stdout),
+        compress,
+        comment: match (matches.get_one::<String>("post-comment"), matches.get_one::<String>("github-token")) {
+            (Some(pull_request), Some(token)) => Some(CommentTarget {
+                pull_request: PullRequest::try_parse(pull_request)?,
+                token: token.clone(),
+            }),
+            (Some(_), None) => return Err(DiffGraphError::Usage("--post-comment needs a token from --github-token or GITHUB_TOKEN".into())),
+            (None, _) => None,
+        },
+        review: None,
+        open: matches.get_flag("open"),
+        notify_url: matches.get_one::<String>("notify-url")
+            .map(|url| Url::parse(url)
+                .map(|_| url.clone())
+                .map_err(|e| DiffGraphError::Usage(format!("Invalid --notify-url '{}': {}", url, e))))
+            .transpose()?,
+        cache: None,
+    };
 
-    if let Some(repo_path_str) = repository_path.to_str() { 
-        Ok(DiffGraphParams { 
-            diff_repository_dir: repo_path_str.to_string(),
-            diff, 
-            install_lang_if_missing,
-            save_default_if_missing: true,
-        })
-    } else {
-        Err(format!("Unable to convert repository path: {}", repository_path.display()))
+    if let Some(("merge", merge_matches)) = matches.subcommand() {
+        if output.format.is_some_and(|format| format != ExportFormat::Json) {
+            return Err(DiffGraphError::Usage("merge writes the json format only".into()));
+        }
+        return Ok(CliCommand::Merge {
+            inputs: merge_matches.get_many::<String>("graphs").unwrap().map(PathBuf::from).collect(),
+            target: output.target,
+            compress: output.compress,
+        });
+    }
+
+    let (repository_path, diff) = match matches.subcommand() {
+        Some(("watch" | "serve" | "mcp" | "index", subcommand_matches)) => {
+            let repository_path = try_get_repository_path(subcommand_matches.get_one::<String>("repo"), None, &config)?;
+            (repository_path, None)
+        },
+        subcommand => {
+            // Benchmarks and analyses name their diff and repository after the subcommand
+            let diff_matches = match subcommand {
+                Some(("bench" | "equivalent" | "compare-revs", subcommand_matches)) => subcommand_matches,
+                Some(("analyze", analyze_matches)) => analyze_matches.subcommand().map_or(matches, |(_, analysis_matches)| analysis_matches),
+                _ => matches,
+            };
+            let diff_arg = diff_matches.get_one::<String>("diff").unwrap();
+            let github_diff = GitHubDiff::try_parse(diff_arg);
+            let gerrit_change = GerritChange::try_parse(diff_arg);
+            // The URL names the repository, which only an explicit --repository overrides
+            let repo_arg = diff_matches.get_one::<String>("repo")
+                .or(github_diff.as_ref().map(|github_diff| &github_diff.repository_url))
+                .or(gerrit_change.as_ref().and_then(|gerrit_change| gerrit_change.repository_url.as_ref()));
+            let repository_path = try_get_repository_path(repo_arg, matches.get_one::<String>("clone"), &config)?;
+            let diff = timings::phase("diff", || match (&github_diff, &gerrit_change) {
+                    (Some(github_diff), _) => github_diff.try_fetch(&repository_path),
+                    (None, Some(gerrit_change)) => {
+                        let (diff, patchset) = gerrit_change.try_fetch(&repository_path)?;
+                        if matches.get_flag("post-review") {
+                            let credentials = match (matches.get_one::<String>("gerrit-user"), matches.get_one::<String>("gerrit-password")) {
+                                (Some(user), Some(password)) => (user.clone(), password.clone()),
+                                _ => return Err(DiffGraphError::Usage("--post-review needs --gerrit-user and --gerrit-password, or GERRIT_USER and GERRIT_HTTP_PASSWORD".into())),
+                            };
+                            output.review = Some(gerrit_change.try_review_target(&repository_path, patchset, credentials)?);
+                        }
+                        Ok(diff)
+                    },
+                    (None, None) if matches.get_flag("post-review") => Err(DiffGraphError::Usage("--post-review needs a Gerrit change as --diff".into())),
+                    (None, None) => try_parse_diff(diff_arg, &repository_path),
+                })?;
+            (repository_path, Some(diff))
+        },
+    };
+
+    let diff_repository_dir = match repository_path.to_str() {
+        Some(repo_path_str) => repo_path_str.to_string(),
+        None => return Err(DiffGraphError::Usage(format!("Unable to convert repository path: {}", repository_path.display()))),
+    };
+    let mut params = DiffGraphParams { 
+        diff_repository_dir,
+        install_lang_if_missing,
+        parser_config_path,
+        grammar_cache_dir,
+        exclude_kinds,
+        limits,
+        policy,
+        path_filter,
+        languages,
+        share_subtrees: matches.get_flag("share-subtrees"),
+        full_tree: matches.get_flag("full-tree") || config.full_tree.unwrap_or(false),
+        ignore_formatting: matches.get_flag("ignore-formatting") || config.ignore_formatting.unwrap_or(false),
+        security_queries: matches.get_one::<String>("security-queries").map(PathBuf::from).or(config.security_queries.clone()),
+        debt_queries: matches.get_one::<String>("debt-queries").map(PathBuf::from).or(config.debt_queries.clone()),
+        architecture_rules: matches.get_one::<String>("architecture-rules").map(PathBuf::from).or(config.architecture_rules.clone()),
+        symbol_index: matches.get_one::<String>("symbol-index")
+            .map(PathBuf::from)
+            .or(config.symbol_index.clone())
+            .or_else(|| index::get_default_path(&repository_path).filter(|path| path.exists())),
+        coverage: matches.get_one::<String>("coverage").map(PathBuf::from).or(config.coverage.clone()),
+        dump_trees: matches.get_one::<String>("dump-trees").map(PathBuf::from),
+        best_effort: matches.get_flag("best-effort"),
+        strict: matches.get_flag("strict"),
+        shard: matches.get_one::<String>("shard")
+            .map(|shard| shard.parse().map_err(DiffGraphError::Usage))
+            .transpose()?,
+        ..Default::default()
+    };
+
+    match diff {
+        Some(diff) => {
+            params.diff = diff.patch;
+            params.source_revision = diff.source_revision;
+            params.git_dir = diff.git_dir;
+            params.binary_files = diff.binary_files;
+            if matches.subcommand_name() == Some("equivalent") {
+                Ok(CliCommand::Equivalent(params))
+            } else if let Some(("compare-revs", compare_matches)) = matches.subcommand() {
+                let json = match output.format {
+                    None => false,
+                    Some(ExportFormat::Json) => true,
+                    Some(format) => return Err(DiffGraphError::Usage(format!("compare-revs writes text or json, not {}", format))),
+                };
+                let other_arg = compare_matches.get_one::<String>("other").unwrap();
+                let other = timings::phase("diff", || try_resolve_diff(other_arg, &repository_path))?;
+                Ok(CliCommand::CompareRevs { params, other, json, target: output.target })
+            } else if let Some(("bench", bench_matches)) = matches.subcommand() {
+                Ok(CliCommand::Bench(BenchParams {
+                    graph_params: params,
+                    iterations: *bench_matches.get_one::<usize>("iterations").unwrap(),
+                }))
+            } else if let Some(("analyze", analyze_matches)) = matches.subcommand() {
+                let analysis = analyze_matches.subcommand_name().and_then(Analysis::from_name)
+                    .ok_or_else(|| DiffGraphError::Usage("Unknown analysis".into()))?;
+                let json = match output.format {
+                    None => false,
+                    Some(ExportFormat::Json) => true,
+                    Some(format) => return Err(DiffGraphError::Usage(format!("Analyses are written as text or json, not {}", format))),
+                };
+                // Options are only defined on the analyses they apply to
+                let analysis_matches = analyze_matches.subcommand_matches(analysis.as_str());
+                let complexity_threshold = match (analysis, analysis_matches) {
+                    (Analysis::Complexity, Some(analysis_matches)) => analysis_matches.get_one::<usize>("threshold").copied(),
+                    _ => None,
+                }.unwrap_or(complexity::DEFAULT_THRESHOLD);
+                let min_similarity = match (analysis, analysis_matches) {
+                    (Analysis::Duplicates, Some(analysis_matches)) => analysis_matches.get_one::<f64>("min-similarity").copied(),
+                    _ => None,
+                }.unwrap_or(duplicates::DEFAULT_SIMILARITY);
+                if !(0.0..=1.0).contains(&min_similarity) {
+                    return Err(DiffGraphError::Usage(format!("--min-similarity must be between 0 and 1, not {}", min_similarity)));
+                }
+                let revisions = match (analysis, analysis_matches) {
+                    (Analysis::Hotspots, Some(analysis_matches)) => analysis_matches.get_one::<String>("diff").cloned(),
+                    (Analysis::Churn, Some(analysis_matches)) => analysis_matches.get_one::<String>("history").cloned(),
+                    _ => None,
+                };
+                let other_diff = match (analysis, analysis_matches) {
+                    (Analysis::Conflicts, Some(analysis_matches)) => analysis_matches.get_one::<String>("other-diff")
+                        .map(|other_diff| timings::phase("diff", || try_parse_diff(other_diff, &repository_path)))
+                        .transpose()?,
+                    _ => None,
+                };
+                let (authors, author) = match (analysis, analysis_matches) {
+                    (Analysis::Ownership, Some(analysis_matches)) => (
+                        analysis_matches.get_one::<PathBuf>("authors").cloned(),
+                        analysis_matches.get_one::<String>("author").cloned().or_else(|| get_git_user_email(&repository_path)),
+                    ),
+                    _ => (None, None),
+                };
+                Ok(CliCommand::Analyze(AnalyzeParams { graph_params: params, analysis, json, target: output.target, complexity_threshold, min_similarity, revisions, other_diff, authors, author }))
+            } else if matches.get_flag("check") {
+                Ok(CliCommand::Check(params))
+            } else if matches.get_flag("stream") {
+                Ok(CliCommand::Stream { params, target: output.target, compress: output.compress })
+            } else {
+                output.cache = try_result_cache(matches, &config, &params, &output)?;
+                Ok(CliCommand::Graph { params, output })
+            }
+        },
+        None if matches!(matches.subcommand(), Some(("serve", _))) => {
+            let serve_matches = matches.subcommand_matches("serve");
+            let address = |id: &str| serve_matches.and_then(|serve_matches| serve_matches.get_one::<SocketAddr>(id)).copied();
+            let transport = match (address("grpc"), address("http")) {
+                (Some(address), _) => Transport::Grpc(address),
+                (None, Some(address)) => Transport::Http(address),
+                (None, None) => Transport::Stdio,
+            };
+            Ok(CliCommand::Serve(ServerParams {
+                repository_dir: repository_path,
+                graph_params: params,
+                transport,
+            }))
+        },
+        None if matches!(matches.subcommand(), Some(("index", _))) => {
+            let target = match output.target {
+                OutputTarget::File(path) => Some(path),
+                OutputTarget::Stdout => index::get_default_path(&repository_path),
+            };
+            let Some(target) = target else {
+                return Err(DiffGraphError::Usage(format!("{} is not a git repository, give the index an --output", repository_path.display())));
+            };
+            Ok(CliCommand::Index { params, target })
+        },
+        None if matches!(matches.subcommand(), Some(("mcp", _))) => Ok(CliCommand::Mcp(McpParams {
+            repository_dir: repository_path,
+            graph_params: params,
+        })),
+        None => Ok(CliCommand::Watch(WatchParams {
+            repository_dir: repository_path,
+            graph_params: params,
+            output,
+        })),
     }
 }
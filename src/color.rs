@@ -0,0 +1,89 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// When to color output, following `--color` and the NO_COLOR convention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color terminals unless NO_COLOR is set
+    Auto = 0,
+    Always = 1,
+    Never = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Bold,
+    Dim,
+    Red,
+    Green,
+    Yellow,
+    Cyan,
+}
+
+impl Style {
+    fn code(&self) -> &'static str {
+        match self {
+            Style::Bold => "1",
+            Style::Dim => "2",
+            Style::Red => "31",
+            Style::Green => "32",
+            Style::Yellow => "33",
+            Style::Cyan => "36",
+        }
+    }
+}
+
+static COLOR_CHOICE: AtomicU8 = AtomicU8::new(ColorChoice::Auto as u8);
+
+pub fn set_choice(choice: ColorChoice) {
+    COLOR_CHOICE.store(choice as u8, Ordering::Relaxed);
+}
+
+pub fn is_enabled(stream: Stream) -> bool {
+    match COLOR_CHOICE.load(Ordering::Relaxed) {
+        1 => true,
+        2 => false,
+        _ => {
+            let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+            let is_terminal = match stream {
+                Stream::Stdout => std::io::stdout().is_terminal(),
+                Stream::Stderr => std::io::stderr().is_terminal(),
+            };
+            !no_color && is_terminal
+        },
+    }
+}
+
+/// The text wrapped in ANSI codes for the style, or unchanged when the stream is not colored.
+pub fn paint(stream: Stream, style: Style, text: &str) -> String {
+    if is_enabled(stream) {
+        format!("\x1b[{}m{}\x1b[0m", style.code(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// The text without the ANSI codes `paint` adds, for output that leaves the terminal.
+pub fn strip(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip the rest of the sequence, which ends at its command letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
@@ -0,0 +1,217 @@
+use std::fmt::Write;
+
+use url::Url;
+
+use crate::error::DiffGraphError;
+use crate::graph::{ChangeStatus, DiffGraph};
+use crate::policy::Condition;
+use crate::syntax;
+
+/// Declarations changed in a file beyond which the comment flags the file as a large change
+const LARGE_CHANGE_DECLARATIONS: usize = 20;
+
+/// Hidden marker identifying comments written by diffgraph
+const COMMENT_MARKER: &str = "<!-- diffgraph -->";
+
+/// A pull request to comment on, from a `https://github.com/OWNER/REPO/pull/NUMBER` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PullRequest {
+    pub owner: String,
+    pub repository: String,
+    pub number: u64,
+}
+
+impl PullRequest {
+    pub fn try_parse(arg: &str) -> Result<Self, DiffGraphError> {
+        let invalid = || DiffGraphError::Usage(format!("'{}' is not a GitHub pull request URL, expected https://github.com/OWNER/REPO/pull/NUMBER", arg));
+        let url = Url::parse(arg).map_err(|_| invalid())?;
+        if url.host_str() != Some("github.com") {
+            return Err(invalid());
+        }
+        let segments: Vec<&str> = url.path_segments().ok_or_else(invalid)?.collect();
+        match segments.as_slice() {
+            [owner, repository, "pull", number, ..] => Ok(Self {
+                owner: owner.to_string(),
+                repository: repository.trim_end_matches(".git").to_string(),
+                number: number.parse().map_err(|_| invalid())?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn comments_url(&self) -> String {
+        format!("https://api.github.com/repos/{}/{}/issues/{}/comments", self.owner, self.repository, self.number)
+    }
+}
+
+/// Where and as whom to post the comment.
+#[derive(Debug, Clone)]
+pub struct CommentTarget {
+    pub pull_request: PullRequest,
+    pub token: String,
+}
+
+struct DeclarationRow {
+    file: usize,
+    kind: String,
+    name: Option<String>,
+    row: usize,
+    statuses: Vec<ChangeStatus>,
+}
+
+impl DeclarationRow {
+    /// Added or removed when every change inside the declaration is, modified otherwise.
+    fn status(&self) -> &'static str {
+        if self.statuses.iter().all(|s| *s == ChangeStatus::Added) {
+            "added"
+        } else if self.statuses.iter().all(|s| *s == ChangeStatus::Removed) {
+            "removed"
+        } else {
+            "modified"
+        }
+    }
+
+    fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("line {}", self.row + 1),
+        }
+    }
+}
+
+fn collect_declarations(graph: &DiffGraph) -> Vec<DeclarationRow> {
+    let mut rows: Vec<DeclarationRow> = Vec::new();
+    for changed in graph.changed_nodes() {
+        let Some(declaration) = changed.declaration else {
+            continue;
+        };
+        let file = changed.node.file;
+        // Declarations edited on both sides are only identifiable by name, as in Diff::changed_declarations
+        let existing = rows.iter_mut().find(|row| row.file == file && row.kind == declaration.kind && match (&row.name, &declaration.name) {
+            (Some(a), Some(b)) => a == b,
+            _ => row.row == declaration.row,
+        });
+        match existing {
+            Some(row) => row.statuses.push(changed.status),
+            None => rows.push(DeclarationRow {
+                file,
+                kind: declaration.kind,
+                name: declaration.name,
+                row: declaration.row,
+                statuses: vec![changed.status],
+            }),
+        }
+    }
+    rows
+}
+
+fn escape_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+fn escape_mermaid(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+/// A markdown pull request comment: a table of changed declarations, a mermaid diagram of them
+/// clustered by file and notes on what reviewers should look at closely.
+pub fn to_markdown(graph: &DiffGraph) -> String {
+    let declarations = collect_declarations(graph);
+    let file_path = |file: usize| graph.diffs().get(file).map_or("", |diff| diff.source_file_path.as_str());
+    let mut markdown = String::new();
+
+    // Writing to a String cannot fail
+    let _ = writeln!(markdown, "{}", COMMENT_MARKER);
+    let _ = writeln!(markdown, "### Diff graph: {} changed declarations in {} files", declarations.len(), graph.diffs().len());
+    let _ = writeln!(markdown);
+
+    if declarations.is_empty() {
+        let _ = writeln!(markdown, "No declarations changed.");
+    } else {
+        let _ = writeln!(markdown, "| File | Declaration | Kind | Change |");
+        let _ = writeln!(markdown, "| --- | --- | --- | --- |");
+        for declaration in declarations.iter() {
+            let _ = writeln!(markdown, "| `{}` | `{}` | {} | {} |", escape_cell(file_path(declaration.file)),
+                escape_cell(&declaration.label()), escape_cell(&declaration.kind), declaration.status());
+        }
+
+        let _ = writeln!(markdown);
+        let _ = writeln!(markdown, "<details><summary>Changed declarations by file</summary>");
+        let _ = writeln!(markdown);
+        let _ = writeln!(markdown, "```mermaid");
+        let _ = writeln!(markdown, "flowchart LR");
+        for (file, diff) in graph.diffs().iter().enumerate() {
+            if !declarations.iter().any(|declaration| declaration.file == file) {
+                continue;
+            }
+            let _ = writeln!(markdown, "  subgraph f{}[\"{}\"]", file, escape_mermaid(&diff.source_file_path));
+            for (i, declaration) in declarations.iter().enumerate().filter(|(_, d)| d.file == file) {
+                let _ = writeln!(markdown, "    d{}[\"{} {}\"]:::{}", i, escape_mermaid(&declaration.kind),
+                    escape_mermaid(&declaration.label()), declaration.status());
+            }
+            let _ = writeln!(markdown, "  end");
+        }
+        let _ = writeln!(markdown, "  classDef added fill:#dafbe1,stroke:#1a7f37");
+        let _ = writeln!(markdown, "  classDef removed fill:#ffebe9,stroke:#cf222e");
+        let _ = writeln!(markdown, "  classDef modified fill:#fff8c5,stroke:#9a6700");
+        let _ = writeln!(markdown, "```");
+        let _ = writeln!(markdown);
+        let _ = writeln!(markdown, "</details>");
+    }
+
+    let notes = risk_notes(graph, &declarations);
+    if !notes.is_empty() {
+        let _ = writeln!(markdown);
+        let _ = writeln!(markdown, "#### Risk notes");
+        let _ = writeln!(markdown);
+        for note in notes {
+            let _ = writeln!(markdown, "- {}", note);
+        }
+    }
+
+    markdown
+}
+
+fn risk_notes(graph: &DiffGraph, declarations: &[DeclarationRow]) -> Vec<String> {
+    let mut notes = Vec::new();
+
+    let removed = declarations.iter().filter(|declaration| declaration.status() == "removed").count();
+    if removed > 0 {
+        notes.push(format!("{} declarations were removed, check that nothing outside the diff still uses them", removed));
+    }
+    for (file, diff) in graph.diffs().iter().enumerate() {
+        let changed = declarations.iter().filter(|declaration| declaration.file == file).count();
+        if changed > LARGE_CHANGE_DECLARATIONS {
+            notes.push(format!("`{}` changes {} declarations, consider splitting it up", diff.source_file_path, changed));
+        }
+        let errors = syntax::count_error_nodes(&diff.target_tree);
+        if errors > 0 {
+            notes.push(format!("`{}` has {} parse errors after the change", diff.source_file_path, errors));
+        }
+    }
+    for finding in graph.findings().iter().filter(|finding| finding.condition != Condition::ParseErrors) {
+        notes.push(finding.message.clone());
+    }
+    for skipped in graph.skipped().iter() {
+        notes.push(format!("`{}` was not analyzed ({})", skipped.path, skipped.reason));
+    }
+    notes
+}
+
+/// Posts the comment on the pull request through the GitHub REST API.
+pub fn try_post(markdown: &str, target: &CommentTarget) -> Result<(), DiffGraphError> {
+    let pull_request = &target.pull_request;
+    let body = serde_json::json!({ "body": markdown }).to_string();
+    reqwest::blocking::Client::new()
+        .post(pull_request.comments_url())
+        .bearer_auth(&target.token)
+        .header("Accept", "application/vnd.github+json")
+        .header("Content-Type", "application/json")
+        .header("User-Agent", "diffgraph")
+        .body(body)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| DiffGraphError::Network(format!("Unable to comment on {}/{}#{}: {}",
+            pull_request.owner, pull_request.repository, pull_request.number, e)))?;
+    Ok(())
+}
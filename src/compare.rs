@@ -0,0 +1,142 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::color::{self, Stream, Style};
+use crate::graph::DiffGraph;
+use crate::parser::ChangedDeclaration;
+
+/// A named declaration changed by one or both of the compared patches.
+#[derive(Debug, Clone, Serialize)]
+pub struct FootprintDeclaration {
+    pub file: String,
+    pub kind: String,
+    pub name: String,
+    /// What the first patch does to the declaration, None if it leaves it alone
+    pub first: Option<String>,
+    /// What the second patch does to the declaration, None if it leaves it alone
+    pub second: Option<String>,
+}
+
+impl fmt::Display for FootprintDeclaration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.kind, color::paint(Stream::Stdout, Style::Bold, &self.name), color::paint(Stream::Stdout, Style::Dim, &self.file))
+    }
+}
+
+/// The changed declarations two patches share and those only one of them changes, such as a
+/// backport or cherry-pick and the original commit. Declarations are matched by file, kind and
+/// name, so unnamed ones are left out.
+#[derive(Debug, Clone, Serialize)]
+pub struct FootprintReport {
+    /// Files both patches change
+    pub shared_files: Vec<String>,
+    /// Files only the first patch changes
+    pub first_only_files: Vec<String>,
+    /// Files only the second patch changes
+    pub second_only_files: Vec<String>,
+    /// Declarations both patches change, in the order of the first patch
+    pub shared: Vec<FootprintDeclaration>,
+    /// Declarations only the first patch changes
+    pub first_only: Vec<FootprintDeclaration>,
+    /// Declarations only the second patch changes
+    pub second_only: Vec<FootprintDeclaration>,
+}
+
+fn describe(declaration: &ChangedDeclaration) -> String {
+    match declaration.change {
+        Some(change) => change.to_string(),
+        None => "changed".to_string(),
+    }
+}
+
+/// Named declarations the patch changes, by file, kind and name, in patch order.
+fn collect_declarations(graph: &DiffGraph) -> Vec<((String, String, String), String)> {
+    graph.diffs()
+        .iter()
+        .flat_map(|diff| diff.changed_declarations().into_iter().filter_map(|declaration| {
+            let change = describe(&declaration);
+            Some(((diff.source_file_path.clone(), declaration.kind, declaration.name?), change))
+        }))
+        .collect()
+}
+
+impl FootprintReport {
+    pub fn from_graphs(first: &DiffGraph, second: &DiffGraph) -> Self {
+        let first_files: BTreeSet<&str> = first.diffs().iter().map(|diff| diff.source_file_path.as_str()).collect();
+        let second_files: BTreeSet<&str> = second.diffs().iter().map(|diff| diff.source_file_path.as_str()).collect();
+        let to_strings = |files: Vec<&&str>| files.into_iter().map(|file| file.to_string()).collect();
+
+        let first_declarations = collect_declarations(first);
+        let second_declarations = collect_declarations(second);
+        let to_declaration = |(file, kind, name): &(String, String, String), first: Option<&String>, second: Option<&String>| FootprintDeclaration {
+            file: file.clone(),
+            kind: kind.clone(),
+            name: name.clone(),
+            first: first.cloned(),
+            second: second.cloned(),
+        };
+
+        let mut shared = Vec::new();
+        let mut first_only = Vec::new();
+        for (key, change) in first_declarations.iter() {
+            match second_declarations.iter().find(|(other, _)| other == key) {
+                Some((_, other_change)) => shared.push(to_declaration(key, Some(change), Some(other_change))),
+                None => first_only.push(to_declaration(key, Some(change), None)),
+            }
+        }
+        let second_only = second_declarations
+            .iter()
+            .filter(|(key, _)| !first_declarations.iter().any(|(other, _)| other == key))
+            .map(|(key, change)| to_declaration(key, None, Some(change)))
+            .collect();
+
+        Self {
+            shared_files: to_strings(first_files.intersection(&second_files).collect()),
+            first_only_files: to_strings(first_files.difference(&second_files).collect()),
+            second_only_files: to_strings(second_files.difference(&first_files).collect()),
+            shared,
+            first_only,
+            second_only,
+        }
+    }
+
+    /// Whether both patches change the same declarations and files.
+    pub fn is_matching(&self) -> bool {
+        self.first_only.is_empty() && self.second_only.is_empty() && self.first_only_files.is_empty() && self.second_only_files.is_empty()
+    }
+}
+
+impl fmt::Display for FootprintReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for declaration in self.shared.iter() {
+            let (first, second) = (declaration.first.as_deref().unwrap_or_default(), declaration.second.as_deref().unwrap_or_default());
+            match first == second {
+                true => writeln!(f, "{} {} ({})", color::paint(Stream::Stdout, Style::Green, "[shared]"), declaration, first)?,
+                false => writeln!(f, "{} {} ({} in the first, {} in the second)", color::paint(Stream::Stdout, Style::Yellow, "[shared]"), declaration, first, second)?,
+            }
+        }
+        for declaration in self.first_only.iter() {
+            writeln!(f, "{} {} ({})", color::paint(Stream::Stdout, Style::Red, "[first only]"), declaration, declaration.first.as_deref().unwrap_or_default())?;
+        }
+        for declaration in self.second_only.iter() {
+            writeln!(f, "{} {} ({})", color::paint(Stream::Stdout, Style::Red, "[second only]"), declaration, declaration.second.as_deref().unwrap_or_default())?;
+        }
+        for (label, files) in [("first only", &self.first_only_files), ("second only", &self.second_only_files)] {
+            for file in files.iter() {
+                writeln!(f, "{} {}", color::paint(Stream::Stdout, Style::Red, &format!("[{} file]", label)), file)?;
+            }
+        }
+
+        if !(self.shared.is_empty() && self.first_only.is_empty() && self.second_only.is_empty()) {
+            writeln!(f)?;
+        }
+        let total = self.shared.len() + self.first_only.len() + self.second_only.len();
+        match self.is_matching() {
+            true => writeln!(f, "The patches change the same {} declarations across {} files", total, self.shared_files.len()),
+            false => writeln!(f, "The patches share {} of {} changed declarations and {} of {} changed files", self.shared.len(), total,
+                self.shared_files.len(), self.shared_files.len() + self.first_only_files.len() + self.second_only_files.len()),
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use serde::Serialize;
+use tree_sitter::{Node as TSNode, Tree};
+
+use crate::color::{self, Stream, Style};
+use crate::graph::DiffGraph;
+use crate::structural::{self, DeclarationKey};
+use crate::syntax;
+
+/// Complexity past which a changed function is flagged, unless `--threshold` says otherwise
+pub const DEFAULT_THRESHOLD: usize = 10;
+
+const HEADERS: [&str; 5] = ["DELTA", "FUNCTION", "FILE", "BEFORE", "AFTER"];
+
+/// Kinds that branch the flow of control, across the grammars of common languages
+const BRANCH_KINDS: [&str; 24] = [
+    "if_statement",
+    "if_expression",
+    "if_let_expression",
+    "elif_clause",
+    "else_if_clause",
+    "for_statement",
+    "for_expression",
+    "for_in_statement",
+    "enhanced_for_statement",
+    "while_statement",
+    "while_expression",
+    "do_statement",
+    "loop_expression",
+    "match_arm",
+    "case_clause",
+    "switch_case",
+    "switch_block_statement_group",
+    "expression_case",
+    "type_case",
+    "catch_clause",
+    "except_clause",
+    "conditional_expression",
+    "ternary_expression",
+    "guard",
+];
+
+/// Short-circuiting operators, each a branch of its own
+const BRANCH_OPERATORS: [&str; 4] = ["&&", "||", "and", "or"];
+
+/// How the complexity of a function changed with the patch.
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionComplexity {
+    pub file: String,
+    pub kind: String,
+    pub name: Option<String>,
+    /// First line of the function, counted from 1, in the patched file unless it was removed
+    pub line: usize,
+    /// Complexity before the patch, None for added functions
+    pub before: Option<usize>,
+    /// Complexity after the patch, None for removed functions
+    pub after: Option<usize>,
+    pub delta: isize,
+    /// Whether the patch took the function past the threshold
+    pub crossed: bool,
+}
+
+impl FunctionComplexity {
+    fn label(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{} {}", self.kind, name),
+            None => self.kind.clone(),
+        }
+    }
+}
+
+/// Changed functions by how much the patch added to their complexity, most first.
+#[derive(Debug, Clone, Serialize)]
+pub struct ComplexityReport {
+    pub threshold: usize,
+    pub functions: Vec<FunctionComplexity>,
+}
+
+/// Cyclomatic complexity approximated by counting branches: one, plus one for every node of
+/// the function that branches, nested functions included.
+pub fn get_complexity(node: &TSNode) -> usize {
+    let mut complexity = 1;
+    let mut cursor = node.walk();
+    'walk: loop {
+        let current = cursor.node();
+        let kind = current.kind();
+        if (current.is_named() && BRANCH_KINDS.contains(&kind)) || (!current.is_named() && BRANCH_OPERATORS.contains(&kind)) {
+            complexity += 1;
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            // A cursor started at the node does not leave its subtree
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+    complexity
+}
+
+/// The innermost named function spanning each row, closures counting towards the function
+/// they are written in.
+fn touched_functions<'t>(tree: &'t Tree, source: &str, rows: &[usize]) -> Vec<TSNode<'t>> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut functions = Vec::new();
+    for row in rows.iter() {
+        let point = syntax::get_line_start(*row, lines.get(*row).unwrap_or(&""));
+        let mut node = tree.root_node().descendant_for_point_range(point, point);
+        while let Some(current) = node {
+            if current.is_named() && syntax::is_function_kind(current.kind()) && syntax::get_declaration_name(&current, source).is_some() {
+                functions.push(current);
+                break;
+            }
+            node = current.parent();
+        }
+    }
+    functions
+}
+
+impl ComplexityReport {
+    pub fn from_graph(graph: &DiffGraph, threshold: usize) -> Self {
+        let mut functions = Vec::new();
+        for diff in graph.diffs().iter() {
+            let old_index = structural::index_declarations(&diff.tree, &diff.source);
+            let new_index = structural::index_declarations(&diff.target_tree, &diff.target_source);
+
+            // Functions are matched between both sides of the patch by kind and qualified name
+            let mut seen: HashSet<DeclarationKey> = HashSet::new();
+            let touched = touched_functions(&diff.target_tree, &diff.target_source, &diff.added_rows)
+                .into_iter()
+                .map(|node| structural::declaration_key(&node, &diff.target_source))
+                .chain(touched_functions(&diff.tree, &diff.source, &diff.removed_rows)
+                    .into_iter()
+                    .map(|node| structural::declaration_key(&node, &diff.source)));
+            for key in touched {
+                if !seen.insert(key.clone()) {
+                    continue;
+                }
+                let old = old_index.get(&key);
+                let new = new_index.get(&key);
+                let before = old.map(get_complexity);
+                let after = new.map(get_complexity);
+                let Some(node) = new.or(old) else {
+                    continue;
+                };
+                functions.push(FunctionComplexity {
+                    file: diff.source_file_path.clone(),
+                    kind: key.0.clone(),
+                    name: key.1.last().cloned().flatten(),
+                    line: node.start_position().row + 1,
+                    before,
+                    after,
+                    delta: after.unwrap_or(0) as isize - before.unwrap_or(0) as isize,
+                    crossed: after.is_some_and(|after| after > threshold) && before.is_none_or(|before| before <= threshold),
+                });
+            }
+        }
+
+        // Ties keep patch order
+        functions.sort_by_key(|function| std::cmp::Reverse(function.delta));
+        Self { threshold, functions }
+    }
+
+    pub fn crossed(&self) -> impl Iterator<Item = &FunctionComplexity> {
+        self.functions.iter().filter(|function| function.crossed)
+    }
+}
+
+impl fmt::Display for ComplexityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.functions.is_empty() {
+            return writeln!(f, "No changed functions");
+        }
+
+        let optional = |complexity: Option<usize>| complexity.map_or("-".to_string(), |complexity| complexity.to_string());
+        let rows: Vec<[String; 5]> = self.functions
+            .iter()
+            .map(|function| [
+                format!("{:+}", function.delta),
+                function.label(),
+                format!("{}:{}", function.file, function.line),
+                optional(function.before),
+                optional(function.after),
+            ])
+            .collect();
+        let mut widths = HEADERS.map(|header| header.len());
+        for row in rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        // Cells are padded before they are painted, escape codes would throw off the widths
+        let write_row = |f: &mut fmt::Formatter<'_>, cells: &[String], style: Option<Style>| -> fmt::Result {
+            for (i, (cell, width)) in cells.iter().zip(widths.iter()).enumerate() {
+                // Names are left aligned, numbers right aligned
+                let padded = if i == 1 || i == 2 { format!("{:<width$}", cell, width = width) } else { format!("{:>width$}", cell, width = width) };
+                let separator = if i == 0 { "" } else { "  " };
+                match style {
+                    Some(style) => write!(f, "{}{}", separator, color::paint(Stream::Stdout, style, &padded))?,
+                    None => write!(f, "{}{}", separator, padded)?,
+                }
+            }
+            writeln!(f)
+        };
+        write_row(f, &HEADERS.map(String::from), Some(Style::Bold))?;
+        for (row, function) in rows.iter().zip(self.functions.iter()) {
+            write_row(f, row, if function.crossed { Some(Style::Red) } else { None })?;
+        }
+
+        let crossed = self.crossed().count();
+        if crossed > 0 {
+            writeln!(f)?;
+            writeln!(f, "{} {} crossed the complexity threshold of {}", color::paint(Stream::Stdout, Style::Yellow, "note:"),
+                if crossed == 1 { "1 function".to_string() } else { format!("{} functions", crossed) }, self.threshold)?;
+        }
+        Ok(())
+    }
+}
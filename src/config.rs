@@ -0,0 +1,63 @@
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+
+use crate::error::DiffGraphError;
+
+const CONFIG_DIR: &str = "diffgraph";
+const CONFIG_FILE: &str = "config.toml";
+
+/// Defaults read from `~/.config/diffgraph/config.toml` (or `--config`).
+/// Every value is optional, and flags given on the command line or through their
+/// `DIFFGRAPH_*` environment variables take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub repository: Option<String>,
+    pub parser_config: Option<PathBuf>,
+    pub clone_cache_dir: Option<PathBuf>,
+    pub result_cache_dir: Option<PathBuf>,
+    pub grammar_cache_dir: Option<PathBuf>,
+    pub install_missing: Option<bool>,
+    pub full_tree: Option<bool>,
+    pub ignore_formatting: Option<bool>,
+    pub security_queries: Option<PathBuf>,
+    pub debt_queries: Option<PathBuf>,
+    pub architecture_rules: Option<PathBuf>,
+    pub symbol_index: Option<PathBuf>,
+    pub coverage: Option<PathBuf>,
+    pub exclude_kinds: Vec<String>,
+    pub paths: Vec<String>,
+    pub languages: Vec<String>,
+    pub error_format: Option<String>,
+    pub threads: Option<usize>,
+    pub max_files: Option<usize>,
+    pub max_nodes: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub max_children: Option<usize>,
+    pub on_limit: Option<String>,
+    pub fail_on: Option<Vec<String>>,
+    pub warn_on: Option<Vec<String>>,
+}
+
+fn get_default_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|path| path.join(CONFIG_DIR).join(CONFIG_FILE))
+}
+
+impl Config {
+    /// Loads the config at `path`, which must exist, or the default config if there is one.
+    pub fn try_load(path: Option<&Path>) -> Result<Self, DiffGraphError> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match get_default_config_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+
+        let config_str = std::fs::read_to_string(&path)
+            .map_err(|e| DiffGraphError::io(format!("Unable to read config '{}'", path.display()), e))?;
+        toml::from_str(&config_str)
+            .map_err(|e| DiffGraphError::Config(format!("Unable to parse config '{}': {}", path.display(), e)))
+    }
+}
@@ -0,0 +1,247 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use serde::{Serialize, Deserialize};
+use tree_sitter::{Node as TSNode, Tree};
+
+use crate::graph::{Edge, NodeInfo};
+use crate::parser::Diff;
+
+/// Languages whose files are compared key by key, by language name, see `grammars::get_language_name`
+const CONFIG_LANGUAGES: [&str; 3] = ["json", "yaml", "toml"];
+
+/// Kind of the graph nodes standing for changed keys, which no grammar has
+pub const CONFIG_KEY_KIND: &str = "config_key";
+
+/// Nodes holding a key and its value
+const PAIR_KINDS: [&str; 3] = ["pair", "block_mapping_pair", "flow_pair"];
+/// Nodes holding pairs
+const MAPPING_KINDS: [&str; 4] = ["object", "block_mapping", "flow_mapping", "inline_table"];
+/// Nodes holding values by position
+const SEQUENCE_KINDS: [&str; 3] = ["array", "block_sequence", "flow_sequence"];
+/// TOML tables, whose header is the path of their pairs from the root of the document
+const TABLE_KINDS: [&str; 2] = ["table", "table_array_element"];
+/// Nodes wrapping a single value or document
+const WRAPPER_KINDS: [&str; 5] = ["document", "stream", "block_node", "flow_node", "block_sequence_item"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+impl ConfigChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConfigChangeKind::Added => "added",
+            ConfigChangeKind::Removed => "removed",
+            ConfigChangeKind::Modified => "modified",
+        }
+    }
+}
+
+/// A key of a YAML, TOML or JSON file whose value the patch adds, removes or changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigChange {
+    pub file: String,
+    /// Keys from the root of the document to the value, such as `server.ports[0]`
+    pub path: String,
+    pub change: ConfigChangeKind,
+    /// Value before the patch, None for added keys
+    pub old_value: Option<String>,
+    /// Value after the patch, None for removed keys
+    pub new_value: Option<String>,
+    /// Line of the value, counted from 1, in the patched file unless it was removed
+    pub line: usize,
+}
+
+impl fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.old_value, &self.new_value) {
+            (Some(old), Some(new)) => write!(f, "{} `{}` → `{}`", self.path, old, new),
+            (None, Some(new)) => write!(f, "{} added as `{}`", self.path, new),
+            (Some(old), None) => write!(f, "{} removed, was `{}`", self.path, old),
+            (None, None) => write!(f, "{} {}", self.path, self.change.as_str()),
+        }
+    }
+}
+
+/// A key path, with sequence positions as `[i]` segments.
+type KeyPath = Vec<String>;
+
+fn format_path(path: &[String]) -> String {
+    let mut text = String::new();
+    for segment in path.iter() {
+        if !text.is_empty() && !segment.starts_with('[') {
+            text.push('.');
+        }
+        text.push_str(segment);
+    }
+    text
+}
+
+/// Entries of a document by key path: the node of each entry, whether a pair, a table or an
+/// item, and the value of each scalar in order.
+#[derive(Default)]
+struct DocumentIndex<'t> {
+    entries: HashMap<KeyPath, TSNode<'t>>,
+    values: Vec<(KeyPath, TSNode<'t>)>,
+}
+
+fn get_text<'s>(node: &TSNode, source: &'s str) -> &'s str {
+    source.get(node.byte_range()).unwrap_or_default()
+}
+
+fn get_key(node: &TSNode, source: &str) -> String {
+    get_text(node, source).trim().trim_matches(['"', '\'']).to_string()
+}
+
+/// Value text with whitespace collapsed, so reindenting a value is not a change.
+fn normalize_value(node: &TSNode, source: &str) -> String {
+    get_text(node, source).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn is_comment(node: &TSNode) -> bool {
+    node.kind() == "comment"
+}
+
+fn index_node<'t>(node: TSNode<'t>, path: &KeyPath, source: &str, index: &mut DocumentIndex<'t>, tables: &mut HashMap<KeyPath, usize>) {
+    let kind = node.kind();
+    let mut cursor = node.walk();
+    let children: Vec<TSNode<'t>> = node.named_children(&mut cursor).filter(|child| !is_comment(child)).collect();
+    if PAIR_KINDS.contains(&kind) {
+        let Some(key) = node.child_by_field_name("key").or(children.first().copied()) else {
+            return;
+        };
+        let mut path = path.clone();
+        path.push(get_key(&key, source));
+        index.entries.insert(path.clone(), node);
+        match node.child_by_field_name("value").or(children.last().copied().filter(|value| *value != key)) {
+            Some(value) => index_node(value, &path, source, index, tables),
+            // A key without a value, such as YAML's `key:`, is null
+            None => index.values.push((path, node)),
+        }
+    } else if TABLE_KINDS.contains(&kind) {
+        let Some(header) = children.first() else {
+            return;
+        };
+        let mut path: KeyPath = get_key(header, source).split('.').map(|key| key.trim().trim_matches(['"', '\'']).to_string()).collect();
+        if kind == "table_array_element" {
+            let position = tables.entry(path.clone()).or_default();
+            path.push(format!("[{}]", position));
+            *position += 1;
+        }
+        index.entries.insert(path.clone(), node);
+        for child in children.iter().skip(1) {
+            index_node(*child, &path, source, index, tables);
+        }
+    } else if SEQUENCE_KINDS.contains(&kind) {
+        for (position, child) in children.iter().enumerate() {
+            let mut path = path.clone();
+            path.push(format!("[{}]", position));
+            index.entries.insert(path.clone(), *child);
+            index_node(*child, &path, source, index, tables);
+        }
+    } else if MAPPING_KINDS.contains(&kind) || WRAPPER_KINDS.contains(&kind) {
+        if children.is_empty() && MAPPING_KINDS.contains(&kind) {
+            index.values.push((path.clone(), node));
+        }
+        for child in children {
+            index_node(child, path, source, index, tables);
+        }
+    } else {
+        index.values.push((path.clone(), node));
+    }
+}
+
+fn index_document<'t>(tree: &'t Tree, source: &str) -> DocumentIndex<'t> {
+    let mut index = DocumentIndex::default();
+    index_node(tree.root_node(), &Vec::new(), source, &mut index, &mut HashMap::new());
+    index
+}
+
+/// Graph node of a changed key, with an id no tree-sitter node has, as those are addresses.
+/// Like every node of the graph it spans bytes of the file before the patch: the old value, or
+/// for added keys the entry they are added to.
+fn to_key_node(file: usize, path: &str, byte_range: std::ops::Range<usize>) -> NodeInfo {
+    let mut hasher = DefaultHasher::new();
+    (file, path).hash(&mut hasher);
+    NodeInfo {
+        id: (hasher.finish() as usize) | (1 << (usize::BITS - 1)),
+        kind_id: u16::MAX,
+        kind: Cow::Borrowed(CONFIG_KEY_KIND),
+        file,
+        byte_range,
+    }
+}
+
+/// The keys of the changed YAML, TOML and JSON files whose values the patch adds, removes or
+/// changes, in the order of their lines. Along with the changes come edges to a node of kind
+/// `config_key` for each, from the entry of the key before the patch, or of the closest key
+/// enclosing it for added keys.
+pub fn find_config_changes(diffs: &[Diff]) -> (Vec<ConfigChange>, Vec<Edge>) {
+    let mut changes = Vec::new();
+    let mut edges = Vec::new();
+    for (file, diff) in diffs.iter().enumerate() {
+        if !CONFIG_LANGUAGES.contains(&diff.language_name.as_str()) {
+            continue;
+        }
+        let old = index_document(&diff.tree, &diff.source);
+        let new = index_document(&diff.target_tree, &diff.target_source);
+        let old_values: HashMap<&KeyPath, &TSNode> = old.values.iter().map(|(path, node)| (path, node)).collect();
+        let new_values: HashMap<&KeyPath, &TSNode> = new.values.iter().map(|(path, node)| (path, node)).collect();
+
+        // Changes with their key path and old value, if any
+        let mut file_changes: Vec<(ConfigChange, KeyPath, Option<TSNode>)> = Vec::new();
+        for (path, node) in new.values.iter() {
+            let new_value = normalize_value(node, &diff.target_source);
+            let old_node = old_values.get(path).copied().copied();
+            let old_value = old_node.map(|old_node| normalize_value(&old_node, &diff.source));
+            let change = match &old_value {
+                None => ConfigChangeKind::Added,
+                Some(old_value) if *old_value == new_value => continue,
+                Some(_) => ConfigChangeKind::Modified,
+            };
+            file_changes.push((ConfigChange {
+                file: diff.source_file_path.clone(),
+                path: format_path(path),
+                change,
+                old_value,
+                new_value: Some(new_value),
+                line: node.start_position().row + 1,
+            }, path.clone(), old_node));
+        }
+        for (path, node) in old.values.iter().filter(|(path, _)| !new_values.contains_key(path)) {
+            file_changes.push((ConfigChange {
+                file: diff.source_file_path.clone(),
+                path: format_path(path),
+                change: ConfigChangeKind::Removed,
+                old_value: Some(normalize_value(node, &diff.source)),
+                new_value: None,
+                line: node.start_position().row + 1,
+            }, path.clone(), Some(*node)));
+        }
+        file_changes.sort_by_key(|(change, _, _)| change.line);
+
+        for (change, path, old_node) in file_changes {
+            // The entry of the key before the patch, or of the closest key enclosing it
+            let anchor = (0..=path.len())
+                .rev()
+                .find_map(|length| old.entries.get(&path[..length]))
+                .copied()
+                .unwrap_or(diff.tree.root_node());
+            let byte_range = old_node.unwrap_or(anchor).byte_range();
+            edges.push(Edge {
+                from: NodeInfo::from_ts_node(file, &anchor),
+                to: to_key_node(file, &change.path, byte_range),
+            });
+            changes.push(change);
+        }
+    }
+    (changes, edges)
+}
@@ -0,0 +1,226 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use serde::Serialize;
+use tree_sitter::Tree;
+
+use crate::color::{self, Stream, Style};
+use crate::graph::DiffGraph;
+use crate::parser::{ChangedDeclaration, Diff};
+use crate::structural::{self, ChangeClass};
+
+/// How two patches could conflict, from most to least likely to be noticed by a merge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictKind {
+    /// Both patches remove or replace the same lines, a line-level merge stops on them
+    Overlapping,
+    /// Both patches change the same declaration on different lines, which merges cleanly but
+    /// may not make sense together
+    SameDeclaration,
+    /// One patch changes the signature of a declaration, or removes it, and the other adds
+    /// lines using it
+    ChangedDependency,
+}
+
+impl ConflictKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConflictKind::Overlapping => "overlapping",
+            ConflictKind::SameDeclaration => "same-declaration",
+            ConflictKind::ChangedDependency => "changed-dependency",
+        }
+    }
+}
+
+impl fmt::Display for ConflictKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A declaration both patches touch, directly or through its users.
+#[derive(Debug, Clone, Serialize)]
+pub struct PredictedConflict {
+    pub kind: ConflictKind,
+    /// File of the declaration
+    pub file: String,
+    pub declaration: String,
+    /// What the first patch does to the declaration
+    pub first: String,
+    /// What the other patch does to the declaration
+    pub second: String,
+}
+
+/// Where two patches of the same base could conflict, the likeliest to be missed by a merge
+/// last. Declarations are matched by kind and name, so unnamed ones are left out.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConflictReport {
+    /// Files both patches change
+    pub shared_files: Vec<String>,
+    pub conflicts: Vec<PredictedConflict>,
+}
+
+fn describe(declaration: &ChangedDeclaration) -> String {
+    match declaration.change {
+        Some(change) => change.to_string(),
+        None => "changed".to_string(),
+    }
+}
+
+/// Identifiers on the rows of the tree.
+fn collect_identifiers(tree: &Tree, source: &str, rows: &[usize], identifiers: &mut HashMap<String, BTreeSet<String>>, path: &str) {
+    let rows: HashSet<usize> = rows.iter().copied().collect();
+    let mut cursor = tree.walk();
+    'walk: loop {
+        let node = cursor.node();
+        if node.child_count() == 0 && node.kind().ends_with("identifier") && rows.contains(&node.start_position().row) {
+            if let Some(text) = source.get(node.byte_range()) {
+                identifiers.entry(text.to_string()).or_default().insert(path.to_string());
+            }
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+}
+
+/// Names on the lines the graph's patch adds, with the files adding them.
+fn get_added_identifiers(graph: &DiffGraph) -> HashMap<String, BTreeSet<String>> {
+    let mut identifiers = HashMap::new();
+    for diff in graph.diffs().iter() {
+        collect_identifiers(&diff.target_tree, &diff.target_source, &diff.added_rows, &mut identifiers, &diff.source_file_path);
+    }
+    identifiers
+}
+
+/// Named declarations the patch changes, by file.
+fn index_changes(graph: &DiffGraph) -> HashMap<&str, (&Diff, Vec<ChangedDeclaration>)> {
+    graph.diffs()
+        .iter()
+        .map(|diff| {
+            let declarations = diff.changed_declarations().into_iter().filter(|declaration| declaration.name.is_some()).collect();
+            (diff.source_file_path.as_str(), (diff, declarations))
+        })
+        .collect()
+}
+
+/// Whether both patches remove lines of the declaration, as it was before either patch.
+fn is_overlapping(first: &Diff, second: &Diff, declaration: &ChangedDeclaration) -> bool {
+    let index = structural::index_declarations(&first.tree, &first.source);
+    let Some(node) = index.iter().find_map(|((kind, path), node)| {
+        (*kind == declaration.kind && path.last() == Some(&declaration.name)).then_some(node)
+    }) else {
+        return false;
+    };
+    let rows = node.start_position().row..=node.end_position().row;
+    let removed: HashSet<usize> = first.removed_rows.iter().copied().filter(|row| rows.contains(row)).collect();
+    second.removed_rows.iter().any(|row| removed.contains(row))
+}
+
+/// Changed declarations whose users the other patch adds lines to, in the file of the
+/// declaration and elsewhere.
+fn find_changed_dependencies(changes: &HashMap<&str, (&Diff, Vec<ChangedDeclaration>)>, users: &HashMap<String, BTreeSet<String>>, first: bool) -> Vec<PredictedConflict> {
+    let mut conflicts = Vec::new();
+    for (path, (_, declarations)) in changes.iter() {
+        for declaration in declarations.iter() {
+            if !matches!(declaration.change, Some(ChangeClass::SignatureChange | ChangeClass::Removed)) {
+                continue;
+            }
+            let Some(files) = declaration.name.as_ref().and_then(|name| users.get(name)) else {
+                continue;
+            };
+            let files: Vec<&str> = files.iter().map(|file| file.as_str()).collect();
+            let change = describe(declaration);
+            let uses = format!("adds uses in {}", files.join(", "));
+            let (first, second) = if first { (change, uses) } else { (uses, change) };
+            conflicts.push(PredictedConflict {
+                kind: ConflictKind::ChangedDependency,
+                file: path.to_string(),
+                declaration: format!("{} {}", declaration.kind, declaration.name.as_deref().unwrap_or_default()),
+                first,
+                second,
+            });
+        }
+    }
+    conflicts
+}
+
+impl ConflictReport {
+    /// Compares the graphs of two patches of the same base. Declarations both patches change
+    /// conflict on their lines when both remove some of the same ones, and otherwise only
+    /// through what the changes mean; a declaration one patch changes the signature of, or
+    /// removes, conflicts with the other patch adding uses of its name.
+    pub fn from_graphs(first: &DiffGraph, second: &DiffGraph) -> Self {
+        let first_changes = index_changes(first);
+        let second_changes = index_changes(second);
+
+        let mut shared_files = Vec::new();
+        let mut conflicts = Vec::new();
+        for diff in first.diffs().iter() {
+            let path = diff.source_file_path.as_str();
+            let (Some((first_diff, first_declarations)), Some((second_diff, second_declarations))) = (first_changes.get(path), second_changes.get(path)) else {
+                continue;
+            };
+            shared_files.push(path.to_string());
+            for declaration in first_declarations.iter() {
+                let Some(other) = second_declarations.iter().find(|other| other.kind == declaration.kind && other.name == declaration.name) else {
+                    continue;
+                };
+                conflicts.push(PredictedConflict {
+                    kind: match is_overlapping(first_diff, second_diff, declaration) {
+                        true => ConflictKind::Overlapping,
+                        false => ConflictKind::SameDeclaration,
+                    },
+                    file: path.to_string(),
+                    declaration: format!("{} {}", declaration.kind, declaration.name.as_deref().unwrap_or_default()),
+                    first: describe(declaration),
+                    second: describe(other),
+                });
+            }
+        }
+
+        let reported: HashSet<(String, String)> = conflicts.iter().map(|conflict| (conflict.file.clone(), conflict.declaration.clone())).collect();
+        let mut dependencies = find_changed_dependencies(&first_changes, &get_added_identifiers(second), true);
+        dependencies.extend(find_changed_dependencies(&second_changes, &get_added_identifiers(first), false));
+        // Declarations both patches change are reported as such
+        dependencies.retain(|conflict| !reported.contains(&(conflict.file.clone(), conflict.declaration.clone())));
+        dependencies.sort_by(|a, b| (&a.file, &a.declaration).cmp(&(&b.file, &b.declaration)));
+        conflicts.extend(dependencies);
+
+        conflicts.sort_by_key(|conflict| conflict.kind);
+        Self { shared_files, conflicts }
+    }
+}
+
+impl fmt::Display for ConflictReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.conflicts.is_empty() {
+            return match self.shared_files.len() {
+                0 => writeln!(f, "The patches change no files in common and depend on nothing the other changes"),
+                count => writeln!(f, "The patches change {} files in common, but no declarations in common", count),
+            };
+        }
+
+        for conflict in self.conflicts.iter() {
+            let style = match conflict.kind {
+                ConflictKind::Overlapping => Style::Red,
+                ConflictKind::SameDeclaration | ConflictKind::ChangedDependency => Style::Yellow,
+            };
+            writeln!(f, "{} {} {}", color::paint(Stream::Stdout, style, &format!("[{}]", conflict.kind)),
+                color::paint(Stream::Stdout, Style::Bold, &conflict.declaration),
+                color::paint(Stream::Stdout, Style::Dim, &conflict.file))?;
+            writeln!(f, "  first: {}", conflict.first)?;
+            writeln!(f, "  other: {}", conflict.second)?;
+        }
+
+        writeln!(f)?;
+        let merged = self.conflicts.iter().filter(|conflict| conflict.kind != ConflictKind::Overlapping).count();
+        writeln!(f, "{} predicted conflicts, {} of which a line-level merge would not stop on", self.conflicts.len(), merged)
+    }
+}
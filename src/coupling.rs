@@ -0,0 +1,322 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use serde::Serialize;
+use tree_sitter::Tree;
+
+use crate::color::{self, Stream, Style};
+use crate::graph::DiffGraph;
+use crate::index::SymbolIndex;
+use crate::parser::Diff;
+
+/// Kinds of import statements, across the grammars of common languages
+pub(crate) const IMPORT_KINDS: [&str; 8] = [
+    "import_statement",
+    "import_from_statement",
+    "import_declaration",
+    "import_spec",
+    "use_declaration",
+    "using_directive",
+    "preproc_include",
+    "namespace_use_declaration",
+];
+
+/// File stems that stand for the directory they are in, such as `mod.rs` or `index.ts`
+const MODULE_INDEX_STEMS: [&str; 5] = ["mod", "index", "__init__", "lib", "main"];
+
+/// Kinds a callee's name can be nested in, such as `self.parse` or `Parser::parse`
+const CALLEE_PATH_KINDS: [&str; 5] = ["field", "member", "attribute", "scoped", "selector"];
+
+/// A changed file importing another changed file.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportEdge {
+    pub importer: String,
+    pub imported: String,
+}
+
+/// A changed file whose changed lines call or reference declarations another changed file changes.
+#[derive(Debug, Clone, Serialize)]
+pub struct UseEdge {
+    pub user: String,
+    pub declarer: String,
+}
+
+/// Two changed files that depend on each other through what the patch changes.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileCoupling {
+    pub files: [String; 2],
+    /// Whether the files are in different directories, so the change reaches across modules
+    pub cross_module: bool,
+    pub imports: Vec<ImportEdge>,
+    /// Declarations changed in one file that the changed lines of the other call
+    pub calls: BTreeSet<String>,
+    /// Declarations changed in one file that the changed lines of the other otherwise
+    /// reference, or that both change
+    pub symbols: BTreeSet<String>,
+    /// Which of the files use what the other changes, through `calls` and `symbols`
+    pub uses: Vec<UseEdge>,
+    pub score: usize,
+}
+
+/// Pairs of changed files coupled through the patch, most coupled first.
+#[derive(Debug, Clone, Serialize)]
+pub struct CouplingReport {
+    pub pairs: Vec<FileCoupling>,
+}
+
+/// What one changed file declares, references and imports.
+struct FileSymbols<'d> {
+    diff: &'d Diff,
+    /// Names of the declarations the patch changes
+    declared: HashSet<String>,
+    /// Identifiers on changed lines in call position
+    called: HashSet<String>,
+    /// Identifiers on changed lines elsewhere
+    referenced: HashSet<String>,
+    /// Words of the file's import statements, on either side of the patch
+    imports: HashSet<String>,
+}
+
+/// The name modules of other files import the file by, such as `parser` for `src/parser.rs`
+/// or `src/parser/mod.rs`.
+fn get_module_name(path: &str) -> Option<String> {
+    let path = Path::new(path);
+    let stem = path.file_stem()?.to_string_lossy().to_string();
+    if !MODULE_INDEX_STEMS.contains(&stem.as_str()) {
+        return Some(stem);
+    }
+    path.parent().and_then(|parent| parent.file_name()).map(|name| name.to_string_lossy().to_string())
+}
+
+fn get_directory(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(directory, _)| directory)
+}
+
+/// Collects the identifiers on the rows of the tree as called or otherwise referenced, and the
+/// words of every import statement.
+fn collect_symbols(tree: &Tree, source: &str, rows: &HashSet<usize>, symbols: &mut FileSymbols) {
+    let mut cursor = tree.walk();
+    'walk: loop {
+        let node = cursor.node();
+        if node.is_named() && IMPORT_KINDS.contains(&node.kind()) {
+            let text = source.get(node.byte_range()).unwrap_or_default();
+            symbols.imports.extend(text
+                .split(|c: char| !c.is_alphanumeric() && c != '_')
+                .filter(|word| !word.is_empty())
+                .map(|word| word.to_string()));
+        } else if node.child_count() == 0 && node.kind().ends_with("identifier") && rows.contains(&node.start_position().row) {
+            // Declarations are not references to themselves
+            let declared = node.parent().is_some_and(|parent| parent.child_by_field_name("name").is_some_and(|name| name.id() == node.id()));
+            let text = source.get(node.byte_range()).unwrap_or_default().to_string();
+            if !declared {
+                let mut callee = node.parent();
+                while callee.is_some_and(|parent| CALLEE_PATH_KINDS.iter().any(|kind| parent.kind().contains(kind))) {
+                    callee = callee.and_then(|parent| parent.parent());
+                }
+                match callee.is_some_and(|callee| callee.kind().contains("call") || callee.kind().contains("invocation")) {
+                    true => symbols.called.insert(text),
+                    false => symbols.referenced.insert(text),
+                };
+            }
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+}
+
+impl<'d> FileSymbols<'d> {
+    fn from_diff(diff: &'d Diff) -> Self {
+        let mut symbols = Self {
+            diff,
+            declared: diff.changed_declarations().into_iter().filter_map(|declaration| declaration.name).collect(),
+            called: HashSet::new(),
+            referenced: HashSet::new(),
+            imports: HashSet::new(),
+        };
+        let added_rows: HashSet<usize> = diff.added_rows.iter().copied().collect();
+        let removed_rows: HashSet<usize> = diff.removed_rows.iter().copied().collect();
+        collect_symbols(&diff.target_tree, &diff.target_source, &added_rows, &mut symbols);
+        collect_symbols(&diff.tree, &diff.source, &removed_rows, &mut symbols);
+        symbols
+    }
+
+    fn path(&self) -> &str {
+        &self.diff.source_file_path
+    }
+
+    fn imports(&self, other: &FileSymbols) -> bool {
+        get_module_name(other.path()).is_some_and(|module| self.imports.contains(&module))
+    }
+}
+
+fn couple(a: &FileSymbols, b: &FileSymbols) -> Option<FileCoupling> {
+    let mut imports = Vec::new();
+    for (importer, imported) in [(a, b), (b, a)] {
+        if importer.imports(imported) {
+            imports.push(ImportEdge { importer: importer.path().to_string(), imported: imported.path().to_string() });
+        }
+    }
+
+    let mut calls = BTreeSet::new();
+    let mut symbols: BTreeSet<String> = a.declared.intersection(&b.declared).cloned().collect();
+    let mut uses = Vec::new();
+    for (user, declarer) in [(a, b), (b, a)] {
+        let called = user.called.intersection(&declarer.declared).cloned().collect::<Vec<_>>();
+        let referenced = user.referenced.intersection(&declarer.declared).cloned().collect::<Vec<_>>();
+        if !called.is_empty() || !referenced.is_empty() {
+            uses.push(UseEdge { user: user.path().to_string(), declarer: declarer.path().to_string() });
+        }
+        calls.extend(called);
+        symbols.extend(referenced);
+    }
+    // A name both called and referenced is coupling through the calls
+    symbols.retain(|symbol| !calls.contains(symbol));
+
+    let score = 3 * imports.len() + 2 * calls.len() + symbols.len();
+    if score == 0 {
+        return None;
+    }
+    Some(FileCoupling {
+        files: [a.path().to_string(), b.path().to_string()],
+        cross_module: get_directory(a.path()) != get_directory(b.path()),
+        imports,
+        calls,
+        symbols,
+        uses,
+        score,
+    })
+}
+
+/// Changed files whose changed lines call or reference declarations another changed file
+/// changes, by name as in `CouplingReport`. With an index, names no changed file declares are
+/// resolved to the file outside the diff defining them, when only one does.
+pub(crate) fn find_uses(diffs: &[Diff], index: Option<&SymbolIndex>) -> Vec<UseEdge> {
+    let files: Vec<FileSymbols> = diffs.iter().map(FileSymbols::from_diff).collect();
+    let mut uses = Vec::new();
+    for user in files.iter() {
+        let used: Vec<&String> = user.called.iter().chain(user.referenced.iter()).collect();
+        for declarer in files.iter().filter(|declarer| declarer.path() != user.path()) {
+            if used.iter().any(|name| declarer.declared.contains(*name)) {
+                uses.push(UseEdge { user: user.path().to_string(), declarer: declarer.path().to_string() });
+            }
+        }
+        let Some(index) = index else {
+            continue;
+        };
+        let declarers: BTreeSet<&str> = used
+            .into_iter()
+            .filter(|name| !files.iter().any(|file| file.declared.contains(*name)))
+            .filter_map(|name| index.resolve(name))
+            .filter(|file| *file != user.path() && !files.iter().any(|changed| changed.path() == *file))
+            .collect();
+        uses.extend(declarers
+            .into_iter()
+            .map(|declarer| UseEdge { user: user.path().to_string(), declarer: declarer.to_string() }));
+    }
+    uses
+}
+
+/// The file rooting the cluster of the file, in a forest of parent indices.
+fn find_root(parents: &mut [usize], file: usize) -> usize {
+    let mut root = file;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[file] = root;
+    root
+}
+
+impl CouplingReport {
+    /// Couples changed files by their imports of each other and by the declarations one
+    /// changes that the other's changed lines call or reference. References are by name alone,
+    /// so a declaration of the same name elsewhere couples the files too.
+    pub fn from_graph(graph: &DiffGraph) -> Self {
+        let files: Vec<FileSymbols> = graph.diffs().iter().map(FileSymbols::from_diff).collect();
+        let mut pairs = Vec::new();
+        for (i, a) in files.iter().enumerate() {
+            for b in files.iter().skip(i + 1) {
+                pairs.extend(couple(a, b));
+            }
+        }
+
+        // Ties keep patch order
+        pairs.sort_by_key(|pair| std::cmp::Reverse(pair.score));
+        Self { pairs }
+    }
+
+    /// Indices of the files of the graph the report is of, grouped into the connected
+    /// components of the coupling: files coupled directly or through other files are one
+    /// cluster. Clusters and their files are in patch order.
+    pub fn clusters(&self, graph: &DiffGraph) -> Vec<Vec<usize>> {
+        let index: HashMap<&str, usize> = graph.diffs()
+            .iter()
+            .enumerate()
+            .map(|(i, diff)| (diff.source_file_path.as_str(), i))
+            .collect();
+        let mut parents: Vec<usize> = (0..graph.diffs().len()).collect();
+        for pair in self.pairs.iter() {
+            let (Some(a), Some(b)) = (index.get(pair.files[0].as_str()), index.get(pair.files[1].as_str())) else {
+                continue;
+            };
+            let (a, b) = (find_root(&mut parents, *a), find_root(&mut parents, *b));
+            // The earlier file roots the cluster, keeping clusters in patch order
+            parents[a.max(b)] = a.min(b);
+        }
+
+        let mut clusters: Vec<Vec<usize>> = Vec::new();
+        let mut roots: HashMap<usize, usize> = HashMap::new();
+        for file in 0..graph.diffs().len() {
+            let root = find_root(&mut parents, file);
+            let cluster = *roots.entry(root).or_insert_with(|| {
+                clusters.push(Vec::new());
+                clusters.len() - 1
+            });
+            clusters[cluster].push(file);
+        }
+        clusters
+    }
+
+    pub fn cross_module(&self) -> impl Iterator<Item = &FileCoupling> {
+        self.pairs.iter().filter(|pair| pair.cross_module)
+    }
+}
+
+impl fmt::Display for CouplingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.pairs.is_empty() {
+            return writeln!(f, "No changed files are coupled");
+        }
+
+        for pair in self.pairs.iter() {
+            write!(f, "{} {} {}  {}", color::paint(Stream::Stdout, Style::Bold, &pair.files[0]),
+                color::paint(Stream::Stdout, Style::Dim, "<->"),
+                color::paint(Stream::Stdout, Style::Bold, &pair.files[1]),
+                color::paint(Stream::Stdout, Style::Dim, &format!("score {}", pair.score)))?;
+            if pair.cross_module {
+                write!(f, "  {}", color::paint(Stream::Stdout, Style::Yellow, "across modules"))?;
+            }
+            writeln!(f)?;
+            for import in pair.imports.iter() {
+                writeln!(f, "  imports: {} imports {}", import.importer, import.imported)?;
+            }
+            let join = |names: &BTreeSet<String>| names.iter().map(|name| name.as_str()).collect::<Vec<_>>().join(", ");
+            if !pair.calls.is_empty() {
+                writeln!(f, "  calls: {}", join(&pair.calls))?;
+            }
+            if !pair.symbols.is_empty() {
+                writeln!(f, "  symbols: {}", join(&pair.symbols))?;
+            }
+        }
+
+        writeln!(f)?;
+        let plural = |count: usize| if count == 1 { "1 coupled pair".to_string() } else { format!("{} coupled pairs", count) };
+        writeln!(f, "{}, {} across modules", plural(self.pairs.len()), self.cross_module().count())
+    }
+}
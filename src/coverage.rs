@@ -0,0 +1,185 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+
+use regex::Regex;
+use serde::Serialize;
+use tracing::debug;
+
+use crate::error::DiffGraphError;
+use crate::parser::Diff;
+use crate::syntax;
+
+/// Hit counts of the instrumented lines of the files a test run covered, read from an lcov
+/// tracefile or a Cobertura XML report.
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    /// Hits by line, counted from 1, by path as the report names the file
+    files: HashMap<String, HashMap<usize, u64>>,
+}
+
+/// Changed lines of a declaration of the patched file that the tests never ran.
+#[derive(Debug, Clone, Serialize)]
+pub struct UntestedChange {
+    pub file: String,
+    /// None for changes outside of any declaration, such as module level statements
+    pub kind: Option<String>,
+    pub name: Option<String>,
+    /// Lines of the declaration, or of the changed lines outside of declarations, counted from 1,
+    /// in the patched file
+    pub start_line: usize,
+    pub end_line: usize,
+    /// Added lines the report instruments but no test ran
+    pub uncovered_lines: Vec<usize>,
+    /// Added lines the report instruments, run or not
+    pub instrumented_lines: usize,
+}
+
+/// Key of a declaration of the patched file: its first and last rows, kind and name
+type DeclarationKey = (usize, usize, Option<String>, Option<String>);
+
+impl UntestedChange {
+    fn label(&self) -> String {
+        match (&self.kind, &self.name) {
+            (Some(kind), Some(name)) => format!("{} {}", kind, name),
+            (Some(kind), None) => kind.clone(),
+            (None, _) => "changes outside of declarations".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for UntestedChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {} of {} added lines never run", self.label(), self.uncovered_lines.len(), self.instrumented_lines)
+    }
+}
+
+impl Coverage {
+    /// Reads an lcov tracefile's `SF:` and `DA:` records. Records of the same file add up, as
+    /// in tracefiles merged from several test runs.
+    pub fn parse_lcov(text: &str) -> Self {
+        let mut coverage = Self::default();
+        let mut file = None;
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some(path) = line.strip_prefix("SF:") {
+                file = Some(path.to_string());
+            } else if line == "end_of_record" {
+                file = None;
+            } else if let (Some(file), Some(data)) = (&file, line.strip_prefix("DA:")) {
+                let mut fields = data.split(',');
+                let (Some(Ok(number)), Some(Ok(hits))) = (fields.next().map(str::parse), fields.next().map(str::parse)) else {
+                    continue;
+                };
+                coverage.add(file, number, hits);
+            }
+        }
+        coverage
+    }
+
+    /// Reads the `line` elements of a Cobertura report's classes, by the class's `filename`.
+    pub fn parse_cobertura(text: &str) -> Self {
+        let element = Regex::new(r"<(class|line)\s([^>]*)>").unwrap();
+        let attribute = Regex::new(r#"([\w-]+)\s*=\s*"([^"]*)""#).unwrap();
+        let mut coverage = Self::default();
+        let mut file = None;
+        for captures in element.captures_iter(text) {
+            let attributes: HashMap<&str, &str> = attribute
+                .captures_iter(&captures[2])
+                .filter_map(|attribute| Some((attribute.get(1)?.as_str(), attribute.get(2)?.as_str())))
+                .collect();
+            match &captures[1] {
+                "class" => file = attributes.get("filename").map(|filename| filename.to_string()),
+                _ => {
+                    let (Some(file), Some(Ok(number)), Some(Ok(hits))) = (&file, attributes.get("number").map(|n| n.parse()), attributes.get("hits").map(|h| h.parse())) else {
+                        continue;
+                    };
+                    coverage.add(file, number, hits);
+                },
+            }
+        }
+        coverage
+    }
+
+    /// Reads a Cobertura report if the text is XML, otherwise an lcov tracefile.
+    pub fn parse(text: &str) -> Self {
+        match text.trim_start().starts_with('<') {
+            true => Self::parse_cobertura(text),
+            false => Self::parse_lcov(text),
+        }
+    }
+
+    pub fn try_load(path: &Path) -> Result<Self, DiffGraphError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| DiffGraphError::io(format!("Unable to read coverage '{}'", path.display()), e))?;
+        let coverage = Self::parse(&text);
+        debug!("Read the coverage of {} files from {}", coverage.files.len(), path.display());
+        Ok(coverage)
+    }
+
+    fn add(&mut self, file: &str, line: usize, hits: u64) {
+        *self.files.entry(file.to_string()).or_default().entry(line).or_default() += hits;
+    }
+
+    /// Hits of the file's instrumented lines, by line counted from 1. Reports often name files
+    /// by absolute path or relative to another root, so a path ending with the file's path in
+    /// the repository is the file's.
+    pub fn get_file(&self, path: &str) -> Option<&HashMap<usize, u64>> {
+        self.files.get(path).or_else(|| self.files
+            .iter()
+            .find(|(file, _)| file.ends_with(path) && file[..file.len() - path.len()].ends_with(['/', '\\']))
+            .map(|(_, lines)| lines))
+    }
+
+    /// Declarations of the patched files whose added lines include some the report
+    /// instruments but no test ran, in patch order. Files the report does not cover are left
+    /// out, as they may not have been measured at all.
+    pub fn find_untested_changes(&self, diffs: &[Diff]) -> Vec<UntestedChange> {
+        let mut untested = Vec::new();
+        for diff in diffs.iter() {
+            let Some(hits) = self.get_file(&diff.source_file_path) else {
+                continue;
+            };
+
+            // Instrumented added lines of each declaration, with their hits
+            let lines: Vec<&str> = diff.target_source.lines().collect();
+            let mut declarations: BTreeMap<DeclarationKey, Vec<(usize, u64)>> = BTreeMap::new();
+            let mut outside: Vec<(usize, u64)> = Vec::new();
+            for row in diff.added_rows.iter() {
+                let Some(count) = hits.get(&(row + 1)) else {
+                    continue;
+                };
+                let point = syntax::get_line_start(*row, lines.get(*row).unwrap_or(&""));
+                match syntax::find_enclosing_declaration(&diff.target_tree, point) {
+                    Some(declaration) => declarations
+                        .entry((declaration.start_position().row, declaration.end_position().row, Some(declaration.kind().to_string()),
+                            syntax::get_declaration_name(&declaration, &diff.target_source)))
+                        .or_default()
+                        .push((row + 1, *count)),
+                    None => outside.push((row + 1, *count)),
+                }
+            }
+            // Changes outside of declarations count as one, spanning their lines
+            if let (Some(first), Some(last)) = (outside.iter().map(|(line, _)| *line).min(), outside.iter().map(|(line, _)| *line).max()) {
+                declarations.insert((first - 1, last - 1, None, None), outside);
+            }
+
+            for ((start_row, end_row, kind, name), lines) in declarations {
+                let uncovered_lines: Vec<usize> = lines.iter().filter(|(_, count)| *count == 0).map(|(line, _)| *line).collect();
+                if uncovered_lines.is_empty() {
+                    continue;
+                }
+                untested.push(UntestedChange {
+                    file: diff.source_file_path.clone(),
+                    kind,
+                    name,
+                    start_line: start_row + 1,
+                    end_line: end_row + 1,
+                    uncovered_lines,
+                    instrumented_lines: lines.len(),
+                });
+            }
+        }
+        untested
+    }
+}
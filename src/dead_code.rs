@@ -0,0 +1,143 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use serde::Serialize;
+use tree_sitter::Tree;
+
+use crate::color::{self, Stream, Style};
+use crate::graph::DiffGraph;
+use crate::syntax;
+
+/// A declaration the patch keeps, but removes the last references to within the patched files.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadCodeCandidate {
+    pub file: String,
+    pub kind: String,
+    pub name: String,
+    /// First line of the declaration in the patched file, counted from 1
+    pub line: usize,
+    /// Public declarations may still be used outside of the patched files
+    pub public: bool,
+    /// References to the declaration before the patch
+    pub removed_references: usize,
+    /// Files the references were removed from
+    pub removed_from: BTreeSet<String>,
+}
+
+/// Declarations that may no longer be used once the patch is applied. Only the patched files
+/// are searched for references, by name, so callers elsewhere are not seen.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadCodeReport {
+    pub candidates: Vec<DeadCodeCandidate>,
+}
+
+/// A named declaration of a tree.
+struct Declaration {
+    kind: String,
+    name: String,
+    row: usize,
+    public: bool,
+}
+
+/// How often each identifier occurs in the tree outside of the names of declarations, and the
+/// named declarations of the tree.
+fn index_tree(tree: &Tree, source: &str, language: &str) -> (HashMap<String, usize>, Vec<Declaration>) {
+    let mut references: HashMap<String, usize> = HashMap::new();
+    let mut declarations = Vec::new();
+    let mut cursor = tree.walk();
+    'walk: loop {
+        let node = cursor.node();
+        if node.is_named() && syntax::is_declaration_kind(node.kind()) {
+            if let Some(name) = syntax::get_declaration_name(&node, source) {
+                declarations.push(Declaration {
+                    kind: node.kind().to_string(),
+                    name,
+                    row: node.start_position().row,
+                    public: syntax::is_public_declaration(&node, source, language),
+                });
+            }
+        }
+        let declared = node.parent().is_some_and(|parent| parent.child_by_field_name("name").is_some_and(|name| name.id() == node.id()));
+        if node.child_count() == 0 && node.kind().ends_with("identifier") && !declared {
+            if let Some(text) = source.get(node.byte_range()) {
+                *references.entry(text.to_string()).or_default() += 1;
+            }
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+    (references, declarations)
+}
+
+impl DeadCodeReport {
+    pub fn from_graph(graph: &DiffGraph) -> Self {
+        let mut before: HashMap<String, usize> = HashMap::new();
+        let mut removed_from: HashMap<String, BTreeSet<String>> = HashMap::new();
+        let mut after: HashMap<String, usize> = HashMap::new();
+        let mut declarations = Vec::new();
+        for diff in graph.diffs().iter() {
+            let language = diff.language_name.as_str();
+            let (old_references, _) = index_tree(&diff.tree, &diff.source, language);
+            let (new_references, new_declarations) = index_tree(&diff.target_tree, &diff.target_source, language);
+            for (name, count) in old_references {
+                // Files keeping as many references as they had are not where they were removed
+                if new_references.get(&name).is_none_or(|new_count| *new_count < count) {
+                    removed_from.entry(name.clone()).or_default().insert(diff.source_file_path.clone());
+                }
+                *before.entry(name).or_default() += count;
+            }
+            for (name, count) in new_references {
+                *after.entry(name).or_default() += count;
+            }
+            declarations.extend(new_declarations.into_iter().map(|declaration| (diff.source_file_path.clone(), declaration)));
+        }
+
+        let candidates = declarations
+            .into_iter()
+            .filter(|(_, declaration)| after.get(&declaration.name).is_none_or(|count| *count == 0))
+            .filter_map(|(file, declaration)| {
+                let removed_references = before.get(&declaration.name).copied().filter(|count| *count > 0)?;
+                Some(DeadCodeCandidate {
+                    file,
+                    line: declaration.row + 1,
+                    public: declaration.public,
+                    removed_references,
+                    removed_from: removed_from.get(&declaration.name).cloned().unwrap_or_default(),
+                    kind: declaration.kind,
+                    name: declaration.name,
+                })
+            })
+            .collect();
+        Self { candidates }
+    }
+}
+
+impl fmt::Display for DeadCodeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.candidates.is_empty() {
+            return writeln!(f, "No declarations lost their last references");
+        }
+
+        for candidate in self.candidates.iter() {
+            let files = candidate.removed_from.iter().map(|file| file.as_str()).collect::<Vec<_>>().join(", ");
+            write!(f, "{} {} {}: {} removed from {}",
+                candidate.kind,
+                color::paint(Stream::Stdout, Style::Bold, &candidate.name),
+                color::paint(Stream::Stdout, Style::Dim, &format!("{}:{}", candidate.file, candidate.line)),
+                if candidate.removed_references == 1 { "1 reference".to_string() } else { format!("{} references", candidate.removed_references) },
+                files)?;
+            if candidate.public {
+                write!(f, " {}", color::paint(Stream::Stdout, Style::Yellow, "(public, may be used elsewhere)"))?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{} possibly dead declarations, only the patched files were searched for references", self.candidates.len())
+    }
+}
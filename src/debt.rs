@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+use tree_sitter::Query;
+
+use crate::error::DiffGraphError;
+use crate::parser::Diff;
+use crate::security::{self, QueryPack};
+
+const PYTHON_QUERIES: &str = include_str!("../queries/debt/python.scm");
+const JAVASCRIPT_QUERIES: &str = include_str!("../queries/debt/javascript.scm");
+const RUST_QUERIES: &str = include_str!("../queries/debt/rust.scm");
+const JAVA_QUERIES: &str = include_str!("../queries/debt/java.scm");
+const GO_QUERIES: &str = include_str!("../queries/debt/go.scm");
+
+/// Query packs shipped with diffgraph by language name, see `grammars::get_language_name`
+const BUILTIN_PACKS: [(&str, &str); 9] = [
+    ("python", PYTHON_QUERIES),
+    ("js", JAVASCRIPT_QUERIES),
+    ("javascript", JAVASCRIPT_QUERIES),
+    ("ts", JAVASCRIPT_QUERIES),
+    ("typescript", JAVASCRIPT_QUERIES),
+    ("tsx", JAVASCRIPT_QUERIES),
+    ("rust", RUST_QUERIES),
+    ("java", JAVA_QUERIES),
+    ("go", GO_QUERIES),
+];
+
+/// A changed node adding debt for later or a way to fail at runtime, such as a TODO comment,
+/// an `unwrap()` or a bare `except:`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebtTag {
+    pub file: String,
+    /// Name of the capture that matched, such as `todo`, `fixme`, `unwrap`, `panic` or `bare-except`
+    pub tag: String,
+    pub kind: String,
+    /// Lines and columns in the patched file, counted from 1
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    /// The first line of the node's text
+    pub excerpt: String,
+}
+
+impl fmt::Display for DebtTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` at {}:{} adds {}", self.excerpt, self.file, self.start_line, self.tag)
+    }
+}
+
+/// Tree-sitter query packs tagging debt and panics, by language name, loaded like
+/// `SecurityQueries` from `--debt-queries`.
+#[derive(Debug, Clone)]
+pub struct DebtQueries {
+    packs: HashMap<String, Vec<QueryPack>>,
+}
+
+impl DebtQueries {
+    /// The packs shipped with diffgraph, and those in `dir` named after their language, such
+    /// as `python.scm`, which are run along with them.
+    pub fn try_load(dir: Option<&Path>) -> Result<Self, DiffGraphError> {
+        Ok(Self { packs: security::try_load_packs(&BUILTIN_PACKS, dir, "debt")? })
+    }
+}
+
+/// Runs the query packs over patched files, compiling each language's queries once.
+pub struct DebtScanner<'q> {
+    queries: &'q DebtQueries,
+    compiled: HashMap<String, Vec<Query>>,
+}
+
+impl<'q> DebtScanner<'q> {
+    pub fn new(queries: &'q DebtQueries) -> Self {
+        Self { queries, compiled: HashMap::new() }
+    }
+
+    /// Nodes of the patched file on added lines that the queries of its language tag, in the
+    /// order they appear.
+    pub fn scan(&mut self, diff: &Diff) -> Vec<DebtTag> {
+        let packs = &self.queries.packs;
+        let queries = self.compiled.entry(diff.language_name.clone()).or_insert_with(|| security::compile_packs(packs, diff.language, &diff.language_name, "debt"));
+        let mut tags: Vec<DebtTag> = security::find_added_captures(queries, diff)
+            .into_iter()
+            .map(|(tag, node)| DebtTag {
+                file: diff.source_file_path.clone(),
+                tag,
+                kind: node.kind().to_string(),
+                start_line: node.start_position().row + 1,
+                start_column: node.start_position().column + 1,
+                end_line: node.end_position().row + 1,
+                end_column: node.end_position().column + 1,
+                excerpt: security::get_excerpt(&node, &diff.target_source),
+            })
+            .collect();
+        tags.sort_by_key(|tag| (tag.start_line, tag.start_column));
+        tags
+    }
+}
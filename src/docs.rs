@@ -0,0 +1,114 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use tree_sitter::{Language, Node as TSNode, Query, QueryCursor, Tree};
+
+use crate::security;
+
+const PYTHON_QUERIES: &str = include_str!("../queries/docs/python.scm");
+const JAVASCRIPT_QUERIES: &str = include_str!("../queries/docs/javascript.scm");
+const RUST_QUERIES: &str = include_str!("../queries/docs/rust.scm");
+const JAVA_QUERIES: &str = include_str!("../queries/docs/java.scm");
+const GO_QUERIES: &str = include_str!("../queries/docs/go.scm");
+
+/// Doc comment query packs shipped with diffgraph by language name, see `grammars::get_language_name`
+const BUILTIN_PACKS: [(&str, &str); 9] = [
+    ("python", PYTHON_QUERIES),
+    ("js", JAVASCRIPT_QUERIES),
+    ("javascript", JAVASCRIPT_QUERIES),
+    ("ts", JAVASCRIPT_QUERIES),
+    ("typescript", JAVASCRIPT_QUERIES),
+    ("tsx", JAVASCRIPT_QUERIES),
+    ("rust", RUST_QUERIES),
+    ("java", JAVA_QUERIES),
+    ("go", GO_QUERIES),
+];
+
+/// Markers starting the lines of comments, the longest first
+const LINE_MARKERS: [&str; 4] = ["///", "//!", "//", "*"];
+/// Quotes around docstrings, the longest first
+const QUOTES: [&str; 4] = ["\"\"\"", "'''", "\"", "'"];
+
+thread_local! {
+    /// Compiled queries of each language, one set per thread like the parsers
+    static QUERIES: RefCell<HashMap<String, Rc<Vec<Query>>>> = RefCell::new(HashMap::new());
+}
+
+/// Leading punctuation of a comment, such as `///` or `//`, which the comments of one doc share.
+fn get_marker(text: &str) -> &str {
+    let end = text.find(|c: char| c.is_alphanumeric() || c.is_whitespace()).unwrap_or(text.len());
+    &text[..end]
+}
+
+/// Text of a comment or docstring without its markers, quotes and indentation.
+fn clean(text: &str) -> String {
+    let mut text = text.trim();
+    if let Some(inner) = QUOTES.iter().find_map(|quotes| text.strip_prefix(quotes).and_then(|text| text.strip_suffix(quotes))) {
+        text = inner;
+    }
+    text = text.strip_prefix("/**").or_else(|| text.strip_prefix("/*")).unwrap_or(text);
+    text = text.strip_suffix("*/").unwrap_or(text);
+    let lines: Vec<&str> = text
+        .lines()
+        .map(|line| {
+            let line = line.trim();
+            let line = LINE_MARKERS.iter().find_map(|marker| line.strip_prefix(marker)).unwrap_or(line);
+            line.strip_prefix(' ').unwrap_or(line).trim_end()
+        })
+        .collect();
+    lines.join("\n").trim().to_string()
+}
+
+/// The doc comment ending with the node, along with the comments of the same kind and marker
+/// right above it, such as each `///` line of a Rust doc.
+fn collect_doc(node: TSNode, source: &str) -> String {
+    let text = |node: &TSNode| source.get(node.byte_range()).unwrap_or_default();
+    let marker = get_marker(text(&node));
+    let mut comments = vec![node];
+    while let Some(previous) = comments.last().and_then(|comment| comment.prev_sibling()) {
+        let adjacent = previous.end_position().row + 1 >= comments.last().map_or(0, |comment| comment.start_position().row);
+        if previous.kind() != node.kind() || !adjacent || get_marker(text(&previous)) != marker {
+            break;
+        }
+        comments.push(previous);
+    }
+    comments.iter().rev().map(|comment| clean(text(comment))).collect::<Vec<_>>().join("\n").trim().to_string()
+}
+
+/// Doc comments and docstrings of the declarations of the tree, by the id of the declaration
+/// node, as the shipped queries of the language find them. Languages without queries have none.
+pub fn find_doc_comments(tree: &Tree, source: &str, language: Language, language_name: &str) -> HashMap<usize, String> {
+    let queries = QUERIES.with(|queries| queries
+        .borrow_mut()
+        .entry(language_name.to_string())
+        .or_insert_with(|| {
+            // Without a directory of user packs loading cannot fail
+            let packs = security::try_load_packs(&BUILTIN_PACKS, None, "doc").unwrap_or_default();
+            Rc::new(security::compile_packs(&packs, language, language_name, "doc"))
+        })
+        .clone());
+
+    let mut docs = HashMap::new();
+    for query in queries.iter() {
+        let mut cursor = QueryCursor::new();
+        for query_match in cursor.matches(query, tree.root_node(), source.as_bytes()) {
+            let mut doc = None;
+            let mut declaration = None;
+            for capture in query_match.captures {
+                match query.capture_names()[capture.index as usize].as_str() {
+                    "doc" => doc = Some(capture.node),
+                    "declaration" => declaration = Some(capture.node),
+                    _ => {},
+                }
+            }
+            if let (Some(doc), Some(declaration)) = (doc, declaration) {
+                let doc = collect_doc(doc, source);
+                if !doc.is_empty() {
+                    docs.insert(declaration.id(), doc);
+                }
+            }
+        }
+    }
+    docs
+}
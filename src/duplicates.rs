@@ -0,0 +1,221 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use serde::Serialize;
+use tree_sitter::Node as TSNode;
+
+use crate::color::{self, Stream, Style};
+use crate::graph::DiffGraph;
+use crate::parser::Diff;
+use crate::syntax;
+
+/// Similarity from which two added subtrees are reported as duplicates
+pub const DEFAULT_SIMILARITY: f64 = 0.85;
+/// Tokens below which an added subtree is too small to be worth extracting
+const MIN_TOKENS: usize = 40;
+/// Tokens in a shingle, the runs of tokens subtrees are compared by
+const SHINGLE_TOKENS: usize = 4;
+
+/// Where an added subtree is.
+#[derive(Debug, Clone, Serialize)]
+pub struct CodeSpan {
+    pub file: String,
+    pub kind: String,
+    pub name: Option<String>,
+    /// Lines of the patched file, counted from 1
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+/// Two subtrees the patch adds that are the same code, up to names and literals.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicatePair {
+    /// Share of the normalized token shingles the subtrees have in common, 1 when they are identical
+    pub similarity: f64,
+    /// Tokens of the smaller subtree
+    pub tokens: usize,
+    pub first: CodeSpan,
+    pub second: CodeSpan,
+}
+
+/// Code the patch adds more than once, most similar first, for pushing for an extraction.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicatesReport {
+    pub min_similarity: f64,
+    pub duplicates: Vec<DuplicatePair>,
+}
+
+/// An added subtree, normalized for comparing.
+struct Candidate<'t> {
+    file: usize,
+    node: TSNode<'t>,
+    tokens: usize,
+    hash: u64,
+    shingles: HashSet<u64>,
+}
+
+/// The subtree's leaves with names and literals replaced by placeholders and comments left out,
+/// so renaming variables or changing constants does not hide a copy.
+fn normalize(node: &TSNode) -> Vec<&'static str> {
+    let mut tokens = Vec::new();
+    let mut cursor = node.walk();
+    'walk: loop {
+        let current = cursor.node();
+        let kind = current.kind();
+        // Comments are skipped whole, strings are one token
+        let comment = kind.contains("comment");
+        if !comment && (current.child_count() == 0 || kind.contains("string")) {
+            tokens.push(if kind.ends_with("identifier") {
+                "$name"
+            } else if ["string", "number", "integer", "float", "char", "literal"].iter().any(|literal| kind.contains(literal)) {
+                "$literal"
+            } else {
+                kind
+            });
+        } else if !comment && cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            // A cursor started at the node does not leave its subtree
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+    tokens
+}
+
+fn hash<T: Hash>(value: T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Subtrees of the patched file made of added lines alone, large enough to be worth comparing:
+/// the outermost such subtrees, and the declarations nested in them.
+fn find_candidates<'t>(file: usize, diff: &'t Diff) -> Vec<Candidate<'t>> {
+    let added_rows: HashSet<usize> = diff.added_rows.iter().copied().collect();
+    let is_added = |node: &TSNode| (node.start_position().row..=node.end_position().row).all(|row| added_rows.contains(&row));
+
+    let mut candidates = Vec::new();
+    let mut stack = vec![diff.target_tree.root_node()];
+    while let Some(node) = stack.pop() {
+        let root = node.parent().is_none();
+        let added = !root && node.is_named() && is_added(&node);
+        let outermost = added && node.parent().is_none_or(|parent| parent.parent().is_none() || !is_added(&parent));
+        if added && (outermost || syntax::is_declaration_kind(node.kind())) {
+            let tokens = normalize(&node);
+            if tokens.len() >= MIN_TOKENS {
+                candidates.push(Candidate {
+                    file,
+                    node,
+                    tokens: tokens.len(),
+                    hash: hash(&tokens),
+                    shingles: tokens.windows(SHINGLE_TOKENS).map(hash).collect(),
+                });
+            }
+        }
+        // Only declarations are looked for below an added subtree
+        let mut cursor = node.walk();
+        stack.extend(node.named_children(&mut cursor).filter(|child| !added || child.named_child_count() > 0));
+    }
+    candidates
+}
+
+fn similarity(a: &Candidate, b: &Candidate) -> f64 {
+    if a.hash == b.hash {
+        return 1.0;
+    }
+    let shared = a.shingles.intersection(&b.shingles).count();
+    let total = a.shingles.len() + b.shingles.len() - shared;
+    if total == 0 { 0.0 } else { shared as f64 / total as f64 }
+}
+
+fn contains(outer: &Candidate, inner: &Candidate) -> bool {
+    outer.file == inner.file && outer.node.start_byte() <= inner.node.start_byte() && inner.node.end_byte() <= outer.node.end_byte()
+}
+
+fn to_span(graph: &DiffGraph, candidate: &Candidate) -> CodeSpan {
+    let diff = &graph.diffs()[candidate.file];
+    CodeSpan {
+        file: diff.source_file_path.clone(),
+        kind: candidate.node.kind().to_string(),
+        name: syntax::get_declaration_name(&candidate.node, &diff.target_source),
+        start_line: candidate.node.start_position().row + 1,
+        end_line: candidate.node.end_position().row + 1,
+    }
+}
+
+impl DuplicatesReport {
+    /// Compares every pair of added subtrees, largest first. Pairs within an already reported
+    /// pair, such as the methods of two copies of a class, are left out.
+    pub fn from_graph(graph: &DiffGraph, min_similarity: f64) -> Self {
+        let mut candidates: Vec<Candidate> = graph.diffs()
+            .iter()
+            .enumerate()
+            .flat_map(|(file, diff)| find_candidates(file, diff))
+            .collect();
+        candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.tokens));
+
+        let mut reported: Vec<(usize, usize)> = Vec::new();
+        let mut duplicates = Vec::new();
+        for (i, a) in candidates.iter().enumerate() {
+            for (j, b) in candidates.iter().enumerate().skip(i + 1) {
+                // Sizes this far apart cannot be similar enough, nor can any smaller subtree after
+                if (b.tokens as f64) < a.tokens as f64 * min_similarity {
+                    break;
+                }
+                // Nested subtrees are not copies of each other
+                if contains(a, b) {
+                    continue;
+                }
+                let within = reported.iter().any(|(first, second)| {
+                    let (first, second) = (&candidates[*first], &candidates[*second]);
+                    (contains(first, a) && contains(second, b)) || (contains(first, b) && contains(second, a))
+                });
+                if within {
+                    continue;
+                }
+                let similarity = similarity(a, b);
+                if similarity < min_similarity {
+                    continue;
+                }
+                reported.push((i, j));
+                duplicates.push(DuplicatePair {
+                    similarity: (similarity * 100.0).round() / 100.0,
+                    tokens: b.tokens,
+                    first: to_span(graph, a),
+                    second: to_span(graph, b),
+                });
+            }
+        }
+
+        duplicates.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        Self { min_similarity, duplicates }
+    }
+}
+
+impl fmt::Display for DuplicatesReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.duplicates.is_empty() {
+            return writeln!(f, "No duplicated code among the added lines");
+        }
+
+        let describe = |span: &CodeSpan| {
+            let label = match &span.name {
+                Some(name) => format!("{} {}", span.kind, name),
+                None => span.kind.clone(),
+            };
+            format!("{} {}", label, color::paint(Stream::Stdout, Style::Dim, &format!("{}:{}-{}", span.file, span.start_line, span.end_line)))
+        };
+        for duplicate in self.duplicates.iter() {
+            writeln!(f, "{} {} tokens", color::paint(Stream::Stdout, Style::Yellow, &format!("{:.0}%", duplicate.similarity * 100.0)), duplicate.tokens)?;
+            writeln!(f, "  {}", describe(&duplicate.first))?;
+            writeln!(f, "  {}", describe(&duplicate.second))?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{} duplicated subtrees, consider extracting them", self.duplicates.len())
+    }
+}
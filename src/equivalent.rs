@@ -0,0 +1,53 @@
+use tree_sitter::Node as TSNode;
+
+use crate::color::{self, Stream, Style};
+use crate::error::DiffGraphError;
+use crate::graph::{DiffGraph, DiffGraphParams};
+use crate::structural;
+
+/// Longest excerpt of a differing token shown
+const MAX_TOKEN_CHARS: usize = 40;
+
+fn describe(node: Option<TSNode>, source: &str) -> String {
+    match node {
+        Some(node) => {
+            let text: String = node.utf8_text(source.as_bytes()).unwrap_or("").chars().take(MAX_TOKEN_CHARS).collect();
+            format!("`{}` at line {}", text, node.start_position().row + 1)
+        },
+        None => "the end of the file".to_string(),
+    }
+}
+
+/// Checks that the patch changes nothing but whitespace, layout and comments, such as a
+/// formatter's or a codemod's, by comparing the tokens of every changed file before and after
+/// it. Prints a line per file, and fails when a file differs or could not be compared.
+pub fn run(params: DiffGraphParams) -> Result<(), DiffGraphError> {
+    let graph = DiffGraph::create(params)?;
+
+    let mut differing = 0;
+    for diff in graph.diffs().iter() {
+        match structural::find_first_difference(diff) {
+            None => println!("{} {}", color::paint(Stream::Stdout, Style::Green, "equivalent"), diff.source_file_path),
+            Some((old, new)) => {
+                differing += 1;
+                println!("{} {}: {} became {}", color::paint(Stream::Stdout, Style::Red, "differs"), diff.source_file_path,
+                    describe(old, &diff.source), describe(new, &diff.target_source));
+            },
+        }
+    }
+    // Files the graph left out were not compared, so nothing can be said of them
+    for skipped in graph.skipped().iter() {
+        differing += 1;
+        println!("{} {} ({})", color::paint(Stream::Stdout, Style::Yellow, "unchecked"), skipped.path, skipped.reason);
+    }
+    for error in graph.errors().iter() {
+        differing += 1;
+        println!("{} {} ({})", color::paint(Stream::Stdout, Style::Yellow, "unchecked"), error.path, error.message);
+    }
+
+    match differing {
+        0 => Ok(()),
+        count => Err(DiffGraphError::NotEquivalent(format!("{} of {} changed files are not equivalent to what they were before the patch, or could not be compared",
+            count, graph.diffs().len() + graph.skipped().len() + graph.errors().len()))),
+    }
+}
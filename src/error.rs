@@ -0,0 +1,166 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde::Serialize;
+use tracing::error;
+
+use crate::limits::Truncation;
+use crate::policy::Finding;
+
+static JSON_ERROR_FORMAT: AtomicBool = AtomicBool::new(false);
+
+/// Stable identifiers for the class of failure, emitted with `--error-format json`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    Usage,
+    Repository,
+    Patch,
+    GrammarConfig,
+    GrammarMissing,
+    Parse,
+    Network,
+    Io,
+    LimitExceeded,
+    Cancelled,
+    BreakingChange,
+    SecurityPattern,
+    NotEquivalent,
+    DebtPattern,
+    ArchitectureViolation,
+}
+
+impl ErrorCode {
+    /// Process exit code for this class of failure. 0 is reserved for success.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ErrorCode::Usage => 1,
+            ErrorCode::Parse => 2,
+            ErrorCode::GrammarMissing => 3,
+            ErrorCode::GrammarConfig => 4,
+            ErrorCode::Repository => 5,
+            ErrorCode::Patch => 6,
+            ErrorCode::Network => 7,
+            ErrorCode::Io => 8,
+            ErrorCode::LimitExceeded => 9,
+            ErrorCode::Cancelled => 10,
+            ErrorCode::BreakingChange => 11,
+            ErrorCode::SecurityPattern => 12,
+            ErrorCode::NotEquivalent => 13,
+            ErrorCode::DebtPattern => 14,
+            ErrorCode::ArchitectureViolation => 15,
+        }
+    }
+}
+
+pub const EXIT_CODES_HELP: &str = "Exit codes:
+  0  success
+  1  usage error
+  2  source file could not be parsed
+  3  no grammar available for a patched file
+  4  grammar configuration could not be loaded
+  5  repository could not be found or cloned
+  6  diff could not be read or applied
+  7  network failure
+  8  I/O failure
+  9  a --max-* limit was exceeded with --on-limit fail
+  10 cancelled by Ctrl-C or --timeout
+  11 a public declaration was removed or changed with --fail-on breaking-changes
+  12 a changed node matched a security-sensitive pattern with --fail-on security-patterns
+  13 equivalent found a file changed beyond whitespace, layout and comments
+  14 a changed node added a TODO, a panic or a similar pattern with --fail-on debt-patterns
+  15 the patch added a dependency the architecture rules forbid with --fail-on architecture-violations";
+
+/// Everything that can go wrong while building a graph, grouped the way callers handle it.
+#[derive(Debug, thiserror::Error)]
+pub enum DiffGraphError {
+    #[error("{0}")]
+    Usage(String),
+    /// The diffgraph config file could not be read or parsed
+    #[error("{0}")]
+    Config(String),
+    /// A git command failed, or the repository could not be found or cloned
+    #[error("{0}")]
+    Git(String),
+    /// The diff could not be read, parsed or applied to its source files
+    #[error("{0}")]
+    Patch(String),
+    /// The tree-sitter grammar configuration could not be loaded or used
+    #[error("{0}")]
+    Grammar(String),
+    #[error("Unable to determine language using tree-sitter parsers for file {}.\nCurrently configured tree-sitter paths: {:?}", path.display(), parser_directories)]
+    GrammarMissing {
+        path: PathBuf,
+        parser_directories: Vec<String>,
+    },
+    #[error("{0}")]
+    Parse(String),
+    #[error("{0}")]
+    Network(String),
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{0}")]
+    LimitExceeded(Truncation),
+    /// The run was interrupted or ran past --timeout
+    #[error("{0}")]
+    Cancelled(String),
+    /// A finding whose condition the policy fails on
+    #[error("[{}] {}", .0.condition, .0.message)]
+    Policy(Finding),
+    /// The patch changes more than whitespace, layout and comments, see `equivalent::run`
+    #[error("{0}")]
+    NotEquivalent(String),
+}
+
+impl DiffGraphError {
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        DiffGraphError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            DiffGraphError::Usage(_) | DiffGraphError::Config(_) => ErrorCode::Usage,
+            DiffGraphError::Git(_) => ErrorCode::Repository,
+            DiffGraphError::Patch(_) => ErrorCode::Patch,
+            DiffGraphError::Grammar(_) => ErrorCode::GrammarConfig,
+            DiffGraphError::GrammarMissing { .. } => ErrorCode::GrammarMissing,
+            DiffGraphError::Parse(_) => ErrorCode::Parse,
+            DiffGraphError::Network(_) => ErrorCode::Network,
+            DiffGraphError::Io { .. } => ErrorCode::Io,
+            DiffGraphError::LimitExceeded(_) => ErrorCode::LimitExceeded,
+            DiffGraphError::Cancelled(_) => ErrorCode::Cancelled,
+            DiffGraphError::Policy(finding) => finding.condition.error_code(),
+            DiffGraphError::NotEquivalent(_) => ErrorCode::NotEquivalent,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Text,
+    Json,
+}
+
+pub fn set_format(format: ErrorFormat) {
+    JSON_ERROR_FORMAT.store(format == ErrorFormat::Json, Ordering::Relaxed);
+}
+
+/// Writes the error to stderr in the configured error format.
+pub fn report(e: &DiffGraphError) {
+    if JSON_ERROR_FORMAT.load(Ordering::Relaxed) {
+        let error = serde_json::json!({ "code": e.code(), "message": e.to_string() });
+        match serde_json::to_string(&serde_json::json!({ "error": error, "exit_code": e.code().exit_code() })) {
+            Ok(json) => eprintln!("{}", json),
+            Err(_) => error!("{}", e),
+        }
+    } else {
+        error!("{}", e);
+    }
+}
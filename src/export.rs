@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::annotations::CheckOutput;
+use crate::bundle;
+use crate::cache::ResultCache;
+use crate::color;
+use crate::api::ApiChanges;
+use crate::comment::{self, CommentTarget};
+use crate::error::DiffGraphError;
+use crate::gerrit::{self, GerritReview};
+use crate::graph::{DiffGraph, NodeInfo};
+use crate::narrative;
+use crate::webhook;
+use crate::sarif::SarifLog;
+use crate::snapshot::GraphSnapshot;
+use crate::shard;
+use crate::structural;
+use crate::summary::Summary;
+use crate::viewer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Dot,
+    /// Markdown comment for a pull request
+    PrComment,
+    /// GitHub check run output with annotations on the patched lines
+    Annotations,
+    /// Directory of JSON, SARIF, markdown and SVG files for CI to archive
+    CiBundle,
+    /// Text listing how each changed declaration changed, field by field
+    Structural,
+    /// Sparse adjacency matrix in Matrix Market format, with node index and string table files next to it
+    MatrixMarket,
+    /// SARIF log of the security-sensitive changes and added debt, for code scanning dashboards
+    Sarif,
+    /// Markdown prose on what changed, for pull request descriptions
+    SummaryMd,
+    /// JSON of the public declarations the patch adds, removes or changes, for release notes
+    ApiChanges,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 10] = [
+        ExportFormat::Json,
+        ExportFormat::Dot,
+        ExportFormat::PrComment,
+        ExportFormat::Annotations,
+        ExportFormat::CiBundle,
+        ExportFormat::Structural,
+        ExportFormat::MatrixMarket,
+        ExportFormat::Sarif,
+        ExportFormat::SummaryMd,
+        ExportFormat::ApiChanges,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Dot => "dot",
+            ExportFormat::PrComment => "pr-comment",
+            ExportFormat::Annotations => "annotations",
+            ExportFormat::CiBundle => "ci-bundle",
+            ExportFormat::Structural => "structural",
+            ExportFormat::MatrixMarket => "matrix-market",
+            ExportFormat::Sarif => "sarif",
+            ExportFormat::SummaryMd => "summary-md",
+            ExportFormat::ApiChanges => "api-changes",
+        }
+    }
+
+    /// Whether the format is data for other tools, which `--compress` can gzip, rather than
+    /// text meant for people or viewers.
+    pub fn is_compressible(&self) -> bool {
+        matches!(self, ExportFormat::Json | ExportFormat::Annotations | ExportFormat::MatrixMarket | ExportFormat::Sarif | ExportFormat::ApiChanges)
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ExportFormat::ALL
+            .iter()
+            .find(|format| format.as_str() == s)
+            .copied()
+            .ok_or_else(|| format!("Unknown format '{}', expected one of: {}", s,
+                ExportFormat::ALL.map(|format| format.as_str()).join(", ")))
+    }
+}
+
+/// Where the graph is written, `-` on the command line meaning stdout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputTarget {
+    Stdout,
+    File(PathBuf),
+}
+
+impl OutputTarget {
+    pub fn from_arg(arg: &str) -> Self {
+        if arg == "-" {
+            OutputTarget::Stdout
+        } else {
+            OutputTarget::File(PathBuf::from(arg))
+        }
+    }
+
+    /// Opens the target for writing, gzip compressing what is written when `compress` is set.
+    pub fn try_open(&self, compress: bool) -> Result<ExportWriter, DiffGraphError> {
+        let writer: Box<dyn Write> = match self {
+            OutputTarget::Stdout => Box::new(io::stdout().lock()),
+            OutputTarget::File(path) => File::create(path)
+                .map(|file| Box::new(file) as Box<dyn Write>)
+                .map_err(|e| DiffGraphError::io(format!("Unable to create '{}'", path.display()), e))?,
+        };
+        let writer = BufWriter::with_capacity(BUFFER_SIZE, writer);
+        let encoder = if compress {
+            Encoder::Gzip(GzEncoder::new(writer, Compression::default()))
+        } else {
+            Encoder::Plain(writer)
+        };
+        Ok(ExportWriter { encoder, unflushed: 0 })
+    }
+}
+
+const BUFFER_SIZE: usize = 64 * 1024;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Bytes written between flushes, so whoever reads the export from a pipe gets it in chunks as
+/// it is written rather than all at once at the end.
+const FLUSH_INTERVAL: usize = 1024 * 1024;
+
+enum Encoder {
+    Plain(BufWriter<Box<dyn Write>>),
+    Gzip(GzEncoder<BufWriter<Box<dyn Write>>>),
+}
+
+/// A buffered writer to an `OutputTarget`, flushed every `FLUSH_INTERVAL` bytes. Must be
+/// finished with `try_finish`, which ends the gzip stream when compressing.
+pub struct ExportWriter {
+    encoder: Encoder,
+    unflushed: usize,
+}
+
+impl ExportWriter {
+    pub fn try_finish(self) -> Result<(), DiffGraphError> {
+        match self.encoder {
+            Encoder::Plain(mut writer) => writer.flush(),
+            Encoder::Gzip(encoder) => encoder.finish().and_then(|mut writer| writer.flush()),
+        }.map_err(|e| DiffGraphError::io("Unable to write the export", e))
+    }
+}
+
+impl Write for ExportWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = match &mut self.encoder {
+            Encoder::Plain(writer) => writer.write(buf)?,
+            Encoder::Gzip(encoder) => encoder.write(buf)?,
+        };
+        self.unflushed += written;
+        if self.unflushed >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.unflushed = 0;
+        match &mut self.encoder {
+            Encoder::Plain(writer) => writer.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// What to do with a graph once it has been built.
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    /// Print a per-file summary table instead of exporting the graph
+    pub summary: bool,
+    /// Format to export the graph in, or None to not export it
+    pub format: Option<ExportFormat>,
+    pub target: OutputTarget,
+    /// Gzip compress the export
+    pub compress: bool,
+    /// Pull request to post the markdown comment on
+    pub comment: Option<CommentTarget>,
+    /// Gerrit patchset to post the summary on as a review
+    pub review: Option<GerritReview>,
+    /// Open the export in a viewer once it is written
+    pub open: bool,
+    /// Webhook to POST the JSON export, or the summary, to
+    pub notify_url: Option<String>,
+    /// Where the export is cached, when nothing but the export is asked for
+    pub cache: Option<ResultCache>,
+}
+
+fn write_json(graph: &DiffGraph, writer: &mut dyn Write) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, &GraphSnapshot::from_graph(graph))?;
+    writeln!(writer)
+}
+
+fn write_annotations(graph: &DiffGraph, writer: &mut dyn Write) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, &CheckOutput::from_graph(graph))?;
+    writeln!(writer)
+}
+
+fn write_sarif(graph: &DiffGraph, writer: &mut dyn Write) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, &SarifLog::from_graph(graph))?;
+    writeln!(writer)
+}
+
+fn write_api_changes(graph: &DiffGraph, writer: &mut dyn Write) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, &ApiChanges::from_graph(graph))?;
+    writeln!(writer)
+}
+
+fn write_dot(graph: &DiffGraph, writer: &mut dyn Write) -> io::Result<()> {
+    write_file_dot(graph, None, writer)
+}
+
+/// DOT of the whole graph, or only of the nodes and edges within one file.
+pub fn write_file_dot(graph: &DiffGraph, file: Option<usize>, writer: &mut dyn Write) -> io::Result<()> {
+    let selected = |node: &NodeInfo| file.is_none() || file == Some(node.file);
+    let ids = graph.stable_ids();
+    writeln!(writer, "digraph diff {{")?;
+    for node in graph.sorted_nodes().into_iter().filter(|node| selected(node)) {
+        let file = graph.diffs().get(node.file).map_or("", |diff| diff.source_file_path.as_str());
+        writeln!(writer, "  n{} [label=\"{}\" tooltip=\"{}:{}-{}\"];", ids[&node.id],
+            node.kind.replace('"', "\\\""), file, node.byte_range.start, node.byte_range.end)?;
+    }
+    for edge in graph.sorted_edges(&ids).into_iter().filter(|edge| selected(&edge.from) && selected(&edge.to)) {
+        writeln!(writer, "  n{} -> n{};", ids[&edge.from.id], ids[&edge.to.id])?;
+    }
+    writeln!(writer, "}}")
+}
+
+/// The node kinds and file paths of the graph, each once, so the node index refers to them by
+/// position instead of repeating them on every row. Paths come first, in patch order, so a
+/// node's file is also its index in the table.
+fn string_table(graph: &DiffGraph) -> (Vec<&str>, HashMap<&str, usize>) {
+    let mut strings: Vec<&str> = graph.diffs().iter().map(|diff| diff.source_file_path.as_str()).collect();
+    let mut kinds = HashMap::new();
+    for node in graph.sorted_nodes() {
+        let kind: &str = &node.kind;
+        if !kinds.contains_key(kind) {
+            kinds.insert(kind, strings.len());
+            strings.push(kind);
+        }
+    }
+    (strings, kinds)
+}
+
+fn write_string_table(graph: &DiffGraph, writer: &mut dyn Write) -> io::Result<()> {
+    writeln!(writer, "string\ttext")?;
+    for (index, text) in string_table(graph).0.into_iter().enumerate() {
+        writeln!(writer, "{}\t{}", index, text)?;
+    }
+    Ok(())
+}
+
+/// Rows of the matrix in order, 1-based as Matrix Market indices are, with the kind and file
+/// of each node as rows of the string table.
+fn write_node_index(graph: &DiffGraph, writer: &mut dyn Write) -> io::Result<()> {
+    let (_, kinds) = string_table(graph);
+    writeln!(writer, "index\tid\tkind_string\tfile_string\tstart_byte\tend_byte")?;
+    for (index, node) in graph.sorted_nodes().into_iter().enumerate() {
+        let kind: &str = &node.kind;
+        writeln!(writer, "{}\t{}\t{}\t{}\t{}\t{}", index + 1, index, kinds[kind], node.file, node.byte_range.start, node.byte_range.end)?;
+    }
+    Ok(())
+}
+
+fn write_matrix_market(graph: &DiffGraph, writer: &mut dyn Write) -> io::Result<()> {
+    // Rows and columns are the stable ids, 1-based
+    let ids = graph.stable_ids();
+    writeln!(writer, "%%MatrixMarket matrix coordinate pattern general")?;
+    writeln!(writer, "% diffgraph adjacency matrix, row i has an entry in column j for an edge from node i to node j")?;
+    writeln!(writer, "{} {} {}", ids.len(), ids.len(), graph.edge_count())?;
+    for edge in graph.sorted_edges(&ids) {
+        writeln!(writer, "{} {}", ids[&edge.from.id] + 1, ids[&edge.to.id] + 1)?;
+    }
+    Ok(())
+}
+
+/// Writes the matrix to the path, the node index to `<path>.nodes.tsv` and the string table it
+/// refers to to `<path>.strings.tsv`, or to `.tsv.gz` files when compressing, in which case the
+/// path should end in `.mtx.gz`.
+fn try_write_matrix_market(graph: &DiffGraph, path: &Path, compress: bool) -> Result<(), DiffGraphError> {
+    let sidecar_path = |extension: &str| match compress {
+        true => path.with_extension("").with_extension(format!("{}.tsv.gz", extension)),
+        false => path.with_extension(format!("{}.tsv", extension)),
+    };
+    let index_path = sidecar_path("nodes");
+    let strings_path = sidecar_path("strings");
+    for (path, write) in [
+        (path, write_matrix_market as fn(&DiffGraph, &mut dyn Write) -> io::Result<()>),
+        (index_path.as_path(), write_node_index),
+        (strings_path.as_path(), write_string_table),
+    ] {
+        let mut writer = OutputTarget::File(path.to_path_buf()).try_open(compress)?;
+        write(graph, &mut writer)
+            .map_err(|e| DiffGraphError::io(format!("Unable to write '{}'", path.display()), e))?;
+        writer.try_finish()?;
+    }
+    Ok(())
+}
+
+/// Writes an already rendered export, such as one from the result cache, to the target.
+pub fn try_write_export(target: &OutputTarget, compress: bool, export: &[u8]) -> Result<(), DiffGraphError> {
+    let mut writer = target.try_open(compress)?;
+    writer.write_all(export)
+        .map_err(|e| DiffGraphError::io("Unable to write the export", e))?;
+    writer.try_finish()
+}
+
+/// Reads back a graph exported in the json format, gzip compressed or not.
+pub fn try_read_snapshot(path: &Path) -> Result<GraphSnapshot, DiffGraphError> {
+    let contents = std::fs::read(path)
+        .map_err(|e| DiffGraphError::io(format!("Unable to read '{}'", path.display()), e))?;
+    let snapshot = match contents.starts_with(&GZIP_MAGIC) {
+        true => serde_json::from_reader(GzDecoder::new(contents.as_slice())),
+        false => serde_json::from_slice(&contents),
+    };
+    snapshot.map_err(|e| DiffGraphError::Usage(format!("'{}' is not a json graph: {}", path.display(), e)))
+}
+
+/// Writes the graphs of every shard of a patch, read from the inputs, as one.
+pub fn try_merge(inputs: &[PathBuf], target: &OutputTarget, compress: bool) -> Result<(), DiffGraphError> {
+    let snapshots = inputs
+        .iter()
+        .map(|input| try_read_snapshot(input))
+        .collect::<Result<Vec<_>, _>>()?;
+    let merged = shard::try_merge(snapshots)?;
+
+    let mut writer = target.try_open(compress)?;
+    serde_json::to_writer(&mut writer, &merged)
+        .map_err(io::Error::from)
+        .and_then(|_| writeln!(writer))
+        .map_err(|e| DiffGraphError::io("Unable to write the merged graph", e))?;
+    writer.try_finish()
+}
+
+/// Copies everything written to the export, when it is also kept for the result cache.
+struct Recorder<'a> {
+    writer: &'a mut dyn Write,
+    copy: Option<Vec<u8>>,
+}
+
+impl Write for Recorder<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        if let Some(copy) = &mut self.copy {
+            copy.extend_from_slice(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Prints the summary, exports the graph, comments on a pull request or Gerrit change, opens
+/// a viewer and notifies a webhook, as the options ask.
+pub fn write(graph: &DiffGraph, options: &OutputOptions) -> Result<(), DiffGraphError> {
+    if options.summary {
+        print!("{}", Summary::from_graph(graph));
+        if let Some(url) = &options.notify_url {
+            webhook::try_notify(url, graph, true)?;
+        }
+        return Ok(());
+    }
+
+    match (options.format, &options.target) {
+        (Some(ExportFormat::CiBundle), OutputTarget::File(dir)) => bundle::try_write(graph, dir)?,
+        (Some(ExportFormat::MatrixMarket), OutputTarget::File(path)) => try_write_matrix_market(graph, path, options.compress)?,
+        (Some(format @ (ExportFormat::CiBundle | ExportFormat::MatrixMarket)), OutputTarget::Stdout) =>
+            return Err(DiffGraphError::Usage(format!("The {} format writes more than one file, name where with --output", format))),
+        (Some(format), target) => {
+            // Written straight to the target as it is rendered, rather than rendered whole first
+            let mut writer = target.try_open(options.compress)?;
+            let mut export = Recorder { writer: &mut writer, copy: options.cache.as_ref().map(|_| Vec::new()) };
+            match format {
+                ExportFormat::Json => write_json(graph, &mut export),
+                ExportFormat::Dot => write_dot(graph, &mut export),
+                ExportFormat::PrComment => export.write_all(comment::to_markdown(graph).as_bytes()),
+                ExportFormat::Annotations => write_annotations(graph, &mut export),
+                ExportFormat::Sarif => write_sarif(graph, &mut export),
+                ExportFormat::SummaryMd => export.write_all(narrative::to_markdown(graph).as_bytes()),
+                ExportFormat::ApiChanges => write_api_changes(graph, &mut export),
+                ExportFormat::Structural => structural::write(graph, &mut export, *target == OutputTarget::Stdout),
+                ExportFormat::CiBundle | ExportFormat::MatrixMarket => unreachable!(),
+            }.map_err(|e| DiffGraphError::io(format!("Unable to write the {} export", format), e))?;
+            let copy = export.copy;
+            writer.try_finish()?;
+            if let (Some(cache), Some(copy)) = (&options.cache, copy) {
+                cache.store(&copy);
+            }
+        },
+        (None, _) => {},
+    }
+
+    if let Some(target) = &options.comment {
+        comment::try_post(&comment::to_markdown(graph), target)?;
+    }
+    if let Some(review) = &options.review {
+        gerrit::try_post_review(&color::strip(&Summary::from_graph(graph).to_string()), review)?;
+    }
+    if options.open {
+        viewer::try_open(graph, options)?;
+    }
+    if let Some(url) = &options.notify_url {
+        webhook::try_notify(url, graph, false)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use crate::error::DiffGraphError;
+
+/// A patchset to post a review on, and the Gerrit account to post it as.
+#[derive(Debug, Clone)]
+pub struct GerritReview {
+    /// Scheme and host of the Gerrit server, such as `https://review.example.com`
+    pub server_url: String,
+    pub project: String,
+    pub change: u64,
+    pub patchset: u32,
+    pub username: String,
+    /// HTTP password from the user's Gerrit settings, not their login password
+    pub password: String,
+}
+
+impl GerritReview {
+    fn review_url(&self) -> String {
+        // Projects are addressed by their URL-encoded name, slashes included
+        format!("{}/a/changes/{}~{}/revisions/{}/review", self.server_url,
+            self.project.replace('%', "%25").replace('/', "%2F"), self.change, self.patchset)
+    }
+}
+
+/// Posts the message as a review comment on the patchset through the Gerrit REST API.
+pub fn try_post_review(message: &str, review: &GerritReview) -> Result<(), DiffGraphError> {
+    let body = serde_json::json!({ "message": message }).to_string();
+    reqwest::blocking::Client::new()
+        .post(review.review_url())
+        .basic_auth(&review.username, Some(&review.password))
+        .header("Content-Type", "application/json")
+        .body(body)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| DiffGraphError::Network(format!("Unable to post a review on change {},{}: {}",
+            review.change, review.patchset, e)))?;
+    Ok(())
+}
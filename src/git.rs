@@ -0,0 +1,277 @@
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use regex::Regex;
+use tracing::debug;
+
+use crate::error::DiffGraphError;
+use crate::jj;
+use crate::patch::{try_create_parsed_diff, try_create_repaired_parsed_diff, try_repair_diff, ParsedDiff};
+
+/// Commits given by their ids: `A..B`, or a single commit diffed against HEAD.
+fn parse_commit_range(arg: &str) -> Option<(String, String)> {
+    let re = Regex::new(r"^([0-9a-fA-F]+)\.\.([0-9a-fA-F]+)$").unwrap();
+    if let Some(captures) = re.captures(arg) {
+        Some((captures.get(1).unwrap().as_str().into(), captures.get(2).unwrap().as_str().into()))
+    } else {
+        let re = Regex::new(r"^[0-9a-fA-F]{6,64}$").unwrap();
+        if re.is_match(arg) {
+            Some(("HEAD".into(), arg.into()))
+        } else {
+            None
+        }
+    }
+}
+
+/// The changes between two revisions of the repository, as git diffs them.
+pub fn try_get_diff_patch(repo_path: &Path, rev_from: &str, rev_to: &str) -> Result<String, DiffGraphError> {
+    let cmd_gitdiff = Command::new("git")
+        .arg("diff")
+        .arg(format!("{}..{}", rev_from, rev_to))
+        .current_dir(repo_path)
+        .output();
+
+    match cmd_gitdiff {
+        Ok(output) if output.status.success() => Ok(String::from_utf8_lossy(&output.stdout).to_string()),
+        Ok(output) => Err(DiffGraphError::Git(format!("Unable to diff {}..{}: {}", rev_from, rev_to, String::from_utf8_lossy(&output.stderr).trim()))),
+        Err(e) => Err(DiffGraphError::io("Unable to run git diff", e))
+    }
+}
+
+/// Whether the diff, read from a file or stdin, applies to the repository. git apply checks
+/// diffs against Mercurial and Subversion working copies too, as it does outside of repositories.
+fn try_check_apply_diff(diff: &str, repo_path: &Path) -> Result<bool, DiffGraphError> {
+    let mut cmd_gitapply = Command::new("git")
+        .arg("apply")
+        .arg("--check")
+        .current_dir(repo_path)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| DiffGraphError::io("Unable to run git apply", e))?;
+
+    if let Some(mut stdin) = cmd_gitapply.stdin.take() {
+        stdin.write_all(diff.as_bytes()).map_err(|e| DiffGraphError::io("Unable to pass the diff to git apply", e))?;
+    }
+    let status = cmd_gitapply.wait().map_err(|e| DiffGraphError::io("Unable to run git apply", e))?;
+
+    Ok(status.success())
+}
+
+fn git_revision_exists(repo_path: &Path, revision: &str) -> bool {
+    Command::new("git")
+        .arg("rev-parse")
+        .arg("--verify")
+        .arg("--quiet")
+        .arg(format!("{}^{{commit}}", revision))
+        .current_dir(repo_path)
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+fn git_branch_exists(repo_path: &Path, branch: &str) -> bool {
+    ["refs/heads/", "refs/remotes/"]
+        .iter()
+        .any(|prefix| git_revision_exists(repo_path, &format!("{}{}", prefix, branch)))
+}
+
+fn try_get_merge_base(repo_path: &Path, rev_a: &str, rev_b: &str) -> Result<String, DiffGraphError> {
+    let output = Command::new("git")
+        .arg("merge-base")
+        .arg(rev_a)
+        .arg(rev_b)
+        .current_dir(repo_path)
+        .output()
+        .map_err(|e| DiffGraphError::io("Unable to run git merge-base", e))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(DiffGraphError::Git(format!("{} and {} have no common ancestor", rev_a, rev_b)))
+    }
+}
+
+/// The branch origin/HEAD points to, or else a local main or master branch
+fn try_get_default_branch(repo_path: &Path) -> Result<String, DiffGraphError> {
+    let output = Command::new("git")
+        .arg("symbolic-ref")
+        .arg("--quiet")
+        .arg("--short")
+        .arg("refs/remotes/origin/HEAD")
+        .current_dir(repo_path)
+        .output()
+        .map_err(|e| DiffGraphError::io("Unable to run git symbolic-ref", e))?;
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+    }
+
+    ["main", "master"]
+        .iter()
+        .find(|branch| git_branch_exists(repo_path, branch))
+        .map(|branch| branch.to_string())
+        .ok_or_else(|| DiffGraphError::Git(format!("Unable to determine the default branch of {}", repo_path.display())))
+}
+
+/// Resolves named revisions the way git does: `a..b`, `a...b` (from the merge base of a and b),
+/// and a single branch meaning the changes made on it since it forked from the default branch.
+pub fn try_resolve_revision_range(repo_path: &Path, arg: &str) -> Result<Option<(String, String)>, DiffGraphError> {
+    fn or_head(rev: &str) -> &str {
+        if rev.is_empty() { "HEAD" } else { rev }
+    }
+
+    if let Some((from, to)) = arg.split_once("...") {
+        let (from, to) = (or_head(from), or_head(to));
+        if !git_revision_exists(repo_path, from) || !git_revision_exists(repo_path, to) {
+            return Ok(None);
+        }
+        return Ok(Some((try_get_merge_base(repo_path, from, to)?, to.to_string())));
+    }
+    if let Some((from, to)) = arg.split_once("..") {
+        let (from, to) = (or_head(from), or_head(to));
+        if !git_revision_exists(repo_path, from) || !git_revision_exists(repo_path, to) {
+            return Ok(None);
+        }
+        return Ok(Some((from.to_string(), to.to_string())));
+    }
+    if git_branch_exists(repo_path, arg) {
+        let default_branch = try_get_default_branch(repo_path)?;
+        debug!("Diffing {} from its merge base with {}", arg, default_branch);
+        return Ok(Some((try_get_merge_base(repo_path, &default_branch, arg)?, arg.to_string())));
+    }
+
+    Ok(None)
+}
+
+/// The commits of a range of revisions, oldest first. Merge commits are left out, their changes
+/// are in the commits they merge.
+pub fn try_list_commits(repo_path: &Path, range: &str) -> Result<Vec<String>, DiffGraphError> {
+    let Some((from, to)) = try_resolve_revision_range(repo_path, range)? else {
+        return Err(DiffGraphError::Usage(format!("'{}' is not a range of revisions such as A..B", range)));
+    };
+    let output = Command::new("git")
+        .arg("rev-list")
+        .arg("--reverse")
+        .arg("--no-merges")
+        .arg(format!("{}..{}", from, to))
+        .current_dir(repo_path)
+        .output()
+        .map_err(|e| DiffGraphError::io("Unable to run git rev-list", e))?;
+    if !output.status.success() {
+        return Err(DiffGraphError::Git(format!("Unable to list the commits of {}: {}", range, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().map(|commit| commit.to_string()).collect())
+}
+
+/// The changes a commit made to its first parent.
+pub fn try_get_commit_diff(repo_path: &Path, commit: &str) -> Result<ParsedDiff, DiffGraphError> {
+    let parent = format!("{}^", commit);
+    try_create_parsed_diff(&try_get_diff_patch(repo_path, &parent, commit)?, Some(parent))
+}
+
+fn try_load_diff_file(path: &Path) -> Result<String, DiffGraphError> {
+    match std::fs::read_to_string(path) {
+        Ok(file_contents) => Ok(file_contents),
+        Err(err) => Err(DiffGraphError::io(format!("Unable to read '{}'", path.display()), err)),
+    }
+}
+
+/// Uncommitted changes in the working tree relative to HEAD.
+pub fn try_get_uncommitted_patch(repo_path: &Path) -> Result<ParsedDiff, DiffGraphError> {
+    let output = Command::new("git")
+        .arg("diff")
+        .arg("HEAD")
+        .current_dir(repo_path)
+        .output()
+        .map_err(|e| DiffGraphError::io("Unable to run git diff", e))?;
+
+    try_create_parsed_diff(&String::from_utf8_lossy(&output.stdout), Some("HEAD".into()))
+}
+
+/// The diff a `--diff` argument names in a local repository: `-` for stdin, git commits,
+/// ranges and branches, jj change ids and revsets, or a patch file that applies to it.
+pub fn try_parse_diff(diff_arg: &str, repo_path: &Path) -> Result<ParsedDiff, DiffGraphError> {
+    if diff_arg == "-" {
+        let mut diff = String::new();
+        std::io::stdin().read_to_string(&mut diff).map_err(|e| DiffGraphError::io("Unable to read diff from stdin", e))?;
+        let diff = try_repair_diff(&diff)?;
+        if !try_check_apply_diff(&diff, repo_path)? {
+            return Err(DiffGraphError::Patch(format!("diff from stdin could not be applied to repository at {:?}", repo_path.display())));
+        }
+        return try_create_repaired_parsed_diff(&diff, None);
+    }
+
+    // Jujutsu change ids and revsets are resolved by jj, which git knows nothing of
+    if jj::is_repository(repo_path) && !Path::new(diff_arg).exists() {
+        if let Some((from, to)) = jj::try_resolve_range(repo_path, diff_arg)? {
+            debug!("Diffing jj revisions {}..{}", from, to);
+            let mut diff = try_create_parsed_diff(&jj::try_get_diff_patch(repo_path, &from, &to)?, Some(from))?;
+            // Files are read at the revision with git, from the store within .jj unless it is colocated
+            if !dir_is_git_repository(repo_path) {
+                diff.git_dir = jj::get_git_dir(repo_path);
+            }
+            return Ok(diff);
+        }
+    }
+
+    let diff_from_commit;
+    let mut source_revision = None;
+    match parse_commit_range(diff_arg) {
+        Some((from, to)) => match try_get_diff_patch(repo_path, &from, &to) {
+            Ok(patch) => {
+                diff_from_commit = Some(patch);
+                source_revision = Some(from);
+            },
+            Err(err) => return Err(err),
+        },
+        // Anything that is not a file may still be a branch or range of named revisions
+        None if !Path::new(diff_arg).exists() => match try_resolve_revision_range(repo_path, diff_arg)? {
+            Some((from, to)) => {
+                diff_from_commit = Some(try_get_diff_patch(repo_path, &from, &to)?);
+                source_revision = Some(from);
+            },
+            None => diff_from_commit = None,
+        },
+        None => diff_from_commit = None,
+    };
+    let diff;
+    if let Some(diff_from_commit) = diff_from_commit {
+        diff = try_repair_diff(&diff_from_commit)?;
+    } else {
+        let path = Path::new(diff_arg);
+        if !path.exists() {
+            return Err(DiffGraphError::Usage(format!("diff '{:?}' is neither a patch file nor a git revision or branch.", path)))
+        }
+        if path.is_dir() {
+            return Err(DiffGraphError::Usage("diff path must be a file, directory is not supported at the moment...".into()))
+        }
+        // Load it and check that it can apply to our repository once repaired
+        diff = try_repair_diff(&try_load_diff_file(path)?)?;
+        if !try_check_apply_diff(&diff, repo_path)? {
+            return Err(DiffGraphError::Patch(format!("diff '{:?}' could not be applied to repository at {:?}", path, repo_path.display())));
+        }
+    }
+
+    try_create_repaired_parsed_diff(&diff, source_revision)
+}
+
+/// Whether the directory is within the working tree of a git repository.
+pub fn dir_is_git_repository(dir: &Path) -> bool {
+    let output = Command::new("git")
+        .arg("rev-parse")
+        .arg("--is-inside-work-tree")
+        .current_dir(dir)
+        .output()
+        .expect("Failed to execute git command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+
+    stdout.trim() == "true" && stderr.is_empty()
+}
+
+/// Whether the directory is the root of a Mercurial or Subversion working copy, which diffs
+/// from `hg diff` and `svn diff` apply to. Only diff files can be graphed against them, as
+/// revisions are read with git.
+pub fn dir_is_working_copy(dir: &Path) -> bool {
+    dir.join(".hg").is_dir() || dir.join(".svn").is_dir()
+}
@@ -1,19 +1,172 @@
 use tree_sitter::Language;
+#[cfg(not(target_arch = "wasm32"))]
 use tree_sitter_loader::*;
-use url::Url;
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::{HashMap, HashSet};
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::hash_map::DefaultHasher;
+#[cfg(not(target_arch = "wasm32"))]
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{MutexGuard, OnceLock};
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+#[cfg(feature = "cli")]
+use regex::Regex;
 use serde::{Serialize, Deserialize};
+use tracing::debug;
+#[cfg(not(target_arch = "wasm32"))]
+use tracing::{info, warn};
 
+use crate::error::DiffGraphError;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::progress;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::stats;
+
+#[cfg(not(target_arch = "wasm32"))]
 const TREE_SITTER_CONFIG_FILE: &'static str = "config.json";
 const PARSERS_CONFIG_FILE: &'static str = "parsers.json";
+#[cfg(not(target_arch = "wasm32"))]
 const PARSERS_PATH: &'static str = "parsers";
+const GRAMMAR_CACHE_PATH: &str = "diffgraph/grammars";
+/// Where `Loader::new` looks for compiled grammars, under the cache directory
+#[cfg(not(target_arch = "wasm32"))]
+const LOADER_LIB_PATH: &str = "tree-sitter/lib";
+#[cfg(not(target_arch = "wasm32"))]
+const DYLIB_EXTENSION: &str = if cfg!(windows) { "dll" } else { "so" };
+#[cfg(feature = "cli")]
+const PARSER_REGISTRY_URL: &str = "https://raw.githubusercontent.com/wiki/tree-sitter/tree-sitter/List-of-parsers.md";
+
+/// Languages compiled into the program, see `register_language`
+static REGISTERED: Mutex<Vec<RegisteredLanguage>> = Mutex::new(Vec::new());
 
+/// The grammars files are parsed with: those registered with `register_language`, then those
+/// tree-sitter-loader finds in the configured parser directories. wasm32 builds have no loader,
+/// so only registered languages.
 pub struct Grammars {
-    loader: Loader,
+    registered: Vec<RegisteredLanguage>,
+    // The loader compiles and caches languages on first use without synchronization
+    #[cfg(not(target_arch = "wasm32"))]
+    loader: Mutex<LazyLoader>,
+    #[cfg(not(target_arch = "wasm32"))]
     ts_config: Config,
+    #[cfg(not(target_arch = "wasm32"))]
     parser_config: ParserConfig,
 }
 
+/// A language compiled into the program along with the file names and extensions it claims.
+#[derive(Clone)]
+struct RegisteredLanguage {
+    name: String,
+    file_types: Vec<String>,
+    language: Language,
+}
+
+/// Registers a language compiled into the program, such as the `language()` of a grammar crate,
+/// for the grammars loaded afterwards. Registered languages claim the file names and extensions
+/// given before any grammar the loader finds, and are the only grammars of wasm32 builds.
+pub fn register_language(name: &str, file_types: &[&str], language: Language) {
+    let language = RegisteredLanguage {
+        name: name.to_string(),
+        file_types: file_types.iter().map(|file_type| file_type.to_string()).collect(),
+        language,
+    };
+    if let Ok(mut registered) = REGISTERED.lock() {
+        registered.retain(|other| other.name != language.name);
+        registered.push(language);
+    }
+}
+
+/// A loader that only registers the grammars claiming a file name or extension once a file of
+/// that type is looked up, rather than every configured grammar up front.
+#[cfg(not(target_arch = "wasm32"))]
+struct LazyLoader {
+    loader: Loader,
+    /// Grammar directories by the file types their package.json lists, read on first lookup
+    file_types: Option<HashMap<String, Vec<PathBuf>>>,
+    registered: HashSet<PathBuf>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LazyLoader {
+    fn index_file_types(parser_directories: &[PathBuf]) -> HashMap<String, Vec<PathBuf>> {
+        let mut file_types: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for parser_directory in parser_directories.iter() {
+            let Ok(entries) = std::fs::read_dir(parser_directory) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let grammar_dir = entry.path();
+                if !entry.file_name().to_string_lossy().starts_with("tree-sitter-") {
+                    continue;
+                }
+                let Some(package) = std::fs::read_to_string(grammar_dir.join("package.json")).ok()
+                    .and_then(|package| serde_json::from_str::<serde_json::Value>(&package).ok()) else {
+                    continue;
+                };
+                let configurations = package.get("tree-sitter").and_then(|c| c.as_array()).cloned().unwrap_or_default();
+                for file_type in configurations.iter()
+                    .filter_map(|c| c.get("file-types").and_then(|f| f.as_array()))
+                    .flatten()
+                    .filter_map(|f| f.as_str()) {
+                    let dirs = file_types.entry(file_type.to_string()).or_default();
+                    if !dirs.contains(&grammar_dir) {
+                        dirs.push(grammar_dir.clone());
+                    }
+                }
+            }
+        }
+        debug!("Indexed {} file types of configured grammars", file_types.len());
+        file_types
+    }
+
+    /// Registers the grammars claiming the file's name or extension with the loader.
+    fn try_register_for(&mut self, path: &Path, parser_directories: &[PathBuf]) -> Result<(), DiffGraphError> {
+        let file_types = self.file_types.get_or_insert_with(|| Self::index_file_types(parser_directories));
+        let keys = [
+            path.file_name().and_then(|n| n.to_str()),
+            path.extension().and_then(|e| e.to_str()),
+        ];
+        let dirs: Vec<PathBuf> = keys.iter()
+            .flatten()
+            .filter_map(|key| file_types.get(*key))
+            .flatten()
+            .filter(|dir| !self.registered.contains(*dir))
+            .cloned()
+            .collect();
+        for dir in dirs {
+            debug!("Registering grammar at {}", dir.display());
+            self.loader.find_language_configurations_at_path(&dir).map_err(|e| DiffGraphError::Grammar(e.to_string()))?;
+            self.registered.insert(dir);
+        }
+        Ok(())
+    }
+}
+
+/// A language configuration that claims a file, and the parser directory it was found in.
+#[derive(Debug)]
+pub struct LanguageMatch {
+    pub scope: Option<String>,
+    pub file_types: Vec<String>,
+    pub root_path: PathBuf,
+    pub parser_directory: Option<PathBuf>,
+}
+
+impl From<&RegisteredLanguage> for LanguageMatch {
+    fn from(registered: &RegisteredLanguage) -> Self {
+        // Named by its root path, as `get_language_name` names grammar directories
+        Self {
+            scope: None,
+            file_types: registered.file_types.clone(),
+            root_path: PathBuf::from(&registered.name),
+            parser_directory: None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ParserConfig {
     pub parsers: Vec<String>,
@@ -26,6 +179,8 @@ fn get_default_config_dir() -> Option<PathBuf> {
         None
     }
 }
+
+#[cfg(not(target_arch = "wasm32"))]
 fn get_default_parsers_dir() -> Option<PathBuf> {
     if let Some(path) = get_default_config_dir() {
         Some(path.join(PARSERS_PATH))
@@ -34,31 +189,147 @@ fn get_default_parsers_dir() -> Option<PathBuf> {
     }
 }
 
-fn try_get_parser_repo_path(parser_url: &str) -> Result<PathBuf, String> {
+/// Short language name, the last scope segment (`source.rust` is `rust`) or else the grammar directory name
+pub fn get_language_name(scope: Option<&str>, root_path: &Path) -> String {
+    if let Some(name) = scope.and_then(|scope| scope.rsplit('.').next()) {
+        return name.to_string();
+    }
+    let dir_name = root_path.file_name().map_or(String::new(), |n| n.to_string_lossy().to_string());
+    dir_name.strip_prefix("tree-sitter-").unwrap_or(&dir_name).to_string()
+}
+
+/// Where compiled grammars are kept for other runs, machines and users to reuse, unless another
+/// directory, such as one shared over the network, is given.
+pub fn get_default_grammar_cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|path| path.join(GRAMMAR_CACHE_PATH))
+}
+
+/// A grammar whose compiled library the loader does not have yet.
+#[cfg(not(target_arch = "wasm32"))]
+struct GrammarBuild {
+    name: String,
+    src_path: PathBuf,
+    /// Directory of the cache the library is compiled into, named by what it was compiled from
+    key: String,
+}
+
+/// The `src` directories of the grammars in a parser repository, one for most repositories and
+/// one for each language listed in its package.json for those with several.
+#[cfg(not(target_arch = "wasm32"))]
+fn find_grammar_src_paths(repo_path: &Path) -> Vec<PathBuf> {
+    let package = std::fs::read_to_string(repo_path.join("package.json")).ok()
+        .and_then(|package| serde_json::from_str::<serde_json::Value>(&package).ok());
+    let mut paths: Vec<PathBuf> = package
+        .as_ref()
+        .and_then(|package| package.get("tree-sitter"))
+        .and_then(|configurations| configurations.as_array())
+        .map(|configurations| configurations
+            .iter()
+            .map(|configuration| repo_path.join(configuration.get("path").and_then(|path| path.as_str()).unwrap_or(".")).join("src"))
+            .collect())
+        .unwrap_or_default();
+    if paths.is_empty() {
+        paths.push(repo_path.join("src"));
+    }
+    paths.dedup();
+    paths.retain(|path| path.join("parser.c").exists());
+    paths
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn try_get_grammar_name(src_path: &Path) -> Option<String> {
+    let grammar = std::fs::read_to_string(src_path.join("grammar.json")).ok()?;
+    let grammar: serde_json::Value = serde_json::from_str(&grammar).ok()?;
+    grammar.get("name")?.as_str().map(|name| name.to_string())
+}
+
+/// The commit the parser repository is at, or else a hash of the generated parser.
+#[cfg(not(target_arch = "wasm32"))]
+fn get_grammar_revision(repo_path: &Path, src_path: &Path) -> String {
+    let output = std::process::Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(repo_path)
+        .output();
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        _ => {
+            let mut hasher = DefaultHasher::new();
+            std::fs::read(src_path.join("parser.c")).unwrap_or_default().hash(&mut hasher);
+            format!("{:016x}", hasher.finish())
+        },
+    }
+}
+
+/// Identifies the C compiler the loader would use and the platform it compiles for, as a library
+/// built by one is of no use to another.
+#[cfg(not(target_arch = "wasm32"))]
+fn get_compiler_id() -> String {
+    let compiler = std::env::var("CC").unwrap_or_else(|_| if cfg!(windows) { "cl".into() } else { "cc".into() });
+    let version = std::process::Command::new(&compiler)
+        .arg("--version")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).lines().next().unwrap_or_default().to_string())
+        .unwrap_or_default();
+    let mut hasher = DefaultHasher::new();
+    (compiler.as_str(), version.as_str(), std::env::consts::ARCH, std::env::consts::OS).hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Whether the library is at least as new as the sources it is compiled from, which is how the
+/// loader decides not to compile a grammar again.
+#[cfg(not(target_arch = "wasm32"))]
+fn is_compiled(library_path: &Path, src_path: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    let Some(library) = modified(library_path) else {
+        return false;
+    };
+    ["parser.c", "scanner.c", "scanner.cc"]
+        .iter()
+        .filter_map(|file| modified(&src_path.join(file)))
+        .all(|source| source <= library)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn try_get_parser_repo_path(parser_url: &str) -> Result<PathBuf, DiffGraphError> {
     if let Some(path) = get_default_parsers_dir() {
-        let url = Url::parse(parser_url).map_err(|e| e.to_string())?; 
-        let repo_path; 
-        if let Some(segments) = url.path_segments() {
-            if let Some(last_segment) = segments.last() {
-                repo_path = path.join(last_segment)
-            } else {
-                return Err(format!("Unable to determine last path segment for repository URL: {}", url));
-            }
-        } else {
-            return Err(format!("Unable to determine path for repository URL: {}", url));
-        }
+        // Parsers are cloned into a directory named after the last segment of their URL
+        let repo_path = match parser_url.split_once("://").map(|(_, rest)| rest.trim_end_matches('/')) {
+            Some(rest) => match rest.split_once('/') {
+                Some((_, repo)) if !repo.is_empty() => path.join(repo.rsplit('/').next().unwrap_or(repo)),
+                _ => return Err(DiffGraphError::Grammar(format!("Unable to determine path for repository URL: {}", parser_url))),
+            },
+            None => return Err(DiffGraphError::Grammar(format!("Invalid parser URL {}", parser_url))),
+        };
 
-        dbg!(&repo_path);
+        debug!("Parser repository path for {}: {}", parser_url, repo_path.display());
 
         Ok(repo_path)
     } else {
-        return Err(format!("Unable to determine default parser path."));
+        Err(DiffGraphError::Grammar("Unable to determine default parser path.".into()))
     }
 }
 
+#[cfg(feature = "cli")]
+fn try_fetch_registry_parsers() -> Result<Vec<String>, DiffGraphError> {
+    let registry = reqwest::blocking::get(PARSER_REGISTRY_URL)
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.text())
+        .map_err(|e| DiffGraphError::Network(format!("Unable to fetch parser registry from {}: {}", PARSER_REGISTRY_URL, e)))?;
+
+    let re = Regex::new(r"https://github\.com/[\w.-]+/tree-sitter-[\w.-]+").unwrap();
+    let mut parsers = Vec::new();
+    for parser_match in re.find_iter(&registry) {
+        let parser_url = parser_match.as_str().trim_end_matches(".git").to_string();
+        if !parsers.contains(&parser_url) {
+            parsers.push(parser_url);
+        }
+    }
+
+    Ok(parsers)
+}
 
 impl ParserConfig {
-    fn create_with_known() -> Result<Self, String> {
+    fn create_with_known() -> Result<Self, DiffGraphError> {
         let parsers: Vec::<String> = vec![
             // https://tree-sitter.github.io/tree-sitter/#parsers
             "https://github.com/briot/tree-sitter-ada".into(),
@@ -180,46 +451,163 @@ impl ParserConfig {
         })
     }
 
-    pub fn try_load(parser_config_path: Option<PathBuf>, save_default_if_missing: bool) -> Result<ParserConfig, String> {
+    fn try_get_config_file_path(parser_config_path: Option<PathBuf>) -> Result<PathBuf, DiffGraphError> {
         let path = if let Some(path) = parser_config_path {
             path
         } else if let Some(default_config_dir) = get_default_config_dir() {
             default_config_dir
         } else {
-            dbg!(dirs::config_dir());
-            return Err(format!("Unable to determine default parser configuration path."));
+            debug!("System configuration directory: {:?}", dirs::config_dir());
+            return Err(DiffGraphError::Grammar("Unable to determine default parser configuration path.".into()));
         };
 
-        // Save default or Load config from fs
-        let path = if !path.is_file() {
-            path.join(PARSERS_CONFIG_FILE)
+        if !path.is_file() {
+            Ok(path.join(PARSERS_CONFIG_FILE))
         } else {
-            path
-        };
+            Ok(path)
+        }
+    }
+
+    pub fn try_load(parser_config_path: Option<PathBuf>, save_default_if_missing: bool) -> Result<ParserConfig, DiffGraphError> {
+        // Save default or Load config from fs
+        let path = Self::try_get_config_file_path(parser_config_path)?;
         if path.exists() {
-            let config_str = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
-            let config = serde_json::from_str(&config_str).map_err(|e| e.to_string())?;
+            let config_str = std::fs::read_to_string(&path).map_err(|e| DiffGraphError::io(format!("Unable to read '{}'", path.display()), e))?;
+            let config = serde_json::from_str(&config_str).map_err(|e| DiffGraphError::Grammar(format!("Invalid parsers configuration {}: {}", path.display(), e)))?;
 
             Ok(config)
         } else if save_default_if_missing {
             let config = ParserConfig::create_with_known()?;
-            let config_str = serde_json::to_string(&config).map_err(|e| e.to_string())?;
-            std::fs::write(path, config_str).map_err(|e| e.to_string())?;
+            let config_str = serde_json::to_string(&config).map_err(|e| DiffGraphError::Grammar(e.to_string()))?;
+            std::fs::write(&path, config_str).map_err(|e| DiffGraphError::io(format!("Unable to write '{}'", path.display()), e))?;
 
             Ok(config)
         } else {
-            return Err(format!("Unable to find parsers configuration at path: {}", path.display()));
+            Err(DiffGraphError::Grammar(format!("Unable to find parsers configuration at path: {}", path.display())))
         }
     }
 }
 
+#[cfg(feature = "cli")]
+impl ParserConfig {
+    /// Merges parsers listed in the upstream tree-sitter registry into the parser configuration on disk,
+    /// returning the newly added parser URLs.
+    pub fn try_sync_registry(parser_config_path: Option<PathBuf>) -> Result<Vec<String>, DiffGraphError> {
+        let mut config = Self::try_load(parser_config_path.clone(), true)?;
+        let registry_parsers = try_fetch_registry_parsers()?;
+
+        let known: Vec<String> = config.parsers
+            .iter()
+            .map(|p| p.trim_end_matches('/').to_lowercase())
+            .collect();
+        let added: Vec<String> = registry_parsers
+            .into_iter()
+            .filter(|p| !known.contains(&p.to_lowercase()))
+            .collect();
+
+        if !added.is_empty() {
+            config.parsers.extend(added.iter().cloned());
+            let path = Self::try_get_config_file_path(parser_config_path)?;
+            let config_str = serde_json::to_string(&config).map_err(|e| DiffGraphError::Grammar(e.to_string()))?;
+            std::fs::write(&path, config_str).map_err(|e| DiffGraphError::io(format!("Unable to write '{}'", path.display()), e))?;
+        }
+
+        Ok(added)
+    }
+}
+
+fn get_registered() -> Vec<RegisteredLanguage> {
+    REGISTERED.lock().map(|registered| registered.clone()).unwrap_or_default()
+}
+
+impl Grammars {
+    /// The registered language claiming the file name, or failing that, its extension.
+    fn find_registered(&self, path: &Path) -> Option<&RegisteredLanguage> {
+        let file_name = path.file_name().and_then(|n| n.to_str());
+        let extension = path.extension().and_then(|e| e.to_str());
+        [file_name, extension].into_iter().flatten().find_map(|file_type| self.registered
+            .iter()
+            .find(|language| language.file_types.iter().any(|t| t == file_type)))
+    }
+
+    /// The language for the file along with its short name, see `get_language_name`.
+    pub fn try_get_language(&self, path: &Path) -> Result<Option<(Language, String)>, DiffGraphError> {
+        match self.find_registered(path) {
+            Some(registered) => Ok(Some((registered.language, registered.name.clone()))),
+            None => self.try_get_loaded_language(path),
+        }
+    }
+
+    /// All configurations whose file types match the file name (or failing that, its extension),
+    /// in the order they would be considered, the registered language first. Does not compile any grammar.
+    pub fn find_candidates(&self, path: &Path) -> Vec<LanguageMatch> {
+        let mut candidates: Vec<LanguageMatch> = self.find_registered(path).map(LanguageMatch::from).into_iter().collect();
+        candidates.extend(self.find_loaded_candidates(path));
+        candidates
+    }
+
+    /// Short name of the language the file most likely is, without loading any grammar.
+    pub fn find_language_name(&self, path: &Path) -> Option<String> {
+        self.find_candidates(path)
+            .first()
+            .map(|candidate| get_language_name(candidate.scope.as_deref(), &candidate.root_path))
+    }
+
+    /// The configuration actually selected for the file, loading its grammar.
+    pub fn try_which(&self, path: &Path) -> Result<Option<LanguageMatch>, DiffGraphError> {
+        match self.find_registered(path) {
+            Some(registered) => Ok(Some(LanguageMatch::from(registered))),
+            None => self.try_which_loaded(path),
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Grammars {
+    /// The registered languages, as there is no loader to find others with.
+    pub fn load(_parser_config_path: Option<PathBuf>, _save_default_if_missing: bool) -> Result<Self, DiffGraphError> {
+        Ok(Self {
+            registered: get_registered(),
+        })
+    }
+
+    fn try_get_loaded_language(&self, _path: &Path) -> Result<Option<(Language, String)>, DiffGraphError> {
+        Ok(None)
+    }
+
+    fn find_loaded_candidates(&self, _path: &Path) -> Vec<LanguageMatch> {
+        Vec::new()
+    }
+
+    fn try_which_loaded(&self, _path: &Path) -> Result<Option<LanguageMatch>, DiffGraphError> {
+        Ok(None)
+    }
+
+    pub fn get_configured_paths(&self) -> Vec<&str> {
+        Vec::new()
+    }
+
+    /// Languages are registered by the program, there are none to install.
+    pub fn try_install_languages(&self) -> Result<(), DiffGraphError> {
+        Ok(())
+    }
+
+    /// Languages are registered by the program, there are none to compile.
+    pub fn try_compile_languages(&self, _cache_dir: Option<&Path>) -> Result<(), DiffGraphError> {
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 impl Grammars {
-    pub fn load(parser_config_path: Option<PathBuf>, save_default_if_missing: bool) -> Result<Self, String> {
+    pub fn load(parser_config_path: Option<PathBuf>, save_default_if_missing: bool) -> Result<Self, DiffGraphError> {
         let ts_config = if let Some(path) = parser_config_path.clone().or_else(|| get_default_config_dir()) {
             let ts_config_path = path.join(TREE_SITTER_CONFIG_FILE);
             if ts_config_path.exists() {
-                let ts_config_str = std::fs::read_to_string(ts_config_path).map_err(|e| e.to_string())?;
-                let ts_config: Config = serde_json::from_str(&ts_config_str).map_err(|e| e.to_string())?;
+                let ts_config_str = std::fs::read_to_string(&ts_config_path)
+                    .map_err(|e| DiffGraphError::io(format!("Unable to read '{}'", ts_config_path.display()), e))?;
+                let ts_config: Config = serde_json::from_str(&ts_config_str)
+                    .map_err(|e| DiffGraphError::Grammar(format!("Invalid tree-sitter configuration {}: {}", ts_config_path.display(), e)))?;
 
                 ts_config
             } else if save_default_if_missing {
@@ -236,24 +624,96 @@ impl Grammars {
         };
         let parser_config = ParserConfig::try_load(parser_config_path, save_default_if_missing)?;
 
-        let mut loader = Loader::new().map_err(|e| e.to_string())?;
-        loader.find_all_languages(&ts_config).map_err(|e| e.to_string())?;
+        if ts_config.parser_directories.is_empty() {
+            warn!("No parser directories are configured, no grammar will be found");
+        }
+        let loader = Loader::new().map_err(|e| DiffGraphError::Grammar(e.to_string()))?;
 
         Ok(Self {
-            loader,
+            registered: get_registered(),
+            loader: Mutex::new(LazyLoader {
+                loader,
+                file_types: None,
+                registered: HashSet::new(),
+            }),
             ts_config,
             parser_config,
         })
     }
 
-    pub fn try_get_language(&self, path: &Path) -> Result<Option<Language>, String> {
-        match self.loader.language_configuration_for_file_name(path).map_err(|e| e.to_string())? {
-            Some((lang, _)) => Ok(Some(lang)),
+    /// The loader with the grammars for the file registered.
+    fn try_loader_for(&self, path: &Path) -> Result<MutexGuard<'_, LazyLoader>, DiffGraphError> {
+        let mut loader = self.loader.lock().map_err(|e| DiffGraphError::Grammar(e.to_string()))?;
+        loader.try_register_for(path, &self.ts_config.parser_directories)?;
+        Ok(loader)
+    }
+
+    fn try_get_loaded_language(&self, path: &Path) -> Result<Option<(Language, String)>, DiffGraphError> {
+        let loader = self.try_loader_for(path)?;
+        match loader.loader.language_configuration_for_file_name(path).map_err(|e| DiffGraphError::Grammar(e.to_string()))? {
+            Some((lang, config)) => Ok(Some((lang, get_language_name(config.scope.as_deref(), &config.root_path)))),
             None => Ok(None),
         }
     }
 
 
+    fn get_parser_directory(&self, root_path: &Path) -> Option<PathBuf> {
+        self.ts_config.parser_directories
+            .iter()
+            .find(|dir| root_path.starts_with(dir))
+            .cloned()
+    }
+
+    /// Configurations of the loader whose file types match the file name (or failing that, its
+    /// extension), in the order the loader would consider them.
+    fn find_loaded_candidates(&self, path: &Path) -> Vec<LanguageMatch> {
+        let file_name = path.file_name().and_then(|n| n.to_str());
+        let extension = path.extension().and_then(|e| e.to_str());
+
+        let loader = match self.try_loader_for(path) {
+            Ok(loader) => loader,
+            Err(e) => {
+                debug!("Unable to register grammars for {}: {}", path.display(), e);
+                return Vec::new();
+            },
+        };
+        let configurations = loader.loader.get_all_language_configurations();
+        let mut matches: Vec<_> = configurations
+            .iter()
+            .filter(|(config, _)| file_name.is_some_and(|n| config.file_types.iter().any(|t| t == n)))
+            .collect();
+        if matches.is_empty() {
+            matches = configurations
+                .iter()
+                .filter(|(config, _)| extension.is_some_and(|e| config.file_types.iter().any(|t| t == e)))
+                .collect();
+        }
+
+        matches
+            .into_iter()
+            .map(|(config, _)| LanguageMatch {
+                scope: config.scope.clone(),
+                file_types: config.file_types.clone(),
+                root_path: config.root_path.clone(),
+                parser_directory: self.get_parser_directory(&config.root_path),
+            })
+            .collect()
+    }
+
+    /// The configuration the loader actually selects for the file, loading its grammar.
+    fn try_which_loaded(&self, path: &Path) -> Result<Option<LanguageMatch>, DiffGraphError> {
+        let loader = self.try_loader_for(path)?;
+        match loader.loader.language_configuration_for_file_name(path).map_err(|e| DiffGraphError::Grammar(e.to_string()))? {
+            Some((_, config)) => Ok(Some(LanguageMatch {
+                scope: config.scope.clone(),
+                file_types: config.file_types.clone(),
+                root_path: config.root_path.clone(),
+                parser_directory: self.get_parser_directory(&config.root_path),
+            })),
+            None => Ok(None),
+        }
+    }
+
     pub fn get_configured_paths(&self) -> Vec<&str> {
         let mut paths = Vec::new();
         for dir in self.ts_config.parser_directories.iter() {
@@ -264,8 +724,8 @@ impl Grammars {
         paths
     }
 
-    pub fn try_install_languages(&self) -> Result<(), String> {
-        fn clone_repo_in_dir(url: &str, dir: &PathBuf) -> Result<(), String> {
+    pub fn try_install_languages(&self) -> Result<(), DiffGraphError> {
+        fn clone_repo_in_dir(url: &str, dir: &PathBuf) -> Result<(), DiffGraphError> {
             let output = std::process::Command::new("git")
                 .arg("clone")
                 .arg(url)
@@ -279,20 +739,99 @@ impl Grammars {
             if output.status.success() {
                 Ok(())
             } else if !stderr.is_empty() {
-                Err(format!("{}", stderr))
+                Err(DiffGraphError::Network(format!("Unable to clone {}: {}", url, stderr.trim())))
             } else {
-                Err(format!("Unable to execute git clone command: {}", stdout))
+                Err(DiffGraphError::Network(format!("Unable to execute git clone command: {}", stdout)))
             }
         }
 
+        let mut missing = Vec::new();
         for parser_url in self.parser_config.parsers.iter() {
             let repo_path = try_get_parser_repo_path(parser_url)?;
             if !repo_path.exists() {
-                println!("Cloning missing language: {}", parser_url);
-                clone_repo_in_dir(&parser_url, &repo_path)?;
+                missing.push((parser_url, repo_path));
             }
         }
 
-        Ok(())
+        let bar = progress::bar(missing.len() as u64, "Installing");
+        let result = missing
+            .par_iter()
+            .try_for_each(|(parser_url, repo_path)| {
+                info!("Cloning missing language: {}", parser_url);
+                clone_repo_in_dir(parser_url, repo_path)?;
+                bar.inc(1);
+                Ok(())
+            });
+        bar.finish_and_clear();
+
+        result
+    }
+
+    /// Compiles the configured grammars the loader has no library for, concurrently rather than
+    /// one at a time on first use. Libraries are kept in the cache directory keyed by grammar
+    /// revision and compiler, so later runs, and other machines sharing the directory, copy them
+    /// instead of compiling again.
+    pub fn try_compile_languages(&self, cache_dir: Option<&Path>) -> Result<(), DiffGraphError> {
+        let (Some(cache_dir), Some(lib_dir)) = (cache_dir.map(Path::to_path_buf).or_else(get_default_grammar_cache_dir),
+            dirs::cache_dir().map(|path| path.join(LOADER_LIB_PATH))) else {
+            debug!("No cache directory, grammars are compiled on first use");
+            return Ok(());
+        };
+
+        let compiler = OnceLock::new();
+        let mut builds = Vec::new();
+        for parser_url in self.parser_config.parsers.iter() {
+            let repo_path = try_get_parser_repo_path(parser_url)?;
+            if !repo_path.exists() {
+                continue;
+            }
+            for src_path in find_grammar_src_paths(&repo_path) {
+                let Some(name) = try_get_grammar_name(&src_path) else {
+                    continue;
+                };
+                if is_compiled(&lib_dir.join(&name).with_extension(DYLIB_EXTENSION), &src_path) {
+                    continue;
+                }
+                let key = format!("{}-{}-{}", name, get_grammar_revision(&repo_path, &src_path), compiler.get_or_init(get_compiler_id));
+                builds.push(GrammarBuild { name, src_path, key });
+            }
+        }
+        if builds.is_empty() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&lib_dir)
+            .map_err(|e| DiffGraphError::io(format!("Unable to create '{}'", lib_dir.display()), e))?;
+
+        let bar = progress::bar(builds.len() as u64, "Compiling");
+        let result = builds
+            .par_iter()
+            .try_for_each(|build| {
+                let library_file = Path::new(&build.name).with_extension(DYLIB_EXTENSION);
+                let cached_dir = cache_dir.join(&build.key);
+                if cached_dir.join(&library_file).exists() {
+                    stats::hit(stats::Cache::Grammars);
+                    debug!("Reusing compiled grammar {} from {}", build.name, cached_dir.display());
+                } else {
+                    stats::miss(stats::Cache::Grammars);
+                    info!("Compiling grammar {}", build.name);
+                    // Compiled next to the entry and renamed into place, so other runs sharing
+                    // the cache never see a library that is still being written
+                    let partial_dir = cache_dir.join(format!("{}.{}.partial", build.key, std::process::id()));
+                    Loader::with_parser_lib_path(partial_dir.clone())
+                        .load_language_at_path(&build.src_path, &build.src_path)
+                        .map_err(|e| DiffGraphError::Grammar(format!("Unable to compile grammar {}: {}", build.name, e)))?;
+                    if std::fs::rename(&partial_dir, &cached_dir).is_err() {
+                        // Another run compiled it first
+                        let _ = std::fs::remove_dir_all(&partial_dir);
+                    }
+                }
+                std::fs::copy(cached_dir.join(&library_file), lib_dir.join(&library_file))
+                    .map_err(|e| DiffGraphError::io(format!("Unable to copy the compiled grammar {}", build.name), e))?;
+                bar.inc(1);
+                Ok(())
+            });
+        bar.finish_and_clear();
+
+        result
     }
 }
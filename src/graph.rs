@@ -1,79 +1,530 @@
 use crate::parser::*;
 use petgraph::graphmap::DiGraphMap;
-use unidiff::PatchSet;
-use tree_sitter::{Tree, TreeCursor};
+use petgraph::Direction;
+use unidiff::{PatchSet, PatchedFile};
+use std::borrow::Cow;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::fmt;
+use std::path::PathBuf;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use tree_sitter::{InputEdit, Tree, TreeCursor};
 use tree_sitter::Node as TSNode;
+use tracing::info;
+use serde::{Serialize, Deserialize};
+
+use crate::cancel;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::git;
+use crate::intern;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::jj;
+use crate::patch;
+use crate::progress;
+use crate::stats;
+use crate::syntax;
+use crate::timings;
+use crate::architecture::{ArchitectureRules, ArchitectureViolation};
+use crate::configs::{self, ConfigChange};
+use crate::coverage::{Coverage, UntestedChange};
+use crate::debt::{DebtQueries, DebtScanner, DebtTag};
+use crate::error::DiffGraphError;
+use crate::index::SymbolIndex;
+use crate::limits::{Limits, LimitAction, Truncation};
+use crate::links::{self, CrossLanguageLink};
+use crate::owners::CodeOwners;
+use crate::policy::{Condition, Finding, Policy};
+use crate::security::{SecurityQueries, SecurityScanner, SecurityTag};
+use crate::pathspec::PathFilter;
+use crate::shard::Shard;
+use crate::visitor::GraphVisitor;
 
 type NodeWeight = usize;
 
-#[derive(Debug)]
+/// Edges of one file's tree, and the limit that cut the walk short if any
+type FileEdges = (Vec<Edge>, Option<Truncation>);
+
+#[derive(Debug, Clone)]
 pub struct DiffGraphParams {
     pub diff_repository_dir: String,
     pub diff: PatchSet,
+    /// Revision the diff applies to, or None when it applies to the working tree
+    pub source_revision: Option<String>,
+    /// Git directory the revision is read from when git does not find it from the repository, as
+    /// with the store of a Jujutsu repository that is not colocated
+    pub git_dir: Option<PathBuf>,
     pub save_default_if_missing: bool, 
     pub install_lang_if_missing: bool,
+    pub parser_config_path: Option<PathBuf>,
+    /// Where grammars compiled with `install_lang_if_missing` are cached, or the default cache
+    pub grammar_cache_dir: Option<PathBuf>,
+    pub exclude_kinds: Vec<String>,
+    pub limits: Limits,
+    pub policy: Policy,
+    /// Binary files listed in the diff, which have no hunks to analyze
+    pub binary_files: Vec<String>,
+    /// Selects which patched files are analyzed
+    pub path_filter: PathFilter,
+    /// Only files in these languages are analyzed, all of them when empty
+    pub languages: Vec<String>,
+    /// Contents of the files before the patch keyed by path, read instead of the repository when set
+    pub sources: Option<HashMap<String, String>>,
+    /// Structurally identical unchanged subtrees, such as repeated imports, share the nodes of the first one
+    pub share_subtrees: bool,
+    /// Every node of the files' trees is graphed, instead of only the changed subtrees and the nodes enclosing them
+    pub full_tree: bool,
+    /// Hunks that only change whitespace, layout or comments are left out, as if the patch did not contain them
+    pub ignore_formatting: bool,
+    /// Directory of `<language>.scm` query packs tagging security-sensitive patterns, run along with the shipped ones
+    pub security_queries: Option<PathBuf>,
+    /// Directory of `<language>.scm` query packs tagging TODOs, panics and similar debt, run along with the shipped ones
+    pub debt_queries: Option<PathBuf>,
+    /// File of dependencies the patch may not add, such as `ui/ !-> db/`, see `ArchitectureRules`
+    pub architecture_rules: Option<PathBuf>,
+    /// Index of the repository's definitions built by `index`, resolving names the patch uses to
+    /// declarations outside of it
+    pub symbol_index: Option<PathBuf>,
+    /// lcov tracefile or Cobertura report of the patched code, flagging the changes no test runs
+    pub coverage: Option<PathBuf>,
+    /// Directory each patched file's trees before and after the patch are written to as DOT, for debugging
+    pub dump_trees: Option<PathBuf>,
+    /// Hunks and files the patch does not apply to are left out and reported, instead of failing the run
+    pub best_effort: bool,
+    /// The first file that fails fails the run, instead of being left out and reported
+    pub strict: bool,
+    /// Only the files of this shard of the patch are analyzed, all of them when None
+    pub shard: Option<Shard>,
+}
+
+impl Default for DiffGraphParams {
+    /// An empty diff of the working tree with every option off, saving the default parser
+    /// configuration when there is none.
+    fn default() -> Self {
+        Self {
+            diff_repository_dir: String::new(),
+            diff: PatchSet::new(),
+            source_revision: None,
+            git_dir: None,
+            save_default_if_missing: true,
+            install_lang_if_missing: false,
+            parser_config_path: None,
+            grammar_cache_dir: None,
+            exclude_kinds: Vec::new(),
+            limits: Limits::default(),
+            policy: Policy::default(),
+            binary_files: Vec::new(),
+            path_filter: PathFilter::default(),
+            languages: Vec::new(),
+            sources: None,
+            share_subtrees: false,
+            full_tree: false,
+            ignore_formatting: false,
+            security_queries: None,
+            debt_queries: None,
+            architecture_rules: None,
+            symbol_index: None,
+            coverage: None,
+            dump_trees: None,
+            best_effort: false,
+            strict: false,
+            shard: None,
+        }
+    }
+}
+
+impl DiffGraphParams {
+    /// Parameters for graphing a diff of a local repository with the default options, taking the
+    /// diffs `git::try_parse_diff` does. GitHub and Gerrit URLs need the `cli` feature to fetch them.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn try_from_repository(repo_path: &Path, diff_arg: &str) -> Result<Self, DiffGraphError> {
+        if !repo_path.is_dir() || !(git::dir_is_git_repository(repo_path) || jj::is_repository(repo_path) || git::dir_is_working_copy(repo_path)) {
+            return Err(DiffGraphError::Git(format!("Repository path '{:?}' is not a git or jj repository, or a Mercurial or Subversion working copy", repo_path)));
+        }
+        let diff = git::try_parse_diff(diff_arg, repo_path)?;
+
+        Ok(Self {
+            diff_repository_dir: repo_path.to_string_lossy().to_string(),
+            diff: diff.patch,
+            source_revision: diff.source_revision,
+            git_dir: diff.git_dir,
+            binary_files: diff.binary_files,
+            ..Default::default()
+        })
+    }
+
+    /// Parameters for graphing the text of a diff against the given contents of the files it
+    /// patches, keyed by path. Nothing is read from git or the filesystem besides the grammars, so
+    /// this is how wasm32 builds, such as a web page graphing a pasted diff, create parameters.
+    pub fn try_from_sources(diff: &str, sources: HashMap<String, String>) -> Result<Self, DiffGraphError> {
+        let diff = patch::try_create_parsed_diff(diff, None)?;
+
+        Ok(Self {
+            diff: diff.patch,
+            binary_files: diff.binary_files,
+            sources: Some(sources),
+            ..Default::default()
+        })
+    }
+
+    /// Where the unpatched contents of the files in the diff are read from.
+    pub fn get_source_location(&self) -> SourceLocation {
+        let repo_dir = PathBuf::from(&self.diff_repository_dir);
+        match (&self.sources, &self.source_revision) {
+            (Some(sources), _) => SourceLocation::Memory(sources.clone()),
+            (None, Some(revision)) => SourceLocation::Revision { repo_dir, revision: revision.clone(), git_dir: self.git_dir.clone() },
+            (None, None) => SourceLocation::WorkingTree(repo_dir),
+        }
+    }
+
+    /// Files in the diff selected by the path filter and in the shard, in patch order.
+    pub fn get_patch_files(&self) -> Vec<&PatchedFile> {
+        let patch_files = self.diff
+            .files()
+            .iter()
+            .filter(|patch_file| self.path_filter.matches(get_patch_file_path(patch_file)))
+            .collect();
+        match &self.shard {
+            Some(shard) => shard.select(patch_files),
+            None => patch_files,
+        }
+    }
+
+    pub fn get_binary_file_findings(&self) -> Vec<Finding> {
+        if self.shard.is_some_and(|shard| !shard.is_first()) {
+            return Vec::new();
+        }
+        self.binary_files
+            .iter()
+            .filter(|file| self.path_filter.matches(file))
+            .map(|file| Finding::new(Condition::BinaryFiles, format!("{} is a binary file and was skipped", file)))
+            .collect()
+    }
 }
 
-#[derive(Debug)]
 pub struct DiffGraph {
     graph: DiGraphMap<NodeWeight, Edge>,
     diffs: Vec<Diff>,
+    truncations: Vec<Truncation>,
+    findings: Vec<Finding>,
+    skipped: Vec<SkippedFile>,
+    errors: Vec<FileError>,
+    shard: Option<Shard>,
+    owners: Option<CodeOwners>,
+    security_tags: Vec<SecurityTag>,
+    debt_tags: Vec<DebtTag>,
+    links: Vec<CrossLanguageLink>,
+    config_changes: Vec<ConfigChange>,
+    violations: Vec<ArchitectureViolation>,
+    untested: Vec<UntestedChange>,
+    /// Whether every node of the trees was graphed rather than only the changed subtrees
+    full_tree: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeInfo {
     pub id: usize,
     pub kind_id: u16,
+    /// Borrowed from the grammar, or interned once deserialized
+    #[serde(deserialize_with = "intern::deserialize")]
+    pub kind: Cow<'static, str>,
+    /// Index of the file in `DiffGraph::diffs`
+    pub file: usize,
     pub byte_range: std::ops::Range<usize>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Edge {
     pub from: NodeInfo,
     pub to: NodeInfo,
 }
 
+/// How a node changed, judged by the lines the patch added or removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeStatus {
+    /// Every line of the node was added
+    Added,
+    /// Every line of the node was removed
+    Removed,
+    /// Only some lines of the node were added or removed
+    Modified,
+}
+
+/// A node on an added or removed line, see `DiffGraph::changed_nodes`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangedNode {
+    pub node: NodeInfo,
+    pub status: ChangeStatus,
+    /// Whether `node` is from the patched file, found through an added line. The graph is built
+    /// from the files before the patch, so only nodes found through removed lines are in it.
+    pub patched: bool,
+    /// The innermost declaration enclosing the changed line
+    pub declaration: Option<ChangedDeclaration>,
+}
+
 impl NodeInfo {
-    pub fn from_ts_node(ts_node: &TSNode) -> Self {
+    pub fn from_ts_node(file: usize, ts_node: &TSNode) -> Self {
         Self {
             id: ts_node.id(),
             kind_id: ts_node.kind_id(),
+            kind: Cow::Borrowed(ts_node.kind()),
+            file,
             byte_range: ts_node.byte_range(),
         }
     }
+
+    /// Orders nodes by file, then by where they start, parents before the children they span.
+    /// Unlike ids, which are addresses of tree-sitter nodes, it is the same on every run.
+    pub fn sort_key(&self) -> (usize, usize, std::cmp::Reverse<usize>, u16) {
+        (self.file, self.byte_range.start, std::cmp::Reverse(self.byte_range.end), self.kind_id)
+    }
 }
 impl Edge {
-    pub fn from_ts_nodes(from: &TSNode, to: &TSNode) -> Self {
+    pub fn from_ts_nodes(file: usize, from: &TSNode, to: &TSNode) -> Self {
         Self {
-            from: NodeInfo::from_ts_node(from),
-            to: NodeInfo::from_ts_node(to),
+            from: NodeInfo::from_ts_node(file, from),
+            to: NodeInfo::from_ts_node(file, to),
+        }
+    }
+}
+
+/// A node of a file's tree in pre-order, with what sharing identical subtrees needs to know.
+struct SubtreeNode {
+    info: NodeInfo,
+    /// Structural hash of the subtree, None when the patch touches it or it is a single leaf
+    hash: Option<u64>,
+    /// Nodes in the subtree, itself included, which follow it in pre-order
+    size: usize,
+}
+
+/// Every node of the file's original tree in pre-order, hashing each subtree by the kinds of its
+/// nodes, the text of its leaves and its shape.
+fn index_subtrees(file: usize, diff: &Diff) -> Vec<SubtreeNode> {
+    let touches_edit = |range: &std::ops::Range<usize>| diff.edits.iter().any(|edit| {
+        // Insertions remove nothing, but still touch the node they are inserted into
+        let end = edit.old_end_byte.max(edit.start_byte + 1);
+        edit.start_byte < range.end && range.start < end
+    });
+
+    let mut nodes: Vec<SubtreeNode> = Vec::new();
+    let mut open: Vec<(usize, DefaultHasher)> = Vec::new();
+    let mut cursor = diff.tree.walk();
+    loop {
+        let node = cursor.node();
+        let mut hasher = DefaultHasher::new();
+        node.kind_id().hash(&mut hasher);
+        if node.child_count() == 0 {
+            diff.source.get(node.byte_range()).hash(&mut hasher);
+        }
+        open.push((nodes.len(), hasher));
+        nodes.push(SubtreeNode { info: NodeInfo::from_ts_node(file, &node), hash: None, size: 1 });
+        if cursor.goto_first_child() {
+            continue;
+        }
+
+        // Close the subtrees the cursor leaves, folding each hash into its parent's
+        loop {
+            let Some((index, hasher)) = open.pop() else {
+                return nodes;
+            };
+            let hash = hasher.finish();
+            let size = nodes.len() - index;
+            nodes[index].size = size;
+            if size > 1 && !touches_edit(&nodes[index].info.byte_range) {
+                nodes[index].hash = Some(hash);
+            }
+            if let Some((_, parent)) = open.last_mut() {
+                (hash, size).hash(parent);
+            }
+            if cursor.goto_next_sibling() {
+                break;
+            }
+            if !cursor.goto_parent() {
+                return nodes;
+            }
         }
     }
 }
 
+/// Maps the nodes of every unchanged subtree identical to an earlier one, in patch order, to
+/// the nodes of that first one.
+fn find_shared_nodes(file_subtrees: &[Vec<SubtreeNode>]) -> HashMap<NodeWeight, NodeInfo> {
+    let mut first_seen: HashMap<(u64, usize), (usize, usize)> = HashMap::new();
+    let mut shared: HashMap<NodeWeight, NodeInfo> = HashMap::new();
+    for (file, subtrees) in file_subtrees.iter().enumerate() {
+        let mut i = 0;
+        while i < subtrees.len() {
+            let node = &subtrees[i];
+            let Some(hash) = node.hash else {
+                i += 1;
+                continue;
+            };
+            match first_seen.get(&(hash, node.size)) {
+                Some(&(first_file, first)) => {
+                    // Identical subtrees have the same shape, so their nodes line up in pre-order
+                    for offset in 0..node.size {
+                        let canonical = &file_subtrees[first_file][first + offset].info;
+                        // Parts of the first subtree may themselves be copies of an earlier one
+                        let canonical = shared.get(&canonical.id).unwrap_or(canonical).clone();
+                        shared.insert(subtrees[i + offset].info.id, canonical);
+                    }
+                    i += node.size;
+                },
+                None => {
+                    first_seen.insert((hash, node.size), (file, i));
+                    i += 1;
+                },
+            }
+        }
+    }
+    shared
+}
+
+/// Byte ranges of the last character before and the first after an insertion, skipping the
+/// whitespace around it, or the insertion itself when there are neither, as in a new file.
+fn find_insertion_neighbours(source: &str, at: usize) -> Vec<(usize, usize)> {
+    let before = source[..at].trim_end().len();
+    let after = source.len() - source[at..].trim_start().len();
+    let mut ranges = Vec::new();
+    if before > 0 {
+        ranges.push((before - 1, before));
+    }
+    if after < source.len() {
+        ranges.push((after, after + 1));
+    }
+    if ranges.is_empty() {
+        ranges.push((at, at));
+    }
+    ranges
+}
+
+/// The enclosing declaration of each edit in the original file, or the smallest node spanning
+/// the edit when no declaration does, leaving out those nested in another. Insertions remove
+/// nothing from the original file, so the code either side of them stands in for them.
+pub fn changed_subtrees(diff: &Diff) -> Vec<TSNode<'_>> {
+    let mut roots: Vec<TSNode> = Vec::new();
+    let mut seen = HashSet::new();
+    let ranges = diff.edits.iter().flat_map(|edit| match edit.old_end_byte > edit.start_byte {
+        true => vec![(edit.start_byte, edit.old_end_byte)],
+        false => find_insertion_neighbours(&diff.source, edit.start_byte),
+    });
+    for (start_byte, end_byte) in ranges {
+        let Some(node) = diff.tree.root_node().descendant_for_byte_range(start_byte, end_byte) else {
+            continue;
+        };
+        let mut root = node;
+        let mut parent = Some(node);
+        while let Some(current) = parent {
+            if current.is_named() && syntax::is_declaration_kind(current.kind()) {
+                root = current;
+                break;
+            }
+            parent = current.parent();
+        }
+        if seen.insert(root.id()) {
+            roots.push(root);
+        }
+    }
+
+    let contains = |outer: &TSNode, inner: &TSNode| outer.id() != inner.id()
+        && outer.start_byte() <= inner.start_byte() && inner.end_byte() <= outer.end_byte();
+    roots.iter()
+        .filter(|root| !roots.iter().any(|other| contains(other, root)))
+        .copied()
+        .collect()
+}
+
 pub struct TreeIterator<'a, F> 
 where F: FnMut(TSNode, TSNode) 
 {
     walker: TreeCursor<'a>,
     traversed: bool,
     relation_cb: F,
+    max_depth: Option<usize>,
+    max_children: Option<usize>,
+    /// Index among its siblings of the cursor's node and of each of its ancestors below the root
+    child_indices: Vec<usize>,
+    depth_limited: bool,
+    children_limited: bool,
 }
 
 impl<'a, F> TreeIterator<'a, F>
 where F: FnMut(TSNode, TSNode) 
 {
     pub fn new(tree: &'a Tree, relation_cb: F) -> Self {
+        Self::from_cursor(tree.walk(), relation_cb)
+    }
+
+    /// Walks only the node's subtree, which a cursor started at the node cannot leave.
+    pub fn from_node(node: TSNode<'a>, relation_cb: F) -> Self {
+        Self::from_cursor(node.walk(), relation_cb)
+    }
+
+    fn from_cursor(walker: TreeCursor<'a>, relation_cb: F) -> Self {
         Self {
-            walker: tree.walk(),
+            walker,
             traversed: false,
             relation_cb,
+            max_depth: None,
+            max_children: None,
+            child_indices: Vec::new(),
+            depth_limited: false,
+            children_limited: false,
+        }
+    }
+
+    /// Skips nodes deeper than `--max-depth` and children past `--max-children`.
+    pub fn with_limits(mut self, limits: &Limits) -> Self {
+        self.max_depth = limits.max_depth;
+        self.max_children = limits.max_children;
+        self
+    }
+
+    /// The first limit the walk left nodes out because of, if any.
+    pub fn truncation(&self, file: &str) -> Option<Truncation> {
+        match (self.depth_limited, self.max_depth, self.children_limited, self.max_children) {
+            (true, Some(limit), _, _) => Some(Truncation::Depth { file: file.to_string(), limit }),
+            (_, _, true, Some(limit)) => Some(Truncation::Children { file: file.to_string(), limit }),
+            _ => None,
+        }
+    }
+
+    fn goto_first_child(&mut self) -> bool {
+        if self.max_depth.is_some_and(|max_depth| self.child_indices.len() >= max_depth) {
+            self.depth_limited |= self.walker.node().child_count() > 0;
+            return false;
+        }
+        if !self.walker.goto_first_child() {
+            return false;
         }
+        self.child_indices.push(0);
+        true
     }
 
-    fn reset(&mut self) {
-        // Reset root
-        while self.walker.goto_parent() {}
-        self.traversed = false;
+    fn goto_next_sibling(&mut self) -> bool {
+        let Some(index) = self.child_indices.last_mut() else {
+            return false;
+        };
+        if self.max_children.is_some_and(|max_children| *index + 1 >= max_children) {
+            self.children_limited |= self.walker.node().next_sibling().is_some();
+            return false;
+        }
+        if !self.walker.goto_next_sibling() {
+            return false;
+        }
+        *index += 1;
+        true
+    }
+
+    fn goto_parent(&mut self) -> bool {
+        if !self.walker.goto_parent() {
+            return false;
+        }
+        self.child_indices.pop();
+        true
     }
 }
 
@@ -88,16 +539,16 @@ where F: FnMut(TSNode, TSNode)
         }
         let node = self.walker.node();
 
-        if self.walker.goto_first_child() || self.walker.goto_next_sibling() {
+        if self.goto_first_child() || self.goto_next_sibling() {
             (self.relation_cb)(node, self.walker.node());
             return Some(node);
         }
         loop {
-            if !self.walker.goto_parent() {
+            if !self.goto_parent() {
                 self.traversed = true;
                 break;
             }
-            if self.walker.goto_next_sibling() {
+            if self.goto_next_sibling() {
                 break;
             }
         }
@@ -109,39 +560,463 @@ where F: FnMut(TSNode, TSNode)
 }
 
 impl DiffGraph {
-    pub fn create(params: DiffGraphParams) -> Result<Self, String> {
-        let diffs = match try_parse_patch(
-            &params.diff, 
-            None, 
-            params.save_default_if_missing, 
-            params.install_lang_if_missing) 
-        {
-            Ok(diffs) => diffs,
-            Err(e) => return Err(e.to_string())
+    pub fn create(params: DiffGraphParams) -> Result<Self, DiffGraphError> {
+        Self::create_with_visitor(params, &mut ())
+    }
+
+    /// Same as `create`, calling back into the visitor for every file, node and edge as the graph is built.
+    pub fn create_with_visitor(params: DiffGraphParams, visitor: &mut dyn GraphVisitor) -> Result<Self, DiffGraphError> {
+        Self::build(params, None, visitor)
+    }
+
+    /// Same as `create`, for a newer version of the patch `previous` was built from with the same
+    /// options, such as the uncommitted changes on every rebuild in watch mode. Trees of unchanged
+    /// files are reused and the others reparsed incrementally, and the graph is kept as it is
+    /// when the files before the patch and the edits pruning their trees are all the same.
+    pub fn update(previous: DiffGraph, params: DiffGraphParams) -> Result<Self, DiffGraphError> {
+        Self::build(params, Some(previous), &mut ())
+    }
+
+    fn build(params: DiffGraphParams, previous: Option<DiffGraph>, visitor: &mut dyn GraphVisitor) -> Result<Self, DiffGraphError> {
+        let source_location = params.get_source_location();
+        let mut report = ParseReport {
+            findings: params.get_binary_file_findings(),
+            ..Default::default()
         };
-        let graph = Self::create_graph_from_diffs(&diffs)?;
-        println!("graph (n# {}, e#: {})", graph.node_count(), graph.edge_count());
 
-        Ok(Self {
+        // A truncated graph depends on more than the trees, so only complete graphs are reused
+        let (previous_graph, previous_diffs) = match previous {
+            Some(previous) if previous.truncations.is_empty() && previous.full_tree == params.full_tree => (Some(previous.graph), previous.diffs),
+            Some(previous) => (None, previous.diffs),
+            None => (None, Vec::new()),
+        };
+        let previous_roots: Vec<usize> = previous_diffs.iter().map(|diff| diff.tree.root_node().id()).collect();
+        let previous_edits: Vec<Vec<InputEdit>> = previous_diffs.iter().map(|diff| diff.edits.clone()).collect();
+        let previous_diffs: HashMap<String, Diff> = previous_diffs
+            .into_iter()
+            .map(|diff| (diff.source_file_path.clone(), diff))
+            .collect();
+        let diffs = try_parse_patch(&params, &source_location, &previous_diffs, &mut report, visitor)?;
+        drop(previous_diffs);
+        let ParseReport { mut truncations, mut findings, skipped, errors } = report;
+        if let Some(dir) = &params.dump_trees {
+            for diff in diffs.iter() {
+                diff.try_dump_trees(dir)?;
+            }
+        }
+
+        // The graph is built from the trees before the patch, which are shared with the previous
+        // diffs rather than reparsed when their text did not change, pruned to the subtrees the
+        // edits change unless every node is graphed
+        let unchanged = diffs.iter().map(|diff| diff.tree.root_node().id()).eq(previous_roots)
+            && (params.full_tree || diffs.iter().map(|diff| &diff.edits).eq(previous_edits.iter()));
+        let mut graph = match previous_graph {
+            Some(graph) if unchanged => {
+                info!("Reusing the previous graph, no file changed before the patch");
+                graph
+            },
+            _ => timings::phase("graph build", || 
+                Self::create_graph_from_diffs(&diffs, &params.exclude_kinds, params.share_subtrees, params.full_tree, &params.limits, &mut truncations, visitor))?,
+        };
+
+        findings.extend(truncations
+            .iter()
+            .map(|truncation| Finding::new(Condition::Truncated, truncation.to_string())));
+
+        let (links, link_edges) = timings::phase("cross-language links", || links::find_links(&diffs));
+        // Both ends must already be in the graph, links never grow it past its limits
+        for edge in link_edges {
+            if !graph.contains_node(edge.from.id) || !graph.contains_node(edge.to.id) {
+                continue;
+            }
+            visitor.on_edge(&edge);
+            graph.add_edge(edge.from.id, edge.to.id, edge);
+        }
+
+        let (config_changes, config_edges) = timings::phase("config changes", || configs::find_config_changes(&diffs));
+        // Each changed key is a node of its own, hanging off its entry in the graph while the node limit allows
+        for edge in config_edges {
+            if !graph.contains_node(edge.from.id) || params.limits.max_nodes.is_some_and(|max_nodes| graph.node_count() >= max_nodes) {
+                continue;
+            }
+            visitor.on_node(&edge.to);
+            visitor.on_edge(&edge);
+            graph.add_edge(edge.from.id, edge.to.id, edge);
+        }
+
+        let security_queries = SecurityQueries::try_load(params.security_queries.as_deref())?;
+        let mut scanner = SecurityScanner::new(&security_queries);
+        let security_tags: Vec<SecurityTag> = timings::phase("security scan", || diffs
+            .iter()
+            .flat_map(|diff| scanner.scan(diff))
+            .collect());
+        findings.extend(security_tags
+            .iter()
+            .map(|tag| Finding::new(Condition::SecurityPatterns, tag.to_string())));
+        let debt_queries = DebtQueries::try_load(params.debt_queries.as_deref())?;
+        let mut debt_scanner = DebtScanner::new(&debt_queries);
+        let debt_tags: Vec<DebtTag> = timings::phase("debt scan", || diffs
+            .iter()
+            .flat_map(|diff| debt_scanner.scan(diff))
+            .collect());
+        findings.extend(debt_tags
+            .iter()
+            .map(|tag| Finding::new(Condition::DebtPatterns, tag.to_string())));
+        let violations = match &params.architecture_rules {
+            Some(path) => {
+                let rules = ArchitectureRules::try_load(path)?;
+                let index = params.symbol_index.as_deref().map(SymbolIndex::try_load).transpose()?;
+                timings::phase("architecture rules", || rules.check(&diffs, index.as_ref()))
+            },
+            None => Vec::new(),
+        };
+        findings.extend(violations
+            .iter()
+            .map(|violation| Finding::new(Condition::ArchitectureViolations, violation.to_string())));
+        params.policy.evaluate(&findings)?;
+
+        let untested = match &params.coverage {
+            Some(path) => {
+                let coverage = Coverage::try_load(path)?;
+                timings::phase("coverage", || coverage.find_untested_changes(&diffs))
+            },
+            None => Vec::new(),
+        };
+
+        let graph = Self {
             graph,
             diffs,
-        })
+            truncations,
+            findings,
+            skipped,
+            errors,
+            shard: params.shard,
+            owners: CodeOwners::load(&source_location),
+            security_tags,
+            debt_tags,
+            links,
+            config_changes,
+            violations,
+            untested,
+            full_tree: params.full_tree,
+        };
+        info!("{}", graph);
+        stats::record_graph(graph.diffs.len(), graph.node_count(), graph.edge_count());
+
+        Ok(graph)
     }
 
-    fn create_graph_from_diffs(diffs: &Vec<Diff>) -> Result<DiGraphMap<NodeWeight, Edge>, String> {
-        let mut graph = DiGraphMap::new();
-        for d in diffs {
-            let mut dfs = TreeIterator::new(&d.tree, |from, to| {
-                let from_node_id = graph.add_node(from.id());
-                let to_node_id = graph.add_node(to.id());
-                let edge = Edge::from_ts_nodes(&from, &to);
-
-                graph.add_edge(from_node_id, to_node_id, edge);
-            });
+    pub fn graph(&self) -> &DiGraphMap<NodeWeight, Edge> {
+        &self.graph
+    }
+
+    /// The parsed files the graph was built from, in patch order.
+    pub fn diffs(&self) -> &Vec<Diff> {
+        &self.diffs
+    }
+
+    /// Conditions noticed while building the graph, see `Policy`.
+    pub fn findings(&self) -> &Vec<Finding> {
+        &self.findings
+    }
+
+    /// Files the options left out of the graph, such as those not in a selected language.
+    pub fn skipped(&self) -> &Vec<SkippedFile> {
+        &self.skipped
+    }
+
+    /// Files that failed and were left out of the graph, empty with `DiffGraphParams::strict`.
+    pub fn errors(&self) -> &Vec<FileError> {
+        &self.errors
+    }
+
+    /// The shard of the patch the graph is of, when it is of only one.
+    pub fn shard(&self) -> Option<Shard> {
+        self.shard
+    }
+
+    /// Owners of the file at the path by the repository's CODEOWNERS, none without one.
+    pub fn get_owners(&self, path: &str) -> &[String] {
+        self.owners.as_ref().map_or(&[], |owners| owners.get_owners(path))
+    }
+
+    /// Changed nodes matching a security-sensitive pattern, in patch order.
+    pub fn security_tags(&self) -> &Vec<SecurityTag> {
+        &self.security_tags
+    }
+
+    /// Changed nodes adding a TODO, a panic or a similar pattern of debt, in patch order.
+    pub fn debt_tags(&self) -> &Vec<DebtTag> {
+        &self.debt_tags
+    }
+
+    /// Definitions of interface definition files the patch changes, linked to the changed
+    /// files of other languages using them. The graph has an edge for each use.
+    pub fn links(&self) -> &Vec<CrossLanguageLink> {
+        &self.links
+    }
+
+    /// Keys of the changed YAML, TOML and JSON files whose values the patch adds, removes or
+    /// changes. The graph has a `config_key` node for each.
+    pub fn config_changes(&self) -> &Vec<ConfigChange> {
+        &self.config_changes
+    }
+
+    /// Imports and uses the patch adds that the architecture rules forbid, empty without rules.
+    pub fn violations(&self) -> &Vec<ArchitectureViolation> {
+        &self.violations
+    }
+
+    /// Changed declarations with added lines the coverage report instruments but no test ran,
+    /// empty without a report.
+    pub fn untested(&self) -> &Vec<UntestedChange> {
+        &self.untested
+    }
+
+    /// Limits that were hit while building the graph, empty when the graph is complete.
+    pub fn truncations(&self) -> &Vec<Truncation> {
+        &self.truncations
+    }
+
+    /// Details of every node in the graph, in no particular order.
+    pub fn nodes(&self) -> impl Iterator<Item = &NodeInfo> + '_ {
+        // Nodes themselves are only ids, their details are kept on the edges they are part of
+        self.graph
+            .nodes()
+            .filter_map(|id| self.node(id))
+    }
+
+    pub fn node(&self, id: NodeWeight) -> Option<&NodeInfo> {
+        if let Some((_, _, edge)) = self.graph.edges_directed(id, Direction::Outgoing).next() {
+            return Some(&edge.from);
+        }
+        self.graph
+            .edges_directed(id, Direction::Incoming)
+            .next()
+            .map(|(_, _, edge)| &edge.to)
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = &Edge> + '_ {
+        self.graph
+            .all_edges()
+            .map(|(_, _, edge)| edge)
+    }
+
+    /// Nodes ordered by `NodeInfo::sort_key`, for output that is the same on every run.
+    pub fn sorted_nodes(&self) -> Vec<&NodeInfo> {
+        let mut nodes: Vec<&NodeInfo> = self.nodes().collect();
+        nodes.sort_by_key(|node| node.sort_key());
+        nodes
+    }
+
+    /// Numbers the nodes from 0 in sorted order, for exports to write in place of node ids,
+    /// which differ from run to run.
+    pub fn stable_ids(&self) -> HashMap<NodeWeight, usize> {
+        self.sorted_nodes()
+            .into_iter()
+            .enumerate()
+            .map(|(index, node)| (node.id, index))
+            .collect()
+    }
+
+    /// Edges ordered by the stable ids of the nodes they join.
+    pub fn sorted_edges(&self, ids: &HashMap<NodeWeight, usize>) -> Vec<&Edge> {
+        let mut edges: Vec<&Edge> = self.edges().collect();
+        edges.sort_by_key(|edge| (ids[&edge.from.id], ids[&edge.to.id]));
+        edges
+    }
+
+    /// The part of the graph built from one file, or None if the path is not one of the diffs.
+    pub fn file_subgraph(&self, path: &str) -> Option<DiGraphMap<NodeWeight, &Edge>> {
+        let file = self.diffs
+            .iter()
+            .position(|diff| diff.source_file_path == path)?;
+
+        let mut subgraph = DiGraphMap::new();
+        for (from, to, edge) in self.graph.all_edges() {
+            if edge.from.file == file {
+                subgraph.add_edge(from, to, edge);
+            }
+        }
+        Some(subgraph)
+    }
+
+    /// The source text a graph node spans, in the file before the patch.
+    pub fn node_source(&self, node: &NodeInfo) -> Option<&str> {
+        self.diffs
+            .get(node.file)?
+            .source
+            .get(node.byte_range.clone())
+    }
+
+    /// Nodes on the lines each file's patch added or removed, file by file in patch order.
+    pub fn changed_nodes(&self) -> impl Iterator<Item = ChangedNode> + '_ {
+        self.diffs
+            .iter()
+            .enumerate()
+            .flat_map(|(file, diff)| diff.changed_nodes(file))
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.graph.node_count()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.graph.edge_count()
+    }
+
+    /// Edges of the file's tree in depth first order, leaving out those touching excluded kinds,
+    /// and whether the depth or children limits cut the walk short. Unless `full_tree` is set,
+    /// only the changed subtrees are walked, each joined to the root of the tree by the nodes
+    /// enclosing it, so a small edit to a large file does not graph all of it.
+    fn collect_file_edges(file: usize, diff: &Diff, exclude_kinds: &[String], limits: &Limits, full_tree: bool) -> FileEdges {
+        let is_excluded = |from: &TSNode, to: &TSNode| exclude_kinds.iter().any(|k| k == from.kind() || k == to.kind());
+        let mut edges = Vec::new();
+        if full_tree {
+            let mut dfs = TreeIterator::new(&diff.tree, |from, to| {
+                if !is_excluded(&from, &to) {
+                    edges.push(Edge::from_ts_nodes(file, &from, &to));
+                }
+            }).with_limits(limits);
             while dfs.next().is_some() {}
-            dfs.reset();
+            let truncation = dfs.truncation(&diff.source_file_path);
+            drop(dfs);
+            return (edges, truncation);
+        }
+
+        let mut enclosing_edges = HashSet::new();
+        let mut truncation = None;
+        for root in changed_subtrees(diff) {
+            let mut chain = vec![root];
+            while let Some(parent) = chain.last().and_then(|node| node.parent()) {
+                chain.push(parent);
+            }
+            // Subtrees in the same declaration share the nodes enclosing it
+            for pair in chain.windows(2).rev() {
+                let (parent, child) = (pair[1], pair[0]);
+                if !is_excluded(&parent, &child) && enclosing_edges.insert((parent.id(), child.id())) {
+                    edges.push(Edge::from_ts_nodes(file, &parent, &child));
+                }
+            }
+
+            let mut dfs = TreeIterator::from_node(root, |from, to| {
+                if !is_excluded(&from, &to) {
+                    edges.push(Edge::from_ts_nodes(file, &from, &to));
+                }
+            }).with_limits(limits);
+            while dfs.next().is_some() {}
+            truncation = truncation.or(dfs.truncation(&diff.source_file_path));
+        }
+        (edges, truncation)
+    }
+
+    /// Walks the files' trees in parallel, then merges their edges in patch order so node limits
+    /// and the visitor see the same graph a serial build would. When sharing subtrees, edges to
+    /// and from a copy of an earlier unchanged subtree are moved onto the nodes of that subtree.
+    fn create_graph_from_diffs(
+        diffs: &[Diff], 
+        exclude_kinds: &[String], 
+        share_subtrees: bool,
+        full_tree: bool,
+        limits: &Limits, 
+        truncations: &mut Vec<Truncation>,
+        visitor: &mut dyn GraphVisitor,
+    ) -> Result<DiGraphMap<NodeWeight, Edge>, DiffGraphError> {
+        let bar = progress::bar(diffs.len() as u64, "Graphing");
+        let (file_edges, file_subtrees): (Vec<FileEdges>, Vec<Vec<SubtreeNode>>) = diffs
+            .par_iter()
+            .enumerate()
+            .map(|(file, d)| {
+                let edges = Self::collect_file_edges(file, d, exclude_kinds, limits, full_tree);
+                let subtrees = if share_subtrees { index_subtrees(file, d) } else { Vec::new() };
+                bar.inc(1);
+                (edges, subtrees)
+            })
+            .unzip();
+        bar.finish_and_clear();
+        cancel::try_check()?;
+
+        let shared = find_shared_nodes(&file_subtrees);
+        drop(file_subtrees);
+        if share_subtrees {
+            info!("{} nodes are shared with an identical subtree", shared.len());
+        }
+
+        let mut graph = DiGraphMap::new();
+        let mut node_limit_hit = false;
+        for (file, (d, (edges, truncation))) in diffs.iter().zip(file_edges).enumerate() {
+            if let Some(truncation) = truncation {
+                if limits.action == LimitAction::Fail {
+                    return Err(DiffGraphError::LimitExceeded(truncation));
+                }
+                truncations.push(truncation);
+            }
+            visitor.on_file_start(file, d);
+            for mut edge in edges {
+                if let Some(canonical) = shared.get(&edge.from.id) {
+                    edge.from = canonical.clone();
+                }
+                if let Some(canonical) = shared.get(&edge.to.id) {
+                    edge.to = canonical.clone();
+                }
+                if edge.from.id == edge.to.id {
+                    continue;
+                }
+                if let Some(max_nodes) = limits.max_nodes {
+                    let new_nodes = [edge.from.id, edge.to.id].iter().filter(|id| !graph.contains_node(**id)).count();
+                    if graph.node_count() + new_nodes > max_nodes {
+                        node_limit_hit = true;
+                        break;
+                    }
+                }
+                for node in [&edge.from, &edge.to] {
+                    if !graph.contains_node(node.id) {
+                        graph.add_node(node.id);
+                        visitor.on_node(node);
+                    }
+                }
+                visitor.on_edge(&edge);
+
+                graph.add_edge(edge.from.id, edge.to.id, edge);
+            }
+            if node_limit_hit {
+                break;
+            }
+        }
+
+        if let (true, Some(limit)) = (node_limit_hit, limits.max_nodes) {
+            let truncation = Truncation::Nodes { limit };
+            if limits.action == LimitAction::Fail {
+                return Err(DiffGraphError::LimitExceeded(truncation));
+            }
+            truncations.push(truncation);
         }
 
         Ok(graph)
     }
 }
+
+impl fmt::Display for DiffGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "graph (n# {}, e#: {}) of {} files", self.node_count(), self.edge_count(), self.diffs.len())?;
+        if !self.truncations.is_empty() {
+            write!(f, ", truncated")?;
+        }
+        Ok(())
+    }
+}
+
+/// Sources and trees are left out, they would drown out everything else.
+impl fmt::Debug for DiffGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiffGraph")
+            .field("files", &self.diffs.iter().map(|diff| diff.source_file_path.as_str()).collect::<Vec<_>>())
+            .field("node_count", &self.node_count())
+            .field("edge_count", &self.edge_count())
+            .field("truncations", &self.truncations)
+            .field("findings", &self.findings)
+            .field("skipped", &self.skipped)
+            .field("errors", &self.errors)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use crate::cli::try_resolve_diff;
+use crate::error::{DiffGraphError, ErrorCode};
+use crate::graph::{DiffGraph, DiffGraphParams, NodeInfo};
+use crate::snapshot::GraphSnapshot;
+
+pub mod proto {
+    tonic::include_proto!("diffgraph");
+}
+
+use proto::diff_graph_service_server::{DiffGraphService, DiffGraphServiceServer};
+
+/// Submitted graphs kept for later requests, the oldest is dropped past this
+const MAX_GRAPHS: usize = 64;
+
+struct GrpcService {
+    repository_dir: PathBuf,
+    graph_params: DiffGraphParams,
+    next_id: AtomicU64,
+    graphs: Mutex<VecDeque<(String, Arc<GraphSnapshot>)>>,
+}
+
+fn to_status(e: DiffGraphError) -> Status {
+    let message = e.to_string();
+    match e.code() {
+        ErrorCode::Usage => Status::invalid_argument(message),
+        ErrorCode::Repository | ErrorCode::Patch | ErrorCode::BreakingChange | ErrorCode::SecurityPattern | ErrorCode::NotEquivalent | ErrorCode::DebtPattern | ErrorCode::ArchitectureViolation => Status::failed_precondition(message),
+        ErrorCode::Network => Status::unavailable(message),
+        ErrorCode::LimitExceeded => Status::resource_exhausted(message),
+        ErrorCode::Cancelled => Status::cancelled(message),
+        _ => Status::internal(message),
+    }
+}
+
+fn to_proto_node(node: &NodeInfo) -> proto::Node {
+    proto::Node {
+        id: node.id as u64,
+        kind: node.kind.to_string(),
+        file: node.file as u32,
+        start_byte: node.byte_range.start as u64,
+        end_byte: node.byte_range.end as u64,
+    }
+}
+
+impl GrpcService {
+    fn try_build(&self, repository: &str, diff_arg: &str) -> Result<GraphSnapshot, DiffGraphError> {
+        let repository_dir = if repository.is_empty() { self.repository_dir.clone() } else { PathBuf::from(repository) };
+        let diff = try_resolve_diff(diff_arg, &repository_dir)?;
+        let graph = DiffGraph::create(DiffGraphParams {
+            diff_repository_dir: repository_dir.to_string_lossy().to_string(),
+            diff: diff.patch,
+            source_revision: diff.source_revision,
+            git_dir: diff.git_dir,
+            binary_files: diff.binary_files,
+            ..self.graph_params.clone()
+        })?;
+        Ok(GraphSnapshot::from_graph(&graph))
+    }
+
+    fn try_get(&self, graph_id: &str) -> Result<Arc<GraphSnapshot>, Status> {
+        let graphs = self.graphs.lock().map_err(|_| Status::internal("Graph store is poisoned"))?;
+        graphs
+            .iter()
+            .find(|(id, _)| id == graph_id)
+            .map(|(_, snapshot)| snapshot.clone())
+            .ok_or_else(|| Status::not_found(format!("No graph with id '{}', it may have been evicted", graph_id)))
+    }
+}
+
+#[tonic::async_trait]
+impl DiffGraphService for Arc<GrpcService> {
+    async fn submit_diff(&self, request: Request<proto::SubmitDiffRequest>) -> Result<Response<proto::SubmitDiffResponse>, Status> {
+        let request = request.into_inner();
+        let service = self.clone();
+        // Parsing and git calls block, keep them off the executor threads
+        let snapshot = tokio::task::spawn_blocking(move || service.try_build(&request.repository, &request.diff))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(to_status)?;
+
+        let graph_id = self.next_id.fetch_add(1, Ordering::Relaxed).to_string();
+        let response = proto::SubmitDiffResponse {
+            graph_id: graph_id.clone(),
+            node_count: snapshot.nodes.len() as u64,
+            edge_count: snapshot.edges.len() as u64,
+            truncations: snapshot.truncations.clone(),
+        };
+        let mut graphs = self.graphs.lock().map_err(|_| Status::internal("Graph store is poisoned"))?;
+        if graphs.len() >= MAX_GRAPHS {
+            graphs.pop_front();
+        }
+        graphs.push_back((graph_id, Arc::new(snapshot)));
+
+        Ok(Response::new(response))
+    }
+
+    async fn get_graph(&self, request: Request<proto::GetGraphRequest>) -> Result<Response<proto::Graph>, Status> {
+        let snapshot = self.try_get(&request.get_ref().graph_id)?;
+        Ok(Response::new(proto::Graph {
+            files: snapshot.files
+                .iter()
+                .map(|file| proto::File { path: file.path.clone(), language: file.language.clone() })
+                .collect(),
+            nodes: snapshot.nodes.iter().map(to_proto_node).collect(),
+            edges: snapshot.edges
+                .iter()
+                .map(|edge| proto::Edge { from: edge.from as u64, to: edge.to as u64 })
+                .collect(),
+            truncations: snapshot.truncations.clone(),
+        }))
+    }
+
+    async fn query_nodes(&self, request: Request<proto::QueryNodesRequest>) -> Result<Response<proto::QueryNodesResponse>, Status> {
+        let request = request.into_inner();
+        let snapshot = self.try_get(&request.graph_id)?;
+        let file = match request.file.as_str() {
+            "" => None,
+            path => Some(snapshot.files
+                .iter()
+                .position(|file| file.path == path)
+                .ok_or_else(|| Status::not_found(format!("'{}' is not in the graph", path)))?),
+        };
+
+        let nodes = snapshot.nodes
+            .iter()
+            .filter(|node| request.kind.is_empty() || node.kind == request.kind)
+            .filter(|node| file.map_or(true, |file| node.file == file))
+            .filter(|node| !request.changed_only || snapshot.changes.iter().any(|changed| !changed.patched && changed.node.id == node.id))
+            .map(to_proto_node)
+            .collect();
+        Ok(Response::new(proto::QueryNodesResponse { nodes }))
+    }
+}
+
+/// Serves the DiffGraphService in `proto/diffgraph.proto` until the process is stopped.
+pub fn run(address: SocketAddr, repository_dir: PathBuf, graph_params: DiffGraphParams) -> Result<(), DiffGraphError> {
+    let service = Arc::new(GrpcService {
+        repository_dir,
+        graph_params,
+        next_id: AtomicU64::new(1),
+        graphs: Mutex::new(VecDeque::new()),
+    });
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| DiffGraphError::io("Unable to start the async runtime", e))?;
+    info!("Serving gRPC on {}", address);
+    runtime
+        .block_on(tonic::transport::Server::builder()
+            .add_service(DiffGraphServiceServer::new(service))
+            .serve(address))
+        .map_err(|e| DiffGraphError::Network(format!("gRPC server failed: {}", e)))
+}
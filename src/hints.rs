@@ -0,0 +1,81 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::graph::DiffGraph;
+
+/// Directories source trees are rooted in, which say nothing of the module a file belongs to
+const SOURCE_ROOTS: [&str; 6] = ["src", "lib", "pkg", "internal", "app", "source"];
+
+/// File stems standing for the directory they are in, such as `mod.rs` and `__init__.py`
+const MODULE_STEMS: [&str; 6] = ["mod", "lib", "main", "index", "__init__", "init"];
+
+/// Most declarations named per scope
+const MAX_DECLARATIONS: usize = 3;
+
+/// A conventional-commit scope the patch touches, such as `parser` in `fix(parser): ...`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScopeHint {
+    pub scope: String,
+    pub files: Vec<String>,
+    /// The first few named declarations the patch changes in the scope, for the subject line
+    pub declarations: Vec<String>,
+    /// Added and removed lines of the scope's files
+    pub changed_lines: usize,
+}
+
+impl fmt::Display for ScopeHint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.scope)?;
+        if !self.declarations.is_empty() {
+            write!(f, " ({})", self.declarations.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The module a file belongs to, going by its path: the first directory under the source
+/// root for files nested in one, otherwise the file itself.
+pub fn get_scope(path: &str) -> Option<String> {
+    let mut components: Vec<&str> = path.split('/').filter(|component| !component.is_empty() && *component != ".").collect();
+    let file = components.pop()?;
+    let stem = file.split('.').next().unwrap_or(file);
+    // Leading components up to the last source root, such as `crates/foo/src`
+    let start = components.iter().rposition(|component| SOURCE_ROOTS.contains(component)).map_or(0, |root| root + 1);
+    let directories = &components[start..];
+    match directories.first() {
+        Some(directory) => Some(directory.to_string()),
+        None if MODULE_STEMS.contains(&stem) => components.iter().rev().find(|component| !SOURCE_ROOTS.contains(component)).map(|component| component.to_string()),
+        None if stem.is_empty() => None,
+        None => Some(stem.to_string()),
+    }
+}
+
+/// Scopes of the changed files, the ones with the most changed lines first, for tooling that
+/// drafts commit messages. A patch with a single scope would usually be committed under it.
+pub fn find_scopes(graph: &DiffGraph) -> Vec<ScopeHint> {
+    let mut hints: Vec<ScopeHint> = Vec::new();
+    for diff in graph.diffs().iter() {
+        let Some(scope) = get_scope(&diff.source_file_path) else {
+            continue;
+        };
+        let index = match hints.iter().position(|hint| hint.scope == scope) {
+            Some(index) => index,
+            None => {
+                hints.push(ScopeHint { scope, files: Vec::new(), declarations: Vec::new(), changed_lines: 0 });
+                hints.len() - 1
+            },
+        };
+        let hint = &mut hints[index];
+        hint.files.push(diff.source_file_path.clone());
+        hint.changed_lines += diff.added_rows.len() + diff.removed_rows.len();
+        for name in diff.changed_declarations().into_iter().filter_map(|declaration| declaration.name) {
+            if hint.declarations.len() < MAX_DECLARATIONS && !hint.declarations.contains(&name) {
+                hint.declarations.push(name);
+            }
+        }
+    }
+
+    hints.sort_by_key(|hint| std::cmp::Reverse(hint.changed_lines));
+    hints
+}
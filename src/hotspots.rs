@@ -0,0 +1,125 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::color::{self, Stream, Style};
+use crate::graph::DiffGraph;
+
+const HEADERS: [&str; 4] = ["CHANGES", "NODES", "DECLARATION", "FILE"];
+
+/// A declaration, by file, kind and name, and how often the commits of a range changed it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Hotspot {
+    pub file: String,
+    /// None for changes outside of any declaration, such as imports
+    pub kind: Option<String>,
+    pub name: Option<String>,
+    /// Commits that changed the declaration
+    pub changes: usize,
+    /// Changed nodes within the declaration, summed over the commits
+    pub nodes: usize,
+}
+
+impl Hotspot {
+    fn label(&self) -> String {
+        match (&self.kind, &self.name) {
+            (Some(kind), Some(name)) => format!("{} {}", kind, name),
+            (Some(kind), None) => kind.clone(),
+            (None, _) => "outside of declarations".to_string(),
+        }
+    }
+}
+
+/// Declarations of a range of commits ranked by how often they changed, then by how much,
+/// for finding the code that churns the most.
+#[derive(Debug, Clone, Serialize)]
+pub struct HotspotReport {
+    pub range: String,
+    /// Commits whose graphs were counted
+    pub commits: usize,
+    pub hotspots: Vec<Hotspot>,
+}
+
+type HotspotKey = (String, Option<String>, Option<String>);
+
+impl HotspotReport {
+    pub fn new(range: &str) -> Self {
+        Self { range: range.to_string(), commits: 0, hotspots: Vec::new() }
+    }
+
+    /// Counts the declarations the graph of one commit changes. Unnamed declarations of a file,
+    /// such as Rust `impl` blocks, are counted together by kind.
+    pub fn add_graph(&mut self, graph: &DiffGraph) {
+        let mut index: HashMap<HotspotKey, usize> = self.hotspots
+            .iter()
+            .enumerate()
+            .map(|(i, hotspot)| ((hotspot.file.clone(), hotspot.kind.clone(), hotspot.name.clone()), i))
+            .collect();
+        let mut changed: HashSet<usize> = HashSet::new();
+        for (file, diff) in graph.diffs().iter().enumerate() {
+            for node in diff.changed_nodes(file) {
+                let (kind, name) = match node.declaration {
+                    Some(declaration) => (Some(declaration.kind), declaration.name),
+                    None => (None, None),
+                };
+                let key = (diff.source_file_path.clone(), kind, name);
+                let i = *index.entry(key.clone()).or_insert_with(|| {
+                    self.hotspots.push(Hotspot { file: key.0, kind: key.1, name: key.2, changes: 0, nodes: 0 });
+                    self.hotspots.len() - 1
+                });
+                self.hotspots[i].nodes += 1;
+                if changed.insert(i) {
+                    self.hotspots[i].changes += 1;
+                }
+            }
+        }
+        self.commits += 1;
+    }
+
+    /// Ranks the hotspots once every commit was added, ties keeping the order they were first changed in.
+    pub fn finish(mut self) -> Self {
+        self.hotspots.sort_by_key(|hotspot| std::cmp::Reverse((hotspot.changes, hotspot.nodes)));
+        self
+    }
+}
+
+impl fmt::Display for HotspotReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.hotspots.is_empty() {
+            return writeln!(f, "No declarations changed in {}", self.range);
+        }
+
+        let rows: Vec<[String; 4]> = self.hotspots
+            .iter()
+            .map(|hotspot| [hotspot.changes.to_string(), hotspot.nodes.to_string(), hotspot.label(), hotspot.file.clone()])
+            .collect();
+        let mut widths = HEADERS.map(|header| header.len());
+        for row in rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        // Cells are padded before they are painted, escape codes would throw off the widths
+        let write_row = |f: &mut fmt::Formatter<'_>, cells: &[String], style: Option<Style>| -> fmt::Result {
+            for (i, (cell, width)) in cells.iter().zip(widths.iter()).enumerate() {
+                // Names are left aligned, counts right aligned
+                let padded = if i >= 2 { format!("{:<width$}", cell, width = width) } else { format!("{:>width$}", cell, width = width) };
+                let separator = if i == 0 { "" } else { "  " };
+                match style {
+                    Some(style) => write!(f, "{}{}", separator, color::paint(Stream::Stdout, style, &padded))?,
+                    None => write!(f, "{}{}", separator, padded)?,
+                }
+            }
+            writeln!(f)
+        };
+        write_row(f, &HEADERS.map(String::from), Some(Style::Bold))?;
+        for row in rows.iter() {
+            write_row(f, row, None)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{} declarations changed over {} commits in {}", self.hotspots.len(), self.commits, self.range)
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+
+use crate::cli::try_resolve_diff;
+use crate::error::DiffGraphError;
+use crate::graph::{DiffGraph, DiffGraphParams};
+use crate::snapshot::GraphSnapshot;
+
+/// Graphs built at once, further submissions wait in the queue
+const WORKERS: usize = 2;
+/// Jobs kept for later requests, the oldest is dropped past this
+const MAX_JOBS: usize = 256;
+const MAX_BODY_LEN: usize = 1 << 20;
+/// How long a connection may take to send its request, so slow clients do not hold a thread forever
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+enum JobStatus {
+    Queued,
+    Running,
+    Done(Arc<GraphSnapshot>),
+    Failed(String),
+}
+
+impl JobStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Done(_) => "done",
+            JobStatus::Failed(_) => "failed",
+        }
+    }
+}
+
+struct JobRequest {
+    id: u64,
+    repository_dir: PathBuf,
+    diff: String,
+}
+
+struct HttpService {
+    repository_dir: PathBuf,
+    graph_params: DiffGraphParams,
+    jobs: Mutex<VecDeque<(u64, JobStatus)>>,
+    next_id: Mutex<u64>,
+}
+
+struct HttpResponse {
+    status: u16,
+    body: Value,
+    location: Option<String>,
+}
+
+impl HttpResponse {
+    fn json(status: u16, body: Value) -> Self {
+        Self { status, body, location: None }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Self::json(status, json!({ "error": message.into() }))
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                        continue;
+                    },
+                    None => decoded.push(b'%'),
+                }
+            },
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (percent_decode(key), percent_decode(value)),
+            None => (percent_decode(pair), String::new()),
+        })
+        .collect()
+}
+
+impl HttpService {
+    fn set_status(&self, id: u64, status: JobStatus) {
+        if let Ok(mut jobs) = self.jobs.lock() {
+            if let Some((_, job)) = jobs.iter_mut().find(|(job_id, _)| *job_id == id) {
+                *job = status;
+            }
+        }
+    }
+
+    fn run_job(&self, job: JobRequest) {
+        self.set_status(job.id, JobStatus::Running);
+        let result = try_resolve_diff(&job.diff, &job.repository_dir).and_then(|diff| DiffGraph::create(DiffGraphParams {
+            diff_repository_dir: job.repository_dir.to_string_lossy().to_string(),
+            diff: diff.patch,
+            source_revision: diff.source_revision,
+            git_dir: diff.git_dir,
+            binary_files: diff.binary_files,
+            ..self.graph_params.clone()
+        }));
+        let status = match result {
+            Ok(graph) => JobStatus::Done(Arc::new(GraphSnapshot::from_graph(&graph))),
+            Err(e) => JobStatus::Failed(e.to_string()),
+        };
+        debug!("Job {} {}", job.id, status.as_str());
+        self.set_status(job.id, status);
+    }
+
+    fn submit(&self, body: &[u8], queue: &mpsc::Sender<JobRequest>) -> HttpResponse {
+        let body: Value = match serde_json::from_slice(body) {
+            Ok(body) => body,
+            Err(e) => return HttpResponse::error(400, format!("Body is not valid JSON: {}", e)),
+        };
+        let diff = match body.get("diff").and_then(|diff| diff.as_str()) {
+            Some(diff) => diff.to_string(),
+            None => return HttpResponse::error(400, "diff is required"),
+        };
+        let repository_dir = body.get("repository")
+            .and_then(|repository| repository.as_str())
+            .map_or(self.repository_dir.clone(), PathBuf::from);
+
+        let id = match self.next_id.lock() {
+            Ok(mut next_id) => {
+                *next_id += 1;
+                *next_id
+            },
+            Err(_) => return HttpResponse::error(500, "Job store is poisoned"),
+        };
+        if let Ok(mut jobs) = self.jobs.lock() {
+            if jobs.len() >= MAX_JOBS {
+                jobs.pop_front();
+            }
+            jobs.push_back((id, JobStatus::Queued));
+        }
+        if queue.send(JobRequest { id, repository_dir, diff }).is_err() {
+            return HttpResponse::error(500, "No workers are running");
+        }
+
+        HttpResponse {
+            status: 202,
+            body: json!({ "id": id, "status": "queued" }),
+            location: Some(format!("/graphs/{}", id)),
+        }
+    }
+
+    fn get_job(&self, id: u64, nodes_query: Option<&HashMap<String, String>>) -> HttpResponse {
+        let jobs = match self.jobs.lock() {
+            Ok(jobs) => jobs,
+            Err(_) => return HttpResponse::error(500, "Job store is poisoned"),
+        };
+        let status = match jobs.iter().find(|(job_id, _)| *job_id == id) {
+            Some((_, status)) => status,
+            None => return HttpResponse::error(404, format!("No graph with id {}, it may have been evicted", id)),
+        };
+
+        match (status, nodes_query) {
+            (JobStatus::Done(snapshot), Some(query)) => {
+                let file = query.get("file").map(|path| snapshot.files.iter().position(|file| &file.path == path));
+                if let Some(None) = file {
+                    return HttpResponse::error(404, format!("{} is not in the graph", query["file"]));
+                }
+                let nodes: Vec<&_> = snapshot.nodes
+                    .iter()
+                    .filter(|node| query.get("kind").is_none_or(|kind| node.kind == kind.as_str()))
+                    .filter(|node| file.flatten().is_none_or(|file| node.file == file))
+                    .collect();
+                HttpResponse::json(200, json!({ "id": id, "nodes": nodes }))
+            },
+            (JobStatus::Done(snapshot), None) => HttpResponse::json(200, json!({
+                "id": id,
+                "status": status.as_str(),
+                "files": snapshot.files,
+                "node_count": snapshot.nodes.len(),
+                "edge_count": snapshot.edges.len(),
+                "truncations": snapshot.truncations,
+            })),
+            (JobStatus::Failed(message), _) => HttpResponse::json(200, json!({ "id": id, "status": status.as_str(), "error": message })),
+            (_, Some(_)) => HttpResponse::json(409, json!({ "id": id, "status": status.as_str(), "error": "The graph is not built yet" })),
+            (_, None) => HttpResponse::json(200, json!({ "id": id, "status": status.as_str() })),
+        }
+    }
+
+    fn route(&self, method: &str, target: &str, body: &[u8], queue: &mpsc::Sender<JobRequest>) -> HttpResponse {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (method, segments.as_slice()) {
+            ("POST", ["graphs"]) => self.submit(body, queue),
+            ("GET", ["graphs", id]) | ("GET", ["graphs", id, "nodes"]) => match id.parse() {
+                Ok(id) => {
+                    let query = parse_query(query);
+                    self.get_job(id, if segments.len() == 3 { Some(&query) } else { None })
+                },
+                Err(_) => HttpResponse::error(404, format!("No graph with id {}", id)),
+            },
+            (_, ["graphs"]) | (_, ["graphs", _]) | (_, ["graphs", _, "nodes"]) => HttpResponse::error(405, format!("{} is not allowed on {}", method, path)),
+            _ => HttpResponse::error(404, format!("No route for {}", path)),
+        }
+    }
+
+    fn handle(&self, stream: TcpStream, queue: &mpsc::Sender<JobRequest>) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut parts = request_line.split_whitespace();
+        let (method, target) = (parts.next().unwrap_or_default().to_string(), parts.next().unwrap_or_default().to_string());
+
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("Content-Length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+
+        let response = if content_length > MAX_BODY_LEN {
+            HttpResponse::error(413, format!("Bodies are limited to {} bytes", MAX_BODY_LEN))
+        } else {
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body)?;
+            self.route(&method, &target, &body, queue)
+        };
+        debug!("{} {} -> {}", method, target, response.status);
+
+        let body = response.body.to_string();
+        let mut stream = stream;
+        write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            response.status, reason(response.status), body.len())?;
+        if let Some(location) = response.location {
+            write!(stream, "Location: {}\r\n", location)?;
+        }
+        write!(stream, "\r\n{}", body)?;
+        stream.flush()
+    }
+}
+
+/// Serves the REST API until the process is stopped:
+///
+/// - `POST /graphs` with `{"diff": ..., "repository": ...}` queues a graph, answering 202 with its id
+/// - `GET /graphs/{id}` reports whether it is queued, running, done or failed
+/// - `GET /graphs/{id}/nodes?kind=...&file=...` lists the nodes of a built graph
+pub fn run(address: SocketAddr, repository_dir: PathBuf, graph_params: DiffGraphParams) -> Result<(), DiffGraphError> {
+    let listener = TcpListener::bind(address)
+        .map_err(|e| DiffGraphError::io(format!("Unable to listen on {}", address), e))?;
+    let service = Arc::new(HttpService {
+        repository_dir,
+        graph_params,
+        jobs: Mutex::new(VecDeque::new()),
+        next_id: Mutex::new(0),
+    });
+
+    let (queue, jobs) = mpsc::channel::<JobRequest>();
+    let jobs = Arc::new(Mutex::new(jobs));
+    for _ in 0..WORKERS {
+        let service = service.clone();
+        let jobs = jobs.clone();
+        thread::spawn(move || loop {
+            let job = match jobs.lock().map(|jobs| jobs.recv()) {
+                Ok(Ok(job)) => job,
+                _ => break,
+            };
+            service.run_job(job);
+        });
+    }
+
+    info!("Serving HTTP on {}", address);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Unable to accept connection: {}", e);
+                continue;
+            },
+        };
+        let service = service.clone();
+        let queue = queue.clone();
+        thread::spawn(move || {
+            if let Err(e) = service.handle(stream, &queue) {
+                debug!("Connection failed: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
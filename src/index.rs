@@ -0,0 +1,266 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use tracing::{debug, info};
+use tree_sitter::{Query, QueryCursor, Tree};
+
+use crate::error::DiffGraphError;
+use crate::grammars::Grammars;
+use crate::parser::{self, SourceLocation};
+use crate::progress;
+use crate::security::{self, QueryPack};
+use crate::syntax;
+
+const PYTHON_QUERIES: &str = include_str!("../queries/tags/python.scm");
+const JAVASCRIPT_QUERIES: &str = include_str!("../queries/tags/javascript.scm");
+const RUST_QUERIES: &str = include_str!("../queries/tags/rust.scm");
+const JAVA_QUERIES: &str = include_str!("../queries/tags/java.scm");
+const GO_QUERIES: &str = include_str!("../queries/tags/go.scm");
+
+/// Tags query packs shipped with diffgraph by language name, see `grammars::get_language_name`
+const BUILTIN_PACKS: [(&str, &str); 9] = [
+    ("python", PYTHON_QUERIES),
+    ("js", JAVASCRIPT_QUERIES),
+    ("javascript", JAVASCRIPT_QUERIES),
+    ("ts", JAVASCRIPT_QUERIES),
+    ("typescript", JAVASCRIPT_QUERIES),
+    ("tsx", JAVASCRIPT_QUERIES),
+    ("rust", RUST_QUERIES),
+    ("java", JAVA_QUERIES),
+    ("go", GO_QUERIES),
+];
+
+/// Where `index` keeps the index of a repository, within its git directory
+const DEFAULT_INDEX_PATH: &str = "diffgraph/symbols.json";
+
+const DEFINITION_PREFIX: &str = "definition.";
+const REFERENCE_PREFIX: &str = "reference.";
+
+/// A declaration of the repository, as the tags queries of its language find it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SymbolDefinition {
+    pub name: String,
+    /// What the tags queries capture it as, such as `function` for `@definition.function`, or
+    /// its node kind in languages without tags queries
+    pub kind: String,
+    pub file: String,
+    /// Lines counted from 1
+    pub line: usize,
+    pub end_line: usize,
+    /// Names the definition calls or otherwise references, none in languages without tags queries
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub references: BTreeSet<String>,
+}
+
+/// Definitions of every file of a repository by name, built with `index` and persisted so the
+/// features resolving names used by the patch can find those defined outside of it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SymbolIndex {
+    /// Version of diffgraph that built the index
+    pub version: String,
+    /// Files whose definitions were indexed, in path order
+    pub files: Vec<String>,
+    definitions: BTreeMap<String, Vec<SymbolDefinition>>,
+}
+
+/// Where the index of the repository is kept by default, in its git directory so it stays out
+/// of the working tree, or None outside of a git repository.
+pub fn get_default_path(repo_dir: &Path) -> Option<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--git-path", DEFAULT_INDEX_PATH])
+        .current_dir(repo_dir)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let path = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    Some(match path.is_relative() {
+        true => repo_dir.join(path),
+        false => path,
+    })
+}
+
+/// Definitions of the tree the queries capture, with the references captured within each
+/// given to the innermost definition spanning them.
+fn collect_tags(path: &str, tree: &Tree, source: &str, queries: &[Query]) -> Vec<SymbolDefinition> {
+    let mut definitions: Vec<(std::ops::Range<usize>, SymbolDefinition)> = Vec::new();
+    let mut references: Vec<(usize, String)> = Vec::new();
+    for query in queries {
+        let mut cursor = QueryCursor::new();
+        for query_match in cursor.matches(query, tree.root_node(), source.as_bytes()) {
+            let mut name = None;
+            let mut tag = None;
+            for capture in query_match.captures {
+                let capture_name = query.capture_names()[capture.index as usize].as_str();
+                match capture_name {
+                    "name" => name = source.get(capture.node.byte_range()),
+                    _ => tag = Some((capture_name, capture.node)),
+                }
+            }
+            let (Some(name), Some((capture_name, node))) = (name, tag) else {
+                continue;
+            };
+            if let Some(kind) = capture_name.strip_prefix(DEFINITION_PREFIX) {
+                definitions.push((node.byte_range(), SymbolDefinition {
+                    name: name.to_string(),
+                    kind: kind.to_string(),
+                    file: path.to_string(),
+                    line: node.start_position().row + 1,
+                    end_line: node.end_position().row + 1,
+                    references: BTreeSet::new(),
+                }));
+            } else if capture_name.starts_with(REFERENCE_PREFIX) {
+                references.push((node.start_byte(), name.to_string()));
+            }
+        }
+    }
+
+    for (byte, name) in references {
+        let innermost = definitions
+            .iter_mut()
+            .filter(|(range, definition)| range.contains(&byte) && definition.name != name)
+            .min_by_key(|(range, _)| range.len());
+        if let Some((_, definition)) = innermost {
+            definition.references.insert(name);
+        }
+    }
+    definitions.into_iter().map(|(_, definition)| definition).collect()
+}
+
+/// Named declarations of the tree, for languages without tags queries.
+fn collect_declarations(path: &str, tree: &Tree, source: &str) -> Vec<SymbolDefinition> {
+    let mut definitions = Vec::new();
+    let mut cursor = tree.walk();
+    'walk: loop {
+        let node = cursor.node();
+        if node.is_named() && syntax::is_declaration_kind(node.kind()) {
+            if let Some(name) = syntax::get_declaration_name(&node, source) {
+                definitions.push(SymbolDefinition {
+                    name,
+                    kind: node.kind().to_string(),
+                    file: path.to_string(),
+                    line: node.start_position().row + 1,
+                    end_line: node.end_position().row + 1,
+                    references: BTreeSet::new(),
+                });
+            }
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+    definitions
+}
+
+impl SymbolIndex {
+    /// Parses every file of the repository in a known language and indexes its definitions,
+    /// with the shipped tags queries where its language has them. Files that cannot be read or
+    /// parsed are left out.
+    pub fn try_build(grammars: &Grammars, source_location: &SourceLocation) -> Result<Self, DiffGraphError> {
+        let mut paths = Vec::new();
+        for path in source_location.try_list_files()? {
+            match grammars.try_get_language(&source_location.get_repo_dir().join(&path)) {
+                Ok(Some((language, language_name))) => paths.push((path, language, language_name)),
+                Ok(None) => {},
+                Err(e) => debug!("Not indexing {}: {}", path, e),
+            }
+        }
+
+        // Each language's queries are compiled once, before the files are parsed in parallel
+        let packs: HashMap<String, Vec<QueryPack>> = security::try_load_packs(&BUILTIN_PACKS, None, "tags")?;
+        let mut compiled: HashMap<String, Vec<Query>> = HashMap::new();
+        for (_, language, language_name) in paths.iter() {
+            if !compiled.contains_key(language_name) {
+                compiled.insert(language_name.clone(), security::compile_packs(&packs, *language, language_name, "tags"));
+            }
+        }
+
+        let bar = progress::bar(paths.len() as u64, "Indexing");
+        let indexed: Vec<Option<(String, Vec<SymbolDefinition>)>> = paths
+            .par_iter()
+            .map(|(path, language, language_name)| {
+                let result = source_location.try_load(path).and_then(|source| {
+                    let tree = parser::try_reparse_source_code(*language, source.as_bytes(), None)?;
+                    Ok(tree.map(|tree| match compiled.get(language_name).filter(|queries| !queries.is_empty()) {
+                        Some(queries) => collect_tags(path, &tree, &source, queries),
+                        None => collect_declarations(path, &tree, &source),
+                    }))
+                });
+                bar.inc(1);
+                match result {
+                    Ok(definitions) => definitions.map(|definitions| (path.clone(), definitions)),
+                    Err(e) => {
+                        debug!("Not indexing {}: {}", path, e);
+                        None
+                    },
+                }
+            })
+            .collect();
+        bar.finish_and_clear();
+
+        let mut index = Self { version: env!("CARGO_PKG_VERSION").to_string(), ..Default::default() };
+        for (path, definitions) in indexed.into_iter().flatten() {
+            index.files.push(path);
+            for definition in definitions {
+                index.definitions.entry(definition.name.clone()).or_default().push(definition);
+            }
+        }
+        index.files.sort();
+        info!("Indexed {} definitions of {} files", index.definition_count(), index.files.len());
+        Ok(index)
+    }
+
+    pub fn try_load(path: &Path) -> Result<Self, DiffGraphError> {
+        let contents = std::fs::read(path)
+            .map_err(|e| DiffGraphError::io(format!("Unable to read the symbol index '{}'", path.display()), e))?;
+        let index: Self = serde_json::from_slice(&contents)
+            .map_err(|e| DiffGraphError::Usage(format!("'{}' is not a symbol index: {}", path.display(), e)))?;
+        debug!("Loaded {} definitions of {} files from {}", index.definition_count(), index.files.len(), path.display());
+        Ok(index)
+    }
+
+    pub fn try_save(&self, path: &Path) -> Result<(), DiffGraphError> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| DiffGraphError::io(format!("Unable to create '{}'", parent.display()), e))?;
+        }
+        let contents = serde_json::to_vec(self).map_err(std::io::Error::from)
+            .map_err(|e| DiffGraphError::io("Unable to serialize the symbol index", e))?;
+        std::fs::write(path, contents)
+            .map_err(|e| DiffGraphError::io(format!("Unable to write the symbol index '{}'", path.display()), e))
+    }
+
+    pub fn definition_count(&self) -> usize {
+        self.definitions.values().map(|definitions| definitions.len()).sum()
+    }
+
+    /// Definitions of the name across the repository, in path order.
+    pub fn get_definitions(&self, name: &str) -> &[SymbolDefinition] {
+        self.definitions.get(name).map_or(&[], |definitions| definitions.as_slice())
+    }
+
+    /// The file defining the name when only one file does, as references are by name alone.
+    pub fn resolve(&self, name: &str) -> Option<&str> {
+        let mut files = self.get_definitions(name).iter().map(|definition| definition.file.as_str());
+        let file = files.next()?;
+        files.all(|other| other == file).then_some(file)
+    }
+
+    /// Definitions outside of the files that reference any of the names, such as the callers of
+    /// changed declarations that the patch leaves unchanged.
+    pub fn find_referrers(&self, names: &BTreeSet<String>, excluded_files: &HashSet<&str>) -> Vec<&SymbolDefinition> {
+        self.definitions
+            .values()
+            .flatten()
+            .filter(|definition| !excluded_files.contains(definition.file.as_str()))
+            .filter(|definition| !definition.references.is_disjoint(names))
+            .collect()
+    }
+}
@@ -0,0 +1,43 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::{Mutex, OnceLock};
+
+use serde::{Deserialize, Deserializer};
+
+/// Every string interned so far, kept for the life of the process.
+static STRINGS: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+/// The one copy of the string shared by everything that interned it. Only meant for small
+/// vocabularies such as node kinds, since interned strings are never freed.
+pub fn intern(text: &str) -> &'static str {
+    let strings = STRINGS.get_or_init(|| Mutex::new(HashSet::new()));
+    let mut strings = match strings.lock() {
+        Ok(strings) => strings,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    if let Some(interned) = strings.get(text) {
+        return interned;
+    }
+    let interned: &'static str = Box::leak(text.to_string().into_boxed_str());
+    strings.insert(interned);
+    interned
+}
+
+/// How many strings are interned and the bytes they take.
+pub fn stats() -> (usize, usize) {
+    let Some(strings) = STRINGS.get() else {
+        return (0, 0);
+    };
+    let strings = match strings.lock() {
+        Ok(strings) => strings,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    (strings.len(), strings.iter().map(|text| text.len()).sum())
+}
+
+/// Deserializes a string into its interned copy, so graphs read back from an export share one
+/// copy of each node kind like graphs built from the grammars do.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Cow<'static, str>, D::Error> {
+    let text = Cow::<str>::deserialize(deserializer)?;
+    Ok(Cow::Borrowed(intern(&text)))
+}
@@ -0,0 +1,85 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::debug;
+
+use crate::error::DiffGraphError;
+
+/// Whether the directory is the root of a Jujutsu repository, colocated with git or not.
+pub fn is_repository(dir: &Path) -> bool {
+    dir.join(".jj").is_dir()
+}
+
+/// The git repository jj stores the commits of the repository in: the colocated `.git`, or
+/// one within `.jj` that git commands do not find on their own.
+pub fn get_git_dir(dir: &Path) -> Option<PathBuf> {
+    let jj_dir = dir.join(".jj");
+    // Workspaces besides the first name the directory of the repository they share in a file
+    let repo = match std::fs::read_to_string(jj_dir.join("repo")) {
+        Ok(path) => jj_dir.join(path.trim()),
+        Err(_) => jj_dir.join("repo"),
+    };
+    let store = repo.join("store");
+    let target = std::fs::read_to_string(store.join("git_target")).ok()?;
+    Some(store.join(target.trim()))
+}
+
+fn try_run(dir: &Path, args: &[&str]) -> Result<std::process::Output, DiffGraphError> {
+    Command::new("jj")
+        .args(["--no-pager", "--color", "never"])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| DiffGraphError::io("Unable to run jj", e))
+}
+
+/// The git commit ids of the revisions the revset resolves to, or None if jj does not know it.
+fn try_resolve_commits(dir: &Path, revset: &str) -> Result<Option<Vec<String>>, DiffGraphError> {
+    let output = try_run(dir, &["log", "--no-graph", "-r", revset, "-T", "commit_id ++ \"\\n\""])?;
+    if !output.status.success() {
+        debug!("jj does not resolve '{}': {}", revset, String::from_utf8_lossy(&output.stderr).trim());
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8_lossy(&output.stdout).lines().map(|commit| commit.trim().to_string()).collect()))
+}
+
+/// The git commit id of the single revision the revset resolves to, or None if jj does not know it.
+fn try_resolve_commit(dir: &Path, revset: &str) -> Result<Option<String>, DiffGraphError> {
+    match try_resolve_commits(dir, revset)?.as_deref() {
+        None => Ok(None),
+        Some([commit]) => Ok(Some(commit.clone())),
+        Some(commits) => Err(DiffGraphError::Usage(format!("'{}' is {} revisions, not one", revset, commits.len()))),
+    }
+}
+
+/// The commit ids a diff argument names in the repository: the revisions on either side of
+/// `A..B`, `@` when one is left out, or a single change id or revset and its parent. None if
+/// jj does not know the revisions, so they may be something else, such as a file.
+pub fn try_resolve_range(dir: &Path, arg: &str) -> Result<Option<(String, String)>, DiffGraphError> {
+    let (from, to) = match arg.split_once("..") {
+        Some((from, to)) => (
+            if from.is_empty() { "@".to_string() } else { from.to_string() },
+            if to.is_empty() { "@".to_string() } else { to.to_string() },
+        ),
+        None => (format!("({})-", arg), arg.to_string()),
+    };
+    let Some(to) = try_resolve_commit(dir, &to)? else {
+        return Ok(None);
+    };
+    // Changes with several parents are diffed against a merge of them that no commit holds
+    let from = match try_resolve_commits(dir, &from)?.as_deref() {
+        None => return Ok(None),
+        Some([from]) => from.clone(),
+        Some(_) => return Err(DiffGraphError::Usage(format!("'{}' is a merge, give the revisions to diff between as A..B", arg))),
+    };
+    Ok(Some((from, to)))
+}
+
+/// The changes between two revisions in the git diff format.
+pub fn try_get_diff_patch(dir: &Path, from: &str, to: &str) -> Result<String, DiffGraphError> {
+    let output = try_run(dir, &["diff", "--git", "--from", from, "--to", to])?;
+    if !output.status.success() {
+        return Err(DiffGraphError::Git(format!("Unable to diff {}..{} with jj: {}", from, to, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
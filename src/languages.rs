@@ -0,0 +1,130 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::color::{self, Stream, Style};
+use crate::graph::DiffGraph;
+
+const HEADERS: [&str; 7] = ["LANGUAGE", "FILES", "HUNKS", "+", "-", "DECLARATIONS", "NODES"];
+
+/// What the patch changes in the files of one language.
+#[derive(Debug, Clone, Serialize)]
+pub struct LanguageStats {
+    pub language: String,
+    pub files: usize,
+    pub hunks: usize,
+    pub added_lines: usize,
+    pub removed_lines: usize,
+    pub changed_declarations: usize,
+    /// Nodes of the graph in the language's files
+    pub nodes: usize,
+    /// Owners of the language's files by the repository's CODEOWNERS, for routing the review
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub owners: BTreeSet<String>,
+}
+
+/// The patch broken down by language, the most changed lines first, for handing each part of a
+/// polyglot patch to reviewers of its language.
+#[derive(Debug, Clone, Serialize)]
+pub struct LanguageReport {
+    pub languages: Vec<LanguageStats>,
+}
+
+impl LanguageReport {
+    pub fn from_graph(graph: &DiffGraph) -> Self {
+        let mut nodes: HashMap<usize, usize> = HashMap::new();
+        for node in graph.nodes() {
+            *nodes.entry(node.file).or_default() += 1;
+        }
+
+        let mut languages: Vec<LanguageStats> = Vec::new();
+        for (file, diff) in graph.diffs().iter().enumerate() {
+            let index = match languages.iter().position(|stats| stats.language == diff.language_name) {
+                Some(index) => index,
+                None => {
+                    languages.push(LanguageStats {
+                        language: diff.language_name.clone(),
+                        files: 0,
+                        hunks: 0,
+                        added_lines: 0,
+                        removed_lines: 0,
+                        changed_declarations: 0,
+                        nodes: 0,
+                        owners: BTreeSet::new(),
+                    });
+                    languages.len() - 1
+                },
+            };
+            let stats = &mut languages[index];
+            stats.files += 1;
+            stats.hunks += diff.hunk_count;
+            stats.added_lines += diff.added_rows.len();
+            stats.removed_lines += diff.removed_rows.len();
+            stats.changed_declarations += diff.changed_declarations().len();
+            stats.nodes += nodes.get(&file).copied().unwrap_or(0);
+            stats.owners.extend(graph.get_owners(&diff.source_file_path).iter().cloned());
+        }
+
+        // Ties keep patch order
+        languages.sort_by_key(|stats| std::cmp::Reverse(stats.added_lines + stats.removed_lines));
+        Self { languages }
+    }
+}
+
+impl fmt::Display for LanguageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.languages.is_empty() {
+            return writeln!(f, "No source files changed");
+        }
+
+        let rows: Vec<[String; 7]> = self.languages
+            .iter()
+            .map(|stats| [
+                stats.language.clone(),
+                stats.files.to_string(),
+                stats.hunks.to_string(),
+                stats.added_lines.to_string(),
+                stats.removed_lines.to_string(),
+                stats.changed_declarations.to_string(),
+                stats.nodes.to_string(),
+            ])
+            .collect();
+        let mut widths = HEADERS.map(|header| header.len());
+        for row in rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        // Cells are padded before they are painted, escape codes would throw off the widths
+        let write_row = |f: &mut fmt::Formatter<'_>, cells: &[String], styles: [Option<Style>; 7]| -> fmt::Result {
+            for (i, ((cell, width), style)) in cells.iter().zip(widths.iter()).zip(styles.iter()).enumerate() {
+                // Languages are left aligned, counts right aligned
+                let padded = if i == 0 { format!("{:<width$}", cell, width = width) } else { format!("{:>width$}", cell, width = width) };
+                let separator = if i == 0 { "" } else { "  " };
+                match style {
+                    Some(style) => write!(f, "{}{}", separator, color::paint(Stream::Stdout, *style, &padded))?,
+                    None => write!(f, "{}{}", separator, padded)?,
+                }
+            }
+            writeln!(f)
+        };
+        write_row(f, &HEADERS.map(String::from), [Some(Style::Bold); 7])?;
+        for row in rows.iter() {
+            write_row(f, row, [Some(Style::Cyan), None, None, Some(Style::Green), Some(Style::Red), None, None])?;
+        }
+
+        let owned: Vec<&LanguageStats> = self.languages.iter().filter(|stats| !stats.owners.is_empty()).collect();
+        if !owned.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "owners:")?;
+            let width = owned.iter().map(|stats| stats.language.chars().count()).max().unwrap_or(0);
+            for stats in owned {
+                let owners: Vec<&str> = stats.owners.iter().map(|owner| owner.as_str()).collect();
+                writeln!(f, "  {}  {}", color::paint(Stream::Stdout, Style::Cyan, &format!("{:<width$}", stats.language, width = width)), owners.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+pub mod api;
+pub mod annotations;
+pub mod cancel;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod git;
+pub mod graph;
+pub mod parser;
+pub mod patch;
+pub mod grammars;
+pub mod intern;
+pub mod progress;
+pub mod error;
+pub mod limits;
+pub mod links;
+pub mod policy;
+pub mod syntax;
+pub mod test_impact;
+pub mod summary;
+pub mod check;
+pub mod equivalent;
+pub mod checklist;
+pub mod churn;
+pub mod architecture;
+pub mod compare;
+pub mod complexity;
+pub mod configs;
+pub mod coverage;
+pub mod conflicts;
+pub mod narrative;
+pub mod hotspots;
+pub mod hints;
+pub mod index;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod jj;
+pub mod languages;
+pub mod coupling;
+pub mod debt;
+pub mod docs;
+pub mod dead_code;
+pub mod duplicates;
+pub mod renames;
+pub mod timings;
+pub mod stats;
+pub mod owners;
+pub mod ownership;
+pub mod security;
+pub mod pathspec;
+pub mod color;
+pub mod repair;
+pub mod sarif;
+pub mod risk;
+pub mod shard;
+pub mod snapshot;
+pub mod splits;
+pub mod structural;
+pub mod unified;
+pub mod stream;
+pub mod visitor;
+#[cfg(feature = "cli")]
+pub mod analyze;
+#[cfg(feature = "cli")]
+pub mod bench;
+#[cfg(feature = "cli")]
+pub mod bundle;
+#[cfg(feature = "cli")]
+pub mod cache;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "cli")]
+pub mod config;
+#[cfg(feature = "cli")]
+pub mod watch;
+#[cfg(feature = "cli")]
+pub mod export;
+#[cfg(feature = "cli")]
+pub mod gerrit;
+#[cfg(feature = "cli")]
+pub mod comment;
+#[cfg(feature = "cli")]
+pub mod viewer;
+#[cfg(feature = "cli")]
+pub mod server;
+#[cfg(feature = "cli")]
+pub mod mcp;
+#[cfg(feature = "cli")]
+pub mod webhook;
+#[cfg(feature = "cli")]
+pub mod http;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "async")]
+pub mod nonblocking;
@@ -0,0 +1,64 @@
+use std::fmt;
+
+/// What to do when a patch exceeds one of the configured limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LimitAction {
+    /// Skip whatever is over the limit and mark the graph as truncated
+    #[default]
+    Truncate,
+    /// Refuse to analyze the patch
+    Fail,
+}
+
+/// Guard rails for patches too large to analyze in full.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// Only the first N patched files are parsed
+    pub max_files: Option<usize>,
+    /// Graph construction stops once it holds N nodes
+    pub max_nodes: Option<usize>,
+    /// Source files larger than N bytes are not parsed
+    pub max_bytes: Option<usize>,
+    /// Nodes more than N levels below the root of a file's tree are left out of the graph
+    pub max_depth: Option<usize>,
+    /// Only the first N children of a node are walked
+    pub max_children: Option<usize>,
+    pub action: LimitAction,
+}
+
+/// Marks where a graph is partial because a limit was hit.
+#[derive(Debug, Clone)]
+pub enum Truncation {
+    Files {
+        kept: usize,
+        total: usize,
+    },
+    Bytes {
+        file: String,
+        size: usize,
+        limit: usize,
+    },
+    Nodes {
+        limit: usize,
+    },
+    Depth {
+        file: String,
+        limit: usize,
+    },
+    Children {
+        file: String,
+        limit: usize,
+    },
+}
+
+impl fmt::Display for Truncation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Truncation::Files { kept, total } => write!(f, "only {} of {} patched files were analyzed (--max-files)", kept, total),
+            Truncation::Bytes { file, size, limit } => write!(f, "{} was skipped, {} bytes exceeds {} (--max-bytes)", file, size, limit),
+            Truncation::Nodes { limit } => write!(f, "graph construction stopped at {} nodes (--max-nodes)", limit),
+            Truncation::Depth { file, limit } => write!(f, "{} was only walked {} levels deep (--max-depth)", file, limit),
+            Truncation::Children { file, limit } => write!(f, "{} has nodes with more than {} children, the rest were left out (--max-children)", file, limit),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
+use tracing::debug;
+use tree_sitter::{Node as TSNode, Query, QueryCursor, Tree};
+
+use crate::graph::{Edge, NodeInfo};
+use crate::parser::Diff;
+
+const PROTO_QUERIES: &str = include_str!("../queries/links/proto.scm");
+const GRAPHQL_QUERIES: &str = include_str!("../queries/links/graphql.scm");
+const THRIFT_QUERIES: &str = include_str!("../queries/links/thrift.scm");
+
+/// Query packs of interface definition languages by language name, see `grammars::get_language_name`
+const DEFINITION_PACKS: [(&str, &str); 3] = [
+    ("proto", PROTO_QUERIES),
+    ("graphql", GRAPHQL_QUERIES),
+    ("thrift", THRIFT_QUERIES),
+];
+
+/// A definition of an interface definition file the patch changes, and a changed file of
+/// another language referring to it by name, such as a `.proto` message and the Go code using
+/// the type generated from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrossLanguageLink {
+    /// Kind of the definition, the name of the capture that matched it, such as `message`
+    pub kind: String,
+    pub name: String,
+    pub definition_file: String,
+    /// First line of the definition, counted from 1, in the patched file unless it was removed
+    pub definition_line: usize,
+    pub user_file: String,
+    pub user_language: String,
+    /// Identifiers of the patched user file matching the name
+    pub references: usize,
+}
+
+impl fmt::Display for CrossLanguageLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} `{}` of {}:{} is referenced {} times in {} ({})", self.kind, self.name,
+            self.definition_file, self.definition_line, self.references, self.user_file, self.user_language)
+    }
+}
+
+/// Identifier leaves of a tree by normalized name
+type IdentifierIndex<'t> = HashMap<String, Vec<TSNode<'t>>>;
+
+/// A definition a query pack matched.
+struct Definition<'t> {
+    kind: String,
+    name: String,
+    node: TSNode<'t>,
+}
+
+fn find_definitions<'t>(query: &Query, tree: &'t Tree, source: &str) -> Vec<Definition<'t>> {
+    let mut definitions = Vec::new();
+    let mut cursor = QueryCursor::new();
+    for query_match in cursor.matches(query, tree.root_node(), source.as_bytes()) {
+        let mut name = None;
+        let mut definition = None;
+        for capture in query_match.captures {
+            match query.capture_names()[capture.index as usize].as_str() {
+                "name" => name = source.get(capture.node.byte_range()),
+                kind if !kind.starts_with('_') => definition = Some((kind.to_string(), capture.node)),
+                _ => {},
+            }
+        }
+        if let (Some(name), Some((kind, node))) = (name, definition) {
+            definitions.push(Definition { kind, name: name.trim().to_string(), node });
+        }
+    }
+    definitions
+}
+
+/// Names compared across languages' conventions, `user_id`, `UserId` and `userId` being one name.
+fn normalize_name(name: &str) -> String {
+    name.chars().filter(|c| *c != '_').flat_map(char::to_lowercase).collect()
+}
+
+/// Identifier leaves of the tree by normalized name.
+fn index_identifiers<'t>(tree: &'t Tree, source: &str) -> IdentifierIndex<'t> {
+    let mut identifiers: IdentifierIndex = HashMap::new();
+    let mut cursor = tree.walk();
+    'walk: loop {
+        let node = cursor.node();
+        if node.child_count() == 0 && node.kind().ends_with("identifier") {
+            if let Some(text) = source.get(node.byte_range()) {
+                identifiers.entry(normalize_name(text)).or_default().push(node);
+            }
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+    identifiers
+}
+
+fn touches(node: &TSNode, rows: &HashSet<usize>) -> bool {
+    (node.start_position().row..=node.end_position().row).any(|row| rows.contains(&row))
+}
+
+/// Links the definitions the patch changes in interface definition files to the changed files
+/// of other languages that use them, matching names across naming conventions. Along with the
+/// links come edges from each definition to the identifiers using it, between nodes of the
+/// files before the patch, for the graph; definitions the patch adds have no such edges.
+pub fn find_links(diffs: &[Diff]) -> (Vec<CrossLanguageLink>, Vec<Edge>) {
+    let mut links = Vec::new();
+    let mut edges = Vec::new();
+    let is_definition_file = |diff: &Diff| DEFINITION_PACKS.iter().any(|(language, _)| *language == diff.language_name);
+    if !diffs.iter().any(is_definition_file) {
+        return (links, edges);
+    }
+
+    // Identifiers of the users, patched and before the patch, indexed once
+    let users: Vec<(usize, IdentifierIndex, IdentifierIndex)> = diffs
+        .iter()
+        .enumerate()
+        .filter(|(_, diff)| !is_definition_file(diff))
+        .map(|(file, diff)| (file, index_identifiers(&diff.target_tree, &diff.target_source), index_identifiers(&diff.tree, &diff.source)))
+        .collect();
+
+    for (file, diff) in diffs.iter().enumerate() {
+        let Some((_, queries)) = DEFINITION_PACKS.iter().find(|(language, _)| *language == diff.language_name) else {
+            continue;
+        };
+        let query = match Query::new(diff.language, queries) {
+            Ok(query) => query,
+            Err(e) => {
+                // The packs are written against one version of each grammar, others may lack their node kinds
+                debug!("Not linking {} across languages: {}", diff.source_file_path, e);
+                continue;
+            },
+        };
+        let added_rows: HashSet<usize> = diff.added_rows.iter().copied().collect();
+        let removed_rows: HashSet<usize> = diff.removed_rows.iter().copied().collect();
+        let old = find_definitions(&query, &diff.tree, &diff.source);
+        let new = find_definitions(&query, &diff.target_tree, &diff.target_source);
+
+        // Definitions touched on either side, each once, with their node before the patch if any
+        let mut changed: Vec<(&Definition, Option<&Definition>)> = Vec::new();
+        for definition in new.iter().filter(|definition| touches(&definition.node, &added_rows)) {
+            let before = old.iter().find(|old| old.kind == definition.kind && old.name == definition.name);
+            changed.push((definition, before));
+        }
+        for definition in old.iter().filter(|definition| touches(&definition.node, &removed_rows)) {
+            if !changed.iter().any(|(known, _)| known.kind == definition.kind && known.name == definition.name) {
+                let after = new.iter().find(|new| new.kind == definition.kind && new.name == definition.name);
+                changed.push((after.unwrap_or(definition), Some(definition)));
+            }
+        }
+
+        for (definition, before) in changed {
+            let name = normalize_name(&definition.name);
+            for (user, patched, original) in users.iter() {
+                let references = patched.get(&name).map_or(0, |nodes| nodes.len());
+                if references == 0 {
+                    continue;
+                }
+                let user_diff = &diffs[*user];
+                links.push(CrossLanguageLink {
+                    kind: definition.kind.clone(),
+                    name: definition.name.clone(),
+                    definition_file: diff.source_file_path.clone(),
+                    definition_line: definition.node.start_position().row + 1,
+                    user_file: user_diff.source_file_path.clone(),
+                    user_language: user_diff.language_name.clone(),
+                    references,
+                });
+                let (Some(before), Some(nodes)) = (before, original.get(&name)) else {
+                    continue;
+                };
+                edges.extend(nodes.iter().map(|node| Edge {
+                    from: NodeInfo::from_ts_node(file, &before.node),
+                    to: NodeInfo::from_ts_node(*user, node),
+                }));
+            }
+        }
+    }
+    (links, edges)
+}
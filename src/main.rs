@@ -1,19 +1,140 @@
-mod cli;
-mod graph;
-mod parser;
-mod grammars;
-
-use graph::*;
-
-fn main() {
-    match cli::get_params() {
-        Ok(params) => {
-            match DiffGraph::create(params) {
-                Ok(_) => (),
-                Err(e) => println!("{}", e),
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use diffdiagram::{analyze, bench, cancel, check, cli, equivalent, error, export, mcp, parser, server, stats, stream, timings, watch};
+use diffdiagram::cli::CliCommand;
+use diffdiagram::compare::FootprintReport;
+use diffdiagram::grammars::{Grammars, ParserConfig};
+use diffdiagram::graph::*;
+use diffdiagram::index::SymbolIndex;
+use diffdiagram::patch::ParsedDiff;
+use diffdiagram::error::{DiffGraphError, ErrorCode};
+use tracing::{debug, warn};
+
+fn print_grammars_which(file: &Path, parser_config_path: Option<PathBuf>) -> Result<(), DiffGraphError> {
+    let grammars = Grammars::load(parser_config_path, true)?;
+
+    let candidates = grammars.find_candidates(file);
+    if candidates.is_empty() {
+        return Err(DiffGraphError::GrammarMissing {
+            path: file.to_path_buf(),
+            parser_directories: grammars.get_configured_paths().iter().map(|p| p.to_string()).collect(),
+        });
+    }
+    println!("Candidates for '{}':", file.display());
+    for candidate in candidates.iter() {
+        println!("  {} ({}) file-types: [{}] from {}",
+            candidate.scope.as_deref().unwrap_or("<no scope>"),
+            candidate.root_path.display(),
+            candidate.file_types.join(", "),
+            candidate.parser_directory.as_ref().map_or("<unknown parser directory>".into(), |d| d.display().to_string()));
+    }
+
+    match grammars.try_which(file)? {
+        Some(chosen) => println!("Selected: {} ({})", 
+            chosen.scope.as_deref().unwrap_or("<no scope>"), 
+            chosen.root_path.display()),
+        None => println!("Selected: none"),
+    }
+
+    Ok(())
+}
+
+fn sync_grammars_registry(parser_config_path: Option<PathBuf>) -> Result<(), DiffGraphError> {
+    let added = ParserConfig::try_sync_registry(parser_config_path)?;
+    if added.is_empty() {
+        println!("Parser configuration is up to date with the registry");
+    } else {
+        println!("Added {} parsers from the registry:", added.len());
+        for parser_url in added.iter() {
+            println!("  {}", parser_url);
+        }
+    }
+
+    Ok(())
+}
+
+fn write_symbol_index(params: &DiffGraphParams, target: &Path) -> Result<(), DiffGraphError> {
+    let grammars = parser::try_load_grammars(params)?;
+    let index = timings::phase("index", || SymbolIndex::try_build(&grammars, &params.get_source_location()))?;
+    index.try_save(target)?;
+    println!("Indexed {} definitions of {} files into {}", index.definition_count(), index.files.len(), target.display());
+    Ok(())
+}
+
+fn compare_revisions(params: DiffGraphParams, other: ParsedDiff, json: bool, target: &export::OutputTarget) -> Result<(), DiffGraphError> {
+    let second = DiffGraph::create(DiffGraphParams {
+        diff: other.patch,
+        source_revision: other.source_revision,
+        git_dir: other.git_dir,
+        binary_files: other.binary_files,
+        ..params.clone()
+    })?;
+    let first = DiffGraph::create(params)?;
+    analyze::write_report(&FootprintReport::from_graphs(&first, &second), json, target)
+}
+
+/// Cancels the run on the first Ctrl-C, so in-flight parses stop and partial clones are removed,
+/// and exits straight away on the second.
+fn handle_interrupts() {
+    let handler = ctrlc::set_handler(|| {
+        if cancel::is_cancelled() {
+            std::process::exit(ErrorCode::Cancelled.exit_code() as i32);
+        }
+        warn!("Cancelling, press Ctrl-C again to exit now");
+        cancel::interrupt();
+    });
+    if let Err(e) = handler {
+        debug!("Unable to handle Ctrl-C: {}", e);
+    }
+}
+
+fn run() -> Result<(), DiffGraphError> {
+    // Commands that run until stopped keep exiting on Ctrl-C. The handler is installed before the
+    // parameters are resolved, as resolving them clones and fetches repositories
+    let matches = cli::parse_args();
+    if !cli::runs_until_stopped(&matches) {
+        handle_interrupts();
+    }
+    let command = cli::get_params(&matches)?;
+    match command {
+        CliCommand::Graph { params, output } => {
+            if let Some(cached) = output.cache.as_ref().and_then(|cache| cache.load()) {
+                return export::try_write_export(&output.target, output.compress, &cached);
             }
+            let graph = DiffGraph::create(params)?;
+            timings::phase("export", || export::write(&graph, &output))
+        },
+        CliCommand::Check(params) => check::run(&params),
+        CliCommand::Analyze(params) => analyze::run(params),
+        CliCommand::Stream { params, target, compress } => {
+            let mut writer = target.try_open(compress)?;
+            // Finished even when the stream fails, so what was written is still valid gzip
+            let result = stream::try_stream(params, &mut writer);
+            let finished = writer.try_finish();
+            result.and(finished)
+        },
+        CliCommand::GrammarsWhich { file, parser_config_path } => print_grammars_which(&file, parser_config_path),
+        CliCommand::GrammarsSyncRegistry { parser_config_path } => sync_grammars_registry(parser_config_path),
+        CliCommand::Merge { inputs, target, compress } => export::try_merge(&inputs, &target, compress),
+        CliCommand::Watch(params) => watch::run(params),
+        CliCommand::Serve(params) => server::run(params),
+        CliCommand::Mcp(params) => mcp::run(params),
+        CliCommand::Bench(params) => bench::run(params),
+        CliCommand::Equivalent(params) => equivalent::run(params),
+        CliCommand::Index { params, target } => write_symbol_index(&params, &target),
+        CliCommand::CompareRevs { params, other, json, target } => compare_revisions(params, other, json, &target),
+    }
+}
 
+fn main() -> ExitCode {
+    let result = run();
+    timings::report();
+    stats::report();
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            error::report(&e);
+            ExitCode::from(e.code().exit_code())
         },
-        Err(e) => println!("{}", e),
     }
 }
@@ -0,0 +1,278 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use petgraph::Direction;
+use serde_json::{json, Value};
+use tracing::{debug, info};
+
+use crate::cli::try_resolve_diff;
+use crate::error::DiffGraphError;
+use crate::graph::{DiffGraph, DiffGraphParams, NodeInfo};
+use crate::progress;
+use crate::syntax;
+use crate::watch::try_build_uncommitted_graph;
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Longest node text returned, longer nodes are cut short
+const MAX_TEXT_LEN: usize = 200;
+const DEFAULT_IMPACT_LIMIT: u64 = 100;
+const DEFAULT_RADIUS: u64 = 2;
+
+#[derive(Debug)]
+pub struct McpParams {
+    pub repository_dir: PathBuf,
+    /// Options for every tool call, the diff itself comes from the call or the uncommitted changes
+    pub graph_params: DiffGraphParams,
+}
+
+fn tool_definitions() -> Value {
+    let diff = json!({
+        "type": "string",
+        "description": "Diff to graph, as taken by --diff: revisions (a..b, a...b), a branch, a patch file or a GitHub URL. Defaults to the uncommitted changes.",
+    });
+    json!([
+        {
+            "name": "get_changed_functions",
+            "description": "Functions and methods the diff adds, removes or modifies, with the file and row they start at.",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "diff": diff },
+            },
+        },
+        {
+            "name": "get_impact",
+            "description": "Nodes reachable from a node by following graph edges, nearest first.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "diff": diff,
+                    "node": { "type": "integer", "description": "Node id, as returned by the other tools" },
+                    "limit": { "type": "integer", "description": "Most nodes to return, 100 by default" },
+                },
+                "required": ["node"],
+            },
+        },
+        {
+            "name": "get_subgraph",
+            "description": "Nodes and edges within a number of edges of a node, or of every changed node when none is given.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "diff": diff,
+                    "node": { "type": "integer", "description": "Node id to center on" },
+                    "radius": { "type": "integer", "description": "Edges to follow in either direction, 2 by default" },
+                },
+            },
+        },
+    ])
+}
+
+fn try_build_graph(params: &McpParams, arguments: &Value) -> Result<Option<DiffGraph>, DiffGraphError> {
+    match arguments.get("diff").and_then(|diff| diff.as_str()) {
+        Some(diff_arg) => {
+            let diff = try_resolve_diff(diff_arg, &params.repository_dir)?;
+            DiffGraph::create(DiffGraphParams {
+                diff: diff.patch,
+                source_revision: diff.source_revision,
+                git_dir: diff.git_dir,
+                binary_files: diff.binary_files,
+                ..params.graph_params.clone()
+            }).map(Some)
+        },
+        None => try_build_uncommitted_graph(&params.repository_dir, &params.graph_params, None),
+    }
+}
+
+fn node_json(graph: &DiffGraph, node: &NodeInfo) -> Value {
+    let text = graph.node_source(node).map(|text| match text.char_indices().nth(MAX_TEXT_LEN) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    });
+    json!({
+        "id": node.id,
+        "kind": node.kind,
+        "file": graph.diffs().get(node.file).map(|diff| diff.source_file_path.as_str()),
+        "start_byte": node.byte_range.start,
+        "end_byte": node.byte_range.end,
+        "text": text,
+    })
+}
+
+fn get_changed_functions(graph: &DiffGraph) -> Value {
+    let mut functions: Vec<Value> = Vec::new();
+    for changed in graph.changed_nodes() {
+        let declaration = match &changed.declaration {
+            Some(declaration) if syntax::is_function_kind(&declaration.kind) => declaration,
+            _ => continue,
+        };
+        let function = json!({
+            "file": graph.diffs().get(changed.node.file).map(|diff| diff.source_file_path.as_str()),
+            "kind": declaration.kind,
+            "name": declaration.name,
+            "row": declaration.row,
+            "change": declaration.change,
+        });
+        if !functions.contains(&function) {
+            functions.push(function);
+        }
+    }
+
+    json!({ "functions": functions })
+}
+
+fn try_get_node_id(graph: &DiffGraph, arguments: &Value) -> Result<Option<usize>, (i64, String)> {
+    let id = match arguments.get("node") {
+        Some(id) => id.as_u64().ok_or((INVALID_PARAMS, "node must be a node id".to_string()))? as usize,
+        None => return Ok(None),
+    };
+    if !graph.graph().contains_node(id) {
+        return Err((INVALID_PARAMS, format!("Node {} is not in the graph", id)));
+    }
+    Ok(Some(id))
+}
+
+fn try_get_impact(graph: &DiffGraph, arguments: &Value) -> Result<Value, (i64, String)> {
+    let start = try_get_node_id(graph, arguments)?.ok_or((INVALID_PARAMS, "node is required".to_string()))?;
+    let limit = arguments.get("limit").and_then(|limit| limit.as_u64()).unwrap_or(DEFAULT_IMPACT_LIMIT) as usize;
+
+    let mut reached = Vec::new();
+    let mut distances = HashMap::from([(start, 0)]);
+    let mut queue = VecDeque::from([start]);
+    while let Some(id) = queue.pop_front() {
+        for next in graph.graph().neighbors_directed(id, Direction::Outgoing) {
+            if reached.len() >= limit {
+                break;
+            }
+            if distances.contains_key(&next) {
+                continue;
+            }
+            distances.insert(next, distances[&id] + 1);
+            reached.push(next);
+            queue.push_back(next);
+        }
+    }
+
+    let nodes: Vec<Value> = reached
+        .iter()
+        .filter_map(|id| graph.node(*id).map(|node| {
+            let mut node = node_json(graph, node);
+            node["distance"] = json!(distances[id]);
+            node
+        }))
+        .collect();
+    Ok(json!({ "node": start, "truncated": nodes.len() >= limit, "nodes": nodes }))
+}
+
+fn try_get_subgraph(graph: &DiffGraph, arguments: &Value) -> Result<Value, (i64, String)> {
+    let radius = arguments.get("radius").and_then(|radius| radius.as_u64()).unwrap_or(DEFAULT_RADIUS) as usize;
+    let centers: Vec<usize> = match try_get_node_id(graph, arguments)? {
+        Some(id) => vec![id],
+        None => graph.changed_nodes()
+            .filter(|changed| !changed.patched && graph.graph().contains_node(changed.node.id))
+            .map(|changed| changed.node.id)
+            .collect(),
+    };
+
+    let mut distances: HashMap<usize, usize> = centers.iter().map(|id| (*id, 0)).collect();
+    let mut queue: VecDeque<usize> = centers.iter().copied().collect();
+    while let Some(id) = queue.pop_front() {
+        if distances[&id] >= radius {
+            continue;
+        }
+        let neighbors = graph.graph()
+            .neighbors_directed(id, Direction::Outgoing)
+            .chain(graph.graph().neighbors_directed(id, Direction::Incoming));
+        for next in neighbors {
+            if !distances.contains_key(&next) {
+                distances.insert(next, distances[&id] + 1);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    let nodes: Vec<Value> = distances
+        .keys()
+        .filter_map(|id| graph.node(*id).map(|node| node_json(graph, node)))
+        .collect();
+    let edges: Vec<Value> = graph.edges()
+        .filter(|edge| distances.contains_key(&edge.from.id) && distances.contains_key(&edge.to.id))
+        .map(|edge| json!({ "from": edge.from.id, "to": edge.to.id }))
+        .collect();
+    Ok(json!({ "nodes": nodes, "edges": edges }))
+}
+
+fn call_tool(params: &McpParams, request: &Value) -> Result<Value, (i64, String)> {
+    let name = request.get("name").and_then(|name| name.as_str()).unwrap_or_default();
+    let arguments = request.get("arguments").cloned().unwrap_or(json!({}));
+    if !["get_changed_functions", "get_impact", "get_subgraph"].contains(&name) {
+        return Err((INVALID_PARAMS, format!("Unknown tool '{}'", name)));
+    }
+
+    // Failures building the graph are the tool's result rather than a protocol error, so the agent sees them
+    let tool_result = match try_build_graph(params, &arguments) {
+        Ok(Some(graph)) => match name {
+            "get_changed_functions" => Ok(get_changed_functions(&graph)),
+            "get_impact" => try_get_impact(&graph, &arguments).map_err(|(_, message)| message),
+            _ => try_get_subgraph(&graph, &arguments).map_err(|(_, message)| message),
+        },
+        Ok(None) => Err("There are no uncommitted changes".to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+
+    Ok(match tool_result {
+        Ok(value) => json!({ "content": [{ "type": "text", "text": value.to_string() }], "isError": false }),
+        Err(message) => json!({ "content": [{ "type": "text", "text": message }], "isError": true }),
+    })
+}
+
+/// Serves the graph as Model Context Protocol tools over stdio, one JSON-RPC message per line.
+pub fn run(params: McpParams) -> Result<(), DiffGraphError> {
+    progress::set_enabled(false);
+    info!("Serving MCP on stdio for {}", params.repository_dir.display());
+
+    let stdin = io::stdin();
+    let mut stdout = io::stdout().lock();
+    for line in stdin.lock().lines() {
+        let line = line.map_err(|e| DiffGraphError::io("Unable to read from stdin", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message: Value = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                debug!("Ignoring message that is not JSON: {}", e);
+                continue;
+            },
+        };
+        let method = message.get("method").and_then(|method| method.as_str()).unwrap_or_default();
+        debug!("Received {}", method);
+        let result = match method {
+            "initialize" => Ok(json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "diffgraph", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": tool_definitions() })),
+            "tools/call" => call_tool(&params, message.get("params").unwrap_or(&Value::Null)),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+        };
+
+        // Notifications such as notifications/initialized have no id and get no response
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => continue,
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }),
+        };
+        writeln!(stdout, "{}", response)
+            .and_then(|_| stdout.flush())
+            .map_err(|e| DiffGraphError::io("Unable to write to stdout", e))?;
+    }
+
+    Ok(())
+}
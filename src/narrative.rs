@@ -0,0 +1,180 @@
+use std::fmt::Write;
+
+use crate::coupling::CouplingReport;
+use crate::graph::DiffGraph;
+use crate::parser::{ChangedDeclaration, Diff};
+use crate::renames;
+use crate::structural::ChangeClass;
+use crate::syntax;
+use crate::test_impact;
+
+/// Declarations of one kind listed by name in a sentence, beyond which they are only counted
+const MAX_LISTED_NAMES: usize = 3;
+/// Files named in a cluster's heading, beyond which they are only counted
+const MAX_HEADING_FILES: usize = 3;
+
+/// Suffixes grammars add to the kinds of their declarations, see `syntax::is_declaration_kind`
+const KIND_SUFFIXES: [&str; 4] = ["_item", "_declaration", "_definition", "_specifier"];
+
+/// What a declaration is called in prose, such as `function` for `function_item`, or `test`
+/// for a function in a test file or named like one.
+fn get_noun(declaration: &ChangedDeclaration, path: &str) -> String {
+    let is_test = syntax::is_function_kind(&declaration.kind) && (test_impact::is_test_path(path)
+        || declaration.name.as_ref().is_some_and(|name| name.starts_with("test") || name.ends_with("Test")));
+    if is_test {
+        return "test".to_string();
+    }
+    let kind = KIND_SUFFIXES
+        .iter()
+        .find_map(|suffix| declaration.kind.strip_suffix(suffix))
+        .unwrap_or(&declaration.kind);
+    kind.replace('_', " ")
+}
+
+/// Plural of a declaration noun, such as `classes` or `properties`.
+fn pluralize(noun: &str) -> String {
+    if ["s", "x", "ch", "sh"].iter().any(|suffix| noun.ends_with(suffix)) {
+        format!("{}es", noun)
+    } else if let Some(stem) = noun.strip_suffix('y').filter(|stem| !stem.ends_with(['a', 'e', 'o', 'u'])) {
+        format!("{}ies", stem)
+    } else {
+        format!("{}s", noun)
+    }
+}
+
+fn get_verb(declaration: &ChangedDeclaration) -> &'static str {
+    match declaration.change {
+        Some(ChangeClass::Added) => "added",
+        Some(ChangeClass::Removed) => "removed",
+        _ => "modified",
+    }
+}
+
+/// Joins clauses the way prose does: `a`, `a and b`, `a, b and c`.
+fn join_clauses(clauses: &[String]) -> String {
+    match clauses {
+        [] => String::new(),
+        [only] => only.clone(),
+        [rest @ .., last] => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// A verb and the noun of the declarations it applies to, such as `added` and `struct`
+type Clause = (&'static str, String);
+
+/// One sentence on what the patch does to the file, such as
+/// "Modified 3 functions and added struct `Foo` in `src/graph.rs`."
+fn describe_file(diff: &Diff, declarations: &[ChangedDeclaration]) -> String {
+    let path = &diff.source_file_path;
+    // Clauses keep the order their declarations first appear in
+    let mut clauses: Vec<(Clause, Vec<Option<String>>)> = Vec::new();
+    for declaration in declarations.iter() {
+        let key = (get_verb(declaration), get_noun(declaration, path));
+        match clauses.iter_mut().find(|(clause, _)| *clause == key) {
+            Some((_, names)) => names.push(declaration.name.clone()),
+            None => clauses.push((key, vec![declaration.name.clone()])),
+        }
+    }
+    if clauses.is_empty() {
+        return format!("Changed +{}/-{} lines of `{}` outside of any declaration.", diff.added_rows.len(), diff.removed_rows.len(), path);
+    }
+
+    let clauses: Vec<String> = clauses
+        .into_iter()
+        .map(|((verb, noun), names)| {
+            let named: Vec<String> = names.iter().flatten().map(|name| format!("`{}`", name)).collect();
+            match names.len() {
+                1 if named.len() == 1 => format!("{} {} {}", verb, noun, named[0]),
+                1 => format!("{} a {}", verb, noun),
+                count if named.len() == count && count <= MAX_LISTED_NAMES => format!("{} {} {} ({})", verb, count, pluralize(&noun), named.join(", ")),
+                count => format!("{} {} {}", verb, count, pluralize(&noun)),
+            }
+        })
+        .collect();
+    format!("{} in `{}`.", capitalize(&join_clauses(&clauses)), path)
+}
+
+/// What each documented declaration claims to do, from the first line of its doc comment,
+/// such as "`to_markdown`: A markdown summary of the patch in prose".
+fn describe_docs(declarations: &[ChangedDeclaration]) -> Vec<String> {
+    declarations
+        .iter()
+        .filter_map(|declaration| {
+            let name = declaration.name.as_ref()?;
+            let summary = declaration.doc.as_ref()?.lines().next().filter(|line| !line.is_empty())?;
+            Some(format!("`{}`: {}", name, summary))
+        })
+        .collect()
+}
+
+fn cluster_heading(graph: &DiffGraph, files: &[usize]) -> String {
+    let names: Vec<String> = files
+        .iter()
+        .take(MAX_HEADING_FILES)
+        .map(|file| {
+            let path = &graph.diffs()[*file].source_file_path;
+            format!("`{}`", path.rsplit_once('/').map_or(path.as_str(), |(_, name)| name))
+        })
+        .collect();
+    match files.len() {
+        count if count > MAX_HEADING_FILES => format!("{} and {} more", names.join(", "), count - MAX_HEADING_FILES),
+        _ => join_clauses(&names),
+    }
+}
+
+/// A markdown summary of the patch in prose, a sentence per file with the files grouped into
+/// clusters of coupled changes, for pasting into a pull request description. Under each file
+/// are what its changed declarations claim to do, as their doc comments say.
+pub fn to_markdown(graph: &DiffGraph) -> String {
+    let mut markdown = String::new();
+    let added: usize = graph.diffs().iter().map(|diff| diff.added_rows.len()).sum();
+    let removed: usize = graph.diffs().iter().map(|diff| diff.removed_rows.len()).sum();
+
+    // Writing to a String cannot fail
+    let _ = writeln!(markdown, "## Summary");
+    let _ = writeln!(markdown);
+    if graph.diffs().is_empty() {
+        let _ = writeln!(markdown, "No source files changed.");
+        return markdown;
+    }
+
+    let clusters = CouplingReport::from_graph(graph).clusters(graph);
+    let _ = writeln!(markdown, "Changes {} across {} (+{}/-{} lines).",
+        if graph.diffs().len() == 1 { "1 file".to_string() } else { format!("{} files", graph.diffs().len()) },
+        if clusters.len() == 1 { "1 group".to_string() } else { format!("{} groups", clusters.len()) },
+        added, removed);
+    let renames = renames::find_renames(graph.diffs());
+    if !renames.is_empty() {
+        let renames: Vec<String> = renames.iter().map(|rename| rename.to_string()).collect();
+        let _ = writeln!(markdown, "{}.", capitalize(&join_clauses(&renames)));
+    }
+    for files in clusters.iter() {
+        let _ = writeln!(markdown);
+        let _ = writeln!(markdown, "### {}", cluster_heading(graph, files));
+        let _ = writeln!(markdown);
+        for file in files.iter() {
+            let diff = &graph.diffs()[*file];
+            let declarations = diff.changed_declarations();
+            let _ = writeln!(markdown, "- {}", describe_file(diff, &declarations));
+            for doc in describe_docs(&declarations) {
+                let _ = writeln!(markdown, "  - {}", doc);
+            }
+        }
+    }
+
+    if !graph.skipped().is_empty() {
+        let _ = writeln!(markdown);
+        let paths: Vec<String> = graph.skipped().iter().map(|skipped| format!("`{}`", skipped.path)).collect();
+        let _ = writeln!(markdown, "Also changes {}, which {} not analyzed.", join_clauses(&paths),
+            if paths.len() == 1 { "was" } else { "were" });
+    }
+    markdown
+}
@@ -0,0 +1,43 @@
+//! Async variants of the operations that wait on the network, for embedding in async services.
+//! Each runs its blocking counterpart on tokio's blocking thread pool, keeping executor threads free.
+
+use std::io;
+use std::path::PathBuf;
+use tokio::task::{self, JoinError};
+
+use crate::cli::{self, GitHubDiff};
+use crate::patch::ParsedDiff;
+use crate::error::DiffGraphError;
+use crate::grammars::Grammars;
+
+fn join_error(e: JoinError) -> DiffGraphError {
+    DiffGraphError::io("Blocking task did not complete", io::Error::new(io::ErrorKind::Other, e))
+}
+
+/// Clones the repository at the URL into `clone_path`, see `cli::try_clone_repo`.
+pub async fn try_clone_repo(url: String, clone_path: String) -> Result<PathBuf, DiffGraphError> {
+    task::spawn_blocking(move || cli::try_clone_repo(&url, &clone_path))
+        .await
+        .map_err(join_error)?
+}
+
+/// Fetches the revisions a GitHub compare or commit URL names into the repository and diffs them.
+pub async fn try_fetch_github_diff(url: String, repo_path: PathBuf) -> Result<ParsedDiff, DiffGraphError> {
+    let github_diff = GitHubDiff::try_parse(&url)
+        .ok_or_else(|| DiffGraphError::Usage(format!("{} is not a GitHub compare or commit URL", url)))?;
+    task::spawn_blocking(move || github_diff.try_fetch(&repo_path))
+        .await
+        .map_err(join_error)?
+}
+
+/// Clones the configured parsers that are not installed yet and compiles them, sharing compiled
+/// grammars through the default grammar cache.
+pub async fn try_install_languages(parser_config_path: Option<PathBuf>) -> Result<(), DiffGraphError> {
+    task::spawn_blocking(move || {
+        let grammars = Grammars::load(parser_config_path, true)?;
+        grammars.try_install_languages()?;
+        grammars.try_compile_languages(None)
+    })
+        .await
+        .map_err(join_error)?
+}
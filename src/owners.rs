@@ -0,0 +1,94 @@
+use regex::Regex;
+use tracing::{debug, warn};
+
+use crate::parser::SourceLocation;
+
+/// Where GitHub and GitLab look for the CODEOWNERS file, in the order they look
+const CODEOWNERS_PATHS: [&str; 3] = [".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+/// Owners of the files of a repository by the patterns of its CODEOWNERS file, the last
+/// matching pattern winning as on GitHub.
+#[derive(Debug, Clone, Default)]
+pub struct CodeOwners {
+    rules: Vec<(Regex, Vec<String>)>,
+}
+
+/// Patterns follow gitignore: a pattern without a slash but at its end matches at any depth,
+/// `*` and `?` stay within a directory, `**` crosses them, and a pattern naming a directory
+/// matches everything beneath it.
+pub(crate) fn pattern_to_regex(pattern: &str) -> String {
+    let anchored = pattern.trim_end_matches('/').contains('/');
+    let mut regex = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+    let mut chars = pattern.trim_start_matches('/').trim_end_matches('/').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            },
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '\\' => if let Some(escaped) = chars.next() {
+                regex.push_str(&regex::escape(&escaped.to_string()));
+            },
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex + "(?:/.*)?$"
+}
+
+impl CodeOwners {
+    /// Reads the rules of a CODEOWNERS file. Lines that are not rules, such as GitLab section
+    /// headers, and patterns that do not compile are left out.
+    pub fn parse(text: &str) -> Self {
+        let mut rules = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with('[') || line.starts_with("^[") {
+                continue;
+            }
+            let mut tokens = line.split_whitespace().take_while(|token| !token.starts_with('#'));
+            let Some(pattern) = tokens.next() else {
+                continue;
+            };
+            // A pattern without owners leaves the files it matches unowned
+            let owners: Vec<String> = tokens.map(|owner| owner.to_string()).collect();
+            match Regex::new(&pattern_to_regex(pattern)) {
+                Ok(regex) => rules.push((regex, owners)),
+                Err(e) => warn!("Ignoring CODEOWNERS line {}, '{}' is not a valid pattern: {}", number + 1, pattern, e),
+            }
+        }
+        Self { rules }
+    }
+
+    /// The CODEOWNERS file of the repository the patch applies to, as it is before the patch.
+    pub fn load(source_location: &SourceLocation) -> Option<Self> {
+        for path in CODEOWNERS_PATHS {
+            if source_location.try_get_size(path).is_none() {
+                continue;
+            }
+            match source_location.try_load(path) {
+                Ok(text) => {
+                    debug!("Loaded code owners from {}", path);
+                    return Some(Self::parse(&text));
+                },
+                Err(e) => warn!("Unable to read {}: {}", path, e),
+            }
+        }
+        None
+    }
+
+    /// Owners of the file at the path, none when no pattern matches it.
+    pub fn get_owners(&self, path: &str) -> &[String] {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(regex, _)| regex.is_match(path))
+            .map_or(&[], |(_, owners)| owners.as_slice())
+    }
+}
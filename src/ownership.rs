@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::Path;
+
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::color::{self, Stream, Style};
+use crate::error::DiffGraphError;
+use crate::graph::DiffGraph;
+use crate::parser::SourceLocation;
+use crate::syntax;
+
+/// Team of the authors who left, in the authors file
+const LEFT: &str = "-";
+
+/// Teams of a repository's authors by email, read from a file with a line per author: their
+/// email and their team, or `-` for authors who left. `#` starts a comment.
+#[derive(Debug, Clone, Default)]
+pub struct Authors {
+    /// None for authors who left
+    teams: HashMap<String, Option<String>>,
+}
+
+impl Authors {
+    /// Reads the lines of an authors file. Lines without a team are left out.
+    pub fn parse(text: &str) -> Self {
+        let mut teams = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let mut tokens = line.split_whitespace().take_while(|token| !token.starts_with('#'));
+            let Some(email) = tokens.next() else {
+                continue;
+            };
+            let Some(team) = tokens.next() else {
+                warn!("Ignoring authors line {}, '{}' has no team", number + 1, email);
+                continue;
+            };
+            let team = (team != LEFT).then(|| team.to_string());
+            teams.insert(email.to_lowercase(), team);
+        }
+        Self { teams }
+    }
+
+    pub fn try_load(path: &Path) -> Result<Self, DiffGraphError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| DiffGraphError::io(format!("Unable to read authors '{}'", path.display()), e))?;
+        Ok(Self::parse(&text))
+    }
+
+    /// The author's team, Some(None) if they left, None if the file does not list them.
+    pub fn get_team(&self, email: &str) -> Option<Option<&str>> {
+        self.teams.get(&email.to_lowercase()).map(|team| team.as_deref())
+    }
+}
+
+/// Why the changed lines of a declaration may need someone else to review them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OwnershipFlag {
+    /// Lines the patch changes were last written by someone who left
+    AuthorLeft,
+    /// Lines the patch changes were last written by someone of another team than the patch's author
+    OtherTeam,
+}
+
+impl OwnershipFlag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OwnershipFlag::AuthorLeft => "author-left",
+            OwnershipFlag::OtherTeam => "other-team",
+        }
+    }
+}
+
+/// Someone who last wrote lines the patch changes in a declaration.
+#[derive(Debug, Clone, Serialize)]
+pub struct BlamedAuthor {
+    pub email: String,
+    /// None for authors who left or who the authors file does not list
+    pub team: Option<String>,
+    pub left: bool,
+    /// Changed lines they last wrote
+    pub lines: usize,
+}
+
+/// A declaration whose changed lines were last written by authors who left or by another team.
+#[derive(Debug, Clone, Serialize)]
+pub struct OwnershipRisk {
+    pub file: String,
+    /// None for changes outside of any declaration, such as imports
+    pub kind: Option<String>,
+    pub name: Option<String>,
+    /// First line of the declaration, or of the changed lines outside of declarations, counted
+    /// from 1, in the file before the patch
+    pub line: usize,
+    pub authors: Vec<BlamedAuthor>,
+    pub flags: BTreeSet<OwnershipFlag>,
+}
+
+impl OwnershipRisk {
+    fn label(&self) -> String {
+        match (&self.kind, &self.name) {
+            (Some(kind), Some(name)) => format!("{} {}", kind, name),
+            (Some(kind), None) => kind.clone(),
+            (None, _) => "outside of declarations".to_string(),
+        }
+    }
+}
+
+/// Changed code whose original authors left or belong to another team than the patch's author,
+/// going by blame of the lines the patch removes or replaces.
+#[derive(Debug, Clone, Serialize)]
+pub struct OwnershipReport {
+    /// Email of the patch's author, whose team the blamed authors are compared with
+    pub author: Option<String>,
+    pub team: Option<String>,
+    /// Changed lines whose authors were found
+    pub blamed_lines: usize,
+    pub declarations: Vec<OwnershipRisk>,
+}
+
+/// Key of a declaration of the file before the patch: its kind, name and first row
+type DeclarationKey = (Option<String>, Option<String>, usize);
+
+impl OwnershipReport {
+    /// Blames the lines the patch removes, which include the old side of the lines it modifies,
+    /// and flags the declarations they belong to when an author left or is of another team than
+    /// `author`. Added lines have no history, and authors the file does not list are not flagged.
+    /// Files that cannot be blamed, such as ones handed over in memory, are left out.
+    pub fn try_from_graph(graph: &DiffGraph, source_location: &SourceLocation, authors: &Authors, author: Option<&str>) -> Result<Self, DiffGraphError> {
+        let team = author.and_then(|author| authors.get_team(author)).flatten();
+        let mut blamed_lines = 0;
+        let mut declarations = Vec::new();
+        for diff in graph.diffs().iter() {
+            let blame = match source_location.try_blame(&diff.source_file_path, &diff.removed_rows) {
+                Ok(blame) => blame,
+                Err(e) => {
+                    warn!("Not checking the ownership of {}: {}", diff.source_file_path, e);
+                    continue;
+                },
+            };
+            debug!("Blamed {} of {} removed lines of {}", blame.len(), diff.removed_rows.len(), diff.source_file_path);
+            blamed_lines += blame.len();
+
+            // Blamed authors of each declaration, by email
+            let lines: Vec<&str> = diff.source.lines().collect();
+            let mut blamed: BTreeMap<DeclarationKey, BTreeMap<&str, usize>> = BTreeMap::new();
+            for row in diff.removed_rows.iter() {
+                let Some(email) = blame.get(row) else {
+                    continue;
+                };
+                let point = syntax::get_line_start(*row, lines.get(*row).unwrap_or(&""));
+                let key = match syntax::find_enclosing_declaration(&diff.tree, point) {
+                    Some(declaration) => (Some(declaration.kind().to_string()), syntax::get_declaration_name(&declaration, &diff.source), declaration.start_position().row),
+                    None => (None, None, *row),
+                };
+                // Changes outside of declarations count as one, at their first line
+                let key = match key {
+                    (None, _, _) => blamed.keys().find(|(kind, _, _)| kind.is_none()).cloned().unwrap_or(key),
+                    key => key,
+                };
+                *blamed.entry(key).or_default().entry(email.as_str()).or_default() += 1;
+            }
+
+            for ((kind, name, row), emails) in blamed {
+                let mut flags = BTreeSet::new();
+                let mut blamed_authors: Vec<BlamedAuthor> = emails
+                    .into_iter()
+                    .map(|(email, lines)| {
+                        let listed = authors.get_team(email);
+                        match listed {
+                            Some(None) => {
+                                flags.insert(OwnershipFlag::AuthorLeft);
+                            },
+                            Some(Some(other)) if team.is_some_and(|team| team != other) => {
+                                flags.insert(OwnershipFlag::OtherTeam);
+                            },
+                            _ => {},
+                        }
+                        BlamedAuthor {
+                            email: email.to_string(),
+                            team: listed.flatten().map(|team| team.to_string()),
+                            left: listed == Some(None),
+                            lines,
+                        }
+                    })
+                    .collect();
+                if flags.is_empty() {
+                    continue;
+                }
+                blamed_authors.sort_by_key(|blamed| std::cmp::Reverse(blamed.lines));
+                declarations.push(OwnershipRisk {
+                    file: diff.source_file_path.clone(),
+                    kind,
+                    name,
+                    line: row + 1,
+                    authors: blamed_authors,
+                    flags,
+                });
+            }
+        }
+
+        Ok(Self {
+            author: author.map(|author| author.to_string()),
+            team: team.map(|team| team.to_string()),
+            blamed_lines,
+            declarations,
+        })
+    }
+}
+
+impl fmt::Display for OwnershipReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.blamed_lines == 0 {
+            return writeln!(f, "No changed lines could be blamed, the patch only adds lines or its files have no history");
+        }
+
+        for declaration in self.declarations.iter() {
+            let flags: Vec<&str> = declaration.flags.iter().map(|flag| flag.as_str()).collect();
+            let style = match declaration.flags.contains(&OwnershipFlag::AuthorLeft) {
+                true => Style::Red,
+                false => Style::Yellow,
+            };
+            writeln!(f, "{} {} {}", color::paint(Stream::Stdout, style, &format!("[{}]", flags.join(", "))),
+                color::paint(Stream::Stdout, Style::Bold, &declaration.label()),
+                color::paint(Stream::Stdout, Style::Dim, &format!("{}:{}", declaration.file, declaration.line)))?;
+            for author in declaration.authors.iter() {
+                let team = match (author.left, &author.team) {
+                    (true, _) => "left",
+                    (false, Some(team)) => team.as_str(),
+                    (false, None) => "unknown team",
+                };
+                writeln!(f, "  {} ({}), {} lines", author.email, team, author.lines)?;
+            }
+        }
+
+        if !self.declarations.is_empty() {
+            writeln!(f)?;
+        }
+        let of = match (&self.author, &self.team) {
+            (Some(author), Some(team)) => format!("{} ({})", author, team),
+            (Some(author), None) => format!("{}, whose team is unknown", author),
+            (None, _) => "the patch's author, who is unknown".to_string(),
+        };
+        writeln!(f, "{} declarations have changed lines by authors who left or of another team than {}, of {} blamed lines",
+            self.declarations.len(), of, self.blamed_lines)
+    }
+}
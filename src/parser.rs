@@ -1,54 +1,64 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
-use unidiff::{PatchSet, PatchedFile, LINE_TYPE_ADDED, LINE_TYPE_REMOVED, LINE_TYPE_CONTEXT };
+use std::io::{self, BufWriter, Write};
+use unidiff::{Hunk, PatchedFile, LINE_TYPE_ADDED, LINE_TYPE_REMOVED, LINE_TYPE_CONTEXT };
 use tree_sitter::{Parser, Tree, Point, InputEdit, Language};
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use tracing::{debug, info, Level};
 
+use crate::api;
+use crate::cancel;
+use crate::docs;
 use crate::grammars::Grammars;
+use crate::graph::{ChangeStatus, ChangedNode, DiffGraphParams, NodeInfo};
+use crate::error::DiffGraphError;
+use crate::progress;
+use crate::limits::{LimitAction, Truncation};
+use crate::policy::{Condition, Finding};
+use crate::syntax;
+use crate::summary::FileSummary;
+use crate::color::Stream;
+use crate::stats;
+use crate::structural::{self, ChangeClass};
+use crate::timings;
+use crate::visitor::GraphVisitor;
 
+/// Longest excerpt of a leaf's text in the labels of dumped trees
+const MAX_DOT_EXCERPT_CHARS: usize = 24;
+
+/// Start byte and content of each line of a source, without the line ending.
 #[derive(Debug)]
 struct LineByteCounter<'a> {
+    content: &'a [u8],
     byte_count: usize,
-    lines: std::str::Lines<'a>,
-    cache: Vec<(usize, &'a str)>,
 }
 
 impl<'a> LineByteCounter<'a> {
-    fn new(content: &'a str) -> LineByteCounter<'a> {
-        let lines = content.lines();
-
+    fn new(content: &'a [u8]) -> LineByteCounter<'a> {
         LineByteCounter { 
-            lines, 
+            content, 
             byte_count: 0,
-            cache: Vec::new(),
-        }
-    }
-    
-    fn get(&self, line_num: usize) -> Option<&(usize, &'a str)> {
-        self.cache.get(line_num)
-    }
-
-    fn last_in_cache(&self) -> Option<(usize, usize, &'a str)> {
-        if let Some(last) = self.cache.last() {
-            Some((self.cache.len() - 1, last.0, last.1))
-        } else {
-            None
         }
     }
 }
 
 impl<'a> Iterator for LineByteCounter<'a> {
-    type Item = (usize, &'a str);
+    type Item = (usize, &'a [u8]);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let line = self.lines.next()?;
+        let rest = self.content.get(self.byte_count..).filter(|rest| !rest.is_empty())?;
+        let (line, line_length) = match rest.iter().position(|b| *b == b'\n') {
+            Some(newline) => (&rest[..newline], newline + 1),
+            None => (rest, rest.len()),
+        };
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
         let byte_count = self.byte_count;
 
-        self.byte_count += line.len() + '\n'.len_utf8();
+        self.byte_count += line_length;
 
-        let item = (byte_count, line);
-        self.cache.push(item);
-
-        Some(item)
+        Some((byte_count, line))
     }
 }
 
@@ -61,9 +71,50 @@ pub struct Diff {
     pub edits: Vec<InputEdit>,
     pub tree: Tree,
     pub language: Language,
+    pub language_name: String,
+    /// Contents of the file once the patch is applied
+    pub target_source: String,
+    pub target_tree: Tree,
+    /// Rows of `source` removed by the patch
+    pub removed_rows: Vec<usize>,
+    /// Rows of `target_source` added by the patch
+    pub added_rows: Vec<usize>,
+    pub hunk_count: usize,
+    /// Hunks left out because they do not apply to the source, with --best-effort
+    pub skipped_hunks: usize,
+    /// Hunks left out because they only change whitespace, layout or comments, with --ignore-formatting
+    pub formatting_hunks: usize,
+}
+
+/// A declaration the patch touches, see `syntax::is_declaration_kind`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangedDeclaration {
+    pub kind: String,
+    pub name: Option<String>,
+    /// First row of the declaration, in the patched file unless only removed lines touch it
+    pub row: usize,
+    /// How the declaration changed, from matching it up between the original and patched trees
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change: Option<ChangeClass>,
+    /// Doc comment or docstring of the declaration, without its comment markers, for languages
+    /// with doc queries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doc: Option<String>,
 }
 
-fn get_fs_file_path<'a>(patch_file_path: &'a str) -> &'a str {
+/// Path of the file in the repository, the target path for added files and the source path otherwise.
+pub fn get_patch_file_path(patch_file: &PatchedFile) -> &str {
+    if patch_file.source_file == "/dev/null" {
+        get_fs_file_path(&patch_file.target_file)
+    } else {
+        get_fs_file_path(&patch_file.source_file)
+    }
+}
+
+/// Path in the repository of a file a diff header names, without the `a/` and `b/` prefixes of
+/// git and Mercurial or a leading `./`. Headers of other tools are rewritten as git's before the
+/// diff is parsed, see `unified::to_git_headers`.
+pub fn get_fs_file_path(patch_file_path: &str) -> &str {
     let file = if let Some(stripped_path) = patch_file_path.strip_prefix("a/") {
         stripped_path
     } else if let Some(stripped_path) = patch_file_path.strip_prefix("b/") {
@@ -72,131 +123,461 @@ fn get_fs_file_path<'a>(patch_file_path: &'a str) -> &'a str {
         patch_file_path
     };
 
-    file
+    file.strip_prefix("./").unwrap_or(file)
+}
+
+/// Line of the source a hunk starts at, 0-based. A hunk without source lines is inserted after
+/// its start line rather than at it.
+fn get_hunk_start(hunk: &Hunk) -> usize {
+    if hunk.source_length == 0 { hunk.source_start } else { hunk.source_start.saturating_sub(1) }
+}
+
+/// Edits of the tree before the patch, a line at a time, for the hunks that are applied. Rows
+/// are counted from 0 and lines of the diff from 1, so a hunk may start with a removed line.
+/// Lines added without removing any are insertions, which remove nothing from the source.
+fn try_get_edits(source: &str, patch_file: &PatchedFile, applied: &[bool]) -> Result<Vec<InputEdit>, DiffGraphError> {
+    let lines: Vec<(usize, &[u8])> = LineByteCounter::new(source.as_bytes()).collect();
+    let mut edits = Vec::new();
+
+    // TODO: Do some funky character specific diff combination instead of just line diffs? 
+
+    // Edits are anchored at the source lines the hunk headers give
+    for (hunk, _) in patch_file.hunks().iter().zip(applied).filter(|(_, applied)| **applied) {
+        // Row of the source the next line of the hunk is at, and whether the lines added there
+        // replace removed ones, which the edits of the removed lines already cover
+        let mut source_row = get_hunk_start(hunk);
+        let mut replacing = false;
+        for line in hunk.lines() {
+            match line.line_type.as_str() {
+                LINE_TYPE_REMOVED => {
+                    let Some(source_line_no) = line.source_line_no else {
+                        continue;
+                    };
+                    let row = source_line_no.saturating_sub(1);
+                    let Some((start_byte, source_line)) = lines.get(row).filter(|_| source_line_no > 0) else {
+                        return Err(DiffGraphError::Patch(format!("Line {} of the diff removes line {} of {}, which has {} lines",
+                            line.diff_line_no, source_line_no, get_patch_file_path(patch_file), lines.len())));
+                    };
+                    // Removed lines end where they start in the patched file
+                    let new_end_row = line.target_line_no.map_or(row, |target_line_no| target_line_no.saturating_sub(1));
+                    edits.push(InputEdit {
+                        start_byte: *start_byte,
+                        old_end_byte: start_byte + source_line.len(),
+                        new_end_byte: start_byte + line.value.len(),
+                        start_position: Point { row, column: 0 },
+                        old_end_position: Point { row, column: source_line.len() },
+                        new_end_position: Point { row: new_end_row, column: line.value.len() },
+                    });
+                    source_row = source_line_no;
+                    replacing = true;
+                },
+                LINE_TYPE_ADDED if !replacing => {
+                    // Inserted before the source row, or at the end of the source past its last row
+                    let start_byte = match lines.get(source_row) {
+                        Some((start_byte, _)) => *start_byte,
+                        None if source_row == lines.len() => source.len(),
+                        None => return Err(DiffGraphError::Patch(format!("Line {} of the diff adds after line {} of {}, which has {} lines",
+                            line.diff_line_no, source_row, get_patch_file_path(patch_file), lines.len()))),
+                    };
+                    let new_end_row = line.target_line_no.map_or(source_row, |target_line_no| target_line_no.saturating_sub(1));
+                    edits.push(InputEdit {
+                        start_byte,
+                        old_end_byte: start_byte,
+                        new_end_byte: start_byte + line.value.len(),
+                        start_position: Point { row: source_row, column: 0 },
+                        old_end_position: Point { row: source_row, column: 0 },
+                        new_end_position: Point { row: new_end_row, column: line.value.len() },
+                    });
+                },
+                LINE_TYPE_CONTEXT => {
+                    let Some(source_line_no) = line.source_line_no else {
+                        return Err(DiffGraphError::Patch(format!("Context line {} in patch requires source line", line.diff_line_no)));
+                    };
+                    source_row = source_line_no;
+                    replacing = false;
+                },
+                _ => continue,
+            }
+        }
+    }
+    Ok(edits)
+}
+
+/// Whether each hunk applies to the source, its context and removed lines being where its header
+/// says and reading the same, after the hunks before it that apply.
+fn find_applying_hunks(source: &str, patch_file: &PatchedFile) -> Vec<bool> {
+    let source_lines: Vec<&str> = source.lines().collect();
+    let mut next_line = 0;
+    patch_file.hunks().iter().map(|hunk| {
+        let hunk_start = get_hunk_start(hunk);
+        let mut source_line = hunk_start;
+        let applies = hunk_start >= next_line && hunk.lines().iter().all(|line| match line.line_type.as_str() {
+            LINE_TYPE_CONTEXT | LINE_TYPE_REMOVED => {
+                source_line += 1;
+                source_lines.get(source_line - 1).is_some_and(|source_line| source_line.trim_end_matches('\r') == line.value)
+            },
+            _ => true,
+        });
+        if applies {
+            next_line = source_line;
+        }
+        applies
+    }).collect()
 }
 
-fn try_load_file_from(file_path: &str) -> Result<String, String> {
-    let path = Path::new(file_path);
-    if !path.exists() {
-        return Err(format!("'{}' does not exist", file_path));
+/// Leaf tokens of the tree starting on the rows, by kind and text, leaving out comments. Rows
+/// whose tokens are the same differ only in whitespace, layout or comments.
+fn get_code_tokens<'s>(tree: &Tree, source: &'s str, rows: std::ops::Range<usize>) -> Vec<(&'static str, &'s str)> {
+    let mut tokens = Vec::new();
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        let overlaps = node.end_position().row >= rows.start && node.start_position().row < rows.end;
+        if overlaps && !node.kind().contains("comment") {
+            if node.child_count() == 0 {
+                if rows.contains(&node.start_position().row) {
+                    tokens.push((node.kind(), source.get(node.byte_range()).unwrap_or_default()));
+                }
+            } else if cursor.goto_first_child() {
+                continue;
+            }
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return tokens;
+            }
+        }
     }
-    if !path.is_file() {
-        return Err(format!("'{}' is not a file", file_path))
+}
+
+/// Whether each hunk only changes whitespace, layout or comments, the code tokens of the rows it
+/// spans being the same before and after the patch. Only `applied` hunks, those the patched tree
+/// was built from, are considered. Indentation tokens of languages where it is significant, such
+/// as Python, are tokens like any other.
+fn find_formatting_hunks(patch_file: &PatchedFile, applied: &[bool], (tree, source): (&Tree, &str), (target_tree, target_source): (&Tree, &str)) -> Vec<bool> {
+    // Patched rows after a hunk that was left out move by the lines it would have added or removed
+    let mut target_shift: isize = 0;
+    patch_file.hunks().iter().zip(applied).map(|(hunk, applied)| {
+        if !applied {
+            target_shift += hunk.target_length as isize - hunk.source_length as isize;
+            return false;
+        }
+        let source_start = get_hunk_start(hunk);
+        let target_start = if hunk.target_length == 0 { hunk.target_start } else { hunk.target_start.saturating_sub(1) };
+        let target_start = (target_start as isize - target_shift).max(0) as usize;
+        get_code_tokens(tree, source, source_start..source_start + hunk.source_length)
+            == get_code_tokens(target_tree, target_source, target_start..target_start + hunk.target_length)
+    }).collect()
+}
+
+/// Rebuilds the patched contents of a file by applying the hunks to its source lines, leaving out
+/// those that `applied` marks as not applying.
+fn try_apply_hunks(source: &str, patch_file: &PatchedFile, applied: &[bool]) -> Result<String, DiffGraphError> {
+    let source_lines: Vec<&str> = source.split_inclusive('\n').collect();
+    let mut target = String::with_capacity(source.len());
+    let mut next_line = 0;
+    for (hunk, _) in patch_file.hunks().iter().zip(applied).filter(|(_, applied)| **applied) {
+        let hunk_start = get_hunk_start(hunk);
+        if hunk_start < next_line || hunk_start > source_lines.len() {
+            return Err(DiffGraphError::Patch(format!("Hunk at line {} of {} does not match the source file", hunk.source_start, patch_file.path())));
+        }
+        target.extend(source_lines[next_line..hunk_start].iter().copied());
+        next_line = hunk_start;
+
+        for line in hunk.lines() {
+            match line.line_type.as_str() {
+                LINE_TYPE_CONTEXT => {
+                    match source_lines.get(next_line) {
+                        Some(source_line) => target.push_str(source_line),
+                        None => return Err(DiffGraphError::Patch(format!("Context line {} in patch is past the end of {}", line.diff_line_no, patch_file.path()))),
+                    }
+                    next_line += 1;
+                },
+                LINE_TYPE_REMOVED => next_line += 1,
+                LINE_TYPE_ADDED => {
+                    target.push_str(&line.value);
+                    target.push('\n');
+                },
+                _ => continue,
+            }
+        }
     }
-    match std::fs::read_to_string(path) {
-        Ok(contents) => Ok(contents),
-        Err(e) => Err(e.to_string())
+    if next_line < source_lines.len() {
+        target.extend(source_lines[next_line..].iter().copied());
     }
+
+    Ok(target)
 }
 
-impl Diff {
-    pub fn from_patch_file(patch_file: &PatchedFile, grammars: &Grammars) -> Result<Self, String> {
+fn try_load_file_from(path: &Path) -> Result<String, DiffGraphError> {
+    std::fs::read_to_string(path).map_err(|e| DiffGraphError::io(format!("Unable to read '{}'", path.display()), e))
+}
 
-        // Load the source file from disk to get byte counts
-        // And later use to parse the entire tree
-        let source: String;
+/// A git command run in the repository, on the git directory given instead of the one git
+/// finds from the repository when there is one.
+pub fn git_command(repo_dir: &Path, git_dir: Option<&Path>) -> std::process::Command {
+    let mut command = std::process::Command::new("git");
+    command.current_dir(repo_dir);
+    if let Some(git_dir) = git_dir {
+        command.env("GIT_DIR", git_dir);
+    }
+    command
+}
+
+fn try_load_file_at_revision(repo_dir: &Path, git_dir: Option<&Path>, revision: &str, file_path: &str) -> Result<String, DiffGraphError> {
+    let output = git_command(repo_dir, git_dir)
+        .arg("show")
+        .arg(format!("{}:{}", revision, file_path))
+        .output()
+        .map_err(|e| DiffGraphError::io("Unable to run git show", e))?;
+
+    if output.status.success() {
+        // Valid UTF-8 is taken over as is, only invalid contents are copied to replace what is invalid
+        Ok(String::from_utf8(output.stdout).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
+    } else {
+        Err(DiffGraphError::Git(format!("Unable to load '{}' at revision {}: {}", file_path, revision, String::from_utf8_lossy(&output.stderr).trim())))
+    }
+}
+
+/// Where the contents of patched files are read from before the patch is applied.
+#[derive(Debug, Clone)]
+pub enum SourceLocation {
+    /// The files as they are checked out in the repository directory.
+    WorkingTree(PathBuf),
+    /// The files as they were committed at a git revision of the repository.
+    Revision {
+        repo_dir: PathBuf,
+        revision: String,
+        /// Git directory the revision is read from when git does not find it from `repo_dir`
+        git_dir: Option<PathBuf>,
+    },
+    /// Contents handed over by the caller keyed by path, for graphing without git or a filesystem.
+    Memory(HashMap<String, String>),
+}
 
-        // Trim off the a/ or b/ from the file
-        let source_file_path = get_fs_file_path(&patch_file.source_file);
-        match try_load_file_from(source_file_path) {
-            Ok(contents) => source = contents,
-            Err(e) => return Err(e),
+impl SourceLocation {
+    pub fn get_repo_dir(&self) -> &Path {
+        match self {
+            SourceLocation::WorkingTree(repo_dir) => repo_dir,
+            SourceLocation::Revision { repo_dir, .. } => repo_dir,
+            // Languages are recognized by file name, which does not need a directory
+            SourceLocation::Memory(_) => Path::new(""),
         }
+    }
 
-        let mut edits = Vec::new();
-        let mut line_byte_counter = LineByteCounter::new(&source);
+    /// Paths of every file of the repository, those git tracks in the working tree or those
+    /// committed at the revision, or the files handed over.
+    pub fn try_list_files(&self) -> Result<Vec<String>, DiffGraphError> {
+        let output = match self {
+            SourceLocation::WorkingTree(repo_dir) => git_command(repo_dir, None)
+                .args(["ls-files", "-z"])
+                .output(),
+            SourceLocation::Revision { repo_dir, revision, git_dir } => git_command(repo_dir, git_dir.as_deref())
+                .args(["ls-tree", "-r", "-z", "--name-only", revision])
+                .output(),
+            SourceLocation::Memory(files) => return Ok(files.keys().cloned().collect()),
+        }.map_err(|e| DiffGraphError::io("Unable to run git to list the files of the repository", e))?;
 
-        // TODO: Do some funky character specific diff combination instead of just line diffs? 
+        if !output.status.success() {
+            return Err(DiffGraphError::Git(format!("Unable to list the files of the repository: {}", String::from_utf8_lossy(&output.stderr).trim())));
+        }
+        Ok(output.stdout
+            .split(|byte| *byte == 0)
+            .filter(|path| !path.is_empty())
+            .map(|path| String::from_utf8_lossy(path).into_owned())
+            .collect())
+    }
 
-        for hunk in patch_file.hunks() {
+    /// Size in bytes of the file without loading it, if it exists.
+    pub fn try_get_size(&self, file_path: &str) -> Option<usize> {
+        match self {
+            SourceLocation::WorkingTree(repo_dir) => std::fs::metadata(repo_dir.join(file_path))
+                .ok()
+                .map(|metadata| metadata.len() as usize),
+            SourceLocation::Revision { repo_dir, revision, git_dir } => {
+                let output = git_command(repo_dir, git_dir.as_deref())
+                    .arg("cat-file")
+                    .arg("-s")
+                    .arg(format!("{}:{}", revision, file_path))
+                    .output()
+                    .ok()?;
+                String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+            },
+            SourceLocation::Memory(files) => files.get(file_path).map(|contents| contents.len()),
+        }
+    }
 
-            let mut last_source_context_line = hunk.source_start;
-            for line in hunk.lines() {
-                println!("{:?}", line);
-                
-                match line.line_type.as_str() {
-                    LINE_TYPE_ADDED | LINE_TYPE_REMOVED => {
-                        if let Some(source_line_no) = line.source_line_no {
-                            let (start_byte, source_line_str) = if let Some(cached_line) = line_byte_counter.get(source_line_no) {
-                                *cached_line
-                            } else {
-                                let mut item = None;
-                                let iterations_to_go = if let Some(last) = line_byte_counter.last_in_cache() {
-                                    item = Some((last.1, last.2));
-                                    last_source_context_line + 1 - last.0
-                                } else {
-                                    last_source_context_line + 1
-                                };
-                                for _ in 0..iterations_to_go {
-                                    if let Some(counter) = line_byte_counter.next() {
-                                        item = Some(counter);
-                                    } else {
-                                        return Err(format!("Line counter could not iterate {}, ran out of lines", iterations_to_go))
-                                    }
-                                }
-                                if let Some(item) = item {
-                                    item
-                                } else {
-                                    return Err(format!("Unable to determine line start byte count for source line {} (L{} in diff)", source_line_no, line.diff_line_no))
-                                }
-                            };
-                            let old_end_byte = start_byte + source_line_str.len();
-                            let new_end_byte = start_byte + line.value.len();
-                            let new_end_row = if let Some(new_end_row) = line.target_line_no {
-                                new_end_row
-                            } else {
-                                // Line was removed, 
-                                assert_eq!(source_line_no - 1, last_source_context_line);
-                                source_line_no - 1
-                            };
-
-                            edits.push(InputEdit { 
-                                start_byte, 
-                                old_end_byte, 
-                                new_end_byte, 
-                                start_position: Point { row: source_line_no, column: 0 }, 
-                                old_end_position: Point { row: source_line_no, column: source_line_str.chars().count() }, 
-                                new_end_position: Point { row: new_end_row, column: line.value.chars().count() } 
-                            });
-                            last_source_context_line = source_line_no;
-                        } else {
-                        }
-                    },
-                    LINE_TYPE_CONTEXT => {
-                        if let Some(source_line_no) = line.source_line_no {
-                            last_source_context_line = source_line_no;
-                        } else {
-                            return Err(format!("Context line {} in patch requires source line", line.diff_line_no));
-                        }
-                    },
-                    _ => continue,
+    /// Email of the author who last changed each of the rows of the file, as git blames them
+    /// at the revision or in the working tree. Contents handed over have no history, so no rows.
+    pub fn try_blame(&self, file_path: &str, rows: &[usize]) -> Result<HashMap<usize, String>, DiffGraphError> {
+        if rows.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let (mut command, revision) = match self {
+            SourceLocation::WorkingTree(repo_dir) => (git_command(repo_dir, None), None),
+            SourceLocation::Revision { repo_dir, revision, git_dir } => (git_command(repo_dir, git_dir.as_deref()), Some(revision)),
+            SourceLocation::Memory(_) => return Ok(HashMap::new()),
+        };
+        command.args(["blame", "--line-porcelain"]);
+        // Runs of rows are blamed as one range each
+        let mut rows = rows.to_vec();
+        rows.sort_unstable();
+        rows.dedup();
+        let mut start = 0;
+        for i in 1..=rows.len() {
+            if i == rows.len() || rows[i] != rows[i - 1] + 1 {
+                command.arg(format!("-L{},{}", rows[start] + 1, rows[i - 1] + 1));
+                start = i;
+            }
+        }
+        if let Some(revision) = revision {
+            command.arg(revision);
+        }
+        let output = command
+            .arg("--")
+            .arg(file_path)
+            .output()
+            .map_err(|e| DiffGraphError::io("Unable to run git blame", e))?;
+        if !output.status.success() {
+            return Err(DiffGraphError::Git(format!("Unable to blame '{}': {}", file_path, String::from_utf8_lossy(&output.stderr).trim())));
+        }
+
+        // Each line comes as a header of its commit and rows, then its commit's fields
+        let mut authors = HashMap::new();
+        let mut row = None;
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if let Some(email) = line.strip_prefix("author-mail ") {
+                if let Some(row) = row.take() {
+                    authors.insert(row, email.trim_start_matches('<').trim_end_matches('>').to_string());
+                }
+            } else if !line.starts_with('\t') {
+                let mut fields = line.split(' ');
+                let is_header = fields.next().is_some_and(|hash| hash.len() >= 40 && hash.chars().all(|c| c.is_ascii_hexdigit()));
+                if is_header {
+                    row = fields.nth(1).and_then(|row| row.parse::<usize>().ok()).map(|row| row - 1);
                 }
-                
             }
         }
-    
+        Ok(authors)
+    }
+
+    pub fn try_load(&self, file_path: &str) -> Result<String, DiffGraphError> {
+        match self {
+            SourceLocation::WorkingTree(repo_dir) => try_load_file_from(&repo_dir.join(file_path)),
+            SourceLocation::Revision { repo_dir, revision, git_dir } => try_load_file_at_revision(repo_dir, git_dir.as_deref(), revision, file_path),
+            SourceLocation::Memory(files) => files
+                .get(file_path)
+                .cloned()
+                .ok_or_else(|| DiffGraphError::Patch(format!("No source was given for '{}'", file_path))),
+        }
+    }
+}
+
+impl Diff {
+    /// Parses the file before and after the patch. A diff of the same file from an earlier
+    /// version of the patch has its trees reused where the text is unchanged, and reparsed
+    /// incrementally where it is not. With `best_effort`, hunks that do not apply to the source
+    /// are left out rather than failing the file, and with `ignore_formatting`, so are hunks that
+    /// only change whitespace, layout or comments.
+    pub fn from_patch_file(patch_file: &PatchedFile, grammars: &Grammars, source_location: &SourceLocation, previous: Option<&Diff>, best_effort: bool, ignore_formatting: bool) -> Result<Self, DiffGraphError> {
+
+        // Trim off the a/ or b/ from the file, added files have no source so use the target path
+        let is_added_file = patch_file.source_file == "/dev/null";
+        let source_file_path = if is_added_file {
+            get_fs_file_path(&patch_file.target_file)
+        } else {
+            get_fs_file_path(&patch_file.source_file)
+        };
+        // Load the source file to get byte counts
+        // And later use to parse the entire tree
+        let source = if is_added_file {
+            String::new()
+        } else {
+            source_location.try_load(source_file_path)?
+        };
+
+        let mut applied = match best_effort {
+            true => find_applying_hunks(&source, patch_file),
+            false => vec![true; patch_file.hunks().len()],
+        };
         let source_file = patch_file.source_file.clone();
         let target_file = patch_file.target_file.clone();
         let source_file_path = source_file_path.to_string();
 
-        let tree_path = Path::new(&source_file_path);
-        dbg!(tree_path);
-        let lang = grammars.try_get_language(tree_path).map_err(|e| e.to_string())?;
-        dbg!(lang);
-
-        let tree: Tree;
-        if let Some(lang) = lang {
-            tree = match try_parse_source_code(lang, &source) {
-                Ok(Some(tree)) => tree,
-                Ok(None) => return Err(format!("Unable to parse patch file: {}", patch_file.path())),
-                Err(e) => return Err(e),
-            };
-        } else {
-            return Err(format!("Unable to determine language using tree-sitter parsers for file {}.\nCurrently configured tree-sitter paths: {:?}", 
-                tree_path.display(), grammars.get_configured_paths()));
-        }
+        let tree_path = source_location.get_repo_dir().join(&source_file_path);
+        let tree_path = tree_path.as_path();
+        debug!("Resolving language for {}", tree_path.display());
+        let lang = grammars.try_get_language(tree_path)?;
+
+        let (lang, language_name) = match lang {
+            Some(lang) => lang,
+            None => return Err(DiffGraphError::GrammarMissing {
+                path: tree_path.to_path_buf(),
+                parser_directories: grammars.get_configured_paths().iter().map(|p| p.to_string()).collect(),
+            }),
+        };
+        let parse = |source_code: &str, old_tree: Option<&Tree>| match try_reparse_source_code(lang, source_code.as_bytes(), old_tree)? {
+            Some(tree) => Ok(tree),
+            None => Err(DiffGraphError::Parse(format!("Unable to parse patch file: {}", patch_file.path()))),
+        };
+        let reparse = |previous_source: &str, previous_tree: &Tree, source_code: &str| {
+            if previous_source == source_code {
+                return Ok(previous_tree.clone());
+            }
+            let mut old_tree = previous_tree.clone();
+            old_tree.edit(&get_text_edit(previous_source.as_bytes(), source_code.as_bytes()));
+            parse(source_code, Some(&old_tree))
+        };
+        let previous = previous.filter(|previous| previous.language_name == language_name);
+
+        let tree = match previous {
+            Some(previous) => reparse(&previous.source, &previous.tree, &source)?,
+            None => parse(&source, None)?,
+        };
         let language = tree.language();
 
+        let mut target_source = try_apply_hunks(&source, patch_file, &applied)?;
+        let mut target_tree = match previous {
+            Some(previous) => reparse(&previous.target_source, &previous.target_tree, &target_source)?,
+            None => parse(&target_source, None)?,
+        };
+
+        let skipped_hunks = applied.iter().filter(|applied| !**applied).count();
+        let mut formatting_hunks = 0;
+        if ignore_formatting {
+            let formatting = find_formatting_hunks(patch_file, &applied, (&tree, &source), (&target_tree, &target_source));
+            formatting_hunks = formatting.iter().filter(|formatting| **formatting).count();
+            // Left out like hunks that do not apply, so neither their edits nor their rows are graphed
+            if formatting_hunks > 0 {
+                for (applied, formatting) in applied.iter_mut().zip(formatting) {
+                    *applied &= !formatting;
+                }
+                let patched = try_apply_hunks(&source, patch_file, &applied)?;
+                target_tree = reparse(&target_source, &target_tree, &patched)?;
+                target_source = patched;
+            }
+        }
+
+        let edits = try_get_edits(&source, patch_file, &applied)?;
+
+        let mut removed_rows = Vec::new();
+        let mut added_rows = Vec::new();
+        // Patched rows after a hunk that was left out move by the lines it would have added or removed
+        let mut target_shift: isize = 0;
+        for (hunk, applied) in patch_file.hunks().iter().zip(&applied) {
+            if !applied {
+                target_shift += hunk.target_length as isize - hunk.source_length as isize;
+                continue;
+            }
+            for line in hunk.lines() {
+                match line.line_type.as_str() {
+                    LINE_TYPE_REMOVED => removed_rows.extend(line.source_line_no.map(|n| n - 1)),
+                    LINE_TYPE_ADDED => added_rows.extend(line.target_line_no.map(|n| (n as isize - 1 - target_shift) as usize)),
+                    _ => {},
+                }
+            }
+        }
+
         Ok(Self {
             source,
             source_file,
@@ -205,65 +586,514 @@ impl Diff {
             edits,
             tree,
             language,
+            language_name,
+            target_source,
+            target_tree,
+            removed_rows,
+            added_rows,
+            hunk_count: patch_file.hunks().len(),
+            skipped_hunks,
+            formatting_hunks,
         })
     }
 
-    fn try_apply_edits(&mut self) -> Result<Tree, String> {
+    /// The innermost named node at the start of each added and removed line, added lines first.
+    /// `file` is the index of this diff in `DiffGraph::diffs`.
+    pub fn changed_nodes(&self, file: usize) -> Vec<ChangedNode> {
+        let mut nodes: Vec<ChangedNode> = Vec::new();
+        let changes = structural::diff_file(self);
+        let sides = [
+            (&self.target_tree, &self.target_source, &self.added_rows, true),
+            (&self.tree, &self.source, &self.removed_rows, false),
+        ];
+        for (tree, source, rows, patched) in sides {
+            let lines: Vec<&str> = source.lines().collect();
+            let changed_rows: HashSet<usize> = rows.iter().copied().collect();
+            let side_start = nodes.len();
+            // Doc comments are only looked up once a changed line is within a declaration
+            let mut docs = None;
+            for row in rows.iter() {
+                let point = syntax::get_line_start(*row, lines.get(*row).unwrap_or(&""));
+                let node = match tree.root_node().named_descendant_for_point_range(point, point) {
+                    Some(node) => node,
+                    None => continue,
+                };
+                if nodes[side_start..].iter().any(|changed| changed.node.id == node.id()) {
+                    continue;
+                }
+                let fully_changed = (node.start_position().row..=node.end_position().row).all(|row| changed_rows.contains(&row));
+                let status = match (fully_changed, patched) {
+                    (true, true) => ChangeStatus::Added,
+                    (true, false) => ChangeStatus::Removed,
+                    (false, _) => ChangeStatus::Modified,
+                };
+                let declaration = syntax::find_enclosing_declaration(tree, point).map(|declaration| {
+                    let name = syntax::get_declaration_name(&declaration, source);
+                    let row = declaration.start_position().row;
+                    let docs = docs.get_or_insert_with(|| docs::find_doc_comments(tree, source, self.language, &self.language_name));
+                    ChangedDeclaration {
+                        kind: declaration.kind().to_string(),
+                        change: structural::find_class(&changes, patched, declaration.kind(), row),
+                        name,
+                        row,
+                        doc: docs.get(&declaration.id()).cloned(),
+                    }
+                });
+
+                nodes.push(ChangedNode {
+                    node: NodeInfo::from_ts_node(file, &node),
+                    status,
+                    patched,
+                    declaration,
+                });
+            }
+        }
+
+        nodes
+    }
+
+    /// Writes the trees of the file before and after the patch as a DOT graph of two clusters,
+    /// with the changed nodes filled: green when added, red when removed and yellow otherwise.
+    pub fn write_trees_dot(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let escape = |text: &str| text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        let changed: HashMap<(bool, usize), ChangeStatus> = self.changed_nodes(0)
+            .into_iter()
+            .map(|changed| ((changed.patched, changed.node.id), changed.status))
+            .collect();
+
+        writeln!(writer, "digraph \"{}\" {{", escape(&self.source_file_path))?;
+        writeln!(writer, "  node [shape=box style=filled fillcolor=white fontname=monospace];")?;
+        for (prefix, label, tree, source, patched) in [("b", "before", &self.tree, &self.source, false), ("a", "after", &self.target_tree, &self.target_source, true)] {
+            writeln!(writer, "  subgraph cluster_{} {{", label)?;
+            writeln!(writer, "    label=\"{}\";", label)?;
+            // Unchanged subtrees are shared by both trees, so each cluster prefixes the ids of its nodes
+            let mut stack = vec![(tree.root_node(), None)];
+            while let Some((node, parent)) = stack.pop() {
+                let fill = match changed.get(&(patched, node.id())) {
+                    Some(ChangeStatus::Added) => "palegreen",
+                    Some(ChangeStatus::Removed) => "lightpink",
+                    Some(ChangeStatus::Modified) => "khaki",
+                    None => "white",
+                };
+                let mut text = node.kind().to_string();
+                if node.named_child_count() == 0 {
+                    let excerpt: String = source.get(node.byte_range()).unwrap_or_default().chars().take(MAX_DOT_EXCERPT_CHARS).collect();
+                    text = format!("{}\n{}", text, excerpt);
+                }
+                writeln!(writer, "    {}{} [label=\"{}\" fillcolor={} tooltip=\"lines {}-{}\"];", prefix, node.id(), escape(&text), fill,
+                    node.start_position().row + 1, node.end_position().row + 1)?;
+                if let Some(parent) = parent {
+                    writeln!(writer, "    {}{} -> {}{};", prefix, parent, prefix, node.id())?;
+                }
+                let mut cursor = node.walk();
+                let children: Vec<_> = node.named_children(&mut cursor).collect();
+                stack.extend(children.into_iter().rev().map(|child| (child, Some(node.id()))));
+            }
+            writeln!(writer, "  }}")?;
+        }
+        writeln!(writer, "}}")
+    }
+
+    /// Writes the DOT graph of the file's trees to `<dir>/<path of the file>.dot`, see `write_trees_dot`.
+    pub fn try_dump_trees(&self, dir: &Path) -> Result<(), DiffGraphError> {
+        let path = dir.join(format!("{}.dot", self.source_file_path));
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| DiffGraphError::io(format!("Unable to create '{}'", parent.display()), e))?;
+        }
+        let file = std::fs::File::create(&path)
+            .map_err(|e| DiffGraphError::io(format!("Unable to create '{}'", path.display()), e))?;
+        let mut writer = BufWriter::new(file);
+        self.write_trees_dot(&mut writer)
+            .and_then(|_| writer.flush())
+            .map_err(|e| DiffGraphError::io(format!("Unable to write '{}'", path.display()), e))?;
+        debug!("Dumped the trees of {} to {}", self.source_file_path, path.display());
+        Ok(())
+    }
+
+    /// The innermost declarations enclosing each added and removed line, see `changed_nodes`.
+    pub fn changed_declarations(&self) -> Vec<ChangedDeclaration> {
+        let mut declarations: Vec<ChangedDeclaration> = Vec::new();
+        for declaration in self.changed_nodes(0).into_iter().filter_map(|changed| changed.declaration) {
+            // Declarations edited on both sides are only identifiable by name
+            let seen = declarations.iter().any(|d| d.kind == declaration.kind && match (&d.name, &declaration.name) {
+                (Some(a), Some(b)) => a == b,
+                _ => d.row == declaration.row,
+            });
+            if !seen {
+                declarations.push(declaration);
+            }
+        }
+
+        declarations
+    }
+
+    /// A copy of the original tree with the patch's edits applied. The graph and changed nodes
+    /// only need the original and patched trees, so the copy is made for callers that ask for it.
+    pub fn edited_tree(&self) -> Tree {
         let mut tree = self.tree.clone();
         for edit in self.edits.iter() {
             tree.edit(edit);
         }
-        Ok(tree)
+        tree
     }
 }
 
-fn export_tree_to_dot(tree: &Option<Tree>) -> Result<(), String> {
-    if let Some(tree) = tree {
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-        let path_str = format!("./tree{}.dot", now.as_secs());
-        let file = std::fs::File::create(Path::new(&path_str)).map_err(|e| e.to_string())?;
-        tree.print_dot_graph(&file);
-        Ok(())
-    } else {
-        Err("No tree has been parsed yet".into())
+/// A patched file left out of the analysis on purpose, rather than because of a problem with it.
+#[derive(Debug, Clone)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: String,
+}
+
+/// A patched file left out of the analysis because it failed, so the rest of the patch could
+/// still be analyzed. Only collected without --strict.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileError {
+    pub path: String,
+    pub message: String,
+}
+
+/// What was noticed while selecting and parsing the files of the patch, besides the diffs.
+#[derive(Debug, Default)]
+pub struct ParseReport {
+    pub truncations: Vec<Truncation>,
+    pub findings: Vec<Finding>,
+    pub skipped: Vec<SkippedFile>,
+    pub errors: Vec<FileError>,
+}
+
+enum ParsedFile {
+    Parsed(Box<Diff>),
+    OverLimit(Truncation),
+}
+
+fn check_file_size(patch_file: &PatchedFile, source_location: &SourceLocation, max_bytes: Option<usize>) -> Option<Truncation> {
+    let max_bytes = max_bytes?;
+    if patch_file.source_file == "/dev/null" {
+        return None;
     }
+
+    let file = get_fs_file_path(&patch_file.source_file);
+    match source_location.try_get_size(file) {
+        Some(size) if size > max_bytes => Some(Truncation::Bytes { file: file.to_string(), size, limit: max_bytes }),
+        _ => None,
+    }
+}
+
+pub fn try_parse_source_code(language: Language, source_code: &str) -> Result<Option<Tree>, DiffGraphError> {
+    try_reparse_source_code(language, source_code.as_bytes(), None)
+}
+
+/// Position of a byte in the text, its column counted in bytes as tree-sitter does.
+fn get_point_at(text: &[u8], byte: usize) -> Point {
+    let before = &text[..byte];
+    let row = before.iter().filter(|b| **b == b'\n').count();
+    let column = byte - before.iter().rposition(|b| *b == b'\n').map_or(0, |newline| newline + 1);
+    Point { row, column }
 }
 
-pub fn try_parse_source_code(language: Language, source_code: &str) -> Result<Option<Tree>, String> {
+/// A single edit turning `old` into `new`, replacing everything between their common prefix and suffix.
+pub fn get_text_edit(old: &[u8], new: &[u8]) -> InputEdit {
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = old.iter().rev().zip(new.iter().rev())
+        .take(old.len().min(new.len()) - prefix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    InputEdit {
+        start_byte: prefix,
+        old_end_byte: old.len() - suffix,
+        new_end_byte: new.len() - suffix,
+        start_position: get_point_at(old, prefix),
+        old_end_position: get_point_at(old, old.len() - suffix),
+        new_end_position: get_point_at(new, new.len() - suffix),
+    }
+}
+
+thread_local! {
+    /// Parsers already set up for each language, one set per thread as parsers cannot be shared,
+    /// so files parsed on the same rayon worker reuse them.
+    static PARSERS: RefCell<HashMap<Language, Parser>> = RefCell::new(HashMap::new());
+}
+
+fn try_create_parser(language: Language) -> Result<Parser, DiffGraphError> {
     let mut parser = Parser::new();
-    parser.set_language(language).map_err(|e| e.to_string())?;
+    parser.set_language(language).map_err(|e| DiffGraphError::Grammar(e.to_string()))?;
 
     let timeout_micros = 1_000_000;
     parser.set_timeout_micros(timeout_micros);
+    // Safe as the flag is static, so it outlives the parser
+    unsafe { parser.set_cancellation_flag(Some(cancel::flag())) };
+    Ok(parser)
+}
 
-    let tree = parser.parse(source_code, None);
+/// Parses the source code, reusing the unchanged parts of `old_tree` when given, which must
+/// already be edited to match the source code.
+pub fn try_reparse_source_code(language: Language, source_code: &[u8], old_tree: Option<&Tree>) -> Result<Option<Tree>, DiffGraphError> {
+    // Taken out of the pool while parsing, so a parse nested in the callback gets its own parser
+    let pooled = PARSERS.with(|parsers| parsers.borrow_mut().remove(&language));
+    let mut parser = match pooled {
+        Some(parser) => {
+            stats::hit(stats::Cache::Parsers);
+            parser
+        },
+        None => {
+            stats::miss(stats::Cache::Parsers);
+            try_create_parser(language)?
+        },
+    };
+
+    // The whole rest of the source is handed over at once, tree-sitter reads it without a copy
+    let tree = parser.parse_with(&mut |byte, _| source_code.get(byte..).unwrap_or_default(), old_tree);
+    if tree.is_none() {
+        // A parse that timed out or was cancelled would otherwise be resumed by the next one
+        parser.reset();
+    }
+    PARSERS.with(|parsers| parsers.borrow_mut().insert(language, parser));
+    cancel::try_check()?;
 
     Ok(tree)
 }
 
-pub fn try_parse_patch(
-    patch: &PatchSet, 
-    parser_config_path: Option<PathBuf>, 
-    save_default_if_missing: bool, 
-    install_lang_if_missing: bool
-) -> Result<Vec<Diff>, String> {
+/// Loads the grammars, installing and compiling missing languages first when asked to.
+pub fn try_load_grammars(params: &DiffGraphParams) -> Result<Grammars, DiffGraphError> {
+    let grammars = timings::phase("grammar load", || Grammars::load(params.parser_config_path.clone(), params.save_default_if_missing))?;
+    if params.install_lang_if_missing {
+        info!("Checking missing languages...");
+        timings::phase("grammar install", || grammars.try_install_languages())?;
+        timings::phase("grammar compile", || grammars.try_compile_languages(params.grammar_cache_dir.as_deref()))?;
+    }
+    Ok(grammars)
+}
 
-    let grammars = Grammars::load(parser_config_path, save_default_if_missing).map_err(|e| e.to_string())?;
-    if install_lang_if_missing {
-        println!("Checking missing languages...");
-        grammars.try_install_languages()?;
+/// Files of the patch to parse, in patch order, leaving out those in languages that were not
+/// selected and those past `--max-files`.
+pub fn try_select_patch_files<'p>(
+    params: &'p DiffGraphParams,
+    grammars: &Grammars,
+    source_location: &SourceLocation,
+    report: &mut ParseReport,
+) -> Result<Vec<&'p PatchedFile>, DiffGraphError> {
+    let limits = &params.limits;
+    let mut patch_files = params.get_patch_files();
+    if !params.languages.is_empty() {
+        let repo_dir = source_location.get_repo_dir();
+        patch_files.retain(|patch_file| {
+            let path = get_patch_file_path(patch_file);
+            let language = grammars.find_language_name(&repo_dir.join(path));
+            let selected = language.as_ref().is_some_and(|language| params.languages.iter().any(|l| l.eq_ignore_ascii_case(language)));
+            if !selected {
+                report.skipped.push(SkippedFile {
+                    path: path.to_string(),
+                    reason: format!("language {} not selected", language.as_deref().unwrap_or("unknown")),
+                });
+            }
+            selected
+        });
+    }
+    if let Some(max_files) = limits.max_files {
+        if patch_files.len() > max_files {
+            let truncation = Truncation::Files { kept: max_files, total: patch_files.len() };
+            if limits.action == LimitAction::Fail {
+                return Err(DiffGraphError::LimitExceeded(truncation));
+            }
+            report.truncations.push(truncation);
+            patch_files.truncate(max_files);
+        }
+    }
+    Ok(patch_files)
+}
+
+fn parse_file(patch_file: &PatchedFile, grammars: &Grammars, source_location: &SourceLocation, params: &DiffGraphParams, previous: Option<&Diff>) -> Result<ParsedFile, DiffGraphError> {
+    cancel::try_check()?;
+    match check_file_size(patch_file, source_location, params.limits.max_bytes) {
+        Some(truncation) => Ok(ParsedFile::OverLimit(truncation)),
+        None => timings::file(&patch_file.path(), || Diff::from_patch_file(patch_file, grammars, source_location, previous, params.best_effort, params.ignore_formatting))
+            .map(|diff| ParsedFile::Parsed(Box::new(diff))),
     }
+}
+
+/// Whether the error means the patch does not fit the file, rather than the run being unable to go on.
+fn is_inapplicable(error: &DiffGraphError) -> bool {
+    match error {
+        DiffGraphError::Patch(_) | DiffGraphError::Git(_) => true,
+        DiffGraphError::Io { source, .. } => source.kind() == std::io::ErrorKind::NotFound,
+        _ => false,
+    }
+}
+
+/// Records what was found while parsing the file, returning its diff unless it was left out.
+fn accept_parsed_file(
+    patch_file: &PatchedFile,
+    parsed_file: Result<ParsedFile, DiffGraphError>,
+    params: &DiffGraphParams,
+    report: &mut ParseReport,
+    visitor: &mut dyn GraphVisitor,
+) -> Result<Option<Diff>, DiffGraphError> {
+    let limits = &params.limits;
+    let parsed_file = match parsed_file {
+        Ok(parsed_file) => parsed_file,
+        Err(e @ DiffGraphError::GrammarMissing { .. }) => {
+            visitor.on_file_error(get_patch_file_path(patch_file), &e);
+            report.findings.push(Finding::new(Condition::UnknownLanguage, e.to_string()));
+            return Ok(None);
+        },
+        Err(e) if params.best_effort && is_inapplicable(&e) => {
+            visitor.on_file_error(get_patch_file_path(patch_file), &e);
+            report.findings.push(Finding::new(Condition::Degraded, format!("{} was left out: {}", get_patch_file_path(patch_file), e)));
+            return Ok(None);
+        },
+        Err(e) if !params.strict && !matches!(e, DiffGraphError::Cancelled(_)) => {
+            let path = get_patch_file_path(patch_file);
+            visitor.on_file_error(path, &e);
+            report.findings.push(Finding::new(Condition::FileErrors, format!("{} was left out: {}", path, e)));
+            report.errors.push(FileError { path: path.to_string(), message: e.to_string() });
+            return Ok(None);
+        },
+        Err(e) => return Err(e),
+    };
+    match parsed_file {
+        ParsedFile::Parsed(diff) => {
+            if diff.target_tree.root_node().has_error() {
+                report.findings.push(Finding::new(Condition::ParseErrors, 
+                    format!("{} contains syntax errors after applying the patch", diff.source_file_path)));
+            }
+            for change in api::find_breaking_changes(&diff) {
+                report.findings.push(Finding::new(Condition::BreakingChanges, change.to_string()));
+            }
+            if diff.skipped_hunks > 0 {
+                report.findings.push(Finding::new(Condition::Degraded, format!("{} of {} hunks of {} do not apply and were left out",
+                    diff.skipped_hunks, diff.hunk_count, diff.source_file_path)));
+            }
+            if diff.formatting_hunks > 0 {
+                debug!("{} of {} hunks of {} only change formatting and were left out", diff.formatting_hunks, diff.hunk_count, diff.source_file_path);
+            }
+            if tracing::enabled!(Level::INFO) {
+                info!("{}", FileSummary::from_diff(&diff).to_line(Stream::Stderr));
+            }
+            Ok(Some(*diff))
+        },
+        ParsedFile::OverLimit(truncation) => {
+            if limits.action == LimitAction::Fail {
+                return Err(DiffGraphError::LimitExceeded(truncation));
+            }
+            report.truncations.push(truncation);
+            Ok(None)
+        },
+    }
+}
+
+/// Parses one file of the patch on the calling thread, for callers that handle files one at a
+/// time rather than holding every diff at once. None when the file was left out of the analysis.
+pub fn try_parse_patch_file(
+    patch_file: &PatchedFile,
+    grammars: &Grammars,
+    source_location: &SourceLocation,
+    params: &DiffGraphParams,
+    report: &mut ParseReport,
+    visitor: &mut dyn GraphVisitor,
+) -> Result<Option<Diff>, DiffGraphError> {
+    let parsed_file = parse_file(patch_file, grammars, source_location, params, None);
+    accept_parsed_file(patch_file, parsed_file, params, report, visitor)
+}
+
+/// Parses every selected file of the patch in parallel. Diffs of an earlier version of the patch,
+/// keyed by path, have their trees reused for the same files, see `Diff::from_patch_file`.
+/// Files that fail are collected into the report's errors and left out, unless `params.strict` is set.
+pub fn try_parse_patch(
+    params: &DiffGraphParams,
+    source_location: &SourceLocation,
+    previous: &HashMap<String, Diff>,
+    report: &mut ParseReport,
+    visitor: &mut dyn GraphVisitor,
+) -> Result<Vec<Diff>, DiffGraphError> {
 
+    let grammars = try_load_grammars(params)?;
+    let patch_files = try_select_patch_files(params, &grammars, source_location, report)?;
+
+    let bar = progress::bar(patch_files.len() as u64, "Parsing");
+    let parsed: Vec<Result<ParsedFile, DiffGraphError>> = timings::phase("parse", || patch_files
+        .par_iter()
+        .map(|patch_file| {
+            let parsed = parse_file(patch_file, &grammars, source_location, params, previous.get(get_patch_file_path(patch_file)));
+            bar.inc(1);
+            parsed
+        })
+        .collect());
+    bar.finish_and_clear();
+
+    // With --strict, report the first failure in patch order, regardless of which thread finished first
     let mut diffs = Vec::new();
-    for patch_file in patch.files() {
-        match Diff::from_patch_file(patch_file, &grammars) {
-            Ok(mut diff) => {
-                let _diff_tree = diff.try_apply_edits()?;
-                diffs.push(diff);
-            },
-            Err(e) => return Err(e),
+    for (patch_file, parsed_file) in patch_files.iter().zip(parsed) {
+        if let Some(diff) = accept_parsed_file(patch_file, parsed_file, params, report, visitor)? {
+            diffs.push(diff);
         }
     }
 
     Ok(diffs)
 }
+
+#[cfg(test)]
+mod tests {
+    use unidiff::PatchSet;
+
+    use super::*;
+
+    fn parse_patch(diff: &str) -> PatchSet {
+        let mut patch = PatchSet::new();
+        patch.parse(diff).unwrap();
+        patch
+    }
+
+    #[test]
+    fn hunk_starting_with_a_removed_line_edits_the_first_row() {
+        let source = "fn a() {}\nfn b() {}\n";
+        let patch = parse_patch("--- a/lib.rs\n+++ b/lib.rs\n@@ -1,2 +1,2 @@\n-fn a() {}\n+fn c() {}\n fn b() {}\n");
+        let edits = try_get_edits(source, &patch[0], &[true]).unwrap();
+
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].start_byte, 0);
+        assert_eq!(edits[0].old_end_byte, "fn a() {}".len());
+        assert_eq!(edits[0].start_position, Point { row: 0, column: 0 });
+        assert_eq!(edits[0].new_end_position, Point { row: 0, column: "fn a() {}".len() });
+    }
+
+    #[test]
+    fn removed_line_past_the_end_of_the_source_is_an_error() {
+        let source = "fn a() {}\n";
+        let patch = parse_patch("--- a/lib.rs\n+++ b/lib.rs\n@@ -3,1 +3,0 @@\n-fn b() {}\n");
+
+        assert!(matches!(try_get_edits(source, &patch[0], &[true]), Err(DiffGraphError::Patch(_))));
+    }
+
+    #[test]
+    fn added_lines_are_inserted_before_the_next_source_row() {
+        let source = "fn a() {}\nfn b() {}\n";
+        let patch = parse_patch("--- a/lib.rs\n+++ b/lib.rs\n@@ -1,2 +1,3 @@\n fn a() {}\n+fn c() {}\n fn b() {}\n");
+        let edits = try_get_edits(source, &patch[0], &[true]).unwrap();
+
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].start_byte, "fn a() {}\n".len());
+        assert_eq!(edits[0].old_end_byte, edits[0].start_byte);
+        assert_eq!(edits[0].start_position, Point { row: 1, column: 0 });
+        assert_eq!(edits[0].new_end_position, Point { row: 1, column: "fn c() {}".len() });
+    }
+
+    #[test]
+    fn new_file_is_inserted_at_the_start() {
+        let patch = parse_patch("--- /dev/null\n+++ b/lib.rs\n@@ -0,0 +1,2 @@\n+fn a() {}\n+fn b() {}\n");
+        let edits = try_get_edits("", &patch[0], &[true]).unwrap();
+
+        assert_eq!(edits.len(), 2);
+        assert!(edits.iter().all(|edit| edit.start_byte == 0 && edit.old_end_byte == 0));
+        assert_eq!(edits[1].new_end_position, Point { row: 1, column: "fn b() {}".len() });
+    }
+
+    #[test]
+    fn hunks_that_do_not_apply_are_not_edited() {
+        let source = "fn a() {}\n";
+        let patch = parse_patch("--- a/lib.rs\n+++ b/lib.rs\n@@ -1,1 +1,1 @@\n-fn a() {}\n+fn b() {}\n");
+
+        assert!(try_get_edits(source, &patch[0], &[false]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,85 @@
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+use regex::Regex;
+use tracing::{debug, warn};
+use unidiff::PatchSet;
+
+use crate::error::DiffGraphError;
+use crate::repair;
+use crate::unified::{self, DiffFormat};
+
+/// A diff read from the command line or handed over, along with what `PatchSet` does not keep
+#[derive(Debug)]
+pub struct ParsedDiff {
+    pub patch: PatchSet,
+    /// Revision the diff applies to, or None when it applies to the working tree
+    pub source_revision: Option<String>,
+    /// Git directory the revision is read from, see `DiffGraphParams::git_dir`
+    pub git_dir: Option<PathBuf>,
+    pub binary_files: Vec<String>,
+}
+
+fn try_create_patch_set(diff: &str) -> Result<PatchSet, DiffGraphError> {
+    let mut patch = PatchSet::new();
+    match patch.parse(diff) {
+        Ok(_) => Ok(patch),
+        Err(e) => {
+            let line = match &e {
+                unidiff::Error::TargetWithoutSource(line) | unidiff::Error::UnexpectedHunk(line) | unidiff::Error::ExpectLine(line) => line,
+            };
+            match diff.split('\n').position(|l| l == line) {
+                Some(line_no) => Err(DiffGraphError::Patch(format!("line {}: {}", line_no + 1, e))),
+                None => Err(DiffGraphError::Patch(e.to_string())),
+            }
+        },
+    }
+}
+
+/// Repairs the diff before it is parsed, see `repair::try_repair`, noting each repair.
+pub fn try_repair_diff(diff: &str) -> Result<String, DiffGraphError> {
+    // Other tools' headers are rewritten as git's first, so the rest only deals with those
+    let format = DiffFormat::detect(diff);
+    let diff = match format {
+        DiffFormat::Git => Cow::Borrowed(diff),
+        format => {
+            debug!("Reading a {} diff", format);
+            Cow::Owned(unified::to_git_headers(diff, format))
+        },
+    };
+    let repaired = repair::try_repair(&diff)?;
+    for repair in repaired.repairs.iter() {
+        warn!("Repaired the diff at {}", repair);
+    }
+    Ok(repaired.text)
+}
+
+/// Binary files only appear in git diffs as a marker line, which unidiff skips
+fn find_binary_files(diff: &str) -> Vec<String> {
+    let re = Regex::new(r"(?m)^Binary files (?:a/)?(.+?) and (?:b/)?(.+?) differ$").unwrap();
+    re.captures_iter(diff)
+        .map(|captures| {
+            let target = captures.get(2).unwrap().as_str();
+            if target == "/dev/null" {
+                captures.get(1).unwrap().as_str().to_string()
+            } else {
+                target.to_string()
+            }
+        })
+        .collect()
+}
+
+/// Repairs and parses the text of a diff, which applies to the revision or the working tree when None.
+pub fn try_create_parsed_diff(diff: &str, source_revision: Option<String>) -> Result<ParsedDiff, DiffGraphError> {
+    try_create_repaired_parsed_diff(&try_repair_diff(diff)?, source_revision)
+}
+
+/// Parses a diff already repaired with `try_repair_diff`.
+pub fn try_create_repaired_parsed_diff(diff: &str, source_revision: Option<String>) -> Result<ParsedDiff, DiffGraphError> {
+    Ok(ParsedDiff {
+        patch: try_create_patch_set(diff)?,
+        source_revision,
+        git_dir: None,
+        binary_files: find_binary_files(diff),
+    })
+}
@@ -0,0 +1,133 @@
+use regex::Regex;
+
+use crate::error::DiffGraphError;
+
+/// Prefixes marking a pattern as an exclusion, as in git pathspecs
+const EXCLUDE_PREFIXES: [&str; 3] = [":(exclude)", ":!", "!"];
+
+/// Include and exclude patterns selecting patched files, following git pathspecs: wildcards
+/// match across directories as in git's default pathspec magic, `**/` matches any leading
+/// directories, and a pattern also matches everything beneath the directory it names.
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.trim_start_matches("./").chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            },
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                regex.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            },
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    let regex = regex.trim_end_matches('/').to_string();
+    regex + "(?:/.*)?$"
+}
+
+impl PathFilter {
+    pub fn try_new(patterns: &[String]) -> Result<Self, DiffGraphError> {
+        let mut filter = Self::default();
+        for pattern in patterns.iter() {
+            let excluded = EXCLUDE_PREFIXES.iter().find_map(|prefix| pattern.strip_prefix(prefix));
+            let glob = excluded.unwrap_or(pattern);
+            let regex = Regex::new(&glob_to_regex(glob))
+                .map_err(|e| DiffGraphError::Usage(format!("Invalid path pattern '{}': {}", pattern, e)))?;
+            match excluded {
+                Some(_) => filter.exclude.push(regex),
+                None => filter.include.push(regex),
+            }
+        }
+        Ok(filter)
+    }
+
+    /// Whether the path is selected. Without include patterns everything not excluded is selected.
+    pub fn matches(&self, path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|r| r.is_match(path)))
+            && !self.exclude.iter().any(|r| r.is_match(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(patterns: &[&str]) -> PathFilter {
+        PathFilter::try_new(&patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn everything_matches_without_patterns() {
+        assert!(filter(&[]).matches("src/lib.rs"));
+    }
+
+    #[test]
+    fn wildcards_match_across_directories() {
+        let filter = filter(&["*.rs"]);
+        assert!(filter.matches("lib.rs"));
+        assert!(filter.matches("src/lib.rs"));
+        assert!(!filter.matches("src/lib.py"));
+    }
+
+    #[test]
+    fn double_star_matches_any_leading_directories() {
+        let filter = filter(&["**/tests/*.rs"]);
+        assert!(filter.matches("tests/a.rs"));
+        assert!(filter.matches("crates/x/tests/a.rs"));
+        assert!(!filter.matches("crates/x/src/a.rs"));
+    }
+
+    #[test]
+    fn directories_match_what_is_beneath_them() {
+        let filter = filter(&["./src/"]);
+        assert!(filter.matches("src/graph/mod.rs"));
+        assert!(!filter.matches("srcs/lib.rs"));
+    }
+
+    #[test]
+    fn exclusions_win_over_inclusions() {
+        for exclusion in [":(exclude)src/gen", ":!src/gen", "!src/gen"] {
+            let filter = filter(&["src", exclusion]);
+            assert!(filter.matches("src/lib.rs"));
+            assert!(!filter.matches("src/gen/parser.rs"), "{}", exclusion);
+        }
+    }
+
+    #[test]
+    fn character_classes_match_one_character() {
+        let filter = filter(&["v[0-9].txt", "[!a]b"]);
+        assert!(filter.matches("v1.txt"));
+        assert!(!filter.matches("vx.txt"));
+        assert!(filter.matches("cb"));
+        assert!(!filter.matches("ab"));
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+use std::str::FromStr;
+use tracing::{debug, warn};
+
+use crate::error::{DiffGraphError, ErrorCode};
+
+/// Conditions found while building a graph that CI may want to fail on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    /// A patched file parsed with ERROR or MISSING nodes
+    ParseErrors,
+    /// No grammar is available for a patched file, so it was skipped
+    UnknownLanguage,
+    /// The diff contains binary files, which are never analyzed
+    BinaryFiles,
+    /// The graph is partial because a --max-* limit was hit
+    Truncated,
+    /// Hunks or whole files that do not apply were left out with --best-effort
+    Degraded,
+    /// A patched file could not be read, applied or parsed, so it was left out unless --strict
+    FileErrors,
+    /// A public declaration was removed, made private or had its signature changed
+    BreakingChanges,
+    /// A changed node matched a security-sensitive pattern, such as an exec call or SQL built from strings
+    SecurityPatterns,
+    /// A changed node added a TODO or FIXME comment, a panic or a similar pattern of debt
+    DebtPatterns,
+    /// The patch added an import or a use that the architecture rules forbid
+    ArchitectureViolations,
+}
+
+impl Condition {
+    pub const ALL: [Condition; 10] = [
+        Condition::ParseErrors, 
+        Condition::UnknownLanguage, 
+        Condition::BinaryFiles, 
+        Condition::Truncated,
+        Condition::Degraded,
+        Condition::FileErrors,
+        Condition::BreakingChanges,
+        Condition::SecurityPatterns,
+        Condition::DebtPatterns,
+        Condition::ArchitectureViolations,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Condition::ParseErrors => "parse-errors",
+            Condition::UnknownLanguage => "unknown-language",
+            Condition::BinaryFiles => "binary-files",
+            Condition::Truncated => "truncated",
+            Condition::Degraded => "degraded",
+            Condition::FileErrors => "file-errors",
+            Condition::BreakingChanges => "breaking-changes",
+            Condition::SecurityPatterns => "security-patterns",
+            Condition::DebtPatterns => "debt-patterns",
+            Condition::ArchitectureViolations => "architecture-violations",
+        }
+    }
+
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            Condition::ParseErrors => ErrorCode::Parse,
+            Condition::UnknownLanguage => ErrorCode::GrammarMissing,
+            Condition::BinaryFiles => ErrorCode::Patch,
+            Condition::Truncated => ErrorCode::LimitExceeded,
+            Condition::Degraded => ErrorCode::Patch,
+            Condition::FileErrors => ErrorCode::Parse,
+            Condition::BreakingChanges => ErrorCode::BreakingChange,
+            Condition::SecurityPatterns => ErrorCode::SecurityPattern,
+            Condition::DebtPatterns => ErrorCode::DebtPattern,
+            Condition::ArchitectureViolations => ErrorCode::ArchitectureViolation,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Condition::ALL
+            .iter()
+            .find(|c| c.as_str() == s)
+            .copied()
+            .ok_or_else(|| format!("Unknown condition '{}', expected one of: {}", s, 
+                Condition::ALL.map(|c| c.as_str()).join(", ")))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub condition: Condition,
+    pub message: String,
+}
+
+impl Finding {
+    pub fn new(condition: Condition, message: impl Into<String>) -> Self {
+        Self {
+            condition,
+            message: message.into(),
+        }
+    }
+}
+
+/// Which conditions fail the run and which are only reported. Conditions in neither list are
+/// logged at debug level, and failing takes precedence when a condition is in both.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    pub fail_on: Vec<Condition>,
+    pub warn_on: Vec<Condition>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            fail_on: vec![Condition::UnknownLanguage],
+            warn_on: Condition::ALL.to_vec(),
+        }
+    }
+}
+
+impl Policy {
+    /// Reports every finding, failing with the first one whose condition is in `fail_on`.
+    pub fn evaluate(&self, findings: &[Finding]) -> Result<(), DiffGraphError> {
+        let mut failure = None;
+        for finding in findings {
+            if self.fail_on.contains(&finding.condition) {
+                if failure.is_none() {
+                    failure = Some(finding);
+                }
+            } else if self.warn_on.contains(&finding.condition) {
+                warn!("[{}] {}", finding.condition, finding.message);
+            } else {
+                debug!("[{}] {}", finding.condition, finding.message);
+            }
+        }
+
+        match failure {
+            Some(finding) => Err(DiffGraphError::Policy(finding.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditions_parse_from_their_names() {
+        for condition in Condition::ALL {
+            assert_eq!(condition.as_str().parse::<Condition>(), Ok(condition));
+        }
+        assert!("everything".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn default_policy_fails_only_on_unknown_languages() {
+        let policy = Policy::default();
+        assert!(policy.evaluate(&[Finding::new(Condition::ParseErrors, "x.rs")]).is_ok());
+        assert!(policy.evaluate(&[Finding::new(Condition::UnknownLanguage, "x.zig")]).is_err());
+    }
+
+    #[test]
+    fn fails_with_the_first_failing_finding() {
+        let policy = Policy { fail_on: vec![Condition::Truncated, Condition::BinaryFiles], warn_on: Vec::new() };
+        let findings = [
+            Finding::new(Condition::ParseErrors, "reported"),
+            Finding::new(Condition::BinaryFiles, "first"),
+            Finding::new(Condition::Truncated, "second"),
+        ];
+        let Err(DiffGraphError::Policy(finding)) = policy.evaluate(&findings) else {
+            panic!("expected a policy failure");
+        };
+        assert_eq!(finding.condition, Condition::BinaryFiles);
+        assert_eq!(finding.message, "first");
+    }
+
+    #[test]
+    fn no_findings_pass() {
+        let policy = Policy { fail_on: Condition::ALL.to_vec(), warn_on: Vec::new() };
+        assert!(policy.evaluate(&[]).is_ok());
+    }
+}
@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "cli")]
+use std::time::Duration;
+#[cfg(feature = "cli")]
+use indicatif::{ProgressBar, ProgressStyle};
+
+static PROGRESS_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Stands in for indicatif's bar when the library is built without the `cli` feature, drawing nothing.
+#[cfg(not(feature = "cli"))]
+#[derive(Debug, Default)]
+pub struct ProgressBar;
+
+#[cfg(not(feature = "cli"))]
+impl ProgressBar {
+    pub fn inc(&self, _delta: u64) {}
+
+    pub fn set_message(&self, _message: impl Into<String>) {}
+
+    pub fn finish_and_clear(&self) {}
+}
+
+pub fn set_enabled(enabled: bool) {
+    PROGRESS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+#[cfg(feature = "cli")]
+fn is_enabled() -> bool {
+    PROGRESS_ENABLED.load(Ordering::Relaxed)
+}
+
+/// A progress bar over `len` steps, drawn to stderr. Hidden when progress is disabled.
+#[cfg(feature = "cli")]
+pub fn bar(len: u64, message: &str) -> ProgressBar {
+    if !is_enabled() {
+        return ProgressBar::hidden();
+    }
+
+    let bar = ProgressBar::new(len);
+    if let Ok(style) = ProgressStyle::with_template("{msg:>12} [{bar:40}] {pos}/{len} {wide_msg}") {
+        bar.set_style(style.progress_chars("=> "));
+    }
+    bar.set_message(message.to_string());
+    bar
+}
+
+#[cfg(not(feature = "cli"))]
+pub fn bar(_len: u64, _message: &str) -> ProgressBar {
+    ProgressBar
+}
+
+/// A spinner for phases of unknown length, such as cloning a repository.
+#[cfg(feature = "cli")]
+pub fn spinner(message: &str) -> ProgressBar {
+    if !is_enabled() {
+        return ProgressBar::hidden();
+    }
+
+    let spinner = ProgressBar::new_spinner();
+    if let Ok(style) = ProgressStyle::with_template("{spinner} {msg} ({elapsed})") {
+        spinner.set_style(style);
+    }
+    spinner.set_message(message.to_string());
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
@@ -0,0 +1,195 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use serde::Serialize;
+use tree_sitter::Node as TSNode;
+
+use crate::parser::Diff;
+use crate::structural;
+
+/// A declaration the patch renames without otherwise changing it, and the changed lines that do
+/// nothing but follow the rename.
+#[derive(Debug, Clone, Serialize)]
+pub struct Rename {
+    pub kind: String,
+    pub from: String,
+    pub to: String,
+    /// File of the declaration
+    pub file: String,
+    /// First line of the declaration in the patched file, counted from 1
+    pub line: usize,
+    /// Added lines that are removed lines with the name replaced, the declaration's own included
+    pub sites: usize,
+    /// Files the sites are in
+    pub files: BTreeSet<String>,
+    /// References to the old name left in the patched files, none when the rename is consistent
+    pub remaining: usize,
+}
+
+impl Rename {
+    pub fn is_consistent(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+/// Such as "rename `foo`→`bar` (17 sites)".
+impl fmt::Display for Rename {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rename `{}`→`{}` ({})", self.from, self.to,
+            if self.sites == 1 { "1 site".to_string() } else { format!("{} sites", self.sites) })
+    }
+}
+
+/// The leaves of the subtree without its comments, the names in `from` replaced by `to`.
+fn get_tokens<'s>(node: &TSNode, source: &'s str, from: &str, to: &'s str) -> Vec<&'s str> {
+    let mut tokens = Vec::new();
+    let mut cursor = node.walk();
+    'walk: loop {
+        let current = cursor.node();
+        let comment = current.kind().contains("comment");
+        if !comment && current.child_count() == 0 {
+            let text = source.get(current.byte_range()).unwrap_or_default();
+            tokens.push(if text == from { to } else { text });
+        } else if !comment && cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+    tokens
+}
+
+/// Declarations the patch removes from the file and adds back under another name, in the same
+/// place and identical up to the name.
+fn find_renamed_declarations(diff: &Diff) -> Vec<Rename> {
+    let old = structural::index_declarations(&diff.tree, &diff.source);
+    let new = structural::index_declarations(&diff.target_tree, &diff.target_source);
+    let added: Vec<_> = new.iter().filter(|(key, _)| !old.contains_key(*key)).collect();
+
+    let mut renames: Vec<Rename> = Vec::new();
+    for ((kind, path), node) in old.iter().filter(|(key, _)| !new.contains_key(*key)) {
+        let Some((Some(from), parents)) = path.split_last() else {
+            continue;
+        };
+        let renamed = added.iter().find_map(|((new_kind, new_path), new_node)| {
+            let (Some(to), new_parents) = new_path.split_last()? else {
+                return None;
+            };
+            let same = new_kind == kind && new_parents == parents
+                && get_tokens(node, &diff.source, from, to) == get_tokens(new_node, &diff.target_source, to, to);
+            same.then_some((to, new_node))
+        });
+        // A name given to two declarations at once is not a rename
+        let Some((to, new_node)) = renamed.filter(|(to, _)| !renames.iter().any(|rename| rename.to == **to)) else {
+            continue;
+        };
+        renames.push(Rename {
+            kind: kind.clone(),
+            from: from.clone(),
+            to: to.clone(),
+            file: diff.source_file_path.clone(),
+            line: new_node.start_position().row + 1,
+            sites: 0,
+            files: BTreeSet::new(),
+            remaining: 0,
+        });
+    }
+    renames.sort_by_key(|rename| rename.line);
+    renames
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The line with every whole word `from` of the renames replaced by its `to`.
+fn apply_renames(line: &str, renames: &HashMap<&str, &str>) -> String {
+    let mut renamed = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find(is_identifier_char) {
+        let end = rest[start..].find(|c: char| !is_identifier_char(c)).map_or(rest.len(), |end| start + end);
+        renamed.push_str(&rest[..start]);
+        let word = &rest[start..end];
+        renamed.push_str(renames.get(word).copied().unwrap_or(word));
+        rest = &rest[end..];
+    }
+    renamed.push_str(rest);
+    renamed
+}
+
+fn count_identifiers(diff: &Diff, name: &str) -> usize {
+    let mut count = 0;
+    let mut cursor = diff.target_tree.walk();
+    'walk: loop {
+        let node = cursor.node();
+        if node.child_count() == 0 && node.kind().ends_with("identifier") && diff.target_source.get(node.byte_range()) == Some(name) {
+            count += 1;
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+    count
+}
+
+/// Declarations the patch renames, and the changed lines across the files that only follow the
+/// renames: an added line is a site when it is a removed line of the same file with the names
+/// replaced. Names are matched as words, so a same-named declaration elsewhere is taken for the
+/// renamed one.
+pub fn find_renames(diffs: &[Diff]) -> Vec<Rename> {
+    let mut renames: Vec<Rename> = diffs.iter().flat_map(find_renamed_declarations).collect();
+    if renames.is_empty() {
+        return renames;
+    }
+
+    let names: HashMap<&str, &str> = renames.iter().map(|rename| (rename.from.as_str(), rename.to.as_str())).collect();
+    let mut sites: HashMap<String, (usize, BTreeSet<String>)> = HashMap::new();
+    for diff in diffs.iter() {
+        let source_lines: Vec<&str> = diff.source.lines().collect();
+        let target_lines: Vec<&str> = diff.target_source.lines().collect();
+        // Removed lines as they would read once renamed, each matching one added line
+        let mut removed: HashMap<String, usize> = HashMap::new();
+        for row in diff.removed_rows.iter() {
+            let Some(line) = source_lines.get(*row) else {
+                continue;
+            };
+            let renamed = apply_renames(line.trim(), &names);
+            if renamed != line.trim() {
+                *removed.entry(renamed).or_default() += 1;
+            }
+        }
+        for row in diff.added_rows.iter() {
+            let Some(line) = target_lines.get(*row).map(|line| line.trim()) else {
+                continue;
+            };
+            let Some(count) = removed.get_mut(line).filter(|count| **count > 0) else {
+                continue;
+            };
+            *count -= 1;
+            for word in line.split(|c: char| !is_identifier_char(c)).collect::<BTreeSet<_>>() {
+                if names.values().any(|to| *to == word) {
+                    let (count, files) = sites.entry(word.to_string()).or_default();
+                    *count += 1;
+                    files.insert(diff.source_file_path.clone());
+                }
+            }
+        }
+    }
+
+    for rename in renames.iter_mut() {
+        if let Some((count, files)) = sites.remove(&rename.to) {
+            rename.sites = count;
+            rename.files = files;
+        }
+        rename.remaining = diffs.iter().map(|diff| count_identifiers(diff, &rename.from)).sum();
+    }
+    renames
+}
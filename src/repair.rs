@@ -0,0 +1,219 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::error::DiffGraphError;
+
+/// A unified diff after the repair pass, with what was changed to get there.
+#[derive(Debug, Clone)]
+pub struct RepairedDiff {
+    pub text: String,
+    /// Where and how the diff was repaired, empty when it was well formed
+    pub repairs: Vec<String>,
+}
+
+fn hunk_header_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^@@ -(\d+)(?:,(\d+))? \+(\d+)(?:,(\d+))? @@(.*)$").unwrap())
+}
+
+fn is_file_header(lines: &[&str], i: usize) -> bool {
+    lines[i].starts_with("--- ") && lines.get(i + 1).is_some_and(|next| next.starts_with("+++ "))
+}
+
+/// Whether the line can still be part of a hunk body, leaving out the headers of the next file.
+fn is_hunk_line(lines: &[&str], i: usize) -> bool {
+    match lines[i].as_bytes().first() {
+        Some(b' ' | b'+' | b'\\') => true,
+        Some(b'-') => !is_file_header(lines, i),
+        _ => false,
+    }
+}
+
+/// Where a hunk is, for error messages and repair notes.
+struct HunkLocation<'a> {
+    line: usize,
+    hunk: usize,
+    file: &'a str,
+}
+
+impl std::fmt::Display for HunkLocation<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, hunk {} of {}", self.line, self.hunk, self.file)
+    }
+}
+
+/// Reads the hunk body starting at `start`, returning its lines, where it ends and the number of
+/// source and target lines it has. Blank lines inside the hunk, as left by editors and email
+/// clients stripping trailing whitespace, are read as empty context lines.
+fn read_hunk_body<'a>(lines: &[&'a str], start: usize, expected: (usize, usize)) -> (Vec<&'a str>, usize, (usize, usize), usize) {
+    let mut body = Vec::new();
+    let (mut source, mut target) = (0, 0);
+    let mut blank_lines = 0;
+    let mut i = start;
+    while i < lines.len() {
+        let line = lines[i].trim_end_matches('\r');
+        let satisfied = (source, target) == expected;
+        if line.is_empty() {
+            // Only blank lines followed by more of the hunk, while the header expects more, are context
+            let continues = lines[i + 1..]
+                .iter()
+                .position(|next| !next.trim_end_matches('\r').is_empty())
+                .is_some_and(|next| is_hunk_line(lines, i + 1 + next));
+            if satisfied || !continues {
+                break;
+            }
+            body.push("");
+            blank_lines += 1;
+            source += 1;
+            target += 1;
+        } else if !is_hunk_line(lines, i) || (satisfied && matches!(line, "-- " | "--")) {
+            // The "-- " line git format-patch ends emails with is left out once the hunk is complete
+            break;
+        } else {
+            match line.as_bytes()[0] {
+                b' ' => { source += 1; target += 1; },
+                b'-' => source += 1,
+                b'+' => target += 1,
+                _ => {},
+            }
+            body.push(lines[i]);
+        }
+        i += 1;
+    }
+    (body, i, (source, target), blank_lines)
+}
+
+/// Repairs a unified diff well enough for it to be parsed as its author meant, before it is
+/// handed to `PatchSet::parse`: hunk headers are recounted from their bodies, blank lines inside
+/// hunks become context lines and carriage returns are trimmed from file and hunk headers.
+/// Diffs that cannot be repaired fail with the line and hunk at fault.
+pub fn try_repair(diff: &str) -> Result<RepairedDiff, DiffGraphError> {
+    let lines: Vec<&str> = diff.split('\n').collect();
+    let mut text = String::with_capacity(diff.len());
+    let mut repairs = Vec::new();
+    let mut file: Option<&str> = None;
+    let mut hunk = 0;
+    let mut header_carriage_returns = 0;
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim_end_matches('\r');
+        let is_header = is_file_header(&lines, i) || trimmed.starts_with("@@ ") || trimmed.starts_with("diff ");
+        if is_header && trimmed.len() != line.len() {
+            header_carriage_returns += 1;
+        }
+
+        if is_file_header(&lines, i) {
+            let target = lines[i + 1].trim_end_matches('\r');
+            let path = target.trim_start_matches("+++ ").split('\t').next().unwrap_or_default();
+            file = Some(path.strip_prefix("b/").unwrap_or(path));
+            hunk = 0;
+            text.push_str(trimmed);
+            text.push('\n');
+            text.push_str(target);
+            text.push('\n');
+            if lines[i + 1].len() != target.len() {
+                header_carriage_returns += 1;
+            }
+            i += 2;
+            continue;
+        }
+
+        let Some(captures) = hunk_header_regex().captures(trimmed) else {
+            text.push_str(if is_header { trimmed } else { line });
+            if i + 1 < lines.len() {
+                text.push('\n');
+            }
+            i += 1;
+            continue;
+        };
+        hunk += 1;
+        let location = HunkLocation { line: i + 1, hunk, file: file.unwrap_or_default() };
+        if file.is_none() {
+            return Err(DiffGraphError::Patch(format!("line {}: hunk header without a ---/+++ file header before it", i + 1)));
+        }
+
+        // An omitted length is 1, as opposed to 0 which unidiff reads it as
+        let number = |group: usize, default: usize| captures.get(group).map_or(Ok(default), |m| m.as_str().parse::<usize>())
+            .map_err(|e| DiffGraphError::Patch(format!("{}: invalid hunk header '{}': {}", location, trimmed, e)));
+        let (source_start, source_length) = (number(1, 0)?, number(2, 1)?);
+        let (target_start, target_length) = (number(3, 0)?, number(4, 1)?);
+        let section = captures.get(5).map_or("", |m| m.as_str());
+
+        let (body, end, (source, target), blank_lines) = read_hunk_body(&lines, i + 1, (source_length, target_length));
+        if body.iter().all(|line| line.starts_with('\\')) {
+            return Err(DiffGraphError::Patch(format!("{}: the hunk has no lines", location)));
+        }
+        if (source, target) != (source_length, target_length) {
+            repairs.push(format!("{}: the header says -{},{} +{},{} but the hunk has -{},{} +{},{}, recounted",
+                location, source_start, source_length, target_start, target_length, source_start, source, target_start, target));
+        } else if captures.get(2).is_none() || captures.get(4).is_none() {
+            repairs.push(format!("{}: filled in the lengths left out of the header", location));
+        }
+        if blank_lines > 0 {
+            repairs.push(format!("{}: read blank lines as empty context lines ({})", location, blank_lines));
+        }
+
+        text.push_str(&format!("@@ -{},{} +{},{} @@{}\n", source_start, source, target_start, target, section));
+        for line in body {
+            text.push_str(if line.is_empty() { " " } else { line });
+            text.push('\n');
+        }
+        i = end;
+    }
+
+    if header_carriage_returns > 0 {
+        repairs.push(format!("trimmed carriage returns from {} header lines", header_carriage_returns));
+    }
+    Ok(RepairedDiff { text, repairs })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn well_formed_diffs_need_no_repairs() {
+        let diff = "--- a/x\n+++ b/x\n@@ -1,2 +1,2 @@\n-a\n+b\n c\n";
+        let repaired = try_repair(diff).unwrap();
+        assert_eq!(repaired.text, diff);
+        assert!(repaired.repairs.is_empty());
+    }
+
+    #[test]
+    fn wrong_hunk_lengths_are_recounted() {
+        let repaired = try_repair("--- a/x\n+++ b/x\n@@ -1,5 +1,7 @@\n-a\n+b\n c\n").unwrap();
+        assert_eq!(repaired.text, "--- a/x\n+++ b/x\n@@ -1,2 +1,2 @@\n-a\n+b\n c\n");
+        assert_eq!(repaired.repairs.len(), 1);
+    }
+
+    #[test]
+    fn omitted_lengths_are_filled_in() {
+        let repaired = try_repair("--- a/x\n+++ b/x\n@@ -1 +1 @@ fn x\n-a\n+b\n").unwrap();
+        assert_eq!(repaired.text, "--- a/x\n+++ b/x\n@@ -1,1 +1,1 @@ fn x\n-a\n+b\n");
+    }
+
+    #[test]
+    fn blank_lines_inside_hunks_are_context() {
+        let repaired = try_repair("--- a/x\n+++ b/x\n@@ -1,3 +1,3 @@\n-a\n+b\n\n c\n").unwrap();
+        assert_eq!(repaired.text, "--- a/x\n+++ b/x\n@@ -1,3 +1,3 @@\n-a\n+b\n \n c\n");
+    }
+
+    #[test]
+    fn carriage_returns_are_trimmed_from_headers() {
+        let repaired = try_repair("--- a/x\r\n+++ b/x\r\n@@ -1,1 +1,1 @@\r\n-a\r\n+b\r\n").unwrap();
+        assert_eq!(repaired.text, "--- a/x\n+++ b/x\n@@ -1,1 +1,1 @@\n-a\r\n+b\r\n");
+    }
+
+    #[test]
+    fn hunks_without_a_file_header_are_an_error() {
+        assert!(matches!(try_repair("@@ -1 +1 @@\n-a\n+b\n"), Err(DiffGraphError::Patch(_))));
+    }
+
+    #[test]
+    fn empty_hunks_are_an_error() {
+        assert!(matches!(try_repair("--- a/x\n+++ b/x\n@@ -1 +1 @@\n"), Err(DiffGraphError::Patch(_))));
+    }
+}
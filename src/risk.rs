@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Serialize;
+use tree_sitter::{Node as TSNode, Tree};
+
+use crate::color::{self, Stream, Style};
+use crate::graph::DiffGraph;
+use crate::parser::Diff;
+use crate::syntax;
+
+const HEADERS: [&str; 7] = ["SCORE", "DECLARATION", "FILE", "FAN-IN", "DEPTH", "CHANGED LINES", "PARSE ERRORS"];
+
+/// How risky a change to one declaration is, from what the graph's trees say about it.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeclarationRisk {
+    pub file: String,
+    pub kind: String,
+    pub name: Option<String>,
+    /// First line of the declaration, counted from 1, in the patched file unless it was removed
+    pub line: usize,
+    /// References to the declaration's name elsewhere in the patched files
+    pub fan_in: usize,
+    /// Declarations the declaration is nested in
+    pub depth: usize,
+    /// Lines of the declaration the patch adds or removes
+    pub changed_lines: usize,
+    /// Syntax errors within the declaration once patched
+    pub parse_errors: usize,
+    pub score: f64,
+}
+
+impl DeclarationRisk {
+    /// Weighs the signals so that widely referenced declarations and broken syntax rank first,
+    /// then larger and more deeply nested changes. Fan-in and size grow logarithmically, a
+    /// hundred references are not a hundred times as risky as one.
+    fn score(fan_in: usize, depth: usize, changed_lines: usize, parse_errors: usize) -> f64 {
+        let score = 2.0 * (1.0 + fan_in as f64).log2()
+            + (1.0 + changed_lines as f64).log2()
+            + 0.5 * depth as f64
+            + if parse_errors > 0 { 4.0 } else { 0.0 };
+        (score * 10.0).round() / 10.0
+    }
+
+    fn label(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{} {}", self.kind, name),
+            None => self.kind.clone(),
+        }
+    }
+}
+
+/// Changed declarations ranked from most to least risky, for prioritizing review.
+#[derive(Debug, Clone, Serialize)]
+pub struct RiskReport {
+    pub declarations: Vec<DeclarationRisk>,
+}
+
+/// How often each identifier occurs in the trees, standing in for the references to what it names.
+fn count_identifiers<'t>(trees: impl Iterator<Item = (&'t Tree, &'t str)>) -> HashMap<&'t str, usize> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for (tree, source) in trees {
+        let mut cursor = tree.walk();
+        'walk: loop {
+            let node = cursor.node();
+            if node.child_count() == 0 && node.kind().ends_with("identifier") {
+                if let Some(text) = source.get(node.byte_range()) {
+                    *counts.entry(text).or_default() += 1;
+                }
+            }
+            if cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+            }
+        }
+    }
+    counts
+}
+
+fn count_name_in(node: &TSNode, name: &str, source: &str) -> usize {
+    let mut count = 0;
+    let mut cursor = node.walk();
+    'walk: loop {
+        let current = cursor.node();
+        if current.child_count() == 0 && current.kind().ends_with("identifier") && source.get(current.byte_range()) == Some(name) {
+            count += 1;
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            // A cursor started at the node does not leave its subtree
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+    count
+}
+
+fn count_enclosing_declarations(node: &TSNode) -> usize {
+    let mut depth = 0;
+    let mut parent = node.parent();
+    while let Some(current) = parent {
+        if current.is_named() && syntax::is_declaration_kind(current.kind()) {
+            depth += 1;
+        }
+        parent = current.parent();
+    }
+    depth
+}
+
+/// A declaration enclosing changed lines of a file.
+struct ChangedSpan<'d> {
+    node: TSNode<'d>,
+    name: Option<String>,
+    /// Whether `node` is from the patched file rather than the file before the patch
+    patched: bool,
+    changed_lines: usize,
+}
+
+/// The declarations enclosing the changed lines of the file. Declarations on both sides of the
+/// patch are matched by kind and name, and taken from the patched side.
+fn find_changed_spans(diff: &Diff) -> Vec<ChangedSpan<'_>> {
+    let mut spans: Vec<ChangedSpan> = Vec::new();
+    let sides = [
+        (&diff.target_tree, &diff.target_source, &diff.added_rows, true),
+        (&diff.tree, &diff.source, &diff.removed_rows, false),
+    ];
+    for (tree, source, rows, patched) in sides {
+        let lines: Vec<&str> = source.lines().collect();
+        for row in rows.iter() {
+            let point = syntax::get_line_start(*row, lines.get(*row).unwrap_or(&""));
+            let Some(node) = syntax::find_enclosing_declaration(tree, point) else {
+                continue;
+            };
+            let name = syntax::get_declaration_name(&node, source);
+            let known = spans.iter_mut().find(|span| span.node.kind() == node.kind() && match (&span.name, &name) {
+                (Some(a), Some(b)) => a == b,
+                _ => span.patched == patched && span.node.id() == node.id(),
+            });
+            match known {
+                Some(span) => span.changed_lines += 1,
+                None => spans.push(ChangedSpan { node, name, patched, changed_lines: 1 }),
+            }
+        }
+    }
+    spans
+}
+
+impl RiskReport {
+    pub fn from_graph(graph: &DiffGraph) -> Self {
+        let identifiers = count_identifiers(graph.diffs()
+            .iter()
+            .map(|diff| (&diff.target_tree, diff.target_source.as_str())));
+
+        let mut declarations = Vec::new();
+        for diff in graph.diffs().iter() {
+            for ChangedSpan { node, name, patched, changed_lines } in find_changed_spans(diff) {
+                // The declaration's own name, and recursive references, are not fan-in
+                let fan_in = name.as_deref().map_or(0, |name| {
+                    let own = if patched { count_name_in(&node, name, &diff.target_source) } else { 0 };
+                    identifiers.get(name).copied().unwrap_or(0).saturating_sub(own)
+                });
+                let depth = count_enclosing_declarations(&node);
+                let parse_errors = match (patched, node.has_error()) {
+                    (true, true) => syntax::find_error_nodes(&diff.target_tree)
+                        .iter()
+                        .filter(|error| node.start_byte() <= error.start_byte() && error.end_byte() <= node.end_byte())
+                        .count(),
+                    _ => 0,
+                };
+                declarations.push(DeclarationRisk {
+                    file: diff.source_file_path.clone(),
+                    kind: node.kind().to_string(),
+                    name,
+                    line: node.start_position().row + 1,
+                    fan_in,
+                    depth,
+                    changed_lines,
+                    parse_errors,
+                    score: DeclarationRisk::score(fan_in, depth, changed_lines, parse_errors),
+                });
+            }
+        }
+
+        // Ties keep patch order
+        declarations.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Self { declarations }
+    }
+}
+
+impl fmt::Display for RiskReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.declarations.is_empty() {
+            return writeln!(f, "No changed declarations");
+        }
+
+        let rows: Vec<[String; 7]> = self.declarations
+            .iter()
+            .map(|declaration| [
+                format!("{:.1}", declaration.score),
+                declaration.label(),
+                format!("{}:{}", declaration.file, declaration.line),
+                declaration.fan_in.to_string(),
+                declaration.depth.to_string(),
+                declaration.changed_lines.to_string(),
+                declaration.parse_errors.to_string(),
+            ])
+            .collect();
+        let mut widths = HEADERS.map(|header| header.len());
+        for row in rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        // Cells are padded before they are painted, escape codes would throw off the widths
+        let write_row = |f: &mut fmt::Formatter<'_>, cells: &[String], style: Option<Style>| -> fmt::Result {
+            for (i, (cell, width)) in cells.iter().zip(widths.iter()).enumerate() {
+                // Names are left aligned, numbers right aligned
+                let padded = if i == 1 || i == 2 { format!("{:<width$}", cell, width = width) } else { format!("{:>width$}", cell, width = width) };
+                let separator = if i == 0 { "" } else { "  " };
+                match style {
+                    Some(style) => write!(f, "{}{}", separator, color::paint(Stream::Stdout, style, &padded))?,
+                    None => write!(f, "{}{}", separator, padded)?,
+                }
+            }
+            writeln!(f)
+        };
+        write_row(f, &HEADERS.map(String::from), Some(Style::Bold))?;
+        for (row, declaration) in rows.iter().zip(self.declarations.iter()) {
+            write_row(f, row, if declaration.parse_errors > 0 { Some(Style::Red) } else { None })?;
+        }
+        Ok(())
+    }
+}
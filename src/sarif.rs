@@ -0,0 +1,170 @@
+use std::collections::BTreeSet;
+
+use serde::Serialize;
+
+use crate::graph::DiffGraph;
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const SARIF_VERSION: &str = "2.1.0";
+
+/// A SARIF 2.1.0 log of the security-sensitive changes of the patch and of the debt it adds,
+/// for code scanning dashboards such as GitHub's. Each tag is a rule, each tagged node a result.
+#[derive(Debug, Clone, Serialize)]
+pub struct SarifLog {
+    #[serde(rename = "$schema")]
+    pub schema: &'static str,
+    pub version: &'static str,
+    pub runs: Vec<SarifRun>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SarifRun {
+    pub tool: SarifTool,
+    pub results: Vec<SarifResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SarifTool {
+    pub driver: SarifDriver,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SarifDriver {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub rules: Vec<SarifRule>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifRule {
+    pub id: String,
+    pub short_description: SarifMessage,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SarifMessage {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifResult {
+    pub rule_id: String,
+    pub level: &'static str,
+    pub message: SarifMessage,
+    pub locations: Vec<SarifLocation>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifLocation {
+    pub physical_location: SarifPhysicalLocation,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifPhysicalLocation {
+    pub artifact_location: SarifArtifactLocation,
+    pub region: SarifRegion,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SarifArtifactLocation {
+    pub uri: String,
+}
+
+/// Lines and columns counted from 1, the end column past the last character
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifRegion {
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+/// What the shipped query packs tag, user packs may tag more
+fn describe_tag(tag: &str) -> String {
+    match tag {
+        "exec" => "Runs a command or process".to_string(),
+        "eval" => "Evaluates code built at runtime".to_string(),
+        "sql" => "Builds SQL by concatenating or formatting strings".to_string(),
+        "crypto" => "Uses a cryptographic primitive".to_string(),
+        tag => format!("Matches the security-sensitive pattern {}", tag),
+    }
+}
+
+/// What the shipped debt query packs tag, user packs may tag more
+fn describe_debt_tag(tag: &str) -> String {
+    match tag {
+        "todo" => "Leaves a TODO comment".to_string(),
+        "fixme" => "Leaves a FIXME or HACK comment".to_string(),
+        "unwrap" => "Panics on None or Err".to_string(),
+        "panic" => "Fails at runtime on purpose".to_string(),
+        "bare-except" => "Catches every exception, including KeyboardInterrupt and SystemExit".to_string(),
+        "debugger" => "Leaves a debugger breakpoint".to_string(),
+        tag => format!("Adds the debt pattern {}", tag),
+    }
+}
+
+/// A result at the span, lines and columns counted from 1.
+fn to_result(rule_id: &str, level: &'static str, text: String, file: &str, span: [usize; 4]) -> SarifResult {
+    let [start_line, start_column, end_line, end_column] = span;
+    SarifResult {
+        rule_id: rule_id.to_string(),
+        level,
+        message: SarifMessage { text },
+        locations: vec![SarifLocation {
+            physical_location: SarifPhysicalLocation {
+                artifact_location: SarifArtifactLocation { uri: file.to_string() },
+                region: SarifRegion { start_line, start_column, end_line, end_column },
+            },
+        }],
+    }
+}
+
+impl SarifLog {
+    pub fn from_graph(graph: &DiffGraph) -> Self {
+        let tags = graph.security_tags();
+        let debt_tags = graph.debt_tags();
+        let mut rules: Vec<SarifRule> = tags
+            .iter()
+            .map(|tag| tag.tag.as_str())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|tag| SarifRule { id: tag.to_string(), short_description: SarifMessage { text: describe_tag(tag) } })
+            .collect();
+        rules.extend(debt_tags
+            .iter()
+            .map(|tag| tag.tag.as_str())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|tag| SarifRule { id: tag.to_string(), short_description: SarifMessage { text: describe_debt_tag(tag) } }));
+        let mut results: Vec<SarifResult> = tags
+            .iter()
+            .map(|tag| to_result(&tag.tag, "warning", format!("{}: `{}`", describe_tag(&tag.tag), tag.excerpt), &tag.file,
+                [tag.start_line, tag.start_column, tag.end_line, tag.end_column]))
+            .collect();
+        // Debt is worth knowing of rather than a problem
+        results.extend(debt_tags
+            .iter()
+            .map(|tag| to_result(&tag.tag, "note", format!("{}: `{}`", describe_debt_tag(&tag.tag), tag.excerpt), &tag.file,
+                [tag.start_line, tag.start_column, tag.end_line, tag.end_column])));
+
+        Self {
+            schema: SARIF_SCHEMA,
+            version: SARIF_VERSION,
+            runs: vec![SarifRun {
+                tool: SarifTool {
+                    driver: SarifDriver {
+                        name: "diffgraph",
+                        version: env!("CARGO_PKG_VERSION"),
+                        rules,
+                    },
+                },
+                results,
+            }],
+        }
+    }
+}
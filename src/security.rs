@@ -0,0 +1,195 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+use tracing::{debug, warn};
+use tree_sitter::{Language, Node as TSNode, Query, QueryCursor};
+
+use crate::error::DiffGraphError;
+use crate::parser::Diff;
+
+const PYTHON_QUERIES: &str = include_str!("../queries/security/python.scm");
+const JAVASCRIPT_QUERIES: &str = include_str!("../queries/security/javascript.scm");
+const RUST_QUERIES: &str = include_str!("../queries/security/rust.scm");
+const JAVA_QUERIES: &str = include_str!("../queries/security/java.scm");
+const GO_QUERIES: &str = include_str!("../queries/security/go.scm");
+
+/// Query packs shipped with diffgraph by language name, see `grammars::get_language_name`
+const BUILTIN_PACKS: [(&str, &str); 9] = [
+    ("python", PYTHON_QUERIES),
+    ("js", JAVASCRIPT_QUERIES),
+    ("javascript", JAVASCRIPT_QUERIES),
+    ("ts", JAVASCRIPT_QUERIES),
+    ("typescript", JAVASCRIPT_QUERIES),
+    ("tsx", JAVASCRIPT_QUERIES),
+    ("rust", RUST_QUERIES),
+    ("java", JAVA_QUERIES),
+    ("go", GO_QUERIES),
+];
+
+/// Longest excerpt of a tagged node kept for reports
+const MAX_EXCERPT_CHARS: usize = 80;
+
+/// A changed node matching a security-sensitive pattern, such as running a command, evaluating
+/// code, building SQL out of strings or using a cryptographic primitive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityTag {
+    pub file: String,
+    /// Name of the capture that matched, such as `exec`, `eval`, `sql` or `crypto`
+    pub tag: String,
+    pub kind: String,
+    /// Lines and columns in the patched file, counted from 1
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    /// The first line of the node's text
+    pub excerpt: String,
+}
+
+impl fmt::Display for SecurityTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` at {}:{} matches the security-sensitive pattern {}", self.excerpt, self.file, self.start_line, self.tag)
+    }
+}
+
+/// Queries of one language, from a file of a directory such as `--security-queries` or
+/// shipped with diffgraph.
+#[derive(Debug, Clone)]
+pub(crate) struct QueryPack {
+    path: Option<PathBuf>,
+    queries: String,
+}
+
+/// The shipped packs, and those in `dir` named after their language, such as `python.scm`,
+/// which are run along with them. `what` names the packs in messages, such as `security`.
+pub(crate) fn try_load_packs(builtin: &[(&str, &str)], dir: Option<&Path>, what: &str) -> Result<HashMap<String, Vec<QueryPack>>, DiffGraphError> {
+    let mut packs: HashMap<String, Vec<QueryPack>> = HashMap::new();
+    for (language, queries) in builtin {
+        packs.entry(language.to_string()).or_default().push(QueryPack { path: None, queries: queries.to_string() });
+    }
+    let Some(dir) = dir else {
+        return Ok(packs);
+    };
+
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| DiffGraphError::io(format!("Unable to read the {} queries in {}", what, dir.display()), e))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().is_none_or(|extension| extension != "scm") {
+            continue;
+        }
+        let Some(language) = path.file_stem().map(|stem| stem.to_string_lossy().to_string()) else {
+            continue;
+        };
+        let queries = std::fs::read_to_string(&path)
+            .map_err(|e| DiffGraphError::io(format!("Unable to read the {} queries in {}", what, path.display()), e))?;
+        debug!("Loaded {} queries for {} from {}", what, language, path.display());
+        packs.entry(language).or_default().push(QueryPack { path: Some(path), queries });
+    }
+    Ok(packs)
+}
+
+/// Compiles the packs of the language, leaving out those that do not compile.
+pub(crate) fn compile_packs(packs: &HashMap<String, Vec<QueryPack>>, language: Language, language_name: &str, what: &str) -> Vec<Query> {
+    let packs = packs.get(language_name).map_or(&[][..], |packs| packs.as_slice());
+    packs
+        .iter()
+        .filter_map(|pack| match (Query::new(language, &pack.queries), &pack.path) {
+            (Ok(query), _) => Some(query),
+            (Err(e), Some(path)) => {
+                warn!("Ignoring {} queries in {}: {}", what, path.display(), e);
+                None
+            },
+            (Err(e), None) => {
+                // The shipped packs are written against one version of each grammar, others may lack their node kinds
+                debug!("Ignoring the shipped {} queries for {}: {}", what, language_name, e);
+                None
+            },
+        })
+        .collect()
+}
+
+/// Nodes of the patched file on added lines that the queries capture, each once per capture
+/// name. Captures whose name starts with an underscore only serve the predicates.
+pub(crate) fn find_added_captures<'t>(queries: &[Query], diff: &'t Diff) -> Vec<(String, TSNode<'t>)> {
+    let added_rows: HashSet<usize> = diff.added_rows.iter().copied().collect();
+    if added_rows.is_empty() {
+        return Vec::new();
+    }
+
+    let mut captures = Vec::new();
+    let mut seen = HashSet::new();
+    let source = diff.target_source.as_bytes();
+    for query in queries {
+        let mut cursor = QueryCursor::new();
+        for query_match in cursor.matches(query, diff.target_tree.root_node(), source) {
+            for capture in query_match.captures {
+                let name = &query.capture_names()[capture.index as usize];
+                let node = capture.node;
+                let changed = (node.start_position().row..=node.end_position().row).any(|row| added_rows.contains(&row));
+                if name.starts_with('_') || !changed || !seen.insert((node.id(), name.clone())) {
+                    continue;
+                }
+                captures.push((name.clone(), node));
+            }
+        }
+    }
+    captures
+}
+
+/// The first line of the node's text, as reports quote it.
+pub(crate) fn get_excerpt(node: &TSNode, source: &str) -> String {
+    let text = source.get(node.byte_range()).unwrap_or_default();
+    text.lines().next().unwrap_or_default().trim().chars().take(MAX_EXCERPT_CHARS).collect()
+}
+
+/// Tree-sitter query packs tagging security-sensitive patterns, by language name. Every
+/// capture whose name does not start with an underscore tags the node it captures.
+#[derive(Debug, Clone)]
+pub struct SecurityQueries {
+    packs: HashMap<String, Vec<QueryPack>>,
+}
+
+impl SecurityQueries {
+    /// The packs shipped with diffgraph, and those in `dir` named after their language, such
+    /// as `python.scm`, which are run along with them.
+    pub fn try_load(dir: Option<&Path>) -> Result<Self, DiffGraphError> {
+        Ok(Self { packs: try_load_packs(&BUILTIN_PACKS, dir, "security")? })
+    }
+}
+
+/// Runs the query packs over patched files, compiling each language's queries once.
+pub struct SecurityScanner<'q> {
+    queries: &'q SecurityQueries,
+    compiled: HashMap<String, Vec<Query>>,
+}
+
+impl<'q> SecurityScanner<'q> {
+    pub fn new(queries: &'q SecurityQueries) -> Self {
+        Self { queries, compiled: HashMap::new() }
+    }
+
+    /// Nodes of the patched file on added lines that the queries of its language tag, in the
+    /// order they appear.
+    pub fn scan(&mut self, diff: &Diff) -> Vec<SecurityTag> {
+        let packs = &self.queries.packs;
+        let queries = self.compiled.entry(diff.language_name.clone()).or_insert_with(|| compile_packs(packs, diff.language, &diff.language_name, "security"));
+        let mut tags: Vec<SecurityTag> = find_added_captures(queries, diff)
+            .into_iter()
+            .map(|(tag, node)| SecurityTag {
+                file: diff.source_file_path.clone(),
+                tag,
+                kind: node.kind().to_string(),
+                start_line: node.start_position().row + 1,
+                start_column: node.start_position().column + 1,
+                end_line: node.end_position().row + 1,
+                end_column: node.end_position().column + 1,
+                excerpt: get_excerpt(&node, &diff.target_source),
+            })
+            .collect();
+        tags.sort_by_key(|tag| (tag.start_line, tag.start_column));
+        tags
+    }
+}
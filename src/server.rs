@@ -0,0 +1,191 @@
+use std::io::{self, BufRead, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::{debug, info};
+
+use crate::error::DiffGraphError;
+use crate::graph::{ChangedNode, DiffGraph, DiffGraphParams};
+use crate::parser::ChangedDeclaration;
+use crate::pathspec::PathFilter;
+use crate::progress;
+use crate::snapshot::{GraphSnapshot, SnapshotEdge, SnapshotFile};
+use crate::watch::try_build_uncommitted_graph;
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+/// How `serve` is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// JSON-RPC over stdio, for editors
+    Stdio,
+    /// The gRPC service in `proto/diffgraph.proto`, needs the grpc feature
+    Grpc(SocketAddr),
+    /// The REST API, see `http::run`
+    Http(SocketAddr),
+}
+
+#[derive(Debug)]
+pub struct ServerParams {
+    pub repository_dir: PathBuf,
+    /// Options for every request, the diff itself is replaced with the uncommitted changes
+    pub graph_params: DiffGraphParams,
+    pub transport: Transport,
+}
+
+/// A changed declaration and the nodes in it, as returned for `diffgraph/changes`.
+#[derive(Debug, Serialize)]
+struct DeclarationChanges {
+    file: usize,
+    #[serde(flatten)]
+    declaration: ChangedDeclaration,
+    nodes: Vec<ChangedNode>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChangesResult {
+    files: Vec<SnapshotFile>,
+    declarations: Vec<DeclarationChanges>,
+    /// Changed nodes outside of any declaration, such as top level statements
+    other: Vec<ChangedNode>,
+    /// Graph edges from or to a changed node
+    edges: Vec<SnapshotEdge>,
+}
+
+/// Reads one message framed with LSP's `Content-Length` header, None once stdin is closed.
+fn try_read_message(reader: &mut impl BufRead) -> Result<Option<Value>, DiffGraphError> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).map_err(|e| DiffGraphError::io("Unable to read from stdin", e))? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let content_length = content_length
+        .ok_or_else(|| DiffGraphError::Usage("Message is missing a Content-Length header".into()))?;
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).map_err(|e| DiffGraphError::io("Unable to read from stdin", e))?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| DiffGraphError::Usage(format!("Message is not valid JSON: {}", e)))
+}
+
+fn try_write_message(writer: &mut impl Write, message: &Value) -> Result<(), DiffGraphError> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| writer.flush())
+        .map_err(|e| DiffGraphError::io("Unable to write to stdout", e))
+}
+
+/// Changes of the uncommitted diff, updating the graph of the previous request.
+fn try_get_changes(params: &ServerParams, request: &Value, previous: &mut Option<DiffGraph>) -> Result<ChangesResult, (i64, String)> {
+    let mut graph_params = params.graph_params.clone();
+    // Editors ask about the buffer they show, which narrows the diff to one file
+    if let Some(path) = request.get("path").and_then(|path| path.as_str()) {
+        graph_params.path_filter = PathFilter::try_new(&[path.to_string()]).map_err(|e| (INVALID_PARAMS, e.to_string()))?;
+    }
+
+    let graph = match try_build_uncommitted_graph(&params.repository_dir, &graph_params, previous.take()) {
+        Ok(Some(graph)) => previous.insert(graph),
+        Ok(None) => return Ok(ChangesResult { files: Vec::new(), declarations: Vec::new(), other: Vec::new(), edges: Vec::new() }),
+        Err(e) => return Err((REQUEST_FAILED, e.to_string())),
+    };
+    let snapshot = GraphSnapshot::from_graph(graph);
+
+    let edges = snapshot.edges
+        .iter()
+        .filter(|edge| snapshot.changes.iter().any(|changed| !changed.patched && (changed.node.id == edge.from || changed.node.id == edge.to)))
+        .copied()
+        .collect();
+    let mut declarations: Vec<DeclarationChanges> = Vec::new();
+    let mut other = Vec::new();
+    for changed in snapshot.changes {
+        match changed.declaration.clone() {
+            Some(declaration) => {
+                let file = changed.node.file;
+                match declarations.iter_mut().find(|d| d.file == file && d.declaration == declaration) {
+                    Some(existing) => existing.nodes.push(changed),
+                    None => declarations.push(DeclarationChanges { file, declaration, nodes: vec![changed] }),
+                }
+            },
+            None => other.push(changed),
+        }
+    }
+
+    Ok(ChangesResult {
+        files: snapshot.files,
+        declarations,
+        other,
+        edges,
+    })
+}
+
+/// Serves requests about the repository until stopped, over the transport the params ask for.
+///
+/// Over stdio, requests are JSON-RPC framed as in the Language Server Protocol. Methods:
+///
+/// - `initialize`: the server's name and methods
+/// - `diffgraph/changes` with an optional `path`: changed declarations and the graph edges of their nodes
+/// - `shutdown`, then the `exit` notification to stop
+pub fn run(params: ServerParams) -> Result<(), DiffGraphError> {
+    match params.transport {
+        Transport::Stdio => run_stdio(params),
+        Transport::Http(address) => crate::http::run(address, params.repository_dir, params.graph_params),
+        #[cfg(feature = "grpc")]
+        Transport::Grpc(address) => crate::grpc::run(address, params.repository_dir, params.graph_params),
+        #[cfg(not(feature = "grpc"))]
+        Transport::Grpc(_) => Err(DiffGraphError::Usage("--grpc needs diffgraph to be built with the grpc feature".into())),
+    }
+}
+
+fn run_stdio(params: ServerParams) -> Result<(), DiffGraphError> {
+    // Progress bars would interleave with whatever the editor logs from stderr
+    progress::set_enabled(false);
+    info!("Serving JSON-RPC on stdio for {}", params.repository_dir.display());
+
+    let stdin = io::stdin();
+    let mut reader = stdin.lock();
+    let mut stdout = io::stdout().lock();
+    let mut previous = None;
+    while let Some(message) = try_read_message(&mut reader)? {
+        let method = message.get("method").and_then(|method| method.as_str()).unwrap_or_default();
+        debug!("Received {}", method);
+        let result = match method {
+            "initialize" => Ok(json!({
+                "serverInfo": { "name": "diffgraph", "version": env!("CARGO_PKG_VERSION") },
+                "methods": ["diffgraph/changes", "shutdown", "exit"],
+            })),
+            "diffgraph/changes" => try_get_changes(&params, message.get("params").unwrap_or(&Value::Null), &mut previous)
+                .map(|changes| serde_json::to_value(changes).unwrap_or(Value::Null)),
+            "shutdown" => Ok(Value::Null),
+            "exit" => return Ok(()),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+        };
+
+        // Notifications have no id and get no response
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => continue,
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }),
+        };
+        try_write_message(&mut stdout, &response)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Serialize, Deserialize};
+use unidiff::PatchedFile;
+
+use crate::error::DiffGraphError;
+use crate::parser::get_patch_file_path;
+use crate::snapshot::{GraphSnapshot, SnapshotEdge};
+
+/// One of `count` parts the files of a patch are split into with `--shard index/count`, so a
+/// huge diff can be graphed by several invocations, such as CI workers, whose partial graphs
+/// are combined with `merge`. `index` counts from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid shard '{}', expected INDEX/COUNT such as 1/4", s);
+        let (index, count) = s.split_once('/').ok_or_else(invalid)?;
+        let index: usize = index.trim().parse().map_err(|_| invalid())?;
+        let count: usize = count.trim().parse().map_err(|_| invalid())?;
+        if index == 0 || index > count {
+            return Err(format!("Invalid shard '{}', the index counts from 1 up to the shard count", s));
+        }
+        Ok(Shard { index, count })
+    }
+}
+
+impl Shard {
+    /// The files of this shard, in patch order. Files are dealt out to the shards in path order,
+    /// so every invocation given the same patch splits it the same way and shards get about as
+    /// many files each.
+    pub fn select<'p>(&self, patch_files: Vec<&'p PatchedFile>) -> Vec<&'p PatchedFile> {
+        let mut paths: Vec<&str> = patch_files.iter().map(|patch_file| get_patch_file_path(patch_file)).collect();
+        paths.sort_unstable();
+        paths.dedup();
+        let shards: HashMap<&str, usize> = paths
+            .into_iter()
+            .enumerate()
+            .map(|(position, path)| (path, position % self.count + 1))
+            .collect();
+        patch_files
+            .into_iter()
+            .filter(|patch_file| shards.get(get_patch_file_path(patch_file)) == Some(&self.index))
+            .collect()
+    }
+
+    /// Whether the shard reports what concerns the whole patch rather than one of its files,
+    /// such as binary files, so merged shards report it once.
+    pub fn is_first(&self) -> bool {
+        self.index == 1
+    }
+}
+
+/// Combines the partial graphs of every shard of a patch into the graph of the whole patch.
+/// Shards are taken in shard order whatever order they are given in, their files and nodes
+/// renumbered to follow those of the shards before them, so merging is deterministic.
+/// Snapshots that are not shards are merged in the order given.
+pub fn try_merge(mut snapshots: Vec<GraphSnapshot>) -> Result<GraphSnapshot, DiffGraphError> {
+    if snapshots.iter().any(|snapshot| snapshot.shard.is_some()) {
+        let mut shards: Vec<Shard> = Vec::new();
+        for snapshot in snapshots.iter() {
+            let Some(shard) = snapshot.shard else {
+                return Err(DiffGraphError::Usage("Unable to merge shards with a graph that is not one".into()));
+            };
+            if shards.first().is_some_and(|first| first.count != shard.count) {
+                return Err(DiffGraphError::Usage(format!("Unable to merge shard {} with shards of {}", shard, shards[0].count)));
+            }
+            if shards.contains(&shard) {
+                return Err(DiffGraphError::Usage(format!("Shard {} is given more than once", shard)));
+            }
+            shards.push(shard);
+        }
+        let count = shards[0].count;
+        if let Some(missing) = (1..=count).find(|index| !shards.iter().any(|shard| shard.index == *index)) {
+            return Err(DiffGraphError::Usage(format!("Shard {}/{} is missing", missing, count)));
+        }
+        snapshots.sort_by_key(|snapshot| snapshot.shard.map(|shard| shard.index));
+    }
+
+    // Changed nodes outside the graph are numbered after every node of the graph, as in a snapshot
+    let total_nodes: usize = snapshots.iter().map(|snapshot| snapshot.nodes.len()).sum();
+    let mut merged = GraphSnapshot {
+        files: Vec::new(),
+        nodes: Vec::with_capacity(total_nodes),
+        edges: Vec::new(),
+        changes: Vec::new(),
+        truncations: Vec::new(),
+        errors: Vec::new(),
+        shard: None,
+    };
+    let mut outside_ids = total_nodes;
+    for snapshot in snapshots {
+        let (file_offset, node_offset) = (merged.files.len(), merged.nodes.len());
+        let node_count = snapshot.nodes.len();
+        let mut outside: HashMap<usize, usize> = HashMap::new();
+        let mut renumber = |id: usize| match id < node_count {
+            true => id + node_offset,
+            false => *outside.entry(id).or_insert_with(|| {
+                outside_ids += 1;
+                outside_ids - 1
+            }),
+        };
+
+        merged.files.extend(snapshot.files);
+        merged.nodes.extend(snapshot.nodes.into_iter().map(|mut node| {
+            node.id += node_offset;
+            node.file += file_offset;
+            node
+        }));
+        merged.edges.extend(snapshot.edges.into_iter().map(|edge| SnapshotEdge {
+            from: edge.from + node_offset,
+            to: edge.to + node_offset,
+        }));
+        merged.changes.extend(snapshot.changes.into_iter().map(|mut changed| {
+            changed.node.id = renumber(changed.node.id);
+            changed.node.file += file_offset;
+            changed
+        }));
+        merged.truncations.extend(snapshot.truncations);
+        merged.errors.extend(snapshot.errors);
+    }
+    Ok(merged)
+}
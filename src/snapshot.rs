@@ -0,0 +1,79 @@
+use serde::{Serialize, Deserialize};
+
+use crate::graph::{ChangedNode, DiffGraph, NodeInfo};
+use crate::parser::FileError;
+use crate::shard::Shard;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    pub path: String,
+    pub language: String,
+    /// Owners of the file by the repository's CODEOWNERS
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub owners: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SnapshotEdge {
+    pub from: usize,
+    pub to: usize,
+}
+
+/// An owned copy of a graph without the sources and trees it was built from, the schema
+/// graphs are exported in and read back from. Files are in patch order, nodes in sorted order
+/// and numbered from 0, and edges ordered by the nodes they join, so exports of the same input
+/// are byte for byte the same.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphSnapshot {
+    pub files: Vec<SnapshotFile>,
+    /// `NodeInfo::file` indexes into `files`
+    pub nodes: Vec<NodeInfo>,
+    pub edges: Vec<SnapshotEdge>,
+    pub changes: Vec<ChangedNode>,
+    pub truncations: Vec<String>,
+    /// Files that failed and were left out, missing from exports older than per-file errors
+    #[serde(default)]
+    pub errors: Vec<FileError>,
+    /// The shard of the patch the graph is of, to be merged with the others
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<Shard>,
+}
+
+impl GraphSnapshot {
+    pub fn from_graph(graph: &DiffGraph) -> Self {
+        let mut ids = graph.stable_ids();
+        let nodes = graph.sorted_nodes()
+            .into_iter()
+            .map(|node| NodeInfo { id: ids[&node.id], ..node.clone() })
+            .collect();
+        let edges = graph.sorted_edges(&ids)
+            .into_iter()
+            .map(|edge| SnapshotEdge { from: ids[&edge.from.id], to: ids[&edge.to.id] })
+            .collect();
+        // Changed nodes outside the graph, such as those of the patched files, are numbered after it
+        let changes = graph.changed_nodes()
+            .map(|mut changed| {
+                let next = ids.len();
+                changed.node.id = *ids.entry(changed.node.id).or_insert(next);
+                changed
+            })
+            .collect();
+
+        Self {
+            files: graph.diffs()
+                .iter()
+                .map(|diff| SnapshotFile {
+                    path: diff.source_file_path.clone(),
+                    language: diff.language_name.clone(),
+                    owners: graph.get_owners(&diff.source_file_path).to_vec(),
+                })
+                .collect(),
+            nodes,
+            edges,
+            changes,
+            truncations: graph.truncations().iter().map(|t| t.to_string()).collect(),
+            errors: graph.errors().clone(),
+            shard: graph.shard(),
+        }
+    }
+}
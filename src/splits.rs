@@ -0,0 +1,172 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::color::{self, Stream, Style};
+use crate::coupling::CouplingReport;
+use crate::graph::DiffGraph;
+
+/// A changed file, with how much of the patch it holds.
+#[derive(Debug, Clone, Serialize)]
+pub struct SplitFile {
+    pub path: String,
+    pub hunks: usize,
+    pub added_lines: usize,
+    pub removed_lines: usize,
+}
+
+/// Changed files to commit together, since each depends on the others, after the commits they
+/// depend on.
+#[derive(Debug, Clone, Serialize)]
+pub struct SplitCommit {
+    pub files: Vec<SplitFile>,
+    /// Earlier commits of the split this one depends on, counted from 1
+    pub depends_on: BTreeSet<usize>,
+}
+
+/// How the patch could be split into commits reviewable one at a time, each only depending on
+/// the ones before it.
+#[derive(Debug, Clone, Serialize)]
+pub struct SplitReport {
+    pub commits: Vec<SplitCommit>,
+}
+
+/// Files that depend on each other, directly or through other files, grouped: the strongly
+/// connected components of the dependencies, each in patch order.
+fn find_components(dependencies: &[BTreeSet<usize>]) -> Vec<Vec<usize>> {
+    let reachable: Vec<BTreeSet<usize>> = (0..dependencies.len())
+        .map(|start| {
+            let mut seen = BTreeSet::new();
+            let mut stack = vec![start];
+            while let Some(file) = stack.pop() {
+                stack.extend(dependencies[file].iter().filter(|dependency| seen.insert(**dependency)));
+            }
+            seen
+        })
+        .collect();
+
+    let mut component_of: Vec<Option<usize>> = vec![None; dependencies.len()];
+    let mut components: Vec<Vec<usize>> = Vec::new();
+    for file in 0..dependencies.len() {
+        if component_of[file].is_some() {
+            continue;
+        }
+        let component: Vec<usize> = (file..dependencies.len())
+            .filter(|other| *other == file || (reachable[file].contains(other) && reachable[*other].contains(&file)))
+            .collect();
+        for member in component.iter() {
+            component_of[*member] = Some(components.len());
+        }
+        components.push(component);
+    }
+    components
+}
+
+impl SplitReport {
+    /// Splits the patch along the dependencies between its files: a file depends on the files
+    /// it imports and on those changing declarations its changed lines use. Files depending on
+    /// each other stay in one commit, and commits come after what they depend on, otherwise in
+    /// patch order.
+    pub fn from_graph(graph: &DiffGraph) -> Self {
+        let index: HashMap<&str, usize> = graph.diffs()
+            .iter()
+            .enumerate()
+            .map(|(i, diff)| (diff.source_file_path.as_str(), i))
+            .collect();
+        let mut dependencies: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); graph.diffs().len()];
+        let coupling = CouplingReport::from_graph(graph);
+        let edges = coupling.pairs.iter().flat_map(|pair| pair.imports
+            .iter()
+            .map(|edge| (edge.importer.as_str(), edge.imported.as_str()))
+            .chain(pair.uses.iter().map(|edge| (edge.user.as_str(), edge.declarer.as_str()))));
+        for (user, used) in edges {
+            if let (Some(user), Some(used)) = (index.get(user), index.get(used)) {
+                dependencies[*user].insert(*used);
+            }
+        }
+
+        let components = find_components(&dependencies);
+        let component_of: HashMap<usize, usize> = components
+            .iter()
+            .enumerate()
+            .flat_map(|(component, files)| files.iter().map(move |file| (*file, component)))
+            .collect();
+        let component_dependencies: Vec<BTreeSet<usize>> = components
+            .iter()
+            .enumerate()
+            .map(|(component, files)| files
+                .iter()
+                .flat_map(|file| dependencies[*file].iter().map(|dependency| component_of[dependency]))
+                .filter(|dependency| *dependency != component)
+                .collect())
+            .collect();
+
+        // Components whose dependencies are all committed, the earliest in patch order first
+        let mut order: Vec<usize> = Vec::new();
+        let mut position: HashMap<usize, usize> = HashMap::new();
+        while order.len() < components.len() {
+            let Some(next) = (0..components.len())
+                .find(|component| !position.contains_key(component)
+                    && component_dependencies[*component].iter().all(|dependency| position.contains_key(dependency))) else {
+                break;
+            };
+            position.insert(next, order.len());
+            order.push(next);
+        }
+
+        let commits = order
+            .iter()
+            .map(|component| SplitCommit {
+                files: components[*component]
+                    .iter()
+                    .map(|file| {
+                        let diff = &graph.diffs()[*file];
+                        SplitFile {
+                            path: diff.source_file_path.clone(),
+                            hunks: diff.hunk_count,
+                            added_lines: diff.added_rows.len(),
+                            removed_lines: diff.removed_rows.len(),
+                        }
+                    })
+                    .collect(),
+                depends_on: component_dependencies[*component].iter().map(|dependency| position[dependency] + 1).collect(),
+            })
+            .collect();
+        Self { commits }
+    }
+}
+
+impl fmt::Display for SplitReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.commits.len() {
+            0 => return writeln!(f, "No source files changed"),
+            1 => return writeln!(f, "Every changed file depends on the others, the patch cannot be split along its dependencies"),
+            _ => {},
+        }
+
+        for (i, commit) in self.commits.iter().enumerate() {
+            let added: usize = commit.files.iter().map(|file| file.added_lines).sum();
+            let removed: usize = commit.files.iter().map(|file| file.removed_lines).sum();
+            write!(f, "{} {}/{}", color::paint(Stream::Stdout, Style::Bold, &format!("commit {}:", i + 1)),
+                color::paint(Stream::Stdout, Style::Green, &format!("+{}", added)),
+                color::paint(Stream::Stdout, Style::Red, &format!("-{}", removed)))?;
+            if commit.depends_on.is_empty() {
+                writeln!(f, " {}", color::paint(Stream::Stdout, Style::Cyan, "depends on no other commit"))?;
+            } else {
+                let after: Vec<String> = commit.depends_on.iter().map(|dependency| dependency.to_string()).collect();
+                writeln!(f, " {}", color::paint(Stream::Stdout, Style::Dim, &format!("after {}", after.join(", "))))?;
+            }
+            let width = commit.files.iter().map(|file| file.path.chars().count()).max().unwrap_or(0);
+            for file in commit.files.iter() {
+                writeln!(f, "  {:<width$}  {}, +{}/-{}", file.path,
+                    if file.hunks == 1 { "1 hunk".to_string() } else { format!("{} hunks", file.hunks) },
+                    file.added_lines, file.removed_lines, width = width)?;
+            }
+        }
+
+        writeln!(f)?;
+        let roots = self.commits.iter().filter(|commit| commit.depends_on.is_empty()).count();
+        writeln!(f, "{} commits, {} depending on no other commit", self.commits.len(), roots)
+    }
+}
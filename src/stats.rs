@@ -0,0 +1,93 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::intern;
+use crate::timings;
+
+static STATS_ENABLED: AtomicBool = AtomicBool::new(false);
+/// Files, nodes and edges of the last graph built
+static GRAPH: Mutex<Option<(usize, usize, usize)>> = Mutex::new(None);
+static HITS: [AtomicUsize; Cache::ALL.len()] = [const { AtomicUsize::new(0) }; Cache::ALL.len()];
+static MISSES: [AtomicUsize; Cache::ALL.len()] = [const { AtomicUsize::new(0) }; Cache::ALL.len()];
+
+/// Caches whose hit rates are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cache {
+    /// Exports cached by base commit, patch and options
+    Results,
+    /// Repositories cloned into the clone cache directory
+    Clones,
+    /// Grammars compiled into the grammar cache directory
+    Grammars,
+    /// Parsers set up for a language, reused across files
+    Parsers,
+}
+
+impl Cache {
+    const ALL: [Cache; 4] = [Cache::Results, Cache::Clones, Cache::Grammars, Cache::Parsers];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Cache::Results => "result cache",
+            Cache::Clones => "clone cache",
+            Cache::Grammars => "grammar cache",
+            Cache::Parsers => "parser pool",
+        }
+    }
+}
+
+pub fn set_enabled(enabled: bool) {
+    STATS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    STATS_ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn hit(cache: Cache) {
+    HITS[cache as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn miss(cache: Cache) {
+    MISSES[cache as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records the size of a graph once it is built.
+pub fn record_graph(files: usize, nodes: usize, edges: usize) {
+    if let Ok(mut graph) = GRAPH.lock() {
+        *graph = Some((files, nodes, edges));
+    }
+}
+
+/// Prints peak memory, the size of the graph, interned strings and cache hit rates to stderr,
+/// for sizing the containers runs are given.
+pub fn report() {
+    if !is_enabled() {
+        return;
+    }
+
+    let mut rows: Vec<(&str, String)> = Vec::new();
+    rows.push(("peak memory", timings::get_peak_rss().map_or("unknown on this platform".into(), timings::format_bytes)));
+    if let Some((files, nodes, edges)) = GRAPH.lock().ok().and_then(|graph| *graph) {
+        rows.push(("files", files.to_string()));
+        rows.push(("nodes", nodes.to_string()));
+        rows.push(("edges", edges.to_string()));
+    }
+    let (strings, bytes) = intern::stats();
+    rows.push(("interned strings", format!("{} ({})", strings, timings::format_bytes(bytes as u64))));
+    for cache in Cache::ALL {
+        let hits = HITS[cache as usize].load(Ordering::Relaxed);
+        let misses = MISSES[cache as usize].load(Ordering::Relaxed);
+        if hits + misses == 0 {
+            continue;
+        }
+        rows.push((cache.as_str(), format!("{} hits, {} misses ({:.0}% hit rate)",
+            hits, misses, 100.0 * hits as f64 / (hits + misses) as f64)));
+    }
+
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    eprintln!("stats:");
+    for (name, value) in rows.iter() {
+        eprintln!("  {:<width$}  {}", name, value, width = width);
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::architecture::ArchitectureRules;
+use crate::coverage::{Coverage, UntestedChange};
+use crate::debt::{DebtQueries, DebtScanner, DebtTag};
+use crate::error::DiffGraphError;
+use crate::graph::{self, ChangedNode, DiffGraphParams, Edge, NodeInfo, TreeIterator};
+use crate::index::SymbolIndex;
+use crate::limits::{LimitAction, Limits, Truncation};
+use crate::owners::CodeOwners;
+use crate::parser::{self, Diff, FileError, ParseReport};
+use crate::policy::{Condition, Finding};
+use crate::progress;
+use crate::security::{SecurityQueries, SecurityScanner, SecurityTag};
+use crate::snapshot::{SnapshotEdge, SnapshotFile};
+
+/// One file's part of the graph, written as a line of JSON as soon as the file is parsed.
+/// Nodes are numbered from 0 in sorted order within each file, like in `GraphSnapshot`, as the
+/// trees they come from are not kept around.
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamedFile {
+    /// Position of the file among the streamed files
+    pub index: usize,
+    pub file: SnapshotFile,
+    pub nodes: Vec<NodeInfo>,
+    pub edges: Vec<SnapshotEdge>,
+    pub changes: Vec<ChangedNode>,
+    /// Changed nodes of the file matching a security-sensitive pattern
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub security: Vec<SecurityTag>,
+    /// Changed nodes of the file adding a TODO, a panic or a similar pattern of debt
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub debt: Vec<DebtTag>,
+    /// Changed declarations of the file with added lines no test ran
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub untested: Vec<UntestedChange>,
+}
+
+/// The last line of the stream, with what is only known once every file was seen.
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamEnd {
+    pub files: usize,
+    pub nodes: usize,
+    pub edges: usize,
+    pub truncations: Vec<String>,
+    pub findings: Vec<String>,
+    pub skipped: Vec<String>,
+    pub errors: Vec<FileError>,
+    /// The run was cancelled, so files after the last one streamed are missing
+    pub cancelled: bool,
+}
+
+/// The file's part of the graph pruned to the changed subtrees, leaving out excluded kinds, and
+/// whether the depth or children limits cut the walk short.
+fn stream_file(index: usize, diff: &Diff, exclude_kinds: &[String], limits: &Limits, owners: Option<&CodeOwners>) -> (StreamedFile, Option<Truncation>) {
+    let mut nodes: Vec<NodeInfo> = Vec::new();
+    let mut node_ids = HashSet::new();
+    let mut edges: Vec<Edge> = Vec::new();
+    let mut truncation = None;
+    for root in graph::changed_subtrees(diff) {
+        let mut dfs = TreeIterator::from_node(root, |from, to| {
+            if exclude_kinds.iter().any(|k| k == from.kind() || k == to.kind()) {
+                return;
+            }
+            let edge = Edge::from_ts_nodes(index, &from, &to);
+            for node in [&edge.from, &edge.to] {
+                if node_ids.insert(node.id) {
+                    nodes.push(node.clone());
+                }
+            }
+            edges.push(edge);
+        }).with_limits(limits);
+        while dfs.next().is_some() {}
+        truncation = truncation.or(dfs.truncation(&diff.source_file_path));
+    }
+
+    nodes.sort_by_key(|node| node.sort_key());
+    let mut ids: HashMap<usize, usize> = nodes.iter().enumerate().map(|(id, node)| (node.id, id)).collect();
+    let mut edges: Vec<SnapshotEdge> = edges
+        .iter()
+        .map(|edge| SnapshotEdge { from: ids[&edge.from.id], to: ids[&edge.to.id] })
+        .collect();
+    edges.sort_by_key(|edge| (edge.from, edge.to));
+    let changes = diff.changed_nodes(index)
+        .into_iter()
+        .map(|mut changed| {
+            let next = ids.len();
+            changed.node.id = *ids.entry(changed.node.id).or_insert(next);
+            changed
+        })
+        .collect();
+    for node in nodes.iter_mut() {
+        node.id = ids[&node.id];
+    }
+
+    let streamed = StreamedFile {
+        index,
+        file: SnapshotFile {
+            path: diff.source_file_path.clone(),
+            language: diff.language_name.clone(),
+            owners: owners.map_or(Vec::new(), |owners| owners.get_owners(&diff.source_file_path).to_vec()),
+        },
+        nodes,
+        edges,
+        changes,
+        security: Vec::new(),
+        debt: Vec::new(),
+        untested: Vec::new(),
+    };
+    (streamed, truncation)
+}
+
+fn write_line<T: Serialize>(writer: &mut dyn Write, value: &T) -> Result<(), DiffGraphError> {
+    serde_json::to_writer(&mut *writer, value)
+        .map_err(std::io::Error::from)
+        .and_then(|_| writeln!(writer))
+        .and_then(|_| writer.flush())
+        .map_err(|e| DiffGraphError::io("Unable to write the stream", e))
+}
+
+/// Parses the patch one file at a time, writing each file's graph pruned to its changed
+/// subtrees as a line of JSON before parsing the next, so peak memory stays that of the
+/// largest file rather than of the whole patch. A `StreamEnd` line closes the stream.
+pub fn try_stream(params: DiffGraphParams, writer: &mut dyn Write) -> Result<(), DiffGraphError> {
+    let source_location = params.get_source_location();
+    let mut report = ParseReport {
+        findings: params.get_binary_file_findings(),
+        ..Default::default()
+    };
+    let grammars = parser::try_load_grammars(&params)?;
+    let patch_files = parser::try_select_patch_files(&params, &grammars, &source_location, &mut report)?;
+    let owners = CodeOwners::load(&source_location);
+    let security_queries = SecurityQueries::try_load(params.security_queries.as_deref())?;
+    let mut scanner = SecurityScanner::new(&security_queries);
+    let debt_queries = DebtQueries::try_load(params.debt_queries.as_deref())?;
+    let mut debt_scanner = DebtScanner::new(&debt_queries);
+    let coverage = params.coverage.as_deref().map(Coverage::try_load).transpose()?;
+    let rules = params.architecture_rules.as_deref().map(ArchitectureRules::try_load).transpose()?.unwrap_or_default();
+    let index = match &params.architecture_rules {
+        Some(_) => params.symbol_index.as_deref().map(SymbolIndex::try_load).transpose()?,
+        None => None,
+    };
+
+    let limits = &params.limits;
+    let bar = progress::bar(patch_files.len() as u64, "Streaming");
+    let (mut files, mut node_count, mut edge_count) = (0, 0, 0);
+    let mut cancelled = None;
+    for patch_file in patch_files {
+        bar.inc(1);
+        let diff = match parser::try_parse_patch_file(patch_file, &grammars, &source_location, &params, &mut report, &mut ()) {
+            // The files streamed so far are complete, so the stream is still closed off
+            Err(e @ DiffGraphError::Cancelled(_)) => {
+                cancelled = Some(e);
+                break;
+            },
+            diff => diff?,
+        };
+        let Some(diff) = diff else {
+            continue;
+        };
+        if let Some(dir) = &params.dump_trees {
+            diff.try_dump_trees(dir)?;
+        }
+        let (mut streamed, truncation) = stream_file(files, &diff, &params.exclude_kinds, limits, owners.as_ref());
+        streamed.security = scanner.scan(&diff);
+        streamed.debt = debt_scanner.scan(&diff);
+        if let Some(coverage) = &coverage {
+            streamed.untested = coverage.find_untested_changes(std::slice::from_ref(&diff));
+        }
+        // Files are seen one at a time, so their uses of each other are not checked, only those the index resolves
+        report.findings.extend(rules
+            .check(std::slice::from_ref(&diff), index.as_ref())
+            .iter()
+            .map(|violation| Finding::new(Condition::ArchitectureViolations, violation.to_string())));
+        drop(diff);
+        if let Some(truncation) = truncation {
+            if limits.action == LimitAction::Fail {
+                return Err(DiffGraphError::LimitExceeded(truncation));
+            }
+            report.truncations.push(truncation);
+        }
+
+        if let Some(max_nodes) = limits.max_nodes {
+            if node_count + streamed.nodes.len() > max_nodes {
+                let truncation = Truncation::Nodes { limit: max_nodes };
+                if limits.action == LimitAction::Fail {
+                    return Err(DiffGraphError::LimitExceeded(truncation));
+                }
+                report.truncations.push(truncation);
+                break;
+            }
+        }
+        node_count += streamed.nodes.len();
+        edge_count += streamed.edges.len();
+        files += 1;
+        report.findings.extend(streamed.security
+            .iter()
+            .map(|tag| Finding::new(Condition::SecurityPatterns, tag.to_string())));
+        report.findings.extend(streamed.debt
+            .iter()
+            .map(|tag| Finding::new(Condition::DebtPatterns, tag.to_string())));
+        write_line(writer, &streamed)?;
+    }
+    bar.finish_and_clear();
+
+    let ParseReport { truncations, mut findings, skipped, errors } = report;
+    findings.extend(truncations
+        .iter()
+        .map(|truncation| Finding::new(Condition::Truncated, truncation.to_string())));
+    write_line(writer, &StreamEnd {
+        files,
+        nodes: node_count,
+        edges: edge_count,
+        truncations: truncations.iter().map(|t| t.to_string()).collect(),
+        findings: findings.iter().map(|finding| finding.message.clone()).collect(),
+        skipped: skipped.iter().map(|skipped| format!("{} ({})", skipped.path, skipped.reason)).collect(),
+        errors,
+        cancelled: cancelled.is_some(),
+    })?;
+
+    if let Some(e) = cancelled {
+        return Err(e);
+    }
+    params.policy.evaluate(&findings)
+}
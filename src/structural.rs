@@ -0,0 +1,431 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+
+use serde::{Serialize, Deserialize};
+use tree_sitter::{Node as TSNode, Tree};
+
+use crate::color::{self, Stream, Style};
+use crate::graph::DiffGraph;
+use crate::parser::Diff;
+use crate::syntax;
+
+/// Field most grammars keep a declaration's implementation in, everything else being its signature
+const BODY_FIELD: &str = "body";
+/// Fields holding what a declaration does or evaluates to rather than how it is used
+const IMPLEMENTATION_FIELDS: [&str; 2] = [BODY_FIELD, "value"];
+
+/// How a declaration differs between the original and patched file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeclarationChange {
+    Added,
+    Removed,
+    /// Fields of the declaration whose tokens changed and those whose did not. Both are empty
+    /// when the original declaration could not be told apart from others of its kind.
+    Modified { changed: Vec<String>, unchanged: Vec<String> },
+    /// Only whitespace or layout changed
+    Formatting,
+    /// Only comments changed, and possibly whitespace
+    Comments,
+}
+
+/// What kind of change a declaration went through, coarser than `DeclarationChange`, for
+/// labelling changed nodes and counting in summaries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeClass {
+    /// Its name, parameters, type, modifiers or other fields its users depend on changed
+    SignatureChange,
+    BodyOnly,
+    CommentOnly,
+    FormattingOnly,
+    Added,
+    Removed,
+    /// Changed in a way that could not be narrowed down, the declaration being unnamed
+    Modified,
+}
+
+impl ChangeClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeClass::SignatureChange => "signature-change",
+            ChangeClass::BodyOnly => "body-only",
+            ChangeClass::CommentOnly => "comment-only",
+            ChangeClass::FormattingOnly => "formatting-only",
+            ChangeClass::Added => "added",
+            ChangeClass::Removed => "removed",
+            ChangeClass::Modified => "modified",
+        }
+    }
+}
+
+impl fmt::Display for ChangeClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StructuralChange {
+    pub kind: String,
+    pub name: Option<String>,
+    /// First row in the patched file, or in the original file for removed declarations
+    pub row: usize,
+    /// First row in the original file, when the declaration is known to have been there before
+    /// the patch
+    pub original_row: Option<usize>,
+    pub change: DeclarationChange,
+}
+
+impl StructuralChange {
+    /// Such as `body changed, signature unchanged` or `type changed, name unchanged`.
+    pub fn describe(&self) -> String {
+        let (changed, unchanged) = match &self.change {
+            DeclarationChange::Added => return "added".into(),
+            DeclarationChange::Removed => return "removed".into(),
+            DeclarationChange::Formatting => return "formatting changed".into(),
+            DeclarationChange::Comments => return "comments changed".into(),
+            DeclarationChange::Modified { changed, .. } if changed.is_empty() => return "changed".into(),
+            DeclarationChange::Modified { changed, unchanged } => (changed, unchanged),
+        };
+
+        let has_body = changed.iter().chain(unchanged.iter()).any(|field| field == BODY_FIELD);
+        if !has_body {
+            return match unchanged.is_empty() {
+                true => format!("{} changed", changed.join(", ")),
+                false => format!("{} changed, {} unchanged", changed.join(", "), unchanged.join(", ")),
+            };
+        }
+
+        let signature: Vec<&str> = changed.iter().map(|f| f.as_str()).filter(|f| *f != BODY_FIELD).collect();
+        let body_changed = changed.iter().any(|field| field == BODY_FIELD);
+        match (signature.is_empty(), body_changed) {
+            (true, _) => "body changed, signature unchanged".into(),
+            (false, false) => format!("signature changed ({}), body unchanged", signature.join(", ")),
+            (false, true) => format!("signature ({}) and body changed", signature.join(", ")),
+        }
+    }
+
+    pub fn classify(&self) -> ChangeClass {
+        match &self.change {
+            DeclarationChange::Added => ChangeClass::Added,
+            DeclarationChange::Removed => ChangeClass::Removed,
+            DeclarationChange::Formatting => ChangeClass::FormattingOnly,
+            DeclarationChange::Comments => ChangeClass::CommentOnly,
+            DeclarationChange::Modified { changed, .. } if changed.is_empty() => ChangeClass::Modified,
+            DeclarationChange::Modified { changed, .. } if changed.iter().all(|field| IMPLEMENTATION_FIELDS.contains(&field.as_str())) => ChangeClass::BodyOnly,
+            DeclarationChange::Modified { .. } => ChangeClass::SignatureChange,
+        }
+    }
+}
+
+/// The class of the change to a declaration of the file, given the changes `diff_file` found.
+/// Declarations are matched by kind and their row on the side of the patch it is in, the
+/// patched file or the original one, as rows of one side say nothing about the other.
+pub fn find_class(changes: &[StructuralChange], patched: bool, kind: &str, row: usize) -> Option<ChangeClass> {
+    changes.iter()
+        .filter(|change| change.kind == kind)
+        .find(|change| match patched {
+            true => !matches!(change.change, DeclarationChange::Removed) && change.row == row,
+            false => change.original_row == Some(row),
+        })
+        .map(StructuralChange::classify)
+}
+
+/// Identifies a declaration across both versions of a file: its kind and the names of it and
+/// the declarations enclosing it, so methods of the same name in different types stay apart.
+pub type DeclarationKey = (String, Vec<Option<String>>);
+
+pub fn declaration_key(node: &TSNode, source: &str) -> DeclarationKey {
+    let mut path = vec![syntax::get_declaration_name(node, source)];
+    let mut parent = node.parent();
+    while let Some(current) = parent {
+        if current.is_named() && syntax::is_declaration_kind(current.kind()) {
+            path.push(syntax::get_declaration_name(&current, source));
+        }
+        parent = current.parent();
+    }
+    path.reverse();
+    (node.kind().to_string(), path)
+}
+
+/// Every named declaration in the tree by key, the first one winning if a key repeats.
+pub fn index_declarations<'t>(tree: &'t Tree, source: &str) -> HashMap<DeclarationKey, TSNode<'t>> {
+    let mut index = HashMap::new();
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        if node.is_named() && syntax::is_declaration_kind(node.kind()) && syntax::get_declaration_name(&node, source).is_some() {
+            index.entry(declaration_key(&node, source)).or_insert(node);
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        loop {
+            if cursor.goto_next_sibling() {
+                break;
+            }
+            if !cursor.goto_parent() {
+                return index;
+            }
+        }
+    }
+}
+
+/// The innermost declaration at the start of each row, each once, in row order.
+fn touched_declarations<'t>(tree: &'t Tree, source: &str, rows: &[usize]) -> Vec<TSNode<'t>> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut seen = HashSet::new();
+    let mut declarations = Vec::new();
+    for row in rows.iter() {
+        let point = syntax::get_line_start(*row, lines.get(*row).unwrap_or(&""));
+        if let Some(declaration) = syntax::find_enclosing_declaration(tree, point) {
+            if seen.insert(declaration.id()) {
+                declarations.push(declaration);
+            }
+        }
+    }
+    declarations
+}
+
+fn is_comment(node: &TSNode) -> bool {
+    node.kind().contains("comment")
+}
+
+/// Leaves of the subtree in order, comments left out unless `comments` is set.
+fn leaves<'t>(node: &TSNode<'t>, comments: bool) -> Vec<TSNode<'t>> {
+    let mut leaves = Vec::new();
+    let mut cursor = node.walk();
+    loop {
+        let current = cursor.node();
+        match (!comments && is_comment(&current), current.child_count()) {
+            // Comments are skipped along with their children
+            (true, _) => {},
+            (false, 0) => leaves.push(current),
+            (false, _) => if cursor.goto_first_child() {
+                continue;
+            },
+        }
+        loop {
+            if cursor.node().id() == node.id() {
+                return leaves;
+            }
+            if cursor.goto_next_sibling() {
+                break;
+            }
+            if !cursor.goto_parent() {
+                return leaves;
+            }
+        }
+    }
+}
+
+/// Leaf tokens of the subtree, which compare equal whatever the whitespace between them.
+/// Comments are left out unless `comments` is set.
+fn tokens<'s>(node: &TSNode, source: &'s str, comments: bool) -> Vec<&'s str> {
+    leaves(node, comments)
+        .iter()
+        .map(|leaf| leaf.utf8_text(source.as_bytes()).unwrap_or(""))
+        .collect()
+}
+
+/// The first tokens at which the patched file differs from the original in anything but
+/// whitespace, layout and comments, None when the two are equivalent. A side is None when
+/// the other has tokens past its end.
+pub fn find_first_difference(diff: &Diff) -> Option<(Option<TSNode<'_>>, Option<TSNode<'_>>)> {
+    let old = leaves(&diff.tree.root_node(), false);
+    let new = leaves(&diff.target_tree.root_node(), false);
+    let text = |leaf: &TSNode, source: &str| leaf.utf8_text(source.as_bytes()).unwrap_or("").to_string();
+    // Grammars can insert zero-width MISSING tokens, which differ by kind alone
+    let differs = |old: &TSNode, new: &TSNode| old.kind() != new.kind() || text(old, &diff.source) != text(new, &diff.target_source);
+    match old.iter().zip(new.iter()).find(|(old, new)| differs(old, new)) {
+        Some((old, new)) => Some((Some(*old), Some(*new))),
+        None if old.len() == new.len() => None,
+        None => Some((old.get(new.len()).copied(), new.get(old.len()).copied())),
+    }
+}
+
+/// Tokens of each field of the declaration, fields in the order they first appear.
+fn field_tokens<'s>(node: &TSNode, source: &'s str) -> Vec<(String, Vec<&'s str>)> {
+    let mut fields: Vec<(String, Vec<&'s str>)> = Vec::new();
+    let mut cursor = node.walk();
+    if !cursor.goto_first_child() {
+        return fields;
+    }
+    loop {
+        if let Some(field) = cursor.field_name() {
+            let child_tokens = tokens(&cursor.node(), source, true);
+            match fields.iter_mut().find(|(name, _)| name == field) {
+                Some((_, field_tokens)) => field_tokens.extend(child_tokens),
+                None => fields.push((field.to_string(), child_tokens)),
+            }
+        }
+        if !cursor.goto_next_sibling() {
+            return fields;
+        }
+    }
+}
+
+/// How a declaration of the original file differs in the patched one, going by its tokens and
+/// those of each of its fields.
+pub fn compare(old: &TSNode, old_source: &str, new: &TSNode, new_source: &str) -> DeclarationChange {
+    if tokens(old, old_source, true) == tokens(new, new_source, true) {
+        return DeclarationChange::Formatting;
+    }
+    if tokens(old, old_source, false) == tokens(new, new_source, false) {
+        return DeclarationChange::Comments;
+    }
+
+    let old_fields = field_tokens(old, old_source);
+    let mut changed = Vec::new();
+    let mut unchanged = Vec::new();
+    for (field, new_tokens) in field_tokens(new, new_source) {
+        match old_fields.iter().find(|(name, _)| *name == field) {
+            Some((_, old_tokens)) if *old_tokens == new_tokens => unchanged.push(field),
+            _ => changed.push(field),
+        }
+    }
+    for (field, _) in old_fields {
+        if !changed.contains(&field) && !unchanged.contains(&field) {
+            changed.push(field);
+        }
+    }
+    // Only unnamed children such as modifiers or attributes differ
+    if changed.is_empty() {
+        changed.push("modifiers".into());
+    }
+
+    DeclarationChange::Modified { changed, unchanged }
+}
+
+/// Whether every row the node spans is in the rows.
+fn fully_within(node: &TSNode, rows: &[usize]) -> bool {
+    (node.start_position().row..=node.end_position().row).all(|row| rows.contains(&row))
+}
+
+/// Changed declarations of a file, matched between the original and patched trees, patched
+/// declarations first in the order they appear and removed ones after.
+pub fn diff_file(diff: &Diff) -> Vec<StructuralChange> {
+    let old_index = index_declarations(&diff.tree, &diff.source);
+    let new_index = index_declarations(&diff.target_tree, &diff.target_source);
+    let mut reported: HashSet<DeclarationKey> = HashSet::new();
+    let mut changes = Vec::new();
+
+    for new in touched_declarations(&diff.target_tree, &diff.target_source, &diff.added_rows) {
+        let key = declaration_key(&new, &diff.target_source);
+        let name = syntax::get_declaration_name(&new, &diff.target_source);
+        let (change, original_row) = match (name.is_some(), old_index.get(&key)) {
+            (true, Some(old)) => (compare(old, &diff.source, &new, &diff.target_source), Some(old.start_position().row)),
+            (true, None) => (DeclarationChange::Added, None),
+            (false, _) if fully_within(&new, &diff.added_rows) => (DeclarationChange::Added, None),
+            (false, _) => (DeclarationChange::Modified { changed: Vec::new(), unchanged: Vec::new() }, None),
+        };
+        reported.insert(key);
+        changes.push(StructuralChange {
+            kind: new.kind().to_string(),
+            name,
+            row: new.start_position().row,
+            original_row,
+            change,
+        });
+    }
+
+    for old in touched_declarations(&diff.tree, &diff.source, &diff.removed_rows) {
+        let key = declaration_key(&old, &diff.source);
+        let name = syntax::get_declaration_name(&old, &diff.source);
+        if name.is_some() && reported.contains(&key) {
+            continue;
+        }
+        let change = match (name.is_some(), new_index.get(&key)) {
+            (true, Some(new)) => compare(&old, &diff.source, new, &diff.target_source),
+            (true, None) => DeclarationChange::Removed,
+            (false, _) if fully_within(&old, &diff.removed_rows) => DeclarationChange::Removed,
+            // Unnamed declarations edited on both sides were already reported from the patched side
+            (false, _) => continue,
+        };
+        let row = match (&change, new_index.get(&key)) {
+            (DeclarationChange::Removed, _) | (_, None) => old.start_position().row,
+            (_, Some(new)) => new.start_position().row,
+        };
+        reported.insert(key);
+        changes.push(StructuralChange {
+            kind: old.kind().to_string(),
+            name,
+            row,
+            original_row: Some(old.start_position().row),
+            change,
+        });
+    }
+
+    changes
+}
+
+/// Writes the changes of every file as a unified listing, one line per declaration marked `+`,
+/// `-` or `~`, colored when written to a terminal.
+pub fn write(graph: &DiffGraph, writer: &mut dyn Write, colored: bool) -> io::Result<()> {
+    let paint = |style: Style, text: &str| if colored { color::paint(Stream::Stdout, style, text) } else { text.to_string() };
+
+    for diff in graph.diffs().iter() {
+        let changes = diff_file(diff);
+        writeln!(writer, "{} ({})", paint(Style::Bold, &diff.source_file_path), paint(Style::Cyan, &diff.language_name))?;
+        if changes.is_empty() {
+            writeln!(writer, "  {}", paint(Style::Dim, "no declarations changed"))?;
+        }
+        for change in changes.iter() {
+            let (marker, style, side) = match change.change {
+                DeclarationChange::Added => ("+", Style::Green, ""),
+                DeclarationChange::Removed => ("-", Style::Red, " before"),
+                DeclarationChange::Modified { .. } => ("~", Style::Yellow, ""),
+                DeclarationChange::Formatting | DeclarationChange::Comments => ("~", Style::Dim, ""),
+            };
+            let name = change.name.as_ref().map_or(String::new(), |name| format!(" {}", name));
+            writeln!(writer, "  {} {}{} {}: {}", paint(style, marker), change.kind, paint(Style::Bold, &name),
+                paint(Style::Dim, &format!("L{}{}", change.row + 1, side)), change.describe())?;
+        }
+    }
+
+    for skipped in graph.skipped().iter() {
+        writeln!(writer, "{} {} ({})", paint(Style::Dim, "skipped:"), skipped.path, skipped.reason)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(kind: &str, name: Option<&str>, row: usize, original_row: Option<usize>, change: DeclarationChange) -> StructuralChange {
+        StructuralChange { kind: kind.to_string(), name: name.map(str::to_string), row, original_row, change }
+    }
+
+    #[test]
+    fn modifications_in_place_are_found_on_both_sides() {
+        let changes = vec![
+            change("function_item", Some("foo"), 4, Some(4), DeclarationChange::Modified { changed: vec!["body".to_string()], unchanged: vec!["name".to_string()] }),
+        ];
+        assert_eq!(find_class(&changes, true, "function_item", 4), Some(ChangeClass::BodyOnly));
+        assert_eq!(find_class(&changes, false, "function_item", 4), Some(ChangeClass::BodyOnly));
+        assert_eq!(find_class(&changes, true, "struct_item", 4), None);
+    }
+
+    #[test]
+    fn declarations_at_the_same_row_are_told_apart_by_side() {
+        let changes = vec![
+            change("impl_item", None, 3, None, DeclarationChange::Added),
+            change("impl_item", None, 3, Some(3), DeclarationChange::Removed),
+        ];
+        assert_eq!(find_class(&changes, true, "impl_item", 3), Some(ChangeClass::Added));
+        assert_eq!(find_class(&changes, false, "impl_item", 3), Some(ChangeClass::Removed));
+    }
+
+    #[test]
+    fn moved_declarations_are_found_at_their_row_on_each_side() {
+        let changes = vec![
+            change("function_item", Some("foo"), 10, Some(2), DeclarationChange::Formatting),
+        ];
+        assert_eq!(find_class(&changes, true, "function_item", 10), Some(ChangeClass::FormattingOnly));
+        assert_eq!(find_class(&changes, false, "function_item", 2), Some(ChangeClass::FormattingOnly));
+        assert_eq!(find_class(&changes, true, "function_item", 2), None);
+    }
+}
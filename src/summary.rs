@@ -0,0 +1,352 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::configs::{ConfigChange, ConfigChangeKind};
+use crate::coverage::UntestedChange;
+use crate::debt::DebtTag;
+use crate::graph::DiffGraph;
+use crate::parser::{Diff, SkippedFile};
+use crate::color::{self, Stream, Style};
+use crate::hints::{self, ScopeHint};
+use crate::links::CrossLanguageLink;
+use crate::policy::Condition;
+use crate::renames::{self, Rename};
+use crate::security::SecurityTag;
+use crate::structural::ChangeClass;
+use crate::syntax;
+
+const HEADERS: [&str; 6] = ["FILE", "LANGUAGE", "HUNKS", "+/-", "DECLARATIONS", "PARSE ERRORS"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileSummary {
+    pub path: String,
+    pub language: String,
+    pub hunks: usize,
+    pub added_lines: usize,
+    pub removed_lines: usize,
+    pub changed_declarations: usize,
+    /// Changed declarations by how they changed
+    pub changes: BTreeMap<ChangeClass, usize>,
+    /// Syntax errors in the patched file
+    pub parse_errors: usize,
+    /// Owners of the file by the repository's CODEOWNERS, see `DiffGraph::get_owners`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub owners: Vec<String>,
+}
+
+impl FileSummary {
+    pub fn from_diff(diff: &Diff) -> Self {
+        let declarations = diff.changed_declarations();
+        let mut changes: BTreeMap<ChangeClass, usize> = BTreeMap::new();
+        for change in declarations.iter().filter_map(|declaration| declaration.change) {
+            *changes.entry(change).or_default() += 1;
+        }
+        Self {
+            path: diff.source_file_path.clone(),
+            language: diff.language_name.clone(),
+            hunks: diff.hunk_count,
+            added_lines: diff.added_rows.len(),
+            removed_lines: diff.removed_rows.len(),
+            changed_declarations: declarations.len(),
+            changes,
+            parse_errors: syntax::count_error_nodes(&diff.target_tree),
+            owners: Vec::new(),
+        }
+    }
+
+    /// One line description, such as
+    /// `src/main.rs: rust, 2 hunks, +10/-3, 3 changed declarations (2 body-only, 1 signature-change)`.
+    pub fn to_line(&self, stream: Stream) -> String {
+        let mut line = format!("{}: {}, {}, {}/{}, {}",
+            color::paint(stream, Style::Bold, &self.path),
+            color::paint(stream, Style::Cyan, &self.language),
+            plural(self.hunks, "hunk"),
+            color::paint(stream, Style::Green, &format!("+{}", self.added_lines)),
+            color::paint(stream, Style::Red, &format!("-{}", self.removed_lines)),
+            plural(self.changed_declarations, "changed declaration"));
+        if !self.changes.is_empty() {
+            line.push_str(&format!(" ({})", describe_changes(&self.changes)));
+        }
+        if self.parse_errors > 0 {
+            line.push_str(", ");
+            line.push_str(&color::paint(stream, Style::Red, &plural(self.parse_errors, "parse error")));
+        }
+        line
+    }
+}
+
+/// Per-file overview of a graph, printed with `--summary` instead of the graph itself.
+#[derive(Debug)]
+pub struct Summary {
+    pub files: Vec<FileSummary>,
+    pub node_count: usize,
+    pub edge_count: usize,
+    /// Why files in the diff are missing from the table: binary, unknown language or over a limit
+    pub notes: Vec<String>,
+    pub skipped: Vec<SkippedFile>,
+    /// Changed nodes matching a security-sensitive pattern, listed apart from the notes
+    pub security: Vec<SecurityTag>,
+    /// Changed nodes adding a TODO, a panic or a similar pattern of debt, listed apart from the notes
+    pub debt: Vec<DebtTag>,
+    /// Changed declarations with added lines the coverage report says no test ran
+    pub untested: Vec<UntestedChange>,
+    /// Declarations renamed along with their references, each standing for the changed lines
+    /// that only follow the rename
+    pub renames: Vec<Rename>,
+    /// Changed interface definitions and the changed files of other languages using them
+    pub links: Vec<CrossLanguageLink>,
+    /// Keys of configuration files whose values the patch adds, removes or changes
+    pub configs: Vec<ConfigChange>,
+    /// Conventional-commit scopes of the changed files, the likeliest first
+    pub scopes: Vec<ScopeHint>,
+}
+
+impl Summary {
+    pub fn from_graph(graph: &DiffGraph) -> Self {
+        let files = graph.diffs()
+            .iter()
+            .map(|diff| FileSummary {
+                owners: graph.get_owners(&diff.source_file_path).to_vec(),
+                ..FileSummary::from_diff(diff)
+            })
+            .collect();
+        let notes = graph.findings()
+            .iter()
+            .filter(|finding| !matches!(finding.condition, Condition::ParseErrors | Condition::SecurityPatterns | Condition::DebtPatterns))
+            .map(|finding| finding.message.clone())
+            .collect();
+
+        Self {
+            files,
+            node_count: graph.node_count(),
+            edge_count: graph.edge_count(),
+            notes,
+            skipped: graph.skipped().clone(),
+            security: graph.security_tags().clone(),
+            debt: graph.debt_tags().clone(),
+            untested: graph.untested().clone(),
+            renames: renames::find_renames(graph.diffs()),
+            links: graph.links().clone(),
+            configs: graph.config_changes().clone(),
+            scopes: hints::find_scopes(graph),
+        }
+    }
+
+    fn rows(&self) -> Vec<[String; 6]> {
+        self.files
+            .iter()
+            .map(|file| [
+                file.path.clone(),
+                file.language.clone(),
+                file.hunks.to_string(),
+                format!("+{}/-{}", file.added_lines, file.removed_lines),
+                file.changed_declarations.to_string(),
+                file.parse_errors.to_string(),
+            ])
+            .collect()
+    }
+}
+
+/// Such as `2 body-only, 1 signature-change`.
+fn describe_changes(changes: &BTreeMap<ChangeClass, usize>) -> String {
+    changes.iter().map(|(change, count)| format!("{} {}", count, change)).collect::<Vec<_>>().join(", ")
+}
+
+fn plural(count: usize, singular: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, singular)
+    } else {
+        format!("{} {}s", count, singular)
+    }
+}
+
+impl Summary {
+    /// Files by the owners that need to review them, files with the same owners together.
+    fn fmt_owners(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.files.iter().all(|file| file.owners.is_empty()) {
+            return Ok(());
+        }
+        let mut groups: BTreeMap<&[String], Vec<&str>> = BTreeMap::new();
+        for file in self.files.iter() {
+            groups.entry(file.owners.as_slice()).or_default().push(&file.path);
+        }
+        let labels: Vec<String> = groups
+            .keys()
+            .map(|owners| if owners.is_empty() { "no owner".to_string() } else { owners.join(" ") })
+            .collect();
+        let width = labels.iter().map(|label| label.chars().count()).max().unwrap_or(0);
+        writeln!(f, "owners:")?;
+        for (label, paths) in labels.iter().zip(groups.values()) {
+            writeln!(f, "  {}  {}", color::paint(Stream::Stdout, Style::Cyan, &format!("{:<width$}", label, width = width)), paths.join(", "))?;
+        }
+        Ok(())
+    }
+
+    /// A line per rename in place of the mechanical changes to its references.
+    fn fmt_renames(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.renames.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "renames:")?;
+        for rename in self.renames.iter() {
+            write!(f, "  {} {}", rename, color::paint(Stream::Stdout, Style::Dim, &format!("{}:{}, {}", rename.file, rename.line, plural(rename.files.len(), "file"))))?;
+            if !rename.is_consistent() {
+                write!(f, " {}", color::paint(Stream::Stdout, Style::Yellow, &format!("({} to `{}` left)", plural(rename.remaining, "reference"), rename.from)))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+
+    /// Users of each changed interface definition, in other languages.
+    fn fmt_links(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.links.is_empty() {
+            return Ok(());
+        }
+        let mut definitions: Vec<(String, Vec<&CrossLanguageLink>)> = Vec::new();
+        for link in self.links.iter() {
+            let label = format!("{} {}", link.kind, link.name);
+            match definitions.iter_mut().find(|(known, _)| *known == label) {
+                Some((_, users)) => users.push(link),
+                None => definitions.push((label, vec![link])),
+            }
+        }
+        writeln!(f, "links:")?;
+        for (label, users) in definitions.iter() {
+            let files: Vec<String> = users.iter().map(|link| format!("{} ({})", link.user_file, link.user_language)).collect();
+            writeln!(f, "  {} {} used by {}", label,
+                color::paint(Stream::Stdout, Style::Dim, &format!("{}:{}", users[0].definition_file, users[0].definition_line)),
+                files.join(", "))?;
+        }
+        Ok(())
+    }
+
+    /// Changed keys of configuration files, by file.
+    fn fmt_configs(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.configs.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "config:")?;
+        for change in self.configs.iter() {
+            let style = match change.change {
+                ConfigChangeKind::Added => Style::Green,
+                ConfigChangeKind::Removed => Style::Red,
+                ConfigChangeKind::Modified => Style::Yellow,
+            };
+            writeln!(f, "  {} {} {}", color::paint(Stream::Stdout, style, &format!("[{}]", change.change.as_str())), change,
+                color::paint(Stream::Stdout, Style::Dim, &format!("{}:{}", change.file, change.line)))?;
+        }
+        Ok(())
+    }
+
+    /// Changed nodes matching a security-sensitive pattern, by file and line.
+    fn fmt_security(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.security.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "{}", color::paint(Stream::Stdout, Style::Red, &format!("security: {}", plural(self.security.len(), "sensitive change"))))?;
+        for tag in self.security.iter() {
+            writeln!(f, "  {} {} {}",
+                color::paint(Stream::Stdout, Style::Red, &format!("[{}]", tag.tag)),
+                color::paint(Stream::Stdout, Style::Dim, &format!("{}:{}", tag.file, tag.start_line)),
+                tag.excerpt)?;
+        }
+        Ok(())
+    }
+
+    /// Changed nodes adding debt, by file and line.
+    fn fmt_debt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.debt.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "{}", color::paint(Stream::Stdout, Style::Yellow, &format!("debt: {}", plural(self.debt.len(), "added pattern"))))?;
+        for tag in self.debt.iter() {
+            writeln!(f, "  {} {} {}",
+                color::paint(Stream::Stdout, Style::Yellow, &format!("[{}]", tag.tag)),
+                color::paint(Stream::Stdout, Style::Dim, &format!("{}:{}", tag.file, tag.start_line)),
+                tag.excerpt)?;
+        }
+        Ok(())
+    }
+
+    /// Changed declarations no test runs, by file and line.
+    fn fmt_untested(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.untested.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "{}", color::paint(Stream::Stdout, Style::Yellow, &format!("untested changes: {}", plural(self.untested.len(), "declaration"))))?;
+        for change in self.untested.iter() {
+            writeln!(f, "  {} {}", color::paint(Stream::Stdout, Style::Dim, &format!("{}:{}", change.file, change.start_line)), change)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = self.rows();
+        let mut widths = HEADERS.map(|header| header.len());
+        for row in rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        // Cells are padded before they are painted, escape codes would throw off the widths
+        let write_row = |f: &mut fmt::Formatter<'_>, cells: [&str; 6], styles: [Option<Style>; 6]| -> fmt::Result {
+            for (i, ((cell, width), style)) in cells.iter().zip(widths.iter()).zip(styles.iter()).enumerate() {
+                // Text columns are left aligned, counts right aligned
+                let padded = if i < 2 { format!("{:<width$}", cell, width = width) } else { format!("{:>width$}", cell, width = width) };
+                let separator = if i == 0 { "" } else { "  " };
+                match style {
+                    Some(style) => write!(f, "{}{}", separator, color::paint(Stream::Stdout, *style, &padded))?,
+                    None => write!(f, "{}{}", separator, padded)?,
+                }
+            }
+            writeln!(f)
+        };
+        write_row(f, HEADERS, [Some(Style::Bold); 6])?;
+        for (row, file) in rows.iter().zip(self.files.iter()) {
+            let errors_style = if file.parse_errors > 0 { Some(Style::Red) } else { None };
+            write_row(f, [&row[0], &row[1], &row[2], &row[3], &row[4], &row[5]],
+                [None, Some(Style::Cyan), None, None, None, errors_style])?;
+        }
+
+        let languages: BTreeSet<&str> = self.files.iter().map(|file| file.language.as_str()).collect();
+        writeln!(f)?;
+        writeln!(f, "{}, {}, {}, {}",
+            plural(self.files.len(), "file"),
+            plural(languages.len(), "language"),
+            plural(self.files.iter().map(|file| file.changed_declarations).sum(), "changed declaration"),
+            plural(self.files.iter().map(|file| file.parse_errors).sum(), "parse error"))?;
+        let mut changes: BTreeMap<ChangeClass, usize> = BTreeMap::new();
+        for (change, count) in self.files.iter().flat_map(|file| file.changes.iter()) {
+            *changes.entry(*change).or_default() += count;
+        }
+        if !changes.is_empty() {
+            writeln!(f, "changes: {}", describe_changes(&changes))?;
+        }
+        writeln!(f, "graph: {}, {}", plural(self.node_count, "node"), plural(self.edge_count, "edge"))?;
+        if !self.scopes.is_empty() {
+            let scopes: Vec<String> = self.scopes.iter().map(|scope| scope.to_string()).collect();
+            writeln!(f, "scopes: {}", scopes.join(", "))?;
+        }
+        self.fmt_renames(f)?;
+        self.fmt_links(f)?;
+        self.fmt_configs(f)?;
+        self.fmt_security(f)?;
+        self.fmt_debt(f)?;
+        self.fmt_untested(f)?;
+        self.fmt_owners(f)?;
+        for skipped in self.skipped.iter() {
+            writeln!(f, "{} {} ({})", color::paint(Stream::Stdout, Style::Dim, "skipped:"), skipped.path, skipped.reason)?;
+        }
+        for note in self.notes.iter() {
+            writeln!(f, "{} {}", color::paint(Stream::Stdout, Style::Yellow, "note:"), note)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use tree_sitter::{Point, Tree};
+use tree_sitter::Node as TSNode;
+
+/// Grammars name their declarations differently, but most follow one of these suffixes
+/// (`function_item`, `class_declaration`, `method_definition`, ...)
+const DECLARATION_KIND_SUFFIXES: [&str; 4] = ["_item", "_declaration", "_definition", "_specifier"];
+
+/// Kinds ending in a declaration suffix that only wrap or annotate another declaration, or that
+/// bind local variables inside a function body
+const NON_DECLARATION_KINDS: [&str; 10] = [
+    "attribute_item",
+    "inner_attribute_item",
+    "use_declaration",
+    "import_declaration",
+    "expression_statement",
+    "storage_class_specifier",
+    "let_declaration",
+    "lexical_declaration",
+    "local_variable_declaration",
+    "short_var_declaration",
+];
+
+/// Whether nodes of this kind declare something, going by the grammar's naming conventions.
+pub fn is_declaration_kind(kind: &str) -> bool {
+    !NON_DECLARATION_KINDS.contains(&kind)
+        && DECLARATION_KIND_SUFFIXES.iter().any(|suffix| kind.ends_with(suffix))
+}
+
+/// Whether nodes of this kind declare a function or method.
+pub fn is_function_kind(kind: &str) -> bool {
+    is_declaration_kind(kind) && (kind.contains("function") || kind.contains("method"))
+}
+
+/// The declared name, from the `name` field when the grammar has one.
+pub fn get_declaration_name(node: &TSNode, source: &str) -> Option<String> {
+    let name = node.child_by_field_name("name")?;
+    name.utf8_text(source.as_bytes()).ok().map(|name| name.to_string())
+}
+
+/// Position of the first non-whitespace character on a line, where its content starts.
+pub fn get_line_start(row: usize, line: &str) -> Point {
+    Point { row, column: line.len() - line.trim_start().len() }
+}
+
+/// The innermost declaration spanning the point.
+pub fn find_enclosing_declaration(tree: &Tree, point: Point) -> Option<TSNode<'_>> {
+    let mut node = tree.root_node().descendant_for_point_range(point, point);
+    while let Some(current) = node {
+        if current.is_named() && is_declaration_kind(current.kind()) {
+            return Some(current);
+        }
+        node = current.parent();
+    }
+
+    None
+}
+
+/// Number of `ERROR` and `MISSING` nodes tree-sitter inserted to recover from syntax errors.
+pub fn count_error_nodes(tree: &Tree) -> usize {
+    find_error_nodes(tree).len()
+}
+
+/// The `ERROR` and `MISSING` nodes tree-sitter inserted to recover from syntax errors, in order.
+pub fn find_error_nodes(tree: &Tree) -> Vec<TSNode<'_>> {
+    let mut nodes = Vec::new();
+    if !tree.root_node().has_error() {
+        return nodes;
+    }
+
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        if node.is_error() || node.is_missing() {
+            nodes.push(node);
+        }
+        // Only descend where an error is known to be
+        if node.has_error() && cursor.goto_first_child() {
+            continue;
+        }
+        loop {
+            if cursor.goto_next_sibling() {
+                break;
+            }
+            if !cursor.goto_parent() {
+                return nodes;
+            }
+        }
+    }
+}
+
+/// Whether the declaration is visible outside its module: `pub` in Rust, `public` modifiers in
+/// Java, C# and TypeScript classes, exported in JavaScript and TypeScript, or capitalized in Go.
+pub fn is_public_declaration(node: &TSNode, source: &str, language_name: &str) -> bool {
+    if node.parent().is_some_and(|parent| parent.kind() == "export_statement") {
+        return true;
+    }
+
+    let mut cursor = node.walk();
+    let public_modifier = node.children(&mut cursor).any(|child| match child.kind() {
+        "visibility_modifier" => child.utf8_text(source.as_bytes()) == Ok("pub"),
+        "modifiers" | "modifier" | "accessibility_modifier" => child.utf8_text(source.as_bytes())
+            .is_ok_and(|text| text.split_whitespace().any(|word| word == "public")),
+        _ => false,
+    });
+    if public_modifier {
+        return true;
+    }
+
+    language_name == "go"
+        && matches!(node.kind(), "function_declaration" | "method_declaration" | "type_spec")
+        && node.child_by_field_name("name")
+            .and_then(|name| name.utf8_text(source.as_bytes()).ok())
+            .and_then(|name| name.chars().next())
+            .is_some_and(|c| c.is_uppercase())
+}
@@ -0,0 +1,218 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use rayon::prelude::*;
+use serde::Serialize;
+use tracing::debug;
+use tree_sitter::{Node as TSNode, Tree};
+
+use crate::color::{self, Stream, Style};
+use crate::error::DiffGraphError;
+use crate::grammars::Grammars;
+use crate::graph::DiffGraph;
+use crate::index::SymbolIndex;
+use crate::parser::{self, SourceLocation};
+use crate::progress;
+use crate::syntax;
+
+/// Directories tests are kept in, across the layouts of common languages
+const TEST_DIRECTORIES: [&str; 6] = ["test", "tests", "__tests__", "spec", "specs", "testing"];
+/// Endings of the file stems of tests, such as `parser_test.go`, `parser.test.ts` or `ParserTest.java`
+const TEST_STEM_SUFFIXES: [&str; 8] = ["_test", "_tests", "_spec", ".test", ".spec", "Test", "Tests", "Spec"];
+
+/// A test that references declarations the patch changes.
+#[derive(Debug, Clone, Serialize)]
+pub struct AffectedTest {
+    pub file: String,
+    /// The test function, None for references outside of any function, such as in fixtures
+    pub function: Option<String>,
+    /// First line of the function, counted from 1
+    pub line: Option<usize>,
+    /// Changed declarations the test references
+    pub symbols: BTreeSet<String>,
+}
+
+/// Tests likely affected by the patch, for running only those in CI.
+#[derive(Debug, Clone, Serialize)]
+pub struct TestImpactReport {
+    /// Files of the affected tests, in path order
+    pub files: Vec<String>,
+    pub tests: Vec<AffectedTest>,
+    /// Names of the changed declarations the tests were searched for
+    pub symbols: BTreeSet<String>,
+    /// Names of the declarations outside of the diff that the symbol index has calling or
+    /// referencing the changed ones, which the tests were searched for too
+    pub callers: BTreeSet<String>,
+}
+
+/// Whether the path is of a test file, going by where common languages keep their tests and
+/// how they name them.
+pub fn is_test_path(path: &str) -> bool {
+    let mut components: Vec<&str> = path.split('/').collect();
+    let Some(file_name) = components.pop() else {
+        return false;
+    };
+    if components.iter().any(|component| TEST_DIRECTORIES.contains(component)) {
+        return true;
+    }
+    let stem = file_name.rsplit_once('.').map_or(file_name, |(stem, _)| stem);
+    stem.starts_with("test_") || TEST_STEM_SUFFIXES.iter().any(|suffix| stem.ends_with(suffix))
+}
+
+/// The innermost named function spanning the node.
+fn find_enclosing_function<'t>(node: &TSNode<'t>, source: &str) -> Option<(TSNode<'t>, String)> {
+    let mut parent = node.parent();
+    while let Some(current) = parent {
+        if current.is_named() && syntax::is_function_kind(current.kind()) {
+            if let Some(name) = syntax::get_declaration_name(&current, source) {
+                return Some((current, name));
+            }
+        }
+        parent = current.parent();
+    }
+    None
+}
+
+/// References in the test file to the symbols, as the tests referencing them.
+fn find_references(path: &str, tree: &Tree, source: &str, symbols: &BTreeSet<String>) -> Vec<AffectedTest> {
+    let mut tests: Vec<AffectedTest> = Vec::new();
+    let mut functions: HashMap<Option<usize>, usize> = HashMap::new();
+    let mut cursor = tree.walk();
+    'walk: loop {
+        let node = cursor.node();
+        let text = source.get(node.byte_range()).unwrap_or_default();
+        // Declarations of the same name in the test file are not references to the changed ones
+        let declared = node.parent().is_some_and(|parent| parent.child_by_field_name("name").is_some_and(|name| name.id() == node.id()));
+        if node.child_count() == 0 && node.kind().ends_with("identifier") && !declared && symbols.contains(text) {
+            let function = find_enclosing_function(&node, source);
+            let index = *functions.entry(function.as_ref().map(|(function, _)| function.id())).or_insert_with(|| {
+                tests.push(AffectedTest {
+                    file: path.to_string(),
+                    line: function.as_ref().map(|(function, _)| function.start_position().row + 1),
+                    function: function.map(|(_, name)| name),
+                    symbols: BTreeSet::new(),
+                });
+                tests.len() - 1
+            });
+            tests[index].symbols.insert(text.to_string());
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+    tests
+}
+
+/// References in the test file as it is in `source_location`, none when its language is unknown.
+fn try_search_file(path: &str, grammars: &Grammars, source_location: &SourceLocation, symbols: &BTreeSet<String>) -> Result<Vec<AffectedTest>, DiffGraphError> {
+    let Some((language, _)) = grammars.try_get_language(&source_location.get_repo_dir().join(path))? else {
+        return Ok(Vec::new());
+    };
+    let source = source_location.try_load(path)?;
+    match parser::try_reparse_source_code(language, source.as_bytes(), None)? {
+        Some(tree) => Ok(find_references(path, &tree, &source, symbols)),
+        None => Ok(Vec::new()),
+    }
+}
+
+impl TestImpactReport {
+    /// Searches every test file of the repository for references to the declarations the patch
+    /// changes. Test files the patch changes are searched as patched, the others as they are in
+    /// `source_location`. References are by name alone, so tests calling another declaration of
+    /// the same name are reported too. With an index, tests reaching the changed declarations
+    /// through the declarations outside of the diff that use them are found as well.
+    pub fn try_from_graph(graph: &DiffGraph, grammars: &Grammars, source_location: &SourceLocation, index: Option<&SymbolIndex>) -> Result<Self, DiffGraphError> {
+        let symbols: BTreeSet<String> = graph.diffs()
+            .iter()
+            .flat_map(|diff| diff.changed_declarations())
+            .filter_map(|declaration| declaration.name)
+            .collect();
+        if symbols.is_empty() {
+            return Ok(Self { files: Vec::new(), tests: Vec::new(), symbols, callers: BTreeSet::new() });
+        }
+
+        // Callers in the tests themselves are what is being searched for, not a way to it
+        let changed_files: HashSet<&str> = graph.diffs().iter().map(|diff| diff.source_file_path.as_str()).collect();
+        let callers: BTreeSet<String> = index
+            .map(|index| index.find_referrers(&symbols, &changed_files))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|definition| !is_test_path(&definition.file) && !symbols.contains(&definition.name))
+            .map(|definition| definition.name.clone())
+            .collect();
+        let searched: BTreeSet<String> = symbols.union(&callers).cloned().collect();
+
+        // Test files the patch changes or adds, searched as patched
+        let patched: HashMap<&str, (&Tree, &str)> = graph.diffs()
+            .iter()
+            .filter(|diff| is_test_path(&diff.source_file_path))
+            .map(|diff| (diff.source_file_path.as_str(), (&diff.target_tree, diff.target_source.as_str())))
+            .collect();
+        let paths: Vec<String> = source_location.try_list_files()?
+            .into_iter()
+            .filter(|path| is_test_path(path) && !patched.contains_key(path.as_str()))
+            .collect();
+
+        let mut tests: Vec<AffectedTest> = Vec::new();
+        for (path, (tree, source)) in patched.iter() {
+            tests.extend(find_references(path, tree, source, &searched));
+        }
+
+        let bar = progress::bar(paths.len() as u64, "Scanning tests");
+        let found: Vec<Vec<AffectedTest>> = paths
+            .par_iter()
+            .map(|path| {
+                let references = try_search_file(path, grammars, source_location, &searched).unwrap_or_else(|e| {
+                    debug!("Unable to search {} for references: {}", path, e);
+                    Vec::new()
+                });
+                bar.inc(1);
+                references
+            })
+            .collect();
+        bar.finish_and_clear();
+        tests.extend(found.into_iter().flatten());
+
+        tests.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+        let files: Vec<String> = tests
+            .iter()
+            .map(|test| test.file.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        Ok(Self { files, tests, symbols, callers })
+    }
+}
+
+impl fmt::Display for TestImpactReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.tests.is_empty() {
+            return writeln!(f, "No tests reference the changed declarations");
+        }
+
+        let mut file = None;
+        for test in self.tests.iter() {
+            if file != Some(&test.file) {
+                writeln!(f, "{}", color::paint(Stream::Stdout, Style::Bold, &test.file))?;
+                file = Some(&test.file);
+            }
+            let symbols = test.symbols.iter().map(|symbol| symbol.as_str()).collect::<Vec<_>>().join(", ");
+            match (&test.function, test.line) {
+                (Some(function), Some(line)) => writeln!(f, "  {} {}: {}", function,
+                    color::paint(Stream::Stdout, Style::Dim, &format!("L{}", line)), symbols)?,
+                _ => writeln!(f, "  {}: {}", color::paint(Stream::Stdout, Style::Dim, "outside of functions"), symbols)?,
+            }
+        }
+        writeln!(f)?;
+        write!(f, "{} test files, {} tests", self.files.len(), self.tests.iter().filter(|test| test.function.is_some()).count())?;
+        match self.callers.len() {
+            0 => writeln!(f),
+            count => writeln!(f, ", searching {} unchanged callers too", count),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How many of the slowest files to list in the report
+const SLOWEST_FILES: usize = 5;
+
+static TIMINGS_ENABLED: AtomicBool = AtomicBool::new(false);
+static PHASES: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+static FILES: Mutex<Vec<(String, Duration)>> = Mutex::new(Vec::new());
+
+pub fn set_enabled(enabled: bool) {
+    TIMINGS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    TIMINGS_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Runs `f`, adding its wall time to the named phase when timings are enabled.
+pub fn phase<T>(name: &'static str, f: impl FnOnce() -> T) -> T {
+    if !is_enabled() {
+        return f();
+    }
+
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    if let Ok(mut phases) = PHASES.lock() {
+        match phases.iter_mut().find(|(phase, _)| *phase == name) {
+            Some((_, total)) => *total += elapsed,
+            None => phases.push((name, elapsed)),
+        }
+    }
+    result
+}
+
+/// Runs `f`, recording its wall time against the file when timings are enabled.
+pub fn file<T>(path: &str, f: impl FnOnce() -> T) -> T {
+    if !is_enabled() {
+        return f();
+    }
+
+    let start = Instant::now();
+    let result = f();
+    if let Ok(mut files) = FILES.lock() {
+        files.push((path.to_string(), start.elapsed()));
+    }
+    result
+}
+
+/// The time spent per phase so far, in the order the phases first ran, starting over.
+pub fn take_phases() -> Vec<(&'static str, Duration)> {
+    PHASES.lock().map(|mut phases| phases.drain(..).collect()).unwrap_or_default()
+}
+
+/// The time spent per file so far, in the order the files finished, starting over.
+pub fn take_files() -> Vec<(String, Duration)> {
+    FILES.lock().map(|mut files| files.drain(..).collect()).unwrap_or_default()
+}
+
+/// The most memory the process has had resident, in bytes, where the platform tells.
+pub fn get_peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line.trim_start_matches("VmHWM:").trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        bytes if bytes >= 1 << 30 => format!("{:.1} GiB", bytes as f64 / (1u64 << 30) as f64),
+        bytes if bytes >= 1 << 20 => format!("{:.1} MiB", bytes as f64 / (1u64 << 20) as f64),
+        bytes if bytes >= 1 << 10 => format!("{:.1} KiB", bytes as f64 / (1u64 << 10) as f64),
+        bytes => format!("{} B", bytes),
+    }
+}
+
+/// Prints the time spent per phase, the peak memory and the slowest files to stderr, then starts
+/// over.
+pub fn report() {
+    if !is_enabled() {
+        return;
+    }
+    let phases = take_phases();
+    let mut files = take_files();
+
+    let width = phases.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    eprintln!("timings:");
+    for (name, elapsed) in phases.iter() {
+        eprintln!("  {:<width$}  {:>9.3}s", name, elapsed.as_secs_f64(), width = width);
+    }
+    if let Some(peak) = get_peak_rss() {
+        eprintln!("peak memory: {}", format_bytes(peak));
+    }
+
+    if files.is_empty() {
+        return;
+    }
+    // Files are parsed in parallel, so their times overlap and add up to more than the parse phase
+    files.sort_by_key(|(_, elapsed)| std::cmp::Reverse(*elapsed));
+    let shown = &files[..files.len().min(SLOWEST_FILES)];
+    let width = shown.iter().map(|(path, _)| path.len()).max().unwrap_or(0);
+    eprintln!("slowest files ({} parsed):", files.len());
+    for (path, elapsed) in shown.iter() {
+        eprintln!("  {:<width$}  {:>9.3}s", path, elapsed.as_secs_f64(), width = width);
+    }
+}
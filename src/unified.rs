@@ -0,0 +1,167 @@
+use std::fmt;
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+/// Tool a unified diff was written by, going by its headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffFormat {
+    Git,
+    /// `hg diff`, which prefixes paths with `a/` and `b/` like git but writes no `diff --git` lines
+    Mercurial,
+    /// `svn diff`, with an `Index:` line before each file and paths as they are
+    Subversion,
+    /// `diff -u` and anything else, with the paths it was given
+    Plain,
+}
+
+impl DiffFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiffFormat::Git => "git",
+            DiffFormat::Mercurial => "mercurial",
+            DiffFormat::Subversion => "subversion",
+            DiffFormat::Plain => "plain",
+        }
+    }
+
+    pub fn detect(diff: &str) -> Self {
+        let mut lines = diff.lines().map(|line| line.trim_end_matches('\r')).peekable();
+        let mut format = DiffFormat::Plain;
+        while let Some(line) = lines.next() {
+            if line.starts_with("diff --git ") {
+                return DiffFormat::Git;
+            } else if line.starts_with("diff -r ") || line == "# HG changeset patch" {
+                format = DiffFormat::Mercurial;
+            } else if line.starts_with("Index: ") && lines.peek().is_some_and(|next| next.starts_with("====")) {
+                format = DiffFormat::Subversion;
+            }
+        }
+        format
+    }
+}
+
+impl fmt::Display for DiffFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Timestamps and revisions that tools write for the missing side of an added or removed file:
+/// Subversion's `(nonexistent)` or `(revision 0)`, and the epoch `diff -N` dates it with.
+fn missing_side_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"\(nonexistent\)|\(revision 0\)|^(1970-01-01|1969-12-31)\b|\b1970$").unwrap())
+}
+
+/// Path and timestamp of a `---` or `+++` header line.
+fn split_header(line: &str) -> (&str, &str) {
+    let header = &line[4..];
+    header.split_once('\t').unwrap_or((header, ""))
+}
+
+/// The path in the repository both sides of a file header name: without Mercurial's `a/` and
+/// `b/`, without the first component of each when only that differs, as with `orig/` and `new/`,
+/// and otherwise the path of the patched file.
+fn get_repository_path<'a>(format: DiffFormat, source: &'a str, target: &'a str, target_missing: bool) -> &'a str {
+    let strip = |path: &'a str, prefix: &str| {
+        let path = path.strip_prefix("./").unwrap_or(path);
+        match format {
+            DiffFormat::Mercurial => path.strip_prefix(prefix).unwrap_or(path),
+            _ => path,
+        }
+    };
+    let (source, target) = (strip(source, "a/"), strip(target, "b/"));
+    match (source.split_once('/'), target.split_once('/')) {
+        (Some((source_root, source_rest)), Some((target_root, target_rest))) if source_root != target_root && source_rest == target_rest => target_rest,
+        _ if target_missing => source,
+        _ => target,
+    }
+}
+
+/// Rewrites the file headers of a unified diff the way git writes them: paths in the repository
+/// prefixed with `a/` and `b/`, without timestamps or revisions, and `/dev/null` for the missing
+/// side of added and removed files. Diffs from `hg diff`, `svn diff` and `diff -u` can then be
+/// parsed and checked like git's, which are returned as they are.
+pub fn to_git_headers(diff: &str, format: DiffFormat) -> String {
+    if format == DiffFormat::Git {
+        return diff.to_string();
+    }
+
+    let lines: Vec<&str> = diff.split('\n').collect();
+    let mut text = String::with_capacity(diff.len());
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_end_matches('\r');
+        let next = lines.get(i + 1).map(|next| next.trim_end_matches('\r'));
+        let Some(next) = next.filter(|next| line.starts_with("--- ") && next.starts_with("+++ ")) else {
+            text.push_str(lines[i]);
+            if i + 1 < lines.len() {
+                text.push('\n');
+            }
+            i += 1;
+            continue;
+        };
+
+        let (source, source_timestamp) = split_header(line);
+        let (target, target_timestamp) = split_header(next);
+        let source_missing = source == "/dev/null" || missing_side_regex().is_match(source_timestamp.trim());
+        let target_missing = target == "/dev/null" || missing_side_regex().is_match(target_timestamp.trim());
+        let path = get_repository_path(format, source, target, target_missing);
+        match source_missing {
+            true => text.push_str("--- /dev/null\n"),
+            false => text.push_str(&format!("--- a/{}\n", path)),
+        }
+        match target_missing {
+            true => text.push_str("+++ /dev/null\n"),
+            false => text.push_str(&format!("+++ b/{}\n", path)),
+        }
+        i += 2;
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_each_format() {
+        assert_eq!(DiffFormat::detect("diff --git a/x b/x\n--- a/x\n+++ b/x\n"), DiffFormat::Git);
+        assert_eq!(DiffFormat::detect("diff -r 1234 x\n--- a/x\n+++ b/x\n"), DiffFormat::Mercurial);
+        assert_eq!(DiffFormat::detect("Index: x\n===================================================================\n--- x\n+++ x\n"), DiffFormat::Subversion);
+        assert_eq!(DiffFormat::detect("--- x.orig\n+++ x\n"), DiffFormat::Plain);
+    }
+
+    #[test]
+    fn git_diffs_are_unchanged() {
+        let diff = "diff --git a/x b/x\n--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b\n";
+        assert_eq!(to_git_headers(diff, DiffFormat::Git), diff);
+    }
+
+    #[test]
+    fn mercurial_headers_lose_their_timestamps() {
+        let diff = "diff -r 1234 src/x.rs\n--- a/src/x.rs\tThu Jan 01 00:00:00 2026 +0000\n+++ b/src/x.rs\tThu Jan 01 00:00:00 2026 +0000\n@@ -1 +1 @@\n-a\n+b\n";
+        assert_eq!(to_git_headers(diff, DiffFormat::Mercurial),
+            "diff -r 1234 src/x.rs\n--- a/src/x.rs\n+++ b/src/x.rs\n@@ -1 +1 @@\n-a\n+b\n");
+    }
+
+    #[test]
+    fn subversion_added_files_come_from_dev_null() {
+        let diff = "Index: src/x.rs\n===\n--- src/x.rs\t(nonexistent)\n+++ src/x.rs\t(working copy)\n@@ -0,0 +1 @@\n+a\n";
+        assert_eq!(to_git_headers(diff, DiffFormat::Subversion),
+            "Index: src/x.rs\n===\n--- /dev/null\n+++ b/src/x.rs\n@@ -0,0 +1 @@\n+a\n");
+    }
+
+    #[test]
+    fn plain_diffs_of_two_trees_take_the_path_inside_them() {
+        let diff = "--- orig/src/x.rs\t2026-01-01 00:00:00\n+++ new/src/x.rs\t2026-01-02 00:00:00\n@@ -1 +1 @@\n-a\n+b\n";
+        assert_eq!(to_git_headers(diff, DiffFormat::Plain), "--- a/src/x.rs\n+++ b/src/x.rs\n@@ -1 +1 @@\n-a\n+b\n");
+    }
+
+    #[test]
+    fn plain_removed_files_go_to_dev_null() {
+        let diff = "--- src/x.rs\t2026-01-01 00:00:00\n+++ src/x.rs\t1970-01-01 00:00:00\n@@ -1 +0,0 @@\n-a\n";
+        assert_eq!(to_git_headers(diff, DiffFormat::Plain), "--- a/src/x.rs\n+++ /dev/null\n@@ -1 +0,0 @@\n-a\n");
+    }
+}
@@ -0,0 +1,79 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use tracing::debug;
+
+use crate::bundle;
+use crate::error::DiffGraphError;
+use crate::export::{self, ExportFormat, OutputOptions, OutputTarget};
+use crate::graph::DiffGraph;
+
+/// Starts the program without waiting on it, false when it is not installed.
+fn try_launch(program: &str, args: &[&str], path: &Path) -> Result<bool, DiffGraphError> {
+    let spawned = Command::new(program)
+        .args(args)
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    match spawned {
+        Ok(_) => {
+            debug!("Opened {} with {}", path.display(), program);
+            Ok(true)
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(DiffGraphError::io(format!("Unable to run {}", program), e)),
+    }
+}
+
+/// Opens the file with the desktop's default application, a browser for SVG and HTML.
+fn try_open_default(path: &Path) -> Result<(), DiffGraphError> {
+    let launched = if cfg!(target_os = "macos") {
+        try_launch("open", &[], path)?
+    } else if cfg!(windows) {
+        try_launch("cmd", &["/C", "start", ""], path)?
+    } else {
+        try_launch("xdg-open", &[], path)?
+    };
+    match launched {
+        true => Ok(()),
+        false => Err(DiffGraphError::Usage(format!("Unable to find a program to open {} with", path.display()))),
+    }
+}
+
+/// Opens DOT in xdot, or renders it to SVG next to it and opens that in the browser.
+fn try_open_dot(dot_path: &Path) -> Result<(), DiffGraphError> {
+    if try_launch("xdot", &[], dot_path)? {
+        return Ok(());
+    }
+
+    let dot = std::fs::read(dot_path).map_err(|e| DiffGraphError::io(format!("Unable to read '{}'", dot_path.display()), e))?;
+    let svg_path = dot_path.with_extension("svg");
+    if !bundle::try_render_svg(&dot, &svg_path)? {
+        return Err(DiffGraphError::Usage("--open needs xdot or Graphviz dot to be installed".into()));
+    }
+    try_open_default(&svg_path)
+}
+
+/// Where the graph is written for viewing when the export itself cannot be opened.
+fn temp_dot_path() -> PathBuf {
+    std::env::temp_dir().join(format!("diffgraph-{}.dot", std::process::id()))
+}
+
+/// Opens what was just exported in a viewer: the bundle's index.html, a DOT file in xdot, or
+/// otherwise the graph written to a temporary DOT file.
+pub fn try_open(graph: &DiffGraph, options: &OutputOptions) -> Result<(), DiffGraphError> {
+    match (options.format, &options.target) {
+        (Some(ExportFormat::CiBundle), OutputTarget::File(dir)) => try_open_default(&dir.join("index.html")),
+        (Some(ExportFormat::Dot), OutputTarget::File(path)) => try_open_dot(path),
+        _ => {
+            let path = temp_dot_path();
+            let mut dot = Vec::new();
+            export::write_file_dot(graph, None, &mut dot).map_err(|e| DiffGraphError::io("Unable to write DOT", e))?;
+            std::fs::write(&path, dot).map_err(|e| DiffGraphError::io(format!("Unable to write '{}'", path.display()), e))?;
+            try_open_dot(&path)
+        },
+    }
+}
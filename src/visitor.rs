@@ -0,0 +1,22 @@
+use crate::error::DiffGraphError;
+use crate::graph::{Edge, NodeInfo};
+use crate::parser::Diff;
+
+/// Callbacks invoked while a graph is built, see `DiffGraph::create_with_visitor`. Every method
+/// does nothing by default, so implementations only override what they need.
+pub trait GraphVisitor {
+    /// A file's tree is about to be added to the graph. `file` indexes `DiffGraph::diffs`.
+    fn on_file_start(&mut self, _file: usize, _diff: &Diff) {}
+
+    /// A node was added to the graph, called once per node.
+    fn on_node(&mut self, _node: &NodeInfo) {}
+
+    /// An edge is being added to the graph.
+    fn on_edge(&mut self, _edge: &Edge) {}
+
+    /// A file could not be parsed and was left out of the graph, which is built without it.
+    fn on_file_error(&mut self, _path: &str, _error: &DiffGraphError) {}
+}
+
+/// Visits nothing, for building a graph without callbacks.
+impl GraphVisitor for () {}
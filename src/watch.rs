@@ -0,0 +1,107 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+use notify::{Event, RecursiveMode, Watcher};
+use tracing::{debug, info};
+
+use crate::git::try_get_uncommitted_patch;
+use crate::error::{self, DiffGraphError};
+use crate::graph::{DiffGraph, DiffGraphParams};
+use crate::export::{self, OutputOptions};
+use crate::webhook;
+use crate::timings;
+
+/// Editors commonly emit several events per save, wait for them to settle before rebuilding
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+#[derive(Debug)]
+pub struct WatchParams {
+    pub repository_dir: PathBuf,
+    /// Options for every rebuild, the diff itself is replaced with the uncommitted changes
+    pub graph_params: DiffGraphParams,
+    /// Written after every rebuild
+    pub output: OutputOptions,
+}
+
+fn is_relevant(event: &notify::Result<Event>) -> bool {
+    match event {
+        Ok(event) => !event.kind.is_access() 
+            && event.paths.iter().any(|p| !p.components().any(|c| c.as_os_str() == ".git")),
+        Err(_) => false,
+    }
+}
+
+/// Graph of the uncommitted changes in the repository, None when there are none. The graph of
+/// the previous build, when given, is updated rather than built again, see `DiffGraph::update`.
+pub fn try_build_uncommitted_graph(repository_dir: &Path, graph_params: &DiffGraphParams, previous: Option<DiffGraph>) -> Result<Option<DiffGraph>, DiffGraphError> {
+    let diff = try_get_uncommitted_patch(repository_dir)?;
+    if diff.patch.files().is_empty() && diff.binary_files.is_empty() {
+        return Ok(None);
+    }
+
+    let params = DiffGraphParams {
+        diff: diff.patch,
+        source_revision: diff.source_revision,
+        git_dir: diff.git_dir,
+        binary_files: diff.binary_files,
+        ..graph_params.clone()
+    };
+    let graph = match previous {
+        Some(previous) => DiffGraph::update(previous, params)?,
+        None => DiffGraph::create(params)?,
+    };
+
+    Ok(Some(graph))
+}
+
+/// Rebuilds and writes the graph, keeping it to update on the next rebuild.
+fn rebuild(params: &WatchParams, previous: &mut Option<DiffGraph>) {
+    match try_build_uncommitted_graph(&params.repository_dir, &params.graph_params, previous.take()) {
+        Ok(Some(graph)) if params.output.summary || params.output.format.is_some() => {
+            if let Err(e) = timings::phase("export", || export::write(&graph, &params.output)) {
+                error::report(&e);
+            }
+            *previous = Some(graph);
+        },
+        Ok(Some(graph)) => {
+            println!("{}", graph);
+            for truncation in graph.truncations() {
+                println!("  truncated: {}", truncation);
+            }
+            if let Some(url) = &params.output.notify_url {
+                if let Err(e) = webhook::try_notify(url, &graph, false) {
+                    error::report(&e);
+                }
+            }
+            *previous = Some(graph);
+        },
+        Ok(None) => println!("No uncommitted changes"),
+        Err(e) => error::report(&e),
+    }
+    timings::report();
+}
+
+/// Rebuilds the graph of uncommitted changes every time a file in the repository changes.
+pub fn run(params: WatchParams) -> Result<(), DiffGraphError> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)
+        .map_err(|e| DiffGraphError::io("Unable to create a file watcher", io::Error::other(e)))?;
+    watcher.watch(&params.repository_dir, RecursiveMode::Recursive)
+        .map_err(|e| DiffGraphError::io(format!("Unable to watch {}", params.repository_dir.display()), io::Error::other(e)))?;
+    info!("Watching {} for changes", params.repository_dir.display());
+
+    let mut previous = None;
+    rebuild(&params, &mut previous);
+    while let Ok(event) = rx.recv() {
+        if !is_relevant(&event) {
+            continue;
+        }
+        debug!("Change detected: {:?}", event);
+        while rx.recv_timeout(DEBOUNCE).is_ok() {}
+
+        rebuild(&params, &mut previous);
+    }
+
+    Ok(())
+}
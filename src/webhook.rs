@@ -0,0 +1,33 @@
+use serde_json::json;
+
+use crate::bundle::Metrics;
+use crate::color;
+use crate::error::DiffGraphError;
+use crate::graph::DiffGraph;
+use crate::snapshot::GraphSnapshot;
+use crate::summary::Summary;
+
+/// The JSON export of the graph, or with `summary` the summary table and its totals.
+pub fn payload(graph: &DiffGraph, summary: bool) -> String {
+    if summary {
+        json!({
+            "summary": color::strip(&Summary::from_graph(graph).to_string()),
+            "metrics": Metrics::from_graph(graph),
+        }).to_string()
+    } else {
+        serde_json::to_string(&GraphSnapshot::from_graph(graph)).unwrap_or_default()
+    }
+}
+
+/// POSTs the results to a webhook once the analysis has finished.
+pub fn try_notify(url: &str, graph: &DiffGraph, summary: bool) -> Result<(), DiffGraphError> {
+    reqwest::blocking::Client::new()
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header("User-Agent", "diffgraph")
+        .body(payload(graph, summary))
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| DiffGraphError::Network(format!("Unable to notify {}: {}", url, e)))?;
+    Ok(())
+}